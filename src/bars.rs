@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use torseed::progress::HashProgress;
+use torseed::util::ByteUnits;
+
+/// Redraw interval of spinners.
+const SPINNER_TICK: Duration = Duration::from_millis(100);
+
+/// Progress bars drawn on an interactive stdout. Log lines are written
+/// through [`Bars::log_writer`] so they appear above the bars instead of
+/// tearing through them.
+#[derive(Clone)]
+pub struct Bars {
+    multi: MultiProgress,
+}
+
+impl Bars {
+    pub fn new() -> Self {
+        Self {
+            multi: MultiProgress::with_draw_target(ProgressDrawTarget::stdout()),
+        }
+    }
+
+    /// A writer for the tracing subscriber that hides the bars while a line
+    /// is printed.
+    pub fn log_writer(&self) -> LogWriter {
+        LogWriter(self.multi.clone())
+    }
+
+    /// A spinner shown until it is finished or dropped.
+    pub fn spinner(&self, message: &'static str) -> ProgressBar {
+        let spinner = self.multi.add(ProgressBar::new_spinner());
+        spinner.set_style(ProgressStyle::with_template("{spinner} {msg} ({elapsed})").expect("valid template"));
+        spinner.set_message(message);
+        spinner.enable_steady_tick(SPINNER_TICK);
+        spinner
+    }
+
+    /// The hashing bar. Without a known length it degrades to a spinner with
+    /// byte counts.
+    pub fn hash_bar(&self, length: Option<u64>) -> ProgressBar {
+        let template = match length {
+            Some(_) => "{bar:30} {percent:>3}% {msg}",
+            None => "{spinner} {msg}",
+        };
+        let bar = self.multi.add(ProgressBar::new(length.unwrap_or(0)));
+        bar.set_style(
+            ProgressStyle::with_template(template)
+                .expect("valid template")
+                .progress_chars("=> "),
+        );
+        bar.enable_steady_tick(SPINNER_TICK);
+        bar
+    }
+}
+
+/// Moves the hashing bar to `progress`.
+pub fn update_hash_bar(bar: &ProgressBar, progress: &HashProgress, units: ByteUnits) {
+    bar.set_position(progress.hashed);
+    let done = match progress.expected {
+        Some(expected) => format!("{} / {}", units.format(progress.hashed), units.format(expected)),
+        None => units.format(progress.hashed),
+    };
+    let rate = progress
+        .rate
+        .map(|rate| units.format_rate(rate))
+        .unwrap_or_else(|| "-".to_string());
+    let eta = progress
+        .eta
+        .map(|eta| humantime::format_duration(Duration::from_secs(eta.as_secs())).to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    bar.set_message(format!("{done} at {rate}, ETA {eta}"));
+}
+
+pub struct LogWriter(MultiProgress);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.suspend(|| io::stdout().write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
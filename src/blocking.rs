@@ -0,0 +1,410 @@
+//! A synchronous [`TorrentBuilder`] for callers that do not run Tokio.
+//!
+//! Each call starts a small single-threaded runtime of its own and shuts it
+//! down before returning, the way `reqwest::blocking` hides its runtime. The
+//! methods mirror [`crate::TorrentBuilder`]; see there for what each one
+//! does. Calling into this module from within an async runtime would stall
+//! it, so every blocking call fails with [`TorseedError::InvalidInput`]
+//! there instead of panicking.
+//!
+//! ```
+//! use torseed::blocking::TorrentBuilder;
+//!
+//! let client = reqwest::Client::new();
+//! let data = std::io::Cursor::new(vec![0u8; 40_000]);
+//! let torrent = TorrentBuilder::from_reader(data, "data.bin", Some(40_000))
+//!     .trackers(["udp://tracker.example.org:1337/announce"])
+//!     .build(&client)?;
+//! assert_eq!(torrent.input.length, 40_000);
+//! # Ok::<(), torseed::TorseedError>(())
+//! ```
+//!
+//! The same call from async code returns an error:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use torseed::TorseedError;
+//!
+//! let client = reqwest::Client::new();
+//! let result = torseed::blocking::TorrentBuilder::from_reader(std::io::empty(), "empty.bin", None)
+//!     .trackers(["udp://tracker.example.org:1337/announce"])
+//!     .build(&client);
+//! assert!(matches!(result, Err(TorseedError::InvalidInput(_))));
+//! # }
+//! ```
+
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use reqwest::Client;
+use tokio::sync::mpsc;
+use tokio_util::io::StreamReader;
+use url::Url;
+
+use crate::cache::DownloadCache;
+use crate::checksum::{ChecksumAlgorithm, DigestHeaderPolicy};
+use crate::error::{Result, TorseedError};
+use crate::http::{HttpOptions, SourceMetadata};
+use crate::progress::EventSink;
+use crate::{CancellationToken, FilePart, Torrent};
+
+/// Bytes read from a [`Read`] source at a time.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks read ahead of the hashers from a [`Read`] source.
+const READ_QUEUE_DEPTH: usize = 16;
+
+/// Blocking counterpart of [`crate::TorrentBuilder`].
+#[derive(Debug)]
+pub struct TorrentBuilder {
+    inner: crate::TorrentBuilder,
+}
+
+impl TorrentBuilder {
+    pub fn new(source: SourceMetadata) -> Self {
+        Self {
+            inner: crate::TorrentBuilder::new(source),
+        }
+    }
+
+    /// Hashes whatever `reader` produces, read on a thread of its own. A
+    /// read error fails the build with [`TorseedError::Io`].
+    ///
+    /// ```
+    /// use std::io::{self, Cursor, Read};
+    /// use torseed::blocking::TorrentBuilder;
+    /// use torseed::TorseedError;
+    ///
+    /// let client = reqwest::Client::new();
+    /// let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    /// let torrent = TorrentBuilder::from_reader(Cursor::new(data.clone()), "data.bin", Some(100_000))
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .build(&client)?;
+    /// let runtime = tokio::runtime::Runtime::new().unwrap();
+    /// let expected = runtime.block_on(
+    ///     torseed::TorrentBuilder::from_reader(Cursor::new(data), "data.bin", Some(100_000))
+    ///         .trackers(["udp://tracker.example.org:1337/announce"])
+    ///         .build(&client),
+    /// )?;
+    /// assert_eq!(torrent.metainfo.infohash_v2, expected.metainfo.infohash_v2);
+    ///
+    /// struct Broken;
+    /// impl Read for Broken {
+    ///     fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+    ///         Err(io::Error::other("disk went away"))
+    ///     }
+    /// }
+    /// let result = TorrentBuilder::from_reader(Broken, "broken.bin", None)
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .build(&client);
+    /// assert!(matches!(result, Err(TorseedError::Io { .. })));
+    /// # Ok::<(), TorseedError>(())
+    /// ```
+    pub fn from_reader(reader: impl Read + Send + 'static, name: &str, length: Option<u64>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<io::Result<Bytes>>(READ_QUEUE_DEPTH);
+        std::thread::spawn(move || {
+            let mut reader = reader;
+            loop {
+                let mut buffer = vec![0; READ_CHUNK_SIZE];
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        Ok(Bytes::from(buffer))
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => Err(err),
+                };
+                let failed = chunk.is_err();
+                // A closed channel means the builder is gone.
+                if sender.blocking_send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let chunks = futures::stream::poll_fn(move |cx| receiver.poll_recv(cx));
+        Self {
+            inner: crate::TorrentBuilder::from_reader(StreamReader::new(chunks), name, length),
+        }
+    }
+
+    /// Hashes a local file, named after its file name.
+    ///
+    /// ```
+    /// use torseed::blocking::TorrentBuilder;
+    ///
+    /// let path = std::env::temp_dir().join(format!("torseed-doc-{}.bin", std::process::id()));
+    /// std::fs::write(&path, vec![1u8; 50_000]).unwrap();
+    /// let client = reqwest::Client::new();
+    /// let torrent = TorrentBuilder::from_file(&path)?
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .build(&client)?;
+    /// std::fs::remove_file(&path).unwrap();
+    /// assert_eq!(torrent.input.length, 50_000);
+    /// assert_eq!(torrent.input.name, path.file_name().unwrap().to_str().unwrap());
+    /// # Ok::<(), torseed::TorseedError>(())
+    /// ```
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| TorseedError::InvalidInput(format!("{} does not name a file", path.display())))?;
+        let file = std::fs::File::open(path)
+            .map_err(|err| TorseedError::io(format!("Failed to open {}", path.display()), err))?;
+        let length = file
+            .metadata()
+            .map_err(|err| TorseedError::io(format!("Failed to read the size of {}", path.display()), err))?
+            .len();
+        let file = tokio::fs::File::from_std(file);
+        Ok(Self {
+            inner: crate::TorrentBuilder::from_reader(file, &name, Some(length)),
+        })
+    }
+
+    /// Probes `url` with HEAD and default [`HttpOptions`], blocking until it
+    /// answers.
+    ///
+    /// ```no_run
+    /// use torseed::blocking::TorrentBuilder;
+    ///
+    /// let client = reqwest::Client::new();
+    /// let url = "https://example.com/release.iso".parse().unwrap();
+    /// let torrent = TorrentBuilder::from_url(&client, url)?
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .build(&client)?;
+    /// println!("{}", hex::encode(torrent.metainfo.infohash_v1.unwrap()));
+    /// # Ok::<(), torseed::TorseedError>(())
+    /// ```
+    pub fn from_url(client: &Client, url: Url) -> Result<Self> {
+        let inner = block_on(crate::TorrentBuilder::from_url(client, url))??;
+        Ok(Self { inner })
+    }
+
+    pub fn from_parts(name: impl Into<String>, parts: Vec<FilePart>) -> Self {
+        Self {
+            inner: crate::TorrentBuilder::from_parts(name, parts),
+        }
+    }
+
+    pub fn source(&self) -> Option<&SourceMetadata> {
+        self.inner.source()
+    }
+
+    pub fn name(self, name: impl Into<String>) -> Self {
+        self.map(|inner| inner.name(name))
+    }
+
+    pub fn announce_tiers(self, tiers: Vec<Vec<String>>) -> Self {
+        self.map(|inner| inner.announce_tiers(tiers))
+    }
+
+    pub fn trackers<I, S>(self, trackers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.map(|inner| inner.trackers(trackers))
+    }
+
+    pub fn webseeds<I, S>(self, webseeds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.map(|inner| inner.webseeds(webseeds))
+    }
+
+    pub fn piece_length(self, piece_length: usize) -> Self {
+        self.map(|inner| inner.piece_length(piece_length))
+    }
+
+    pub fn target_pieces(self, target: u64) -> Self {
+        self.map(|inner| inner.target_pieces(target))
+    }
+
+    pub fn created_by(self, created_by: impl Into<String>) -> Self {
+        self.map(|inner| inner.created_by(created_by))
+    }
+
+    pub fn name_utf8(self, enabled: bool) -> Self {
+        self.map(|inner| inner.name_utf8(enabled))
+    }
+
+    pub fn match_torrent(self, reference: impl Into<Vec<u8>>) -> Self {
+        self.map(|inner| inner.match_torrent(reference))
+    }
+
+    pub fn creation_date(self, timestamp: i64) -> Self {
+        self.map(|inner| inner.creation_date(timestamp))
+    }
+
+    pub fn resume_file(self, path: impl Into<PathBuf>) -> Self {
+        self.map(|inner| inner.resume_file(path))
+    }
+
+    pub fn checksums(self, algorithms: impl IntoIterator<Item = ChecksumAlgorithm>) -> Self {
+        self.map(|inner| inner.checksums(algorithms))
+    }
+
+    pub fn digest_header(self, policy: DigestHeaderPolicy) -> Self {
+        self.map(|inner| inner.digest_header(policy))
+    }
+
+    pub fn max_download_size(self, limit: u64) -> Self {
+        self.map(|inner| inner.max_download_size(limit))
+    }
+
+    pub fn cache(self, cache: DownloadCache) -> Self {
+        self.map(|inner| inner.cache(cache))
+    }
+
+    pub fn save_payload(self, path: impl Into<PathBuf>) -> Self {
+        self.map(|inner| inner.save_payload(path))
+    }
+
+    pub fn http_options(self, options: HttpOptions) -> Self {
+        self.map(|inner| inner.http_options(options))
+    }
+
+    pub fn truncate_overlong(self, truncate: bool) -> Self {
+        self.map(|inner| inner.truncate_overlong(truncate))
+    }
+
+    pub fn hash_buffer_size(self, bytes: usize) -> Self {
+        self.map(|inner| inner.hash_buffer_size(bytes))
+    }
+
+    #[cfg(feature = "http3")]
+    pub fn http3(self, enabled: bool) -> Self {
+        self.map(|inner| inner.http3(enabled))
+    }
+
+    /// Events are sent with `try_send`; read them from another thread with
+    /// [`Receiver::blocking_recv`](tokio::sync::mpsc::Receiver::blocking_recv).
+    pub fn events(self, events: EventSink) -> Self {
+        self.map(|inner| inner.events(events))
+    }
+
+    /// Cancelling the token from another thread stops a running `build`.
+    pub fn cancel_token(self, cancel: CancellationToken) -> Self {
+        self.map(|inner| inner.cancel_token(cancel))
+    }
+
+    /// Downloads and hashes the source, blocking until the torrent is built.
+    pub fn build(self, client: &Client) -> Result<Torrent> {
+        block_on(self.inner.build(client))?
+    }
+
+    fn map(self, configure: impl FnOnce(crate::TorrentBuilder) -> crate::TorrentBuilder) -> Self {
+        Self {
+            inner: configure(self.inner),
+        }
+    }
+}
+
+/// Runs `future` on a runtime that lives for this call only.
+fn block_on<F: Future>(future: F) -> Result<F::Output> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(TorseedError::InvalidInput(
+            "torseed::blocking was called from within an async runtime, which it would stall; \
+             use torseed::TorrentBuilder there instead"
+                .to_string(),
+        ));
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| TorseedError::io("Failed to start a runtime for torseed::blocking", err))?;
+    Ok(runtime.block_on(future))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{payload, response, serve};
+
+    /// The same settings for both entry points, with a fixed date so the
+    /// encoded torrents compare equal.
+    fn configure(builder: crate::TorrentBuilder) -> crate::TorrentBuilder {
+        builder
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .webseeds(["https://example.com/data.bin"])
+            .piece_length(16_384)
+            .creation_date(1_700_000_000)
+            .checksums([ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Md5])
+    }
+
+    fn assert_same(blocking: &Torrent, expected: &Torrent) {
+        assert_eq!(blocking.metainfo.torrent, expected.metainfo.torrent);
+        assert_eq!(blocking.metainfo.infohash_v1, expected.metainfo.infohash_v1);
+        assert_eq!(blocking.metainfo.infohash_v2, expected.metainfo.infohash_v2);
+        assert_eq!(blocking.checksums, expected.checksums);
+    }
+
+    #[test]
+    fn http_sources_build_the_same_torrent() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let data = payload(100_000);
+        let head = response(Some(100_000), b"");
+        let get = response(Some(100_000), &data);
+        let url = runtime.block_on(serve(vec![head.clone(), get.clone(), head, get]));
+
+        let blocking = TorrentBuilder::from_url(&Client::new(), url.clone()).unwrap();
+        assert_eq!(blocking.source().map(|source| source.content_length), Some(100_000));
+        let blocking = blocking.map(configure).build(&Client::new()).unwrap();
+        let expected = runtime
+            .block_on(async {
+                let client = Client::new();
+                configure(crate::TorrentBuilder::from_url(&client, url).await?).build(&client).await
+            })
+            .unwrap();
+        assert_eq!(blocking.input.length, 100_000);
+        assert_same(&blocking, &expected);
+    }
+
+    #[test]
+    fn readers_and_files_build_the_same_torrent() {
+        let data = payload(70_000);
+        let path = std::env::temp_dir().join(format!("torseed-blocking-{}.bin", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let from_file = TorrentBuilder::from_file(&path).unwrap().name("data.bin").map(configure);
+        let from_file = from_file.build(&Client::new());
+        std::fs::remove_file(&path).unwrap();
+        let from_reader = TorrentBuilder::from_reader(io::Cursor::new(data.clone()), "data.bin", Some(70_000))
+            .map(configure)
+            .build(&Client::new())
+            .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let expected = runtime
+            .block_on(
+                configure(crate::TorrentBuilder::from_reader(io::Cursor::new(data), "data.bin", Some(70_000)))
+                    .build(&Client::new()),
+            )
+            .unwrap();
+        assert_same(&from_file.unwrap(), &expected);
+        assert_same(&from_reader, &expected);
+    }
+
+    #[test]
+    fn both_entry_points_honour_cancellation() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let result = TorrentBuilder::from_reader(io::repeat(7), "data.bin", None)
+            .map(configure)
+            .cancel_token(cancel.clone())
+            .build(&Client::new());
+        assert!(matches!(result, Err(TorseedError::Cancelled)), "{result:?}");
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let result = runtime.block_on(
+            configure(crate::TorrentBuilder::from_reader(tokio::io::repeat(7), "data.bin", None))
+                .cancel_token(cancel)
+                .build(&Client::new()),
+        );
+        assert!(matches!(result, Err(TorseedError::Cancelled)), "{result:?}");
+    }
+}
@@ -0,0 +1,2017 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use reqwest::Client;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::cache::{DownloadCache, PayloadWriter};
+use crate::checksum::{self, Checksum, ChecksumAlgorithm, ChecksumHasher, DigestHeaderPolicy, ServedDigest};
+use crate::digest;
+use crate::error::{describe, Result, TorseedError};
+use crate::hash_v1::V1Hasher;
+use crate::hash_v2::{V2Hasher, V2Summary};
+use crate::http::{self, HttpOptions, SourceMetadata};
+use crate::metainfo::{self, BuildFile, BuildInput, FileLayout, Metainfo, ParsedTorrent};
+use crate::progress::{Event, EventSink, Progress, TransferStats};
+use crate::resume::{self, Checkpoint, ResumeState};
+use crate::util::{choose_piece_length, format_bytes, piece_length_for_target, sanitize_filename};
+
+/// Creates a torrent by streaming a source once through both hashers.
+///
+/// For HTTP sources the URL becomes the first webseed unless
+/// [`TorrentBuilder::webseeds`] replaces the list. Without an explicit piece
+/// length, one is chosen from the source size.
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// use torseed::TorrentBuilder;
+///
+/// let client = reqwest::Client::new();
+/// let url = "https://example.com/release.iso".parse()?;
+/// let torrent = TorrentBuilder::from_url(&client, url)
+///     .await?
+///     .trackers(["udp://tracker.example.org:1337/announce"])
+///     .build(&client)
+///     .await?;
+/// std::fs::write("release.iso.torrent", &torrent.metainfo.torrent)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TorrentBuilder {
+    source: Source,
+    name: Option<String>,
+    announce_tiers: Vec<Vec<String>>,
+    webseeds: Option<Vec<String>>,
+    piece_length: Option<usize>,
+    target_pieces: Option<u64>,
+    created_by: String,
+    name_utf8: bool,
+    creation_date: Option<i64>,
+    resume_file: Option<PathBuf>,
+    checksums: Vec<ChecksumAlgorithm>,
+    digest_header: DigestHeaderPolicy,
+    reference: Option<Vec<u8>>,
+    max_download_size: Option<u64>,
+    cache: Option<DownloadCache>,
+    save_payload: Option<PathBuf>,
+    http_options: HttpOptions,
+    truncate_overlong: bool,
+    hash_buffer_size: Option<usize>,
+    #[cfg(feature = "http3")]
+    http3: bool,
+    events: EventSink,
+    cancel: CancellationToken,
+}
+
+/// Piece length used when a reader's length is unknown and none was set. It
+/// keeps the piece count near the usual ~16k ceiling for payloads up to
+/// 64 GiB, at the cost of coarse pieces for small ones.
+pub const UNKNOWN_LENGTH_PIECE_LENGTH: usize = 4 * 1024 * 1024;
+
+/// Bytes requested from a reader per chunk sent to the hashing thread.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+/// Minimum spacing between `BytesHashed` events.
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(250);
+
+enum Source {
+    Http(SourceMetadata),
+    Parts(Vec<FilePart>),
+    Reader {
+        reader: Box<dyn AsyncRead + Send + Unpin>,
+        length: Option<u64>,
+    },
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(source) => f.debug_tuple("Http").field(source).finish(),
+            Self::Parts(parts) => f.debug_tuple("Parts").field(parts).finish(),
+            Self::Reader { length, .. } => f.debug_struct("Reader").field("length", length).finish_non_exhaustive(),
+        }
+    }
+}
+
+/// One file of a multi-file source, see [`TorrentBuilder::from_parts`].
+#[derive(Debug, Clone)]
+pub struct FilePart {
+    /// Path components below the torrent name.
+    pub path: Vec<String>,
+    pub source: SourceMetadata,
+}
+
+/// A finished torrent along with the inputs it was built from.
+#[derive(Debug, Clone)]
+pub struct Torrent {
+    pub input: BuildInput,
+    pub metainfo: Metainfo,
+    pub transfer: TransferStats,
+    /// Whole-file digests requested with [`TorrentBuilder::checksums`].
+    pub checksums: Vec<Checksum>,
+}
+
+impl TorrentBuilder {
+    /// Starts from metadata already fetched with [`http::head_source`].
+    pub fn new(source: SourceMetadata) -> Self {
+        Self::with_source(Source::Http(source))
+    }
+
+    /// Hashes bytes from `reader` instead of downloading them, e.g. an upload
+    /// already flowing through the caller. There is no URL to fall back on,
+    /// so webseeds and trackers must be supplied explicitly.
+    ///
+    /// When `length` is `None` the piece length cannot be derived from the
+    /// size; set [`TorrentBuilder::piece_length`] if the rough size is known,
+    /// otherwise [`UNKNOWN_LENGTH_PIECE_LENGTH`] is used. A known `length`
+    /// that the reader does not match fails the build.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> torseed::Result<()> {
+    /// use torseed::TorrentBuilder;
+    ///
+    /// let data = std::io::Cursor::new(vec![7u8; 100_000]);
+    /// let torrent = TorrentBuilder::from_reader(data, "data.bin", Some(100_000))
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .webseeds(["https://example.com/data.bin"])
+    ///     .build(&reqwest::Client::new())
+    ///     .await?;
+    /// assert_eq!(torrent.input.length, 100_000);
+    /// assert!(torrent.metainfo.infohash_v2.is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_reader(reader: impl AsyncRead + Send + Unpin + 'static, name: &str, length: Option<u64>) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            ..Self::with_source(Source::Reader {
+                reader: Box::new(reader),
+                length,
+            })
+        }
+    }
+
+    fn with_source(source: Source) -> Self {
+        Self {
+            source,
+            name: None,
+            announce_tiers: Vec::new(),
+            webseeds: None,
+            piece_length: None,
+            target_pieces: None,
+            created_by: format!("torseed {}", env!("CARGO_PKG_VERSION")),
+            name_utf8: false,
+            creation_date: None,
+            resume_file: None,
+            checksums: Vec::new(),
+            digest_header: DigestHeaderPolicy::default(),
+            reference: None,
+            max_download_size: None,
+            cache: None,
+            save_payload: None,
+            http_options: HttpOptions::default(),
+            truncate_overlong: false,
+            hash_buffer_size: None,
+            #[cfg(feature = "http3")]
+            http3: false,
+            events: EventSink::none(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Probes `url` for its size and filename with default
+    /// [`HttpOptions`], then starts a builder for it. Probe with
+    /// [`http::head_source`] and start from [`TorrentBuilder::new`] to use
+    /// other options.
+    pub async fn from_url(client: &Client, url: Url) -> Result<Self> {
+        Ok(Self::new(http::head_source(client, &HttpOptions::default(), url).await?))
+    }
+
+    /// Builds a multi-file torrent named `name` from `parts`, downloaded one
+    /// after another. Parts are ordered by path, as the v2 file tree is, and
+    /// every part but the last is followed by a pad file so each starts on a
+    /// piece boundary, as hybrid torrents require.
+    ///
+    /// A multi-file webseed names the directory that holds `name`, which only
+    /// the caller knows, so no webseed is added unless set with
+    /// [`TorrentBuilder::webseeds`]. Resume files and whole-file checksums
+    /// are not supported for parts and are ignored.
+    pub fn from_parts(name: impl Into<String>, mut parts: Vec<FilePart>) -> Self {
+        parts.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut builder = Self::with_source(Source::Parts(parts));
+        builder.name = Some(name.into());
+        builder
+    }
+
+    /// Metadata of an HTTP source; `None` for parts and readers.
+    pub fn source(&self) -> Option<&SourceMetadata> {
+        match &self.source {
+            Source::Http(source) => Some(source),
+            Source::Parts(_) | Source::Reader { .. } => None,
+        }
+    }
+
+    /// Torrent name; defaults to the sanitized source filename.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Announce-list tiers, as returned by [`crate::gather_trackers`].
+    pub fn announce_tiers(mut self, tiers: Vec<Vec<String>>) -> Self {
+        self.announce_tiers = tiers;
+        self
+    }
+
+    /// Puts all trackers in a single tier.
+    pub fn trackers<I, S>(mut self, trackers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.announce_tiers = vec![trackers.into_iter().map(Into::into).collect()];
+        self
+    }
+
+    /// Replaces the webseed list, which otherwise holds just the source URL,
+    /// or nothing for readers.
+    pub fn webseeds<I, S>(mut self, webseeds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.webseeds = Some(webseeds.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Fixed piece length in bytes; a power of two of at least 16 KiB.
+    pub fn piece_length(mut self, piece_length: usize) -> Self {
+        self.piece_length = Some(piece_length);
+        self
+    }
+
+    /// Picks the piece length that yields roughly this many pieces. Ignored
+    /// when [`TorrentBuilder::piece_length`] is set or the length is unknown.
+    pub fn target_pieces(mut self, target: u64) -> Self {
+        self.target_pieces = Some(target);
+        self
+    }
+
+    /// Replaces the `torseed <version>` creator string; an empty one leaves
+    /// the field out. It sits outside the info dictionary, so the infohashes
+    /// stay the same either way.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> torseed::Result<()> {
+    /// use torseed::{metainfo, TorrentBuilder};
+    ///
+    /// let client = reqwest::Client::new();
+    /// let mut torrents = Vec::new();
+    /// for created_by in [None, Some("ACME Release Bot"), Some("")] {
+    ///     let payload = std::io::Cursor::new(vec![7u8; 40_000]);
+    ///     let mut builder = TorrentBuilder::from_reader(payload, "a.bin", Some(40_000))
+    ///         .trackers(["udp://tracker.example.org:1337/announce"]);
+    ///     if let Some(created_by) = created_by {
+    ///         builder = builder.created_by(created_by);
+    ///     }
+    ///     torrents.push(builder.build(&client).await?.metainfo);
+    /// }
+    /// let parsed: Vec<_> = torrents.iter().map(|torrent| metainfo::parse(&torrent.torrent).unwrap()).collect();
+    /// assert!(parsed[0].created_by.as_deref().unwrap().starts_with("torseed "));
+    /// assert_eq!(parsed[1].created_by.as_deref(), Some("ACME Release Bot"));
+    /// assert_eq!(parsed[2].created_by, None);
+    /// for torrent in &torrents[1..] {
+    ///     assert_eq!(torrent.infohash_v1, torrents[0].infohash_v1);
+    ///     assert_eq!(torrent.infohash_v2, torrents[0].infohash_v2);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn created_by(mut self, created_by: impl Into<String>) -> Self {
+        self.created_by = created_by.into();
+        self
+    }
+
+    /// Also writes the name as `name.utf-8` in the info dictionary, for older
+    /// clients that only read non-ASCII names from there. Both keys hold the
+    /// same sanitized name. This changes the infohashes.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> torseed::Result<()> {
+    /// use bendy::decoding::FromBencode;
+    /// use bendy::value::Value;
+    /// use torseed::TorrentBuilder;
+    ///
+    /// let payload = std::io::Cursor::new(vec![7u8; 40_000]);
+    /// let torrent = TorrentBuilder::from_reader(payload, "Café menu.pdf", Some(40_000))
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .name_utf8(true)
+    ///     .build(&reqwest::Client::new())
+    ///     .await?;
+    /// let Ok(Value::Dict(root)) = Value::from_bencode(&torrent.metainfo.torrent) else { panic!() };
+    /// let Some(Value::Dict(info)) = root.get(&b"info"[..]) else { panic!() };
+    /// let text = |key: &[u8]| match info.get(key) {
+    ///     Some(Value::Bytes(bytes)) => String::from_utf8(bytes.to_vec()).unwrap(),
+    ///     other => panic!("{other:?}"),
+    /// };
+    /// assert_eq!(text(b"name"), "Café menu.pdf");
+    /// assert_eq!(text(b"name.utf-8"), text(b"name"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn name_utf8(mut self, enabled: bool) -> Self {
+        self.name_utf8 = enabled;
+        self
+    }
+
+    /// Recreates the torrent `reference` instead of creating a new one, so
+    /// the result joins its swarm. The name and piece length come from the
+    /// reference, and each v1 piece is compared with it as soon as it is
+    /// hashed; the first difference fails the build with
+    /// [`TorseedError::Mismatch`]. The written torrent carries the
+    /// reference's `info` dictionary byte for byte, private flag and all,
+    /// around this builder's trackers, webseeds, creation date and creator
+    /// (see [`metainfo::rewrap`]). Only single-file references with v1
+    /// pieces can be matched.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> torseed::Result<()> {
+    /// use torseed::{metainfo, TorrentBuilder, TorseedError};
+    ///
+    /// let client = reqwest::Client::new();
+    /// let payload = |byte| std::io::Cursor::new(vec![byte; 100_000]);
+    /// let official = TorrentBuilder::from_reader(payload(7), "distro.iso", Some(100_000))
+    ///     .trackers(["udp://official.example:1337/announce"])
+    ///     .piece_length(16_384)
+    ///     .build(&client)
+    ///     .await?
+    ///     .metainfo;
+    ///
+    /// let joined = TorrentBuilder::from_reader(payload(7), "mirror-copy.bin", Some(100_000))
+    ///     .trackers(["udp://mine.example:6969/announce"])
+    ///     .webseeds(["https://mirror.example/distro.iso"])
+    ///     .match_torrent(official.torrent.clone())
+    ///     .build(&client)
+    ///     .await?;
+    /// let (parsed, reference) = (metainfo::parse(&joined.metainfo.torrent)?, metainfo::parse(&official.torrent)?);
+    /// assert_eq!(parsed.info, reference.info);
+    /// assert_eq!(joined.metainfo.infohash_v1, reference.infohash_v1());
+    /// assert_eq!(joined.metainfo.infohash_v2, reference.infohash_v2());
+    /// assert_eq!(joined.input.name, "distro.iso");
+    /// assert_eq!(parsed.announce_tiers, [["udp://mine.example:6969/announce"]]);
+    ///
+    /// let mut corrupted = vec![7u8; 100_000];
+    /// corrupted[40_000] = 0;
+    /// let mismatch = TorrentBuilder::from_reader(std::io::Cursor::new(corrupted), "x", Some(100_000))
+    ///     .trackers(["udp://mine.example:6969/announce"])
+    ///     .match_torrent(official.torrent.clone())
+    ///     .build(&client)
+    ///     .await;
+    /// assert!(matches!(mismatch, Err(TorseedError::Mismatch(message)) if message.contains("Piece 2 ")));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn match_torrent(mut self, reference: impl Into<Vec<u8>>) -> Self {
+        self.reference = Some(reference.into());
+        self
+    }
+
+    /// Unix timestamp for `creation date`; defaults to when hashing finishes.
+    pub fn creation_date(mut self, timestamp: i64) -> Self {
+        self.creation_date = Some(timestamp);
+        self
+    }
+
+    /// Checkpoints hashing to `path` so an interrupted build can continue
+    /// with a Range request. Needs an HTTP source that sends an ETag or
+    /// Last-Modified; ignored for readers.
+    pub fn resume_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.resume_file = Some(path.into());
+        self
+    }
+
+    /// Also computes these whole-file digests over exactly the bytes that
+    /// were hashed into pieces. They need the whole payload, so a resume
+    /// checkpoint is ignored and the source is hashed from the start.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> torseed::Result<()> {
+    /// use torseed::checksum::ChecksumAlgorithm;
+    /// use torseed::TorrentBuilder;
+    ///
+    /// let data = std::io::Cursor::new(b"abc".to_vec());
+    /// let torrent = TorrentBuilder::from_reader(data, "abc.txt", Some(3))
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .checksums([ChecksumAlgorithm::Sha256])
+    ///     .build(&reqwest::Client::new())
+    ///     .await?;
+    /// assert_eq!(
+    ///     torrent.checksums[0].hex(),
+    ///     "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn checksums(mut self, algorithms: impl IntoIterator<Item = ChecksumAlgorithm>) -> Self {
+        self.checksums = algorithms.into_iter().collect();
+        self
+    }
+
+    /// How to treat a `Digest`, `Repr-Digest` or `Content-MD5` header on the
+    /// download. Digests the server claims are computed alongside the piece
+    /// hashes and, by default, a difference fails the build with
+    /// [`TorseedError::Mismatch`]. A download resumed mid-file is not checked.
+    ///
+    /// ```no_run
+    /// # async fn run(source: torseed::SourceMetadata) -> torseed::Result<()> {
+    /// use torseed::checksum::DigestHeaderPolicy;
+    /// use torseed::TorrentBuilder;
+    ///
+    /// // Log a wrong digest instead of failing the build.
+    /// let torrent = TorrentBuilder::new(source)
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .digest_header(DigestHeaderPolicy::Warn)
+    ///     .build(&reqwest::Client::new())
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn digest_header(mut self, policy: DigestHeaderPolicy) -> Self {
+        self.digest_header = policy;
+        self
+    }
+
+    /// Refuses sources larger than `limit` bytes with
+    /// [`TorseedError::InvalidInput`]: up front when the size is known, and
+    /// otherwise as soon as the stream goes past it, whatever the server
+    /// claimed.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use torseed::{TorrentBuilder, TorseedError};
+    ///
+    /// let client = reqwest::Client::new();
+    /// let build = |length| {
+    ///     TorrentBuilder::from_reader(std::io::Cursor::new(vec![0u8; 300_000]), "big.bin", length)
+    ///         .trackers(["udp://tracker.example.org:1337/announce"])
+    ///         .max_download_size(200_000)
+    ///         .build(&client)
+    /// };
+    /// let Err(TorseedError::InvalidInput(message)) = build(Some(300_000)).await else { panic!() };
+    /// assert!(message.contains("larger than the download size cap"), "{message}");
+    ///
+    /// let Err(TorseedError::InvalidInput(message)) = build(None).await else { panic!() };
+    /// assert!(message.contains("went past the download size cap"), "{message}");
+    /// # }
+    /// ```
+    pub fn max_download_size(mut self, limit: u64) -> Self {
+        self.max_download_size = Some(limit);
+        self
+    }
+
+    /// Keeps a copy of an HTTP source in `cache` while downloading it, and
+    /// hashes that copy instead of the network when the source's length,
+    /// ETag and Last-Modified are unchanged since. A copy whose SHA-256 no
+    /// longer matches is dropped and the source downloaded as usual. Ignored
+    /// for readers and multi-file sources.
+    pub fn cache(mut self, cache: DownloadCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Writes the downloaded payload to `path`, which only appears once the
+    /// download is complete. Unlike a [`cache`](Self::cache) copy, the copy
+    /// is not optional: a failed write stops the download and fails the
+    /// build with [`TorseedError::Io`]. Resume state is ignored, since the
+    /// copy needs the source from the start. Fails with
+    /// [`TorseedError::InvalidInput`] for readers and multi-file sources.
+    pub fn save_payload(mut self, path: impl Into<PathBuf>) -> Self {
+        self.save_payload = Some(path.into());
+        self
+    }
+
+    /// Settings for the download: Retry-After limits, the stall timeout and
+    /// netrc credentials. Pass the options the source was probed with;
+    /// [`HttpOptions::default`] otherwise.
+    pub fn http_options(mut self, options: HttpOptions) -> Self {
+        self.http_options = options;
+        self
+    }
+
+    /// By default an HTTP source that keeps sending past its declared
+    /// length fails the build with [`TorseedError::Stream`] as soon as it
+    /// does. With `truncate` set, the bytes up to the declared length are
+    /// hashed and the rest ignored. Either way the excess never reaches the
+    /// hashers.
+    ///
+    /// ```no_run
+    /// # async fn run(source: torseed::SourceMetadata) -> torseed::Result<()> {
+    /// use torseed::TorrentBuilder;
+    ///
+    /// // Hash the first `source.content_length` bytes of a server that sends more.
+    /// let torrent = TorrentBuilder::new(source)
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .truncate_overlong(true)
+    ///     .build(&reqwest::Client::new())
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn truncate_overlong(mut self, truncate: bool) -> Self {
+        self.truncate_overlong = truncate;
+        self
+    }
+
+    /// Gathers up to `bytes` of the incoming stream before hashing it, so
+    /// the hashers see a few large slices rather than every small network
+    /// chunk. Defaults to 4 MiB; the hashes do not depend on it.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> torseed::Result<()> {
+    /// use torseed::TorrentBuilder;
+    ///
+    /// let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    /// let client = reqwest::Client::new();
+    /// let build = |bytes| {
+    ///     TorrentBuilder::from_reader(std::io::Cursor::new(data.clone()), "data.bin", Some(300_000))
+    ///         .trackers(["udp://tracker.example.org:1337/announce"])
+    ///         .piece_length(32_768)
+    ///         .hash_buffer_size(bytes)
+    ///         .build(&client)
+    /// };
+    /// let reference = build(1).await?;
+    /// // Buffers that straddle 16 KiB leaves and 32 KiB pieces.
+    /// for bytes in [16_383, 40_000, 1 << 20] {
+    ///     let torrent = build(bytes).await?;
+    ///     assert_eq!(torrent.metainfo.infohash_v1, reference.metainfo.infohash_v1);
+    ///     assert_eq!(torrent.metainfo.infohash_v2, reference.metainfo.infohash_v2);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn hash_buffer_size(mut self, bytes: usize) -> Self {
+        self.hash_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Asks for the payload over HTTP/3, falling back to HTTP/2 or HTTP/1.1
+    /// when QUIC fails. Only the download itself uses it; the protocol used
+    /// ends up in [`TransferStats::protocol`].
+    ///
+    /// ```no_run
+    /// # async fn run() -> torseed::Result<()> {
+    /// use torseed::TorrentBuilder;
+    ///
+    /// let client = reqwest::Client::new();
+    /// let url = "https://cdn.example.com/release.iso".parse().unwrap();
+    /// let torrent = TorrentBuilder::from_url(&client, url).await?.http3(true).build(&client).await?;
+    /// println!("Downloaded over {}", torrent.transfer.protocol.unwrap_or("?"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "http3")]
+    pub fn http3(mut self, enabled: bool) -> Self {
+        self.http3 = enabled;
+        self
+    }
+
+    /// Reports progress as typed events; see [`EventSink`].
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> torseed::Result<()> {
+    /// use torseed::{Event, EventSink, TorrentBuilder};
+    ///
+    /// let (sender, mut receiver) = tokio::sync::mpsc::channel(64);
+    /// let data = std::io::Cursor::new(vec![0u8; 40_000]);
+    /// TorrentBuilder::from_reader(data, "zeros.bin", Some(40_000))
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .events(EventSink::new(sender))
+    ///     .build(&reqwest::Client::new())
+    ///     .await?;
+    ///
+    /// let mut hashed = 0;
+    /// while let Some(event) = receiver.recv().await {
+    ///     if let Event::BytesHashed(progress) = event {
+    ///         hashed = progress.hashed;
+    ///     }
+    /// }
+    /// assert_eq!(hashed, 40_000);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn events(mut self, events: EventSink) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Stops the build when `cancel` fires, failing it with
+    /// [`TorseedError::Cancelled`]. With a resume file, hashing stops at the
+    /// next piece boundary still in flight and checkpoints there, falling
+    /// back to the last periodic checkpoint.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use torseed::{CancellationToken, TorrentBuilder, TorseedError};
+    ///
+    /// let cancel = CancellationToken::new();
+    /// cancel.cancel();
+    /// let data = std::io::Cursor::new(vec![0u8; 40_000]);
+    /// let result = TorrentBuilder::from_reader(data, "zeros.bin", Some(40_000))
+    ///     .cancel_token(cancel)
+    ///     .build(&reqwest::Client::new())
+    ///     .await;
+    /// assert!(matches!(result, Err(TorseedError::Cancelled)));
+    /// # }
+    /// ```
+    pub fn cancel_token(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Downloads and hashes the source, then encodes the torrent.
+    ///
+    /// When the download of an HTTP source reports a different
+    /// Content-Length than the probe did, as behind some load balancers,
+    /// the download's size is used and a piece length not set explicitly is
+    /// chosen again before hashing starts.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> torseed::Result<()> {
+    /// use torseed::TorrentBuilder;
+    ///
+    /// let data = std::io::Cursor::new(vec![7u8; 300_000]);
+    /// let torrent = TorrentBuilder::from_reader(data, "data.bin", Some(300_000))
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .target_pieces(4)
+    ///     .build(&reqwest::Client::new())
+    ///     .await?;
+    /// assert_eq!(torrent.input.piece_length, 128 * 1024);
+    /// assert_eq!(torrent.input.pieces.len(), 3 * 20);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn build(mut self, client: &Client) -> Result<Torrent> {
+        if self.cancel.is_cancelled() {
+            return Err(TorseedError::Cancelled);
+        }
+        let known_length = match &self.source {
+            Source::Http(source) => Some(source.content_length),
+            Source::Parts(parts) => Some(parts.iter().map(|part| part.source.content_length).sum()),
+            Source::Reader { length, .. } => *length,
+        };
+        if let (Some(limit), Some(length)) = (self.max_download_size, known_length)
+            && length > limit
+        {
+            return Err(TorseedError::InvalidInput(format!(
+                "The source is {} ({length} bytes), larger than the download size cap of {} ({limit} bytes)",
+                format_bytes(length),
+                format_bytes(limit)
+            )));
+        }
+        let reference = match &self.reference {
+            Some(bytes) => Some(check_reference(bytes, &self.source, known_length)?),
+            None => None,
+        };
+        if let Some(reference) = &reference {
+            info!("Matching the reference torrent {:?}", reference.name_lossy());
+            self.name = Some(reference.name_lossy().into_owned());
+            self.piece_length = Some(reference.piece_length as usize);
+        }
+        let piece_length_rule = match (self.piece_length, self.target_pieces) {
+            (Some(_), _) => PieceLengthRule::Fixed,
+            (None, Some(target)) => PieceLengthRule::Target(target),
+            (None, None) => PieceLengthRule::Automatic,
+        };
+        let piece_length = match (self.piece_length, self.target_pieces, known_length) {
+            (Some(piece_length), _, _) => piece_length,
+            (None, Some(target), Some(length)) => piece_length_for_target(length, target),
+            (None, None, Some(length)) => choose_piece_length(length),
+            (None, _, None) => UNKNOWN_LENGTH_PIECE_LENGTH,
+        };
+        match known_length {
+            Some(length) => info!(
+                "Using v1 piece length {} KiB ({} pieces)",
+                piece_length / 1024,
+                length.div_ceil(piece_length as u64)
+            ),
+            None => info!("Using v1 piece length {} KiB for a source of unknown length", piece_length / 1024),
+        }
+
+        let options = HashOptions {
+            expected_pieces: reference.as_ref().and_then(|reference| reference.pieces.clone()),
+            checksums: self.checksums,
+            digest_header: self.digest_header,
+            max_bytes: self.max_download_size,
+            piece_length_rule,
+            truncate_overlong: self.truncate_overlong,
+            save_to: self.save_payload,
+            http: self.http_options,
+            buffer_size: self.hash_buffer_size,
+            #[cfg(feature = "http3")]
+            http3: self.http3,
+            ..HashOptions::default()
+        };
+        let (name, length, webseeds, files, (hashed, transfer)) = match self.source {
+            Source::Http(source) => {
+                self.events.emit(Event::MetadataResolved(source.clone()));
+                let resume_file = self.resume_file.as_deref();
+                let hashed = match &self.cache {
+                    Some(cache) => {
+                        let (events, cancel) = (&self.events, &self.cancel);
+                        hash_cached(client, cache, &source, piece_length, resume_file, options, events, cancel).await?
+                    }
+                    None => {
+                        hash_source(client, &source, piece_length, resume_file, options, &self.events, &self.cancel)
+                            .await?
+                    }
+                };
+                (
+                    self.name.unwrap_or_else(|| sanitize_filename(&source.filename)),
+                    hashed.0.length,
+                    self.webseeds.unwrap_or_else(|| vec![source.url.to_string()]),
+                    Vec::new(),
+                    hashed,
+                )
+            }
+            Source::Parts(_) | Source::Reader { .. } if options.save_to.is_some() => {
+                return Err(TorseedError::InvalidInput(
+                    "Saving the payload only applies to single HTTP sources".to_string(),
+                ));
+            }
+            Source::Parts(parts) => {
+                if self.resume_file.is_some() {
+                    warn!("Resume files are not supported for multi-file sources; hashing from the start");
+                }
+                if !options.checksums.is_empty() {
+                    warn!("Whole-file checksums are not computed for multi-file sources");
+                }
+                let options = HashOptions {
+                    checksums: Vec::new(),
+                    ..options
+                };
+                let (files, pieces, transfer) =
+                    hash_parts(client, &parts, piece_length, options, &self.events, &self.cancel).await?;
+                let length = files.iter().map(|file| file.length).sum();
+                let hashed = Hashed {
+                    pieces,
+                    v2: None,
+                    length,
+                    piece_length,
+                    checksums: Vec::new(),
+                };
+                (
+                    self.name.unwrap_or_default(),
+                    length,
+                    self.webseeds.unwrap_or_default(),
+                    files,
+                    (hashed, transfer),
+                )
+            }
+            Source::Reader { reader, length } => {
+                if self.resume_file.is_some() {
+                    warn!("Resume files only apply to HTTP sources; hashing the reader from the start");
+                }
+                let (hashed, transfer) =
+                    hash_reader(reader, piece_length, length, options, &self.events, &self.cancel).await?;
+                (
+                    self.name.unwrap_or_default(),
+                    hashed.length,
+                    self.webseeds.unwrap_or_default(),
+                    Vec::new(),
+                    (hashed, transfer),
+                )
+            }
+        };
+        let Hashed {
+            pieces,
+            v2,
+            checksums,
+            piece_length,
+            ..
+        } = hashed;
+        if let Some(v2) = &v2 {
+            self.events.emit(Event::PieceLayerFinalized {
+                pieces_root: v2.pieces_root,
+                pieces: v2.piece_layers.len() / 32,
+            });
+        }
+
+        let input = BuildInput {
+            name,
+            length,
+            piece_length: u32::try_from(piece_length)
+                .map_err(|_| TorseedError::InvalidInput(format!("Piece length {piece_length} is too large")))?,
+            pieces,
+            announce_tiers: self.announce_tiers,
+            webseeds,
+            creation_date: self.creation_date.unwrap_or_else(unix_now),
+            created_by: self.created_by,
+            name_utf8: self.name_utf8,
+            v2,
+            files,
+        };
+        let metainfo = match &self.reference {
+            Some(reference) => metainfo::rewrap(reference, &input)?,
+            None => metainfo::build(&input)?,
+        };
+        self.events.emit(Event::TorrentBuilt {
+            infohash_v1: metainfo.infohash_v1,
+            infohash_v2: metainfo.infohash_v2,
+            size: metainfo.torrent.len(),
+        });
+        Ok(Torrent {
+            input,
+            metainfo,
+            transfer,
+            checksums,
+        })
+    }
+}
+
+/// Chunks buffered between the download and the hashing thread. Bounds memory
+/// use when hashing is slower than the network.
+const HASH_QUEUE_DEPTH: usize = 64;
+/// Bytes the hashing thread gathers from small chunks before hashing them,
+/// unless set with [`TorrentBuilder::hash_buffer_size`].
+const HASH_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+/// How often `--resume` state is written while hashing.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How to fetch and hash a source beyond the piece hashes.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HashOptions {
+    /// Each v1 piece is compared as soon as it is hashed and the first
+    /// difference fails the stream with [`TorseedError::Mismatch`].
+    pub(crate) expected_pieces: Option<Vec<u8>>,
+    /// Whole-file digests to accumulate.
+    pub(crate) checksums: Vec<ChecksumAlgorithm>,
+    /// How to treat digests the server claims in response headers.
+    pub(crate) digest_header: DigestHeaderPolicy,
+    /// Zero-pad the last v1 piece to full length, as a BEP 47 pad file
+    /// following the source would.
+    pub(crate) pad_v1: bool,
+    /// Stop with [`TorseedError::InvalidInput`] once more bytes than this
+    /// arrive, counting any resumed prefix.
+    pub(crate) max_bytes: Option<u64>,
+    /// How to choose the piece length again when the download reports a
+    /// different size than the probe did.
+    pub(crate) piece_length_rule: PieceLengthRule,
+    /// Hash a source that sends more than its declared length up to that
+    /// length, instead of failing with [`TorseedError::Stream`].
+    pub(crate) truncate_overlong: bool,
+    /// Copy the download to this cache path as it streams, giving up on the
+    /// copy when a write fails. Skipped when resuming mid-file.
+    pub(crate) cache_to: Option<PathBuf>,
+    /// Copy the download to this path as it streams; a failed write fails
+    /// the stream. Resume state is ignored, as the copy needs the source
+    /// from the start.
+    pub(crate) save_to: Option<PathBuf>,
+    /// Retry, stall and credential settings for the download.
+    pub(crate) http: HttpOptions,
+    /// Bytes to gather before hashing, `HASH_BUFFER_SIZE` when unset.
+    pub(crate) buffer_size: Option<usize>,
+    /// Try HTTP/3 for the download first.
+    #[cfg(feature = "http3")]
+    pub(crate) http3: bool,
+}
+
+/// How the piece length follows the source size.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) enum PieceLengthRule {
+    /// Set explicitly or taken from a reference torrent; never changes.
+    #[default]
+    Fixed,
+    /// [`TorrentBuilder::target_pieces`].
+    Target(u64),
+    /// [`choose_piece_length`].
+    Automatic,
+}
+
+impl PieceLengthRule {
+    fn choose(self, current: usize, length: u64) -> usize {
+        match self {
+            Self::Fixed => current,
+            Self::Target(target) => piece_length_for_target(length, target),
+            Self::Automatic => choose_piece_length(length),
+        }
+    }
+}
+
+/// What the hashing thread produced.
+#[derive(Debug)]
+pub(crate) struct Hashed {
+    pub(crate) pieces: Vec<u8>,
+    pub(crate) v2: Option<V2Summary>,
+    /// Bytes hashed, including those restored from a checkpoint.
+    pub(crate) length: u64,
+    /// The v1 piece length used, which differs from the one asked for when
+    /// the download corrected the source size.
+    pub(crate) piece_length: usize,
+    pub(crate) checksums: Vec<Checksum>,
+}
+
+/// Streams the source once and feeds both hashers.
+///
+/// Some load balancers answer HEAD with a stale or rounded Content-Length.
+/// When the GET reports a different size, that size wins: the piece length
+/// is chosen again by `options.piece_length_rule` before any byte is hashed.
+pub(crate) async fn hash_source(
+    client: &Client,
+    source: &http::SourceMetadata,
+    mut piece_length: usize,
+    resume_path: Option<&Path>,
+    options: HashOptions,
+    events: &EventSink,
+    cancel: &CancellationToken,
+) -> Result<(Hashed, TransferStats)> {
+    let mut checkpoint = match resume_path {
+        Some(_) if source.etag.is_none() && source.last_modified.is_none() => {
+            warn!("Not checkpointing: {} sends neither ETag nor Last-Modified", source.url);
+            None
+        }
+        Some(path) => Some(Checkpoint {
+            path: path.to_path_buf(),
+            source: source.clone(),
+            piece_length,
+        }),
+        None => None,
+    };
+    let restored = match &checkpoint {
+        Some(checkpoint) => resume::load(&checkpoint.path)?,
+        None => None,
+    };
+    let restored = match restored {
+        Some(state) if !options.checksums.is_empty() => {
+            warn!(
+                "Ignoring resume state at {}: whole-file checksums need the source from the start",
+                format_bytes(state.offset)
+            );
+            None
+        }
+        Some(state) if options.save_to.is_some() => {
+            warn!(
+                "Ignoring resume state at {}: saving the payload needs the source from the start",
+                format_bytes(state.offset)
+            );
+            None
+        }
+        restored => restored,
+    };
+    if let Some(state) = &restored {
+        state.validate(source, piece_length)?;
+        info!("Resuming hashing at {}", format_bytes(state.offset));
+    }
+    let start_bytes = restored.as_ref().map_or(0, |state| state.offset);
+
+    let stream = async {
+        #[cfg(feature = "http3")]
+        if options.http3 {
+            return http::stream_from_http3(client, &options.http, source, start_bytes).await;
+        }
+        http::stream_from(client, &options.http, source, start_bytes).await
+    };
+    let response = cancel
+        .run_until_cancelled(stream)
+        .await
+        .ok_or(TorseedError::Cancelled)??;
+    let protocol = http::protocol_name(response.version());
+    info!("Downloading {} over {protocol}", source.url);
+    let mut expected_length = source.content_length;
+    if start_bytes == 0
+        && let Some(served) = response.content_length()
+        && served != source.content_length
+    {
+        warn!(
+            "{} is {} ({served} bytes) according to the GET response, but its HEAD response said {} ({} bytes); \
+             using the GET size",
+            source.url,
+            format_bytes(served),
+            format_bytes(source.content_length),
+            source.content_length
+        );
+        if let Some(max) = options.max_bytes
+            && served > max
+        {
+            return Err(TorseedError::InvalidInput(format!(
+                "The source is {} ({served} bytes), larger than the download size cap of {} ({max} bytes)",
+                format_bytes(served),
+                format_bytes(max)
+            )));
+        }
+        let chosen = options.piece_length_rule.choose(piece_length, served);
+        if chosen != piece_length {
+            info!(
+                "Using v1 piece length {} KiB for the corrected size ({} pieces)",
+                chosen / 1024,
+                served.div_ceil(chosen as u64)
+            );
+            piece_length = chosen;
+        }
+        if checkpoint.take().is_some() {
+            warn!("Not checkpointing: a resumed run would probe the HEAD size again and refuse the checkpoint");
+        }
+        expected_length = served;
+    }
+    let policy = options.digest_header;
+    let mut served = match policy {
+        DigestHeaderPolicy::Ignore => Vec::new(),
+        _ if start_bytes > 0 => {
+            debug!("Not checking digest headers of {}: the download resumed mid-file", source.url);
+            Vec::new()
+        }
+        _ => checksum::served_digests(response.headers()),
+    };
+    let requested = options.checksums.clone();
+    let truncate_overlong = options.truncate_overlong;
+    let idle = options.http.read_timeout;
+    let mut options = options;
+    options.checksums.extend(served.iter().map(|served| served.checksum.algorithm));
+    let mut cached = match options.cache_to.take() {
+        Some(_) if start_bytes > 0 => {
+            debug!("Not caching {}: the download resumed mid-file", source.url);
+            None
+        }
+        Some(path) => match PayloadWriter::create(path).await {
+            Ok(writer) => Some(writer),
+            Err(err) => {
+                warn!("Not caching {}: {err}", source.url);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut saved = match options.save_to.take() {
+        Some(path) => {
+            let writer = PayloadWriter::create(path.clone())
+                .await
+                .map_err(|err| TorseedError::io(format!("Failed to create {}", path.display()), err))?;
+            Some((writer, path))
+        }
+        None => None,
+    };
+    let mut pipeline = Pipeline::start(
+        piece_length,
+        restored,
+        checkpoint,
+        options,
+        expected_length,
+        events.clone(),
+        cancel.clone(),
+    );
+
+    // On cancellation the loop just stops feeding; the hashing thread sees
+    // the token and reports `Cancelled` from `finish`. Bytes past the
+    // declared length never reach the hashers, so the pieces cannot cover
+    // more than the torrent will claim.
+    let mut stream = response.bytes_stream();
+    let mut received = start_bytes;
+    let mut overlong = false;
+    while let Some(next) = cancel.run_until_cancelled(tokio::time::timeout(idle, stream.next())).await {
+        let chunk = match next {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(_) => {
+                return Err(http::stream_error(
+                    &source.url,
+                    format!(
+                        "No data from {} for {}; the connection stalled after {}",
+                        source.url,
+                        humantime::format_duration(idle),
+                        format_bytes(received)
+                    ),
+                    None,
+                ));
+            }
+        };
+        let mut chunk = chunk.map_err(|err| {
+            http::stream_error(&source.url, format!("Error while reading HTTP stream from {}", source.url), Some(err))
+        })?;
+        let remaining = expected_length - received;
+        if chunk.len() as u64 > remaining {
+            overlong = true;
+            chunk.truncate(remaining as usize);
+        }
+        received += chunk.len() as u64;
+        if let Some(writer) = &mut cached
+            && let Err(err) = writer.write(chunk.clone()).await
+        {
+            warn!("Stopped caching {}: {err}", source.url);
+            cached = None;
+        }
+        if let Some((writer, path)) = &mut saved
+            && let Err(err) = writer.write(chunk.clone()).await
+        {
+            return Err(TorseedError::io(format!("Failed to write {}", path.display()), err));
+        }
+        if !pipeline.push(chunk).await || overlong {
+            break;
+        }
+    }
+    let (mut hashed, mut stats) = pipeline.finish().await?;
+    stats.protocol = Some(protocol);
+    stats.served_length = Some(expected_length);
+    if overlong {
+        if !truncate_overlong {
+            return Err(http::stream_error(
+                &source.url,
+                format!(
+                    "{} sent more than its declared {expected_length} bytes; stopped at the declared length \
+                     (--truncate-overlong hashes only those bytes)",
+                    source.url
+                ),
+                None,
+            ));
+        }
+        warn!("{} sent more than its declared {expected_length} bytes; hashed only those", source.url);
+        served.clear();
+    }
+    if !overlong && hashed.length < expected_length {
+        return Err(http::stream_error(
+            &source.url,
+            format!(
+                "{} ended after {} of its {expected_length} bytes",
+                source.url,
+                format_bytes(hashed.length)
+            ),
+            None,
+        ));
+    }
+    if let Some(writer) = cached
+        && let Err(err) = writer.finish(received).await
+    {
+        warn!("Could not cache {}: {err}", source.url);
+    }
+    if let Some((writer, path)) = saved {
+        writer
+            .finish(received)
+            .await
+            .map_err(|err| TorseedError::io(format!("Failed to write {}", path.display()), err))?;
+    }
+    check_served_digests(&source.url, &served, &hashed.checksums, policy)?;
+    hashed.checksums.retain(|checksum| requested.contains(&checksum.algorithm));
+    Ok((hashed, stats))
+}
+
+/// [`hash_source`] through `cache`: hashes the cached copy when it is still
+/// fresh, copying it to `options.save_to` if set, and otherwise downloads
+/// while saving a new copy. SHA-256 is always
+/// computed, to check the copy, but only returned when requested.
+#[allow(clippy::too_many_arguments)]
+async fn hash_cached(
+    client: &Client,
+    cache: &DownloadCache,
+    source: &SourceMetadata,
+    piece_length: usize,
+    resume_path: Option<&Path>,
+    mut options: HashOptions,
+    events: &EventSink,
+    cancel: &CancellationToken,
+) -> Result<(Hashed, TransferStats)> {
+    let requested = options.checksums.clone();
+    if !requested.contains(&ChecksumAlgorithm::Sha256) {
+        options.checksums.push(ChecksumAlgorithm::Sha256);
+    }
+    let sha256 = |hashed: &Hashed| {
+        hashed
+            .checksums
+            .iter()
+            .find(|checksum| checksum.algorithm == ChecksumAlgorithm::Sha256)
+            .map(|checksum| checksum.digest.clone())
+    };
+
+    if let Some(copy) = cache.lookup(source)
+        && cache.confirm(client, &options.http, source, &copy).await
+    {
+        info!("Reusing cached copy of {} from {}", source.url, copy.path.display());
+        let reused = match tokio::fs::File::open(&copy.path).await {
+            Ok(file) => {
+                let reader = Box::new(file);
+                hash_reader(reader, piece_length, Some(source.content_length), options.clone(), events, cancel).await
+            }
+            Err(err) => Err(TorseedError::io("Failed to open the cached copy", err)),
+        };
+        match reused {
+            Ok((mut hashed, stats)) if sha256(&hashed).as_ref() == Some(&copy.sha256) => {
+                if let Some(path) = options.save_to {
+                    PayloadWriter::copy(&copy.path, path.clone())
+                        .await
+                        .map_err(|err| TorseedError::io(format!("Failed to write {}", path.display()), err))?;
+                }
+                hashed.checksums.retain(|checksum| requested.contains(&checksum.algorithm));
+                return Ok((hashed, stats));
+            }
+            Err(TorseedError::Cancelled) => return Err(TorseedError::Cancelled),
+            Ok(_) => debug!("Cached copy of {} no longer matches its SHA-256", source.url),
+            Err(err) => debug!("Could not hash the cached copy of {}: {err}", source.url),
+        }
+    }
+
+    cache.evict(&source.url);
+    options.cache_to = Some(cache.payload_path(&source.url));
+    let (mut hashed, stats) = hash_source(client, source, piece_length, resume_path, options, events, cancel).await?;
+    if hashed.length == source.content_length
+        && cache.payload_path(&source.url).exists()
+        && let Some(digest) = sha256(&hashed)
+        && let Err(err) = cache.store(source, &digest)
+    {
+        warn!("{err}");
+    }
+    hashed.checksums.retain(|checksum| requested.contains(&checksum.algorithm));
+    Ok((hashed, stats))
+}
+
+/// Compares the digests the server claimed with those computed from the
+/// bytes it actually sent.
+fn check_served_digests(
+    url: &Url,
+    served: &[ServedDigest],
+    computed: &[Checksum],
+    policy: DigestHeaderPolicy,
+) -> Result<()> {
+    for served in served {
+        let algorithm = served.checksum.algorithm;
+        let Some(computed) = computed.iter().find(|computed| computed.algorithm == algorithm) else {
+            continue;
+        };
+        if computed.digest == served.checksum.digest {
+            debug!("{url} matches its {} {} header", served.header, algorithm.name());
+            continue;
+        }
+        let message = format!(
+            "{url} served bytes with {} {}, but its {} header claims {}",
+            algorithm.name(),
+            computed.hex(),
+            served.header,
+            served.checksum.hex()
+        );
+        match policy {
+            DigestHeaderPolicy::Enforce => return Err(TorseedError::Mismatch(message)),
+            _ => warn!("{message}"),
+        }
+    }
+    Ok(())
+}
+
+/// Streams `parts` one after another. Each is hashed on its own so its v2
+/// tree stays separate, and every part but the last is zero-padded for v1,
+/// matching the pad files of the torrent.
+async fn hash_parts(
+    client: &Client,
+    parts: &[FilePart],
+    piece_length: usize,
+    options: HashOptions,
+    events: &EventSink,
+    cancel: &CancellationToken,
+) -> Result<(Vec<BuildFile>, Vec<u8>, TransferStats)> {
+    let mut files: Vec<BuildFile> = Vec::with_capacity(parts.len());
+    let mut pieces = Vec::new();
+    let mut transfer = TransferStats {
+        bytes: 0,
+        elapsed: Duration::ZERO,
+        protocol: None,
+        served_length: None,
+    };
+    for (index, part) in parts.iter().enumerate() {
+        info!("Hashing part {} of {}: {}", index + 1, parts.len(), part.path.join("/"));
+        events.emit(Event::MetadataResolved(part.source.clone()));
+        let hashed_so_far: u64 = files.iter().map(|file| file.length).sum();
+        let options = HashOptions {
+            pad_v1: index + 1 < parts.len(),
+            max_bytes: options.max_bytes.map(|max| max.saturating_sub(hashed_so_far)),
+            ..options.clone()
+        };
+        let (hashed, stats) = hash_source(client, &part.source, piece_length, None, options, events, cancel).await?;
+        pieces.extend_from_slice(&hashed.pieces);
+        transfer.bytes += stats.bytes;
+        transfer.elapsed += stats.elapsed;
+        transfer.protocol = stats.protocol;
+        files.push(BuildFile {
+            path: part.path.clone(),
+            length: hashed.length,
+            v2: hashed.v2.filter(|_| hashed.length > 0),
+        });
+    }
+    Ok((files, pieces, transfer))
+}
+
+/// Reads `reader` to the end and feeds both hashers.
+async fn hash_reader(
+    mut reader: Box<dyn AsyncRead + Send + Unpin>,
+    piece_length: usize,
+    length: Option<u64>,
+    options: HashOptions,
+    events: &EventSink,
+    cancel: &CancellationToken,
+) -> Result<(Hashed, TransferStats)> {
+    let mut pipeline = Pipeline::start(
+        piece_length,
+        None,
+        None,
+        options,
+        length.unwrap_or(0),
+        events.clone(),
+        cancel.clone(),
+    );
+    loop {
+        let mut buffer = BytesMut::with_capacity(READ_CHUNK_SIZE);
+        let Some(read) = cancel.run_until_cancelled(reader.read_buf(&mut buffer)).await else {
+            break;
+        };
+        let read = read.map_err(|err| TorseedError::io("Failed to read from the source reader", err))?;
+        if read == 0 || !pipeline.push(buffer.freeze()).await {
+            break;
+        }
+    }
+    let (hashed, stats) = pipeline.finish().await?;
+
+    if let Some(expected) = length
+        && hashed.length != expected
+    {
+        return Err(TorseedError::Hashing(format!(
+            "Reader produced {} bytes, expected {expected}",
+            hashed.length
+        )));
+    }
+    Ok((hashed, stats))
+}
+
+/// Producer side of the hashing thread: forwards chunks and reports progress.
+struct Pipeline {
+    chunks: mpsc::Sender<Bytes>,
+    hasher: JoinHandle<Result<Hashed>>,
+    progress: Progress,
+    events: EventSink,
+    last_event: Instant,
+    /// Bytes pushed so far, plus any resumed prefix.
+    streamed: u64,
+    max_bytes: Option<u64>,
+}
+
+impl Pipeline {
+    /// `expected` is the total source length, or 0 when unknown.
+    fn start(
+        piece_length: usize,
+        restored: Option<ResumeState>,
+        checkpoint: Option<Checkpoint>,
+        options: HashOptions,
+        expected: u64,
+        events: EventSink,
+        cancel: CancellationToken,
+    ) -> Self {
+        let start_bytes = restored.as_ref().map_or(0, |state| state.offset);
+        let max_bytes = options.max_bytes;
+        let (chunks, hasher) = spawn_hasher(piece_length, restored, checkpoint, options, events.clone(), cancel);
+        debug!("Hash backend: {}", digest::describe());
+        Self {
+            chunks,
+            hasher,
+            progress: Progress::new(start_bytes, expected),
+            events,
+            last_event: Instant::now(),
+            streamed: start_bytes,
+            max_bytes,
+        }
+    }
+
+    /// Returns `false` once the hashing thread has stopped or the source went
+    /// past `max_bytes`; `finish` reports why.
+    async fn push(&mut self, chunk: Bytes) -> bool {
+        self.streamed += chunk.len() as u64;
+        if self.max_bytes.is_some_and(|max| self.streamed > max) {
+            return false;
+        }
+        self.progress.record(chunk.len() as u64);
+        if self.chunks.send(chunk).await.is_err() {
+            return false;
+        }
+
+        if self.last_event.elapsed() >= PROGRESS_EVENT_INTERVAL {
+            self.events.emit(Event::BytesHashed(self.progress.snapshot()));
+            self.last_event = Instant::now();
+        }
+        true
+    }
+
+    async fn finish(self) -> Result<(Hashed, TransferStats)> {
+        drop(self.chunks);
+        let output = self.hasher.await;
+        if let Some(max) = self.max_bytes
+            && self.streamed > max
+        {
+            return Err(TorseedError::InvalidInput(format!(
+                "The source went past the download size cap of {} ({max} bytes); stopped downloading",
+                format_bytes(max)
+            )));
+        }
+        let output = output.map_err(|err| TorseedError::Hashing(format!("Hashing thread panicked: {err}")))??;
+        self.events.emit(Event::BytesHashed(self.progress.snapshot()));
+
+        let stats = self.progress.stats();
+        debug!(
+            "Streamed {} of {} in {:.1}s",
+            format_bytes(stats.bytes),
+            format_bytes(self.progress.total()),
+            stats.elapsed.as_secs_f64()
+        );
+        Ok((output, stats))
+    }
+}
+
+/// Starts a blocking thread that owns both hashers and consumes chunks until
+/// the sender is dropped, returning the pieces and the number of bytes hashed.
+/// Requested whole-file checksums see every chunk in order. Chunks smaller
+/// than the buffer size are gathered before hashing.
+/// With a checkpoint, state is saved at piece boundaries every
+/// `CHECKPOINT_INTERVAL` and removed once hashing completes. Once `cancel`
+/// fires, the thread checkpoints at the next piece boundary and stops; the
+/// state file is left in place for the next run. With expected pieces, the
+/// thread stops at the first v1 piece that differs. Completed pieces are
+/// reported to `events` when it asks for them.
+fn spawn_hasher(
+    piece_length: usize,
+    restored: Option<ResumeState>,
+    checkpoint: Option<Checkpoint>,
+    options: HashOptions,
+    events: EventSink,
+    cancel: CancellationToken,
+) -> (mpsc::Sender<Bytes>, JoinHandle<Result<Hashed>>) {
+    let HashOptions {
+        expected_pieces,
+        checksums,
+        pad_v1,
+        buffer_size,
+        ..
+    } = options;
+    let buffer_size = buffer_size.unwrap_or(HASH_BUFFER_SIZE).max(1);
+    let (sender, mut receiver) = mpsc::channel::<Bytes>(HASH_QUEUE_DEPTH);
+    let handle = tokio::task::spawn_blocking(move || {
+        let (mut v1_hasher, mut v2_hasher, mut hashed_bytes) = match restored {
+            Some(state) => (
+                V1Hasher::resume(piece_length, state.v1_pieces),
+                V2Hasher::resume(piece_length, state.v2),
+                state.offset,
+            ),
+            None => (V1Hasher::new(piece_length), V2Hasher::new(piece_length), 0),
+        };
+        let mut checksums = ChecksumHasher::new(&checksums);
+        let mut last_checkpoint = std::time::Instant::now();
+
+        let mut pending = Vec::with_capacity(buffer_size);
+        loop {
+            let next = receiver.blocking_recv();
+            let done = next.is_none();
+            let chunk;
+            let data: &[u8] = match next {
+                Some(next) if pending.is_empty() && next.len() >= buffer_size => {
+                    chunk = next;
+                    &chunk
+                }
+                Some(next) => {
+                    pending.extend_from_slice(&next);
+                    if pending.len() < buffer_size {
+                        continue;
+                    }
+                    &pending
+                }
+                None => &pending,
+            };
+            checksums.update(data);
+            // v1 stops at every piece boundary; v2 only catches up when a
+            // checkpoint needs its state, so it sees long runs of leaves.
+            let mut v2_pending = data;
+            let mut data = data;
+            while !data.is_empty() {
+                let to_boundary = piece_length - (hashed_bytes % piece_length as u64) as usize;
+                let take = to_boundary.min(data.len());
+                v1_hasher.update(&data[..take]);
+                hashed_bytes += take as u64;
+                data = &data[take..];
+
+                if take != to_boundary {
+                    continue;
+                }
+                if let Some(expected) = &expected_pieces {
+                    check_piece(expected, v1_hasher.snapshot(), piece_length)?;
+                }
+                if events.wants_piece_events() {
+                    emit_last_piece(&events, v1_hasher.snapshot());
+                }
+                let cancelled = cancel.is_cancelled();
+                if let Some(checkpoint) = &checkpoint
+                    && (cancelled || last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL)
+                {
+                    v2_hasher.update(&v2_pending[..v2_pending.len() - data.len()]);
+                    v2_pending = data;
+                    match checkpoint.save(hashed_bytes, &v1_hasher, &mut v2_hasher) {
+                        Ok(()) if cancelled => info!("Resume state saved at {}", format_bytes(hashed_bytes)),
+                        Ok(()) => {}
+                        Err(err) => warn!("Failed to save resume state: {}", describe(&err)),
+                    }
+                    last_checkpoint = std::time::Instant::now();
+                }
+                if cancelled {
+                    return Err(TorseedError::Cancelled);
+                }
+            }
+            v2_hasher.update(v2_pending);
+            pending.clear();
+            if done {
+                break;
+            }
+        }
+        if cancel.is_cancelled() {
+            return Err(TorseedError::Cancelled);
+        }
+
+        let partial = (hashed_bytes % piece_length as u64) as usize;
+        if pad_v1 && partial != 0 {
+            v1_hasher.update(&vec![0; piece_length - partial]);
+        }
+        let pieces = v1_hasher.finalize();
+        if partial != 0 {
+            emit_last_piece(&events, &pieces);
+        }
+        if let Some(expected) = &expected_pieces {
+            check_piece(expected, &pieces, piece_length)?;
+            if pieces.len() != expected.len() {
+                return Err(TorseedError::Mismatch(format!(
+                    "Source has {} pieces, expected {}",
+                    pieces.len() / 20,
+                    expected.len() / 20
+                )));
+            }
+        }
+        let v2_summary = Some(v2_hasher.finalize());
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.clear();
+        }
+        Ok(Hashed {
+            pieces,
+            v2: v2_summary,
+            length: hashed_bytes,
+            piece_length,
+            checksums: checksums.finalize(),
+        })
+    });
+    (sender, handle)
+}
+
+fn emit_last_piece(events: &EventSink, pieces: &[u8]) {
+    if let Some(end) = pieces.len().checked_sub(20) {
+        let mut hash = [0u8; 20];
+        hash.copy_from_slice(&pieces[end..]);
+        events.emit(Event::PieceHashed { index: end / 20, hash });
+    }
+}
+
+/// Parses the reference of [`TorrentBuilder::match_torrent`] and checks,
+/// before anything is downloaded, that the source can match it.
+fn check_reference(bytes: &[u8], source: &Source, known_length: Option<u64>) -> Result<ParsedTorrent> {
+    let reference = metainfo::parse(bytes)?;
+    let FileLayout::Single { length } = reference.layout else {
+        return Err(TorseedError::InvalidInput(
+            "Only single-file reference torrents can be matched".to_string(),
+        ));
+    };
+    if reference.pieces.is_none() {
+        return Err(TorseedError::InvalidInput(
+            "Only reference torrents with v1 pieces can be matched".to_string(),
+        ));
+    }
+    if matches!(source, Source::Parts(_)) {
+        return Err(TorseedError::InvalidInput(
+            "A multi-file source cannot match a single-file reference torrent".to_string(),
+        ));
+    }
+    if usize::try_from(reference.piece_length).map_or(true, |piece_length| piece_length == 0) {
+        return Err(TorseedError::InvalidInput(format!(
+            "Unusable piece length {} in the reference torrent",
+            reference.piece_length
+        )));
+    }
+    if let Some(actual) = known_length
+        && actual != length
+    {
+        return Err(TorseedError::Mismatch(format!(
+            "The source is {actual} bytes, the reference torrent expects {length}"
+        )));
+    }
+    Ok(reference)
+}
+
+/// Compares the most recently completed piece in `pieces` with `expected`.
+fn check_piece(expected: &[u8], pieces: &[u8], piece_length: usize) -> Result<()> {
+    let Some(end) = pieces.len().checked_sub(20) else {
+        return Ok(());
+    };
+    let index = end / 20;
+    if expected.get(end..end + 20) == Some(&pieces[end..]) {
+        return Ok(());
+    }
+    Err(TorseedError::Mismatch(format!(
+        "Piece {index} at byte {} does not match the expected hash",
+        index as u64 * piece_length as u64
+    )))
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{payload, response, serve, serve_slowly, serve_stalled};
+
+    fn probed(url: Url, content_length: u64) -> SourceMetadata {
+        SourceMetadata {
+            url,
+            content_length,
+            filename: "data.bin".to_string(),
+            original_filename: None,
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    async fn build(source: SourceMetadata) -> Result<Torrent> {
+        TorrentBuilder::new(source)
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .piece_length(16_384)
+            .build(&Client::new())
+            .await
+    }
+
+    async fn expected(data: &[u8]) -> Torrent {
+        TorrentBuilder::from_reader(std::io::Cursor::new(data.to_vec()), "data.bin", Some(data.len() as u64))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .piece_length(16_384)
+            .build(&Client::new())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn get_size_wins_over_smaller_head_size() {
+        let data = payload(40_000);
+        let url = serve(vec![response(Some(40_000), &data)]).await;
+        let torrent = build(probed(url, 30_000)).await.unwrap();
+        assert_eq!(torrent.input.length, 40_000);
+        assert_eq!(torrent.transfer.served_length, Some(40_000));
+        assert_eq!(torrent.metainfo.infohash_v1, expected(&data).await.metainfo.infohash_v1);
+    }
+
+    #[tokio::test]
+    async fn get_size_wins_over_larger_head_size() {
+        let data = payload(40_000);
+        let url = serve(vec![response(Some(40_000), &data)]).await;
+        let torrent = build(probed(url, 50_000)).await.unwrap();
+        assert_eq!(torrent.input.length, 40_000);
+        assert_eq!(torrent.transfer.served_length, Some(40_000));
+        assert_eq!(torrent.metainfo.infohash_v2, expected(&data).await.metainfo.infohash_v2);
+    }
+
+    #[tokio::test]
+    async fn piece_length_is_chosen_again_for_the_get_size() {
+        for probed_length in [40_000, 3_000_000] {
+            let url = serve(vec![response(Some(300_000), &payload(300_000))]).await;
+            let torrent = TorrentBuilder::new(probed(url, probed_length))
+                .trackers(["udp://tracker.example.org:1337/announce"])
+                .target_pieces(4)
+                .build(&Client::new())
+                .await
+                .unwrap();
+            assert_eq!(torrent.input.length, 300_000);
+            assert_eq!(torrent.input.piece_length, 128 * 1024);
+            assert_eq!(torrent.input.pieces.len(), 3 * 20);
+        }
+    }
+
+    #[tokio::test]
+    async fn short_stream_is_a_stream_error() {
+        let url = serve(vec![response(None, &payload(20_000))]).await;
+        let result = build(probed(url, 40_000)).await;
+        assert!(matches!(result, Err(TorseedError::Stream { .. })), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn overlong_stream_is_a_stream_error_unless_truncated() {
+        // Just past the declared length, and a whole piece past it.
+        for extra in [3, 16_384] {
+            let data = payload(40_000 + extra);
+            let url = serve(vec![response(None, &data), response(None, &data)]).await;
+            let result = build(probed(url.clone(), 40_000)).await;
+            assert!(matches!(result, Err(TorseedError::Stream { .. })), "{result:?}");
+            let torrent = TorrentBuilder::new(probed(url, 40_000))
+                .trackers(["udp://tracker.example.org:1337/announce"])
+                .piece_length(16_384)
+                .truncate_overlong(true)
+                .build(&Client::new())
+                .await
+                .unwrap();
+            let exact = expected(&data[..40_000]).await;
+            assert_eq!(torrent.input.length, 40_000);
+            assert_eq!(torrent.metainfo.infohash_v1, exact.metainfo.infohash_v1);
+            assert_eq!(torrent.metainfo.infohash_v2, exact.metainfo.infohash_v2);
+        }
+    }
+
+    async fn build_with_read_timeout(url: Url, timeout: Duration) -> Result<Torrent> {
+        TorrentBuilder::new(probed(url, 8_000))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .http_options(HttpOptions::default().read_timeout(timeout))
+            .build(&Client::new())
+            .await
+    }
+
+    #[tokio::test]
+    async fn read_timeout_lets_slow_streams_finish() {
+        // Longer than the timeout in total, but never idle that long.
+        let data = payload(8_000);
+        let url = serve_slowly(response(Some(8_000), &data), 1_000, Duration::from_millis(100)).await;
+        let torrent = build_with_read_timeout(url, Duration::from_millis(400)).await.unwrap();
+        assert_eq!(torrent.input.length, 8_000);
+    }
+
+    #[tokio::test]
+    async fn read_timeout_fails_stalled_streams() {
+        let url = serve_stalled(response(Some(8_000), &payload(1_000))).await;
+        let result = build_with_read_timeout(url, Duration::from_millis(200)).await;
+        let Err(TorseedError::Stream { message, .. }) = result else { panic!("{result:?}") };
+        assert!(message.contains("No data"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn wrong_digest_headers_fail_unless_only_warned_about() {
+        // Claims the SHA-256 of "abd" while serving "abc".
+        let answer = || {
+            let mut answer = b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n".to_vec();
+            answer.extend_from_slice(b"Repr-Digest: sha-256=:pS0VnyYrLG3bckphhAvvw26zDIiHekAwtly+himESck=:\r\n\r\nabc");
+            answer
+        };
+        let url = serve(vec![answer(), answer()]).await;
+        let builder = || {
+            TorrentBuilder::new(probed(url.clone(), 3)).trackers(["udp://tracker.example.org:1337/announce"])
+        };
+        let result = builder().build(&Client::new()).await;
+        assert!(matches!(result, Err(TorseedError::Mismatch(_))), "{result:?}");
+
+        let torrent = builder()
+            .digest_header(DigestHeaderPolicy::Warn)
+            .build(&Client::new())
+            .await
+            .unwrap();
+        assert!(torrent.checksums.is_empty());
+    }
+
+    #[tokio::test]
+    async fn matches_a_hybrid_reference_from_another_tool() {
+        // 65,537 bytes make five 16 KiB leaves, which BEP 52 pads with zero
+        // hashes to a power of two.
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/qbittorrent_hybrid_odd.torrent");
+        let reference = std::fs::read(path).unwrap();
+        let parsed = metainfo::parse(&reference).unwrap();
+        let data = payload(65_537);
+        let url = serve(vec![response(Some(65_537), &data)]).await;
+        let joined = TorrentBuilder::new(probed(url, 65_537))
+            .trackers(["udp://mine.example:6969/announce"])
+            .match_torrent(reference)
+            .build(&Client::new())
+            .await
+            .unwrap();
+        assert_eq!(metainfo::parse(&joined.metainfo.torrent).unwrap().info, parsed.info);
+        assert_eq!(joined.metainfo.infohash_v1, parsed.infohash_v1());
+        assert_eq!(joined.metainfo.infohash_v2, parsed.infohash_v2());
+    }
+
+    #[tokio::test]
+    async fn v2_only_references_cannot_be_matched() {
+        let data = payload(40_000);
+        let reference = TorrentBuilder::from_reader(std::io::Cursor::new(data.clone()), "data.bin", Some(40_000))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .build(&Client::new())
+            .await
+            .unwrap();
+        let v2_only = metainfo::build_version(&reference.input, metainfo::MetaVersion::V2).unwrap();
+        let result = TorrentBuilder::from_reader(std::io::Cursor::new(data), "data.bin", Some(40_000))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .match_torrent(v2_only.torrent)
+            .build(&Client::new())
+            .await;
+        assert!(matches!(result, Err(TorseedError::InvalidInput(_))), "{result:?}");
+    }
+
+    fn is_cap_error(result: &Result<Torrent>, wording: &str) -> bool {
+        matches!(result, Err(TorseedError::InvalidInput(message)) if message.contains(wording))
+    }
+
+    #[tokio::test]
+    async fn download_cap_rejects_known_sizes_up_front() {
+        // Nothing is served, so any request would fail with a stream error.
+        let url = serve(Vec::new()).await;
+        let result = TorrentBuilder::new(probed(url, 50_000))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .max_download_size(40_000)
+            .build(&Client::new())
+            .await;
+        assert!(is_cap_error(&result, "larger than the download size cap"), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn download_cap_applies_to_the_get_size() {
+        let data = payload(50_000);
+        let url = serve(vec![response(Some(50_000), &data), response(Some(50_000), &data)]).await;
+        let client = Client::new();
+        let capped = |limit| {
+            TorrentBuilder::new(probed(url.clone(), 30_000))
+                .trackers(["udp://tracker.example.org:1337/announce"])
+                .max_download_size(limit)
+                .build(&client)
+        };
+        let result = capped(40_000).await;
+        assert!(is_cap_error(&result, "larger than the download size cap"), "{result:?}");
+        assert_eq!(capped(50_000).await.unwrap().input.length, 50_000);
+    }
+
+    #[tokio::test]
+    async fn download_cap_covers_all_parts() {
+        let url = serve(vec![
+            response(Some(20_000), &payload(20_000)),
+            response(Some(30_000), &payload(30_000)),
+        ])
+        .await;
+        let part = |name: &str| FilePart {
+            path: vec![name.to_string()],
+            source: probed(url.clone(), 20_000),
+        };
+        let result = TorrentBuilder::from_parts("data", vec![part("a.bin"), part("b.bin")])
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .max_download_size(45_000)
+            .build(&Client::new())
+            .await;
+        assert!(is_cap_error(&result, "larger than the download size cap"), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn download_cap_stops_endless_readers() {
+        let result = TorrentBuilder::from_reader(tokio::io::repeat(7), "data.bin", None)
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .max_download_size(1 << 20)
+            .build(&Client::new())
+            .await;
+        assert!(is_cap_error(&result, "went past the download size cap"), "{result:?}");
+
+        // A reader longer than it claimed is stopped the same way.
+        let reader = std::io::Cursor::new(payload(300_000));
+        let result = TorrentBuilder::from_reader(reader, "data.bin", Some(100_000))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .max_download_size(200_000)
+            .build(&Client::new())
+            .await;
+        assert!(is_cap_error(&result, "went past the download size cap"), "{result:?}");
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("torseed-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn saves_the_payload() {
+        let dir = temp_dir("save");
+        let data = payload(100_000);
+        let url = serve(vec![response(Some(100_000), &data)]).await;
+        let path = dir.join("data.bin");
+        let torrent = TorrentBuilder::new(probed(url, 100_000))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .save_payload(&path)
+            .build(&Client::new())
+            .await
+            .unwrap();
+        assert_eq!(torrent.input.length, 100_000);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn saves_a_reused_cached_copy() {
+        let dir = temp_dir("save-cached");
+        let data = payload(100_000);
+        // Only one response: the second build must not download.
+        let url = serve(vec![response(Some(100_000), &data)]).await;
+        let source = SourceMetadata {
+            etag: Some("\"v1\"".to_string()),
+            ..probed(url, 100_000)
+        };
+        let client = Client::new();
+        let build = |save: Option<PathBuf>| {
+            let mut builder = TorrentBuilder::new(source.clone())
+                .trackers(["udp://tracker.example.org:1337/announce"])
+                .cache(DownloadCache::new(dir.join("cache")));
+            if let Some(path) = save {
+                builder = builder.save_payload(path);
+            }
+            builder.build(&client)
+        };
+        let first = build(None).await.unwrap();
+        let second = build(Some(dir.join("data.bin"))).await.unwrap();
+        assert_eq!(first.metainfo.infohash_v2, second.metainfo.infohash_v2);
+        assert_eq!(second.transfer.protocol, None);
+        assert_eq!(std::fs::read(dir.join("data.bin")).unwrap(), data);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn failed_save_stops_the_download() {
+        let dir = temp_dir("save-full");
+        let path = dir.join("data.bin");
+        // Every write to /dev/full fails with ENOSPC.
+        std::os::unix::fs::symlink("/dev/full", dir.join("data.bin.part")).unwrap();
+        let data = payload(1 << 20);
+        let url = serve(vec![response(Some(1 << 20), &data)]).await;
+        let result = TorrentBuilder::new(probed(url, 1 << 20))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .save_payload(&path)
+            .build(&Client::new())
+            .await;
+        assert!(matches!(result, Err(TorseedError::Io { .. })), "{result:?}");
+        assert!(!path.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Files below `dir`, recursively.
+    fn files_in(dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(files_in(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn cancelling_leaves_no_partial_files() {
+        let dir = temp_dir("cancel");
+        let url = serve_stalled(response(Some(1 << 20), &payload(100_000))).await;
+        let cancel = CancellationToken::new();
+        // Cancel once the download is under way and the saved copy has data.
+        let watcher = tokio::spawn({
+            let (cancel, part) = (cancel.clone(), dir.join("data.bin.part"));
+            async move {
+                while !std::fs::metadata(&part).is_ok_and(|metadata| metadata.len() > 0) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                cancel.cancel();
+            }
+        });
+        let result = TorrentBuilder::new(probed(url, 1 << 20))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .save_payload(dir.join("data.bin"))
+            .cache(DownloadCache::new(dir.join("cache")))
+            .cancel_token(cancel)
+            .build(&Client::new())
+            .await;
+        watcher.await.unwrap();
+        assert!(matches!(result, Err(TorseedError::Cancelled)), "{result:?}");
+        assert_eq!(files_in(&dir), Vec::<PathBuf>::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn saving_needs_an_http_source() {
+        let result = TorrentBuilder::from_reader(std::io::Cursor::new(payload(100)), "data.bin", Some(100))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .save_payload(std::env::temp_dir().join("torseed-never-written.bin"))
+            .build(&Client::new())
+            .await;
+        assert!(matches!(result, Err(TorseedError::InvalidInput(_))), "{result:?}");
+    }
+}
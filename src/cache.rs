@@ -0,0 +1,409 @@
+//! A directory of downloaded payloads, so running again on a source that has
+//! not changed hashes the local copy instead of downloading it again.
+//!
+//! Each entry is a payload file and a small JSON record of the URL, the
+//! validators the server sent (ETag, Last-Modified), the length and the
+//! SHA-256 of the payload. A copy is only reused when the fresh probe of the
+//! source reports the same length and validators, and the SHA-256 is checked
+//! again while hashing it; anything else means a normal download.
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use reqwest::Client;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::debug;
+use url::Url;
+
+use crate::error::{Result, TorseedError};
+use crate::http::{self, HttpOptions, SourceMetadata};
+use crate::util;
+
+/// Bytes compared per sample by [`DownloadCache::revalidate`].
+const SAMPLE_LENGTH: u64 = 64 * 1024;
+/// Chunks queued for the cache writer before the download waits for the
+/// disk.
+const WRITE_QUEUE_DEPTH: usize = 64;
+
+/// A cache directory. See the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct DownloadCache {
+    dir: PathBuf,
+    revalidate: bool,
+}
+
+/// A payload found in the cache for an unchanged source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedCopy {
+    pub path: PathBuf,
+    /// SHA-256 of the payload when it was stored.
+    pub sha256: Vec<u8>,
+}
+
+impl DownloadCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            revalidate: false,
+        }
+    }
+
+    /// Before reusing a copy, also compare a few ranges of it with the
+    /// source, for servers whose validators cannot be trusted.
+    pub fn revalidate(mut self, revalidate: bool) -> Self {
+        self.revalidate = revalidate;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the payload of `url` is kept.
+    pub fn payload_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.payload", entry_key(url)))
+    }
+
+    fn record_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.json", entry_key(url)))
+    }
+
+    /// The stored copy of `source`, when its record matches the URL, length
+    /// and validators just probed. A source without an ETag or
+    /// Last-Modified never matches: there is no telling whether it changed.
+    /// Unreadable records and missing payloads count as misses.
+    ///
+    /// ```
+    /// use torseed::cache::DownloadCache;
+    /// use torseed::http::SourceMetadata;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("torseed-cache-doc-{}", std::process::id()));
+    /// let cache = DownloadCache::new(&dir);
+    /// let source = SourceMetadata {
+    ///     url: "https://example.com/data.bin".parse().unwrap(),
+    ///     content_length: 3,
+    ///     filename: "data.bin".to_string(),
+    ///     original_filename: None,
+    ///     etag: Some("\"v1\"".to_string()),
+    ///     last_modified: None,
+    /// };
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(cache.payload_path(&source.url), b"abc").unwrap();
+    /// cache.store(&source, &[0xab; 32]).unwrap();
+    /// assert_eq!(cache.lookup(&source).unwrap().sha256, [0xab; 32]);
+    ///
+    /// let changed = SourceMetadata { etag: Some("\"v2\"".to_string()), ..source.clone() };
+    /// assert!(cache.lookup(&changed).is_none());
+    /// let unvalidated = SourceMetadata { etag: None, ..source.clone() };
+    /// assert!(cache.lookup(&unvalidated).is_none());
+    ///
+    /// std::fs::write(cache.payload_path(&source.url), b"abcd").unwrap();
+    /// assert!(cache.lookup(&source).is_none());
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn lookup(&self, source: &SourceMetadata) -> Option<CachedCopy> {
+        let url = &source.url;
+        if source.etag.is_none() && source.last_modified.is_none() {
+            debug!("Not using the cache for {url}: it sends neither ETag nor Last-Modified");
+            return None;
+        }
+        let record: Value = fs::read(self.record_path(url))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())?;
+        let text = |key: &str| record.get(key).and_then(Value::as_str);
+        let fresh = text("url") == Some(url.as_str())
+            && record.get("length").and_then(Value::as_u64) == Some(source.content_length)
+            && text("etag") == source.etag.as_deref()
+            && text("last_modified") == source.last_modified.as_deref();
+        if !fresh {
+            debug!("Cached copy of {url} is stale");
+            return None;
+        }
+        let sha256 = text("sha256").and_then(|hex| hex::decode(hex).ok()).filter(|hash| hash.len() == 32)?;
+        let path = self.payload_path(url);
+        let on_disk = fs::metadata(&path).ok()?.len();
+        if on_disk != source.content_length {
+            debug!("Cached copy of {url} is {on_disk} bytes, expected {}", source.content_length);
+            return None;
+        }
+        Some(CachedCopy { path, sha256 })
+    }
+
+    /// Records the payload already at [`payload_path`](Self::payload_path)
+    /// as the copy of `source`.
+    pub fn store(&self, source: &SourceMetadata, sha256: &[u8]) -> Result<()> {
+        let record = json!({
+            "url": source.url.as_str(),
+            "etag": source.etag,
+            "last_modified": source.last_modified,
+            "length": source.content_length,
+            "sha256": hex::encode(sha256),
+        });
+        let path = self.record_path(&source.url);
+        util::write_atomic(&path, record.to_string().as_bytes())
+            .map_err(|err| TorseedError::io(format!("Failed to write cache record {}", path.display()), err))
+    }
+
+    /// Drops the entry of `url`, e.g. after its payload failed a check.
+    pub fn evict(&self, url: &Url) {
+        let _ = fs::remove_file(self.record_path(url));
+        let _ = fs::remove_file(self.payload_path(url));
+    }
+
+    /// With [`revalidate`](Self::revalidate) set, compares the first, middle
+    /// and last 64 KiB of `copy` with ranged requests to the source. Any
+    /// difference or failed request means the copy is not reused.
+    pub(crate) async fn confirm(
+        &self,
+        client: &Client,
+        http_options: &HttpOptions,
+        source: &SourceMetadata,
+        copy: &CachedCopy,
+    ) -> bool {
+        if !self.revalidate {
+            return true;
+        }
+        let length = source.content_length;
+        let sample = SAMPLE_LENGTH.min(length);
+        let mut offsets = vec![0, (length - sample) / 2, length - sample];
+        offsets.dedup();
+        for offset in offsets {
+            if sample == 0 {
+                break;
+            }
+            let remote = match http::fetch_range(client, http_options, &source.url, offset, sample).await {
+                Ok(remote) => remote,
+                Err(err) => {
+                    debug!("Could not revalidate the cached copy of {}: {err}", source.url);
+                    return false;
+                }
+            };
+            match read_at(&copy.path, offset, sample) {
+                Ok(local) if local == remote => {}
+                _ => {
+                    debug!("Cached copy of {} differs from the source at byte {offset}", source.url);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// File name stem for the entry of `url`.
+fn entry_key(url: &Url) -> String {
+    hex::encode(&Sha256::digest(url.as_str().as_bytes())[..16])
+}
+
+fn read_at(path: &Path, offset: u64, length: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.take(length).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Copies a download into the cache, or to the path given to
+/// [`TorrentBuilder::save_payload`](crate::TorrentBuilder::save_payload), as
+/// it streams. Chunks are written by a task of their own, so disk latency
+/// overlaps hashing and network reads. The payload only appears under its
+/// final name once [`finish`](Self::finish) renames it; dropping the writer
+/// earlier removes the partial file.
+pub(crate) struct PayloadWriter {
+    chunks: mpsc::Sender<Bytes>,
+    task: WriteTask<tokio::fs::File>,
+    temp: PathBuf,
+    path: PathBuf,
+    _guard: util::PartialWrite,
+}
+
+impl PayloadWriter {
+    pub(crate) async fn create(path: PathBuf) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut temp = path.clone().into_os_string();
+        temp.push(".part");
+        let temp = PathBuf::from(temp);
+        let guard = util::PartialWrite::register(&temp);
+        let file = tokio::fs::File::create(&temp).await?;
+        let (chunks, task) = spawn_writes(file);
+        Ok(Self {
+            chunks,
+            task,
+            temp,
+            path,
+            _guard: guard,
+        })
+    }
+
+    /// Queues `chunk` for writing. Fails with the error of an earlier write
+    /// as soon as one went wrong; the writer must be dropped after that.
+    pub(crate) async fn write(&mut self, chunk: Bytes) -> std::io::Result<()> {
+        if self.chunks.send(chunk).await.is_ok() {
+            return Ok(());
+        }
+        // The task only stops while the sender is alive after a failed write.
+        match (&mut self.task).await {
+            Ok(Err(err)) => Err(err),
+            Ok(Ok(_)) => Err(std::io::Error::other("the payload writer stopped")),
+            Err(err) => Err(std::io::Error::other(err)),
+        }
+    }
+
+    /// Waits for the queued chunks, checks that `expected` bytes were
+    /// written, and syncs the payload before moving it into place.
+    pub(crate) async fn finish(self, expected: u64) -> std::io::Result<()> {
+        drop(self.chunks);
+        let (mut file, written) = self.task.await.map_err(std::io::Error::other)??;
+        if written != expected {
+            return Err(std::io::Error::other(format!("wrote {written} bytes, expected {expected}")));
+        }
+        file.flush().await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&self.temp, &self.path).await
+    }
+
+    /// Copies the finished file at `from` to `to`, which, as with a streamed
+    /// copy, only appears once complete.
+    pub(crate) async fn copy(from: &Path, to: PathBuf) -> std::io::Result<()> {
+        let writer = Self::create(to).await?;
+        drop(writer.chunks);
+        let (mut file, _) = writer.task.await.map_err(std::io::Error::other)??;
+        let mut source = tokio::fs::File::open(from).await?;
+        tokio::io::copy(&mut source, &mut file).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&writer.temp, &writer.path).await
+    }
+}
+
+type WriteTask<W> = JoinHandle<std::io::Result<(W, u64)>>;
+
+/// Writes the chunks sent to the returned sender to `out` in order, on a
+/// task of its own, and hands `out` back with the byte count once the
+/// sender is dropped. At most [`WRITE_QUEUE_DEPTH`] chunks wait, so a slow
+/// disk slows the download rather than filling memory.
+fn spawn_writes<W: AsyncWrite + Send + Unpin + 'static>(mut out: W) -> (mpsc::Sender<Bytes>, WriteTask<W>) {
+    let (chunks, mut receiver) = mpsc::channel::<Bytes>(WRITE_QUEUE_DEPTH);
+    let task = tokio::spawn(async move {
+        let mut written = 0;
+        while let Some(chunk) = receiver.recv().await {
+            out.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        Ok((out, written))
+    });
+    (chunks, task)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Takes a millisecond per write and fails once `fail_after` bytes are
+    /// written.
+    struct SlowWriter {
+        written: Vec<u8>,
+        delay: Option<Pin<Box<tokio::time::Sleep>>>,
+        fail_after: Option<usize>,
+    }
+
+    impl SlowWriter {
+        fn new(fail_after: Option<usize>) -> Self {
+            Self {
+                written: Vec::new(),
+                delay: None,
+                fail_after,
+            }
+        }
+    }
+
+    impl AsyncWrite for SlowWriter {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            let delay = self
+                .delay
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(Duration::from_millis(1))));
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+            if self.fail_after.is_some_and(|limit| self.written.len() >= limit) {
+                return Poll::Ready(Err(std::io::Error::other("disk full")));
+            }
+            self.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn chunks() -> Vec<Bytes> {
+        (0..WRITE_QUEUE_DEPTH * 3).map(|i| Bytes::from(vec![i as u8; 1000])).collect()
+    }
+
+    #[tokio::test]
+    async fn slow_writer_gets_every_chunk_in_order() {
+        let (sender, task) = spawn_writes(SlowWriter::new(None));
+        let chunks = chunks();
+        for chunk in &chunks {
+            sender.send(chunk.clone()).await.unwrap();
+            assert!(sender.max_capacity() - sender.capacity() <= WRITE_QUEUE_DEPTH);
+        }
+        drop(sender);
+        let (writer, written) = task.await.unwrap().unwrap();
+        assert_eq!(written, chunks.iter().map(|chunk| chunk.len() as u64).sum::<u64>());
+        assert_eq!(writer.written, chunks.concat());
+    }
+
+    #[tokio::test]
+    async fn failed_write_stops_the_writer() {
+        let (sender, task) = spawn_writes(SlowWriter::new(Some(10_000)));
+        let mut sent = 0;
+        for chunk in chunks() {
+            if sender.send(chunk).await.is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        assert!(sent < WRITE_QUEUE_DEPTH * 3, "the writer kept accepting chunks after failing");
+        let Err(err) = task.await.unwrap() else {
+            panic!("the failed write went unreported");
+        };
+        assert_eq!(err.to_string(), "disk full");
+    }
+
+    #[tokio::test]
+    async fn payload_appears_only_when_finished() {
+        let dir = std::env::temp_dir().join(format!("torseed-cache-test-{}", std::process::id()));
+        let path = dir.join("payload.bin");
+        let mut writer = PayloadWriter::create(path.clone()).await.unwrap();
+        for chunk in chunks() {
+            writer.write(chunk).await.unwrap();
+        }
+        assert!(!path.exists());
+        writer.finish((WRITE_QUEUE_DEPTH * 3000) as u64).await.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), chunks().concat());
+
+        let writer = PayloadWriter::create(dir.join("short.bin")).await.unwrap();
+        assert!(writer.finish(1).await.is_err());
+        assert!(!dir.join("short.bin").exists() && !dir.join("short.bin.part").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+//! Whole-file digests computed from the same bytes as the piece hashes, so
+//! they can be compared with checksums published upstream.
+
+use std::fmt::Write as _;
+
+#[cfg(feature = "http")]
+use data_encoding::BASE64;
+#[cfg(feature = "http")]
+use reqwest::header::{self, HeaderMap};
+#[cfg(feature = "http")]
+use sha1::Digest;
+
+/// A whole-file digest to compute while hashing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    /// Lowercase name, as used on the command line and in JSON.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha1 => "sha1",
+            Self::Md5 => "md5",
+        }
+    }
+
+    /// Tag used by the BSD-style `ALGO (file) = digest` checksum format.
+    fn tag(self) -> &'static str {
+        match self {
+            Self::Sha256 => "SHA256",
+            Self::Sha1 => "SHA1",
+            Self::Md5 => "MD5",
+        }
+    }
+
+    /// Recognizes the names used by the `Digest` and `Repr-Digest` headers,
+    /// ignoring case.
+    #[cfg(feature = "http")]
+    fn from_header_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha-256" => Some(Self::Sha256),
+            "sha" => Some(Self::Sha1),
+            "md5" => Some(Self::Md5),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "http")]
+    fn output_len(self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha1 => 20,
+            Self::Md5 => 16,
+        }
+    }
+}
+
+/// What to do when a source's own `Digest`, `Repr-Digest` or `Content-MD5`
+/// header disagrees with the bytes it served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DigestHeaderPolicy {
+    /// Fail with [`TorseedError::Mismatch`](crate::TorseedError::Mismatch).
+    #[default]
+    Enforce,
+    /// Log the mismatch and keep the result.
+    Warn,
+    /// Do not read the headers at all.
+    Ignore,
+}
+
+/// A whole-file digest a server claimed for its response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServedDigest {
+    /// The header it came from.
+    pub header: &'static str,
+    pub checksum: Checksum,
+}
+
+/// Collects the digests `headers` claim for the full response body, from
+/// `Repr-Digest` (RFC 9530), `Digest` (RFC 3230) and `Content-MD5`. Each
+/// header may list several algorithms; ones torseed cannot compute, and
+/// values that are not base64 of the right length, are skipped. An encoded
+/// response yields nothing, since its digests cover the encoded bytes.
+///
+/// ```
+/// use reqwest::header::{HeaderMap, HeaderValue};
+/// use torseed::checksum::{self, ChecksumAlgorithm};
+///
+/// let mut headers = HeaderMap::new();
+/// headers.insert(
+///     "repr-digest",
+///     HeaderValue::from_static("sha-512=:AAAA:, sha-256=:ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=:"),
+/// );
+/// headers.insert("digest", HeaderValue::from_static("UNIXsum=30637, SHA=qZk+NkcGgWq6PiVxeFDCbJzQ2J0="));
+/// headers.insert("content-md5", HeaderValue::from_static("kAFQmDzST7DWlj99KOF/cg=="));
+/// let served = checksum::served_digests(&headers);
+/// let found: Vec<_> = served.iter().map(|served| (served.header, served.checksum.algorithm)).collect();
+/// assert_eq!(
+///     found,
+///     [
+///         ("Repr-Digest", ChecksumAlgorithm::Sha256),
+///         ("Digest", ChecksumAlgorithm::Sha1),
+///         ("Content-MD5", ChecksumAlgorithm::Md5),
+///     ]
+/// );
+/// assert_eq!(served[0].checksum.hex(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+///
+/// headers.insert("content-encoding", HeaderValue::from_static("gzip"));
+/// assert!(checksum::served_digests(&headers).is_empty());
+/// ```
+#[cfg(feature = "http")]
+pub fn served_digests(headers: &HeaderMap) -> Vec<ServedDigest> {
+    let encoded = headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| !value.trim().eq_ignore_ascii_case("identity"));
+    if encoded {
+        return Vec::new();
+    }
+    let values = |name: &'static str| {
+        headers
+            .get_all(name)
+            .into_iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(move |value| (name, value))
+    };
+    let mut served = Vec::new();
+    for (header, value) in values("Repr-Digest").chain(values("Digest")) {
+        for member in value.split(',') {
+            let Some((name, encoded)) = member.split_once('=') else {
+                continue;
+            };
+            // RFC 9530 wraps the value as a structured-field byte sequence
+            // and may append parameters; RFC 3230 uses plain base64.
+            let encoded = encoded.split(';').next().unwrap_or_default().trim();
+            let encoded = encoded
+                .strip_prefix(':')
+                .and_then(|encoded| encoded.strip_suffix(':'))
+                .unwrap_or(encoded);
+            if let Some(algorithm) = ChecksumAlgorithm::from_header_name(name.trim())
+                && let Some(checksum) = decode_digest(algorithm, encoded)
+            {
+                served.push(ServedDigest { header, checksum });
+            }
+        }
+    }
+    for (header, value) in values("Content-MD5") {
+        if let Some(checksum) = decode_digest(ChecksumAlgorithm::Md5, value.trim()) {
+            served.push(ServedDigest { header, checksum });
+        }
+    }
+    served
+}
+
+#[cfg(feature = "http")]
+fn decode_digest(algorithm: ChecksumAlgorithm, encoded: &str) -> Option<Checksum> {
+    let digest = BASE64.decode(encoded.as_bytes()).ok()?;
+    (digest.len() == algorithm.output_len()).then_some(Checksum { algorithm, digest })
+}
+
+/// One finished whole-file digest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde"))]
+    pub digest: Vec<u8>,
+}
+
+impl Checksum {
+    pub fn hex(&self) -> String {
+        hex::encode(&self.digest)
+    }
+}
+
+/// Formats `checksums` as a checksum file for `filename`. A single digest
+/// uses the `sha256sum` layout; several use the tagged layout, which
+/// `cksum --check` reads with mixed algorithms.
+///
+/// ```
+/// use torseed::checksum::{self, Checksum, ChecksumAlgorithm};
+///
+/// let sha256 = Checksum { algorithm: ChecksumAlgorithm::Sha256, digest: vec![0xab; 32] };
+/// let md5 = Checksum { algorithm: ChecksumAlgorithm::Md5, digest: vec![0x01; 16] };
+/// assert_eq!(
+///     checksum::sums_file(std::slice::from_ref(&sha256), "data.bin"),
+///     format!("{}  data.bin\n", "ab".repeat(32))
+/// );
+/// assert_eq!(
+///     checksum::sums_file(&[sha256, md5], "data.bin"),
+///     format!("SHA256 (data.bin) = {}\nMD5 (data.bin) = {}\n", "ab".repeat(32), "01".repeat(16))
+/// );
+/// ```
+pub fn sums_file(checksums: &[Checksum], filename: &str) -> String {
+    let mut out = String::new();
+    match checksums {
+        [checksum] => {
+            let _ = writeln!(out, "{}  {filename}", checksum.hex());
+        }
+        _ => {
+            for checksum in checksums {
+                let _ = writeln!(out, "{} ({filename}) = {}", checksum.algorithm.tag(), checksum.hex());
+            }
+        }
+    }
+    out
+}
+
+/// Accumulates the selected digests. Selecting nothing costs nothing.
+#[cfg(feature = "http")]
+pub(crate) struct ChecksumHasher {
+    hashers: Vec<AlgorithmHasher>,
+}
+
+#[cfg(feature = "http")]
+enum AlgorithmHasher {
+    Sha256(sha2::Sha256),
+    Sha1(sha1::Sha1),
+    Md5(md5::Md5),
+}
+
+#[cfg(feature = "http")]
+impl ChecksumHasher {
+    /// Duplicates in `algorithms` are computed once.
+    pub(crate) fn new(algorithms: &[ChecksumAlgorithm]) -> Self {
+        let mut selected: Vec<ChecksumAlgorithm> = Vec::new();
+        for &algorithm in algorithms {
+            if !selected.contains(&algorithm) {
+                selected.push(algorithm);
+            }
+        }
+        Self {
+            hashers: selected
+                .into_iter()
+                .map(|algorithm| match algorithm {
+                    ChecksumAlgorithm::Sha256 => AlgorithmHasher::Sha256(sha2::Sha256::new()),
+                    ChecksumAlgorithm::Sha1 => AlgorithmHasher::Sha1(sha1::Sha1::new()),
+                    ChecksumAlgorithm::Md5 => AlgorithmHasher::Md5(md5::Md5::new()),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for hasher in &mut self.hashers {
+            match hasher {
+                AlgorithmHasher::Sha256(hasher) => hasher.update(data),
+                AlgorithmHasher::Sha1(hasher) => hasher.update(data),
+                AlgorithmHasher::Md5(hasher) => hasher.update(data),
+            }
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Vec<Checksum> {
+        self.hashers
+            .into_iter()
+            .map(|hasher| match hasher {
+                AlgorithmHasher::Sha256(hasher) => Checksum {
+                    algorithm: ChecksumAlgorithm::Sha256,
+                    digest: hasher.finalize().to_vec(),
+                },
+                AlgorithmHasher::Sha1(hasher) => Checksum {
+                    algorithm: ChecksumAlgorithm::Sha1,
+                    digest: hasher.finalize().to_vec(),
+                },
+                AlgorithmHasher::Md5(hasher) => Checksum {
+                    algorithm: ChecksumAlgorithm::Md5,
+                    digest: hasher.finalize().to_vec(),
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use super::*;
+
+    const ALL: [ChecksumAlgorithm; 3] = [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Sha1, ChecksumAlgorithm::Md5];
+
+    fn hex_digests(chunks: &[&[u8]]) -> Vec<(&'static str, String)> {
+        let mut hasher = ChecksumHasher::new(&ALL);
+        for chunk in chunks {
+            hasher.update(chunk);
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|checksum| (checksum.algorithm.name(), checksum.hex()))
+            .collect()
+    }
+
+    #[test]
+    fn known_answers() {
+        let empty = [
+            ("sha256", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            ("sha1", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            ("md5", "d41d8cd98f00b204e9800998ecf8427e"),
+        ];
+        let abc = [
+            ("sha256", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            ("sha1", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            ("md5", "900150983cd24fb0d6963f7d28e17f72"),
+        ];
+        let expected = |answers: [(&'static str, &str); 3]| {
+            answers.map(|(name, digest)| (name, digest.to_string())).to_vec()
+        };
+        assert_eq!(hex_digests(&[]), expected(empty));
+        assert_eq!(hex_digests(&[b""]), expected(empty));
+        assert_eq!(hex_digests(&[b"abc"]), expected(abc));
+        // Digests do not depend on how the stream was split.
+        assert_eq!(hex_digests(&[b"a", b"", b"bc"]), expected(abc));
+    }
+
+    #[test]
+    fn duplicates_are_computed_once() {
+        let checksums = ChecksumHasher::new(&[ChecksumAlgorithm::Md5, ChecksumAlgorithm::Md5]).finalize();
+        assert_eq!(checksums.len(), 1);
+        assert!(ChecksumHasher::new(&[]).finalize().is_empty());
+    }
+}
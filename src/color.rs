@@ -0,0 +1,53 @@
+use std::fmt::Display;
+
+use anstyle::{AnsiColor, Style};
+use clap::ColorChoice;
+
+/// Whether to emit ANSI colors on a stream: `auto` follows the NO_COLOR
+/// convention and only colors terminals.
+pub fn enabled(choice: ColorChoice, is_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+    }
+}
+
+/// Styles for the final summary; every method returns the plain text when
+/// colors are off.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    /// Output files.
+    pub fn path(&self, text: impl Display) -> String {
+        self.paint(Style::new().bold(), text)
+    }
+
+    /// Infohashes and other identifiers worth finding at a glance.
+    pub fn hash(&self, text: impl Display) -> String {
+        self.paint(AnsiColor::Cyan.on_default().bold(), text)
+    }
+
+    pub fn success(&self, text: impl Display) -> String {
+        self.paint(AnsiColor::Green.on_default(), text)
+    }
+
+    pub fn warning(&self, text: impl Display) -> String {
+        self.paint(AnsiColor::Yellow.on_default(), text)
+    }
+
+    fn paint(&self, style: Style, text: impl Display) -> String {
+        if self.enabled {
+            format!("{style}{text}{style:#}")
+        } else {
+            text.to_string()
+        }
+    }
+}
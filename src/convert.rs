@@ -0,0 +1,149 @@
+//! Upgrades v1-only torrents to hybrid ones by re-hashing their webseed.
+
+use reqwest::Client;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::builder::{self, HashOptions, Hashed, Torrent};
+use crate::error::{Result, TorseedError};
+use crate::http::{self, HttpOptions, SourceMetadata};
+use crate::metainfo::{self, BuildInput, FileLayout, MetaVersion, ParsedTorrent, RootEdits};
+use crate::progress::{Event, EventSink};
+
+/// Finds a webseed of `torrent` that serves a file of the right length.
+/// Webseeds ending in `/` are treated as directories holding the file, as
+/// BEP 19 allows.
+pub async fn find_source(client: &Client, http_options: &HttpOptions, torrent: &ParsedTorrent) -> Result<SourceMetadata> {
+    let length = single_file_length(torrent)?;
+    for webseed in &torrent.webseeds {
+        let url = match webseed_url(webseed, torrent) {
+            Ok(url) => url,
+            Err(err) => {
+                warn!("Skipping webseed {webseed}: {err}");
+                continue;
+            }
+        };
+        match http::head_source(client, http_options, url).await {
+            Ok(source) if source.content_length == length => return Ok(source),
+            Ok(source) => warn!(
+                "Skipping webseed {webseed}: serves {} bytes, the torrent expects {length}",
+                source.content_length
+            ),
+            Err(err) => warn!("Skipping webseed {webseed}: {err}"),
+        }
+    }
+    Err(TorseedError::InvalidInput(format!(
+        "None of the {} webseeds serves the payload of this torrent",
+        torrent.webseeds.len()
+    )))
+}
+
+/// Streams `source`, checks every piece against the v1 hashes of `torrent`
+/// and builds a hybrid torrent with the same name, piece length, trackers,
+/// webseeds and comment. The first piece that differs fails with
+/// [`TorseedError::Mismatch`].
+///
+/// The result has new infohashes: the v2 keys become part of the info
+/// dictionary.
+pub async fn to_hybrid(
+    client: &Client,
+    http_options: &HttpOptions,
+    torrent: &ParsedTorrent,
+    source: &SourceMetadata,
+    events: &EventSink,
+    cancel: &CancellationToken,
+) -> Result<Torrent> {
+    if torrent.version() != MetaVersion::V1 {
+        return Err(TorseedError::InvalidInput(format!(
+            "Only v1 torrents can be converted; this one is {}",
+            torrent.version()
+        )));
+    }
+    if torrent.private {
+        return Err(TorseedError::InvalidInput(
+            "Private torrents cannot be converted: the private flag would be lost".to_string(),
+        ));
+    }
+    let length = single_file_length(torrent)?;
+    if source.content_length != length {
+        return Err(TorseedError::Mismatch(format!(
+            "{} is {} bytes, the torrent expects {length}",
+            source.url, source.content_length
+        )));
+    }
+    let expected = torrent.pieces.as_deref().unwrap_or_default();
+    let piece_length = u32::try_from(torrent.piece_length)
+        .ok()
+        .filter(|length| *length > 0)
+        .ok_or_else(|| TorseedError::InvalidInput(format!("Unusable piece length {}", torrent.piece_length)))?;
+
+    info!("Re-hashing {} against {} v1 pieces", source.url, expected.len() / 20);
+    events.emit(Event::MetadataResolved(source.clone()));
+    let options = HashOptions {
+        expected_pieces: Some(expected.to_vec()),
+        http: http_options.clone(),
+        ..HashOptions::default()
+    };
+    let (Hashed { pieces, v2, .. }, transfer) =
+        builder::hash_source(client, source, piece_length as usize, None, options, events, cancel).await?;
+    if let Some(v2) = &v2 {
+        events.emit(Event::PieceLayerFinalized {
+            pieces_root: v2.pieces_root,
+            pieces: v2.piece_layers.len() / 32,
+        });
+    }
+
+    let input = BuildInput {
+        name: torrent.name_lossy().into_owned(),
+        length,
+        piece_length,
+        pieces,
+        announce_tiers: torrent.announce_tiers.clone(),
+        webseeds: torrent.webseeds.clone(),
+        creation_date: torrent.creation_date.unwrap_or_else(builder::unix_now),
+        created_by: format!("torseed {}", env!("CARGO_PKG_VERSION")),
+        name_utf8: false,
+        v2,
+        files: Vec::new(),
+    };
+    let mut metainfo = metainfo::build(&input)?;
+    if let Some(comment) = &torrent.comment {
+        let edits = RootEdits {
+            comment: Some(comment.clone()),
+            ..RootEdits::default()
+        };
+        metainfo.torrent = metainfo::edit(&metainfo.torrent, &edits)?;
+    }
+    events.emit(Event::TorrentBuilt {
+        infohash_v1: metainfo.infohash_v1,
+        infohash_v2: metainfo.infohash_v2,
+        size: metainfo.torrent.len(),
+    });
+    Ok(Torrent {
+        input,
+        metainfo,
+        transfer,
+        checksums: Vec::new(),
+    })
+}
+
+fn single_file_length(torrent: &ParsedTorrent) -> Result<u64> {
+    match torrent.layout {
+        FileLayout::Single { length } => Ok(length),
+        FileLayout::Multi { .. } => Err(TorseedError::InvalidInput(
+            "Only single-file torrents can be converted".to_string(),
+        )),
+    }
+}
+
+fn webseed_url(webseed: &str, torrent: &ParsedTorrent) -> Result<Url> {
+    let mut url = Url::parse(webseed).map_err(|err| TorseedError::InvalidInput(format!("Invalid URL: {err}")))?;
+    if url.path().ends_with('/') {
+        url.path_segments_mut()
+            .map_err(|()| TorseedError::InvalidInput("URL cannot hold a path".to_string()))?
+            .pop_if_empty()
+            .push(&torrent.name_lossy());
+    }
+    Ok(url)
+}
@@ -0,0 +1,99 @@
+//! SHA-1 / SHA-256 backend used by the piece hashers. The default is the
+//! RustCrypto implementation; the `fast-hash` feature switches to ring's
+//! assembly routines. Both pick CPU SHA extensions at runtime when present.
+
+#[cfg(feature = "fast-hash")]
+mod backend {
+    use ring::digest::{self, Context, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+
+    #[cfg(feature = "http")]
+    pub const NAME: &str = "ring";
+
+    pub struct Sha1(Context);
+
+    impl Sha1 {
+        pub fn new() -> Self {
+            Self(Context::new(&SHA1_FOR_LEGACY_USE_ONLY))
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        pub fn finalize(self) -> [u8; 20] {
+            self.0.finish().as_ref().try_into().expect("SHA-1 digest is 20 bytes")
+        }
+    }
+
+    pub fn sha256(data: &[u8]) -> [u8; 32] {
+        digest::digest(&SHA256, data).as_ref().try_into().expect("SHA-256 digest is 32 bytes")
+    }
+
+    pub fn sha256_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut context = Context::new(&SHA256);
+        context.update(left);
+        context.update(right);
+        context.finish().as_ref().try_into().expect("SHA-256 digest is 32 bytes")
+    }
+}
+
+#[cfg(not(feature = "fast-hash"))]
+mod backend {
+    use sha1::Digest;
+    use sha2::Sha256;
+
+    #[cfg(feature = "http")]
+    pub const NAME: &str = "RustCrypto";
+
+    pub struct Sha1(sha1::Sha1);
+
+    impl Sha1 {
+        pub fn new() -> Self {
+            Self(sha1::Sha1::new())
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        pub fn finalize(self) -> [u8; 20] {
+            self.0.finalize().into()
+        }
+    }
+
+    pub fn sha256(data: &[u8]) -> [u8; 32] {
+        Sha256::digest(data).into()
+    }
+
+    pub fn sha256_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().into()
+    }
+}
+
+pub use backend::{sha256, sha256_pair, Sha1};
+
+/// Backend name plus whether the CPU offers SHA instructions, for logging.
+#[cfg(feature = "http")]
+pub fn describe() -> String {
+    let accelerated = if cpu_has_sha_extensions() { "SHA extensions" } else { "no SHA extensions" };
+    format!("{} ({accelerated})", backend::NAME)
+}
+
+#[cfg(feature = "http")]
+fn cpu_has_sha_extensions() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        std::arch::is_x86_feature_detected!("sha")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("sha2")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
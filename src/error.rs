@@ -0,0 +1,106 @@
+use std::io;
+
+#[cfg(feature = "http")]
+use url::Url;
+
+/// Errors returned by the library, split by what a caller would do about them:
+/// an unreachable source may be worth retrying, an encoding failure is not.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum TorseedError {
+    /// Probing the source with HEAD, or the ranged GET fallback, failed or
+    /// returned no usable size.
+    #[cfg(feature = "http")]
+    #[error("{message}")]
+    Metadata {
+        url: Url,
+        message: String,
+        #[source]
+        source: Option<reqwest::Error>,
+    },
+    /// The payload download could not be started or broke off midway.
+    #[cfg(feature = "http")]
+    #[error("{message}")]
+    Stream {
+        url: Url,
+        message: String,
+        #[source]
+        source: Option<reqwest::Error>,
+    },
+    /// Hashing did not produce a consistent set of pieces.
+    #[error("{0}")]
+    Hashing(String),
+    /// A resume checkpoint is unreadable or belongs to a different run.
+    #[error("{0}")]
+    Resume(String),
+    /// No usable announce list could be assembled.
+    #[error("{0}")]
+    Trackers(String),
+    /// Bencoding part of the torrent failed.
+    #[error("Failed to encode {what}: {message}")]
+    Encode { what: &'static str, message: String },
+    /// An existing torrent could not be decoded.
+    #[error("{0}")]
+    Decode(String),
+    /// The caller supplied input the torrent cannot be built from.
+    #[error("{0}")]
+    InvalidInput(String),
+    /// A source does not match the torrent it was checked against.
+    #[error("{0}")]
+    Mismatch(String),
+    /// A torrent client's RPC endpoint did not answer or refused the request.
+    #[cfg(feature = "http")]
+    #[error("{message}")]
+    Rpc {
+        message: String,
+        #[source]
+        source: Option<reqwest::Error>,
+    },
+    /// The operation was stopped through its cancellation token.
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+pub type Result<T, E = TorseedError> = std::result::Result<T, E>;
+
+impl TorseedError {
+    pub(crate) fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Self::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Whether the same request might succeed if repeated: network failures,
+    /// timeouts, 5xx and 429 responses, but not other 4xx, refused redirects
+    /// or malformed data.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            #[cfg(feature = "http")]
+            Self::Metadata { source, .. } | Self::Stream { source, .. } | Self::Rpc { source, .. } => {
+                source.as_ref().is_some_and(|err| {
+                    !err.is_redirect()
+                        && !err.status().is_some_and(|status| {
+                            status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+                        })
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `err` followed by each of its causes, the way `{:#}` prints an anyhow chain.
+#[cfg(feature = "http")]
+pub(crate) fn describe(err: &dyn std::error::Error) -> String {
+    std::iter::successors(Some(err), |err| err.source())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
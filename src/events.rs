@@ -0,0 +1,138 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use serde_json::{json, Value};
+use torseed::progress::Event;
+
+use crate::bars::Bars;
+
+/// How progress is reported while a torrent is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressFormat {
+    /// A progress bar on a terminal, periodic log lines otherwise.
+    Human,
+    /// Newline-delimited JSON events, one object per line.
+    Json,
+}
+
+/// Writer for `--progress json`. Every line is an object with an `event`
+/// name and an RFC 3339 `timestamp`; clones share the same output.
+#[derive(Clone)]
+pub struct JsonEvents {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// Minimum spacing between `hash_progress` events.
+    pub hash_interval: Duration,
+    /// Whether to emit `piece_completed` for every v1 piece.
+    pub pieces: bool,
+}
+
+impl JsonEvents {
+    /// Writes to stderr, or to the already open file descriptor `fd`.
+    pub fn open(fd: Option<u32>, hash_interval: Duration, pieces: bool) -> Result<Self> {
+        let out: Box<dyn Write + Send> = match fd {
+            Some(fd) => Box::new(
+                OpenOptions::new()
+                    .append(true)
+                    .open(format!("/dev/fd/{fd}"))
+                    .with_context(|| format!("Failed to open progress file descriptor {fd}"))?,
+            ),
+            None => Box::new(io::stderr()),
+        };
+        Ok(Self {
+            out: Arc::new(Mutex::new(out)),
+            hash_interval,
+            pieces,
+        })
+    }
+
+    /// Writes one event. Output errors are ignored: a reader that went away
+    /// must not fail the build.
+    pub fn write(&self, event: &str, mut fields: Value) {
+        fields["event"] = json!(event);
+        fields["timestamp"] = json!(humantime::format_rfc3339_millis(SystemTime::now()).to_string());
+        let mut out = self.out.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = writeln!(out, "{fields}");
+        let _ = out.flush();
+    }
+
+    /// Translates a library event. Throttling `hash_progress` is left to the
+    /// caller, which sees every `BytesHashed`.
+    pub fn library_event(&self, event: &Event) {
+        match event {
+            Event::MetadataResolved(source) => self.write(
+                "metadata",
+                json!({
+                    "url": source.url.as_str(),
+                    "filename": source.filename,
+                    "length": source.content_length,
+                }),
+            ),
+            Event::BytesHashed(progress) => self.write(
+                "hash_progress",
+                json!({
+                    "bytes": progress.hashed,
+                    "total": progress.expected,
+                    "rate": progress.rate,
+                    "average_rate": progress.average_rate,
+                    "eta_secs": progress.eta.map(|eta| eta.as_secs()),
+                }),
+            ),
+            Event::PieceHashed { index, hash } => {
+                self.write("piece_completed", json!({ "index": index, "hash": hex::encode(hash) }))
+            }
+            Event::WebseedVerified { url } => {
+                self.write("webseed_checked", json!({ "url": url.as_str(), "ok": true }))
+            }
+            Event::WebseedRejected { url, reason } => self.write(
+                "webseed_checked",
+                json!({ "url": url.as_str(), "ok": false, "reason": reason }),
+            ),
+            Event::TrackersGathered { trackers, tiers } => {
+                self.write("tracker_gathering", json!({ "trackers": trackers, "tiers": tiers }))
+            }
+            Event::PieceLayerFinalized { pieces_root, pieces } => self.write(
+                "piece_layer",
+                json!({ "pieces_root": hex::encode(pieces_root), "pieces": pieces }),
+            ),
+            Event::TorrentBuilt {
+                infohash_v1,
+                infohash_v2,
+                size,
+            } => self.write(
+                "torrent_built",
+                json!({
+                    "infohash_v1": infohash_v1.map(hex::encode),
+                    "infohash_v2": infohash_v2.map(hex::encode),
+                    "size": size,
+                }),
+            ),
+            Event::TorrentWritten { path } => {
+                self.write("torrent_written", json!({ "path": path.display().to_string() }))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Where build progress goes.
+#[derive(Clone)]
+pub enum ProgressOutput {
+    /// Periodic log lines.
+    Log,
+    Bars(Bars),
+    Json(JsonEvents),
+}
+
+impl ProgressOutput {
+    /// A spinner for a setup step; hidden unless bars are drawn.
+    pub fn spinner(&self, message: &'static str) -> ProgressBar {
+        match self {
+            Self::Bars(bars) => bars.spinner(message),
+            Self::Log | Self::Json(_) => ProgressBar::hidden(),
+        }
+    }
+}
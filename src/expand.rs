@@ -0,0 +1,119 @@
+//! Brace expansion for URL templates such as `part-{000..127}.bin`, which
+//! name the parts of a multi-file source.
+
+/// Expands every `{…}` group in `template`. `{a..b}` and `{a..b..step}`
+/// count from `a` to `b`, downwards when `a > b`; when either bound is written
+/// with a leading zero, every number is padded to the wider bound. `{x,y,z}`
+/// substitutes each item in turn. Several groups expand to every combination,
+/// the first group varying slowest. Braces holding neither form stay as they
+/// are.
+///
+/// Fails on malformed ranges, a zero step, an unclosed brace, or when the
+/// expansion would produce more than `max` strings.
+///
+/// ```
+/// use torseed::expand::expand;
+///
+/// assert_eq!(
+///     expand("part-{000..003}.bin", 10).unwrap(),
+///     ["part-000.bin", "part-001.bin", "part-002.bin", "part-003.bin"]
+/// );
+/// assert_eq!(expand("{8..11}", 10).unwrap(), ["8", "9", "10", "11"]);
+/// assert_eq!(expand("{08..11}", 10).unwrap(), ["08", "09", "10", "11"]);
+/// assert_eq!(expand("{0..10..5}", 10).unwrap(), ["0", "5", "10"]);
+/// assert_eq!(expand("{3..1}", 10).unwrap(), ["3", "2", "1"]);
+/// assert_eq!(expand("{1..8..3}", 10).unwrap(), ["1", "4", "7"]);
+/// assert_eq!(expand("{a,b}-{1..2}", 10).unwrap(), ["a-1", "a-2", "b-1", "b-2"]);
+/// assert_eq!(expand("data.{tar,}", 10).unwrap(), ["data.tar", "data."]);
+/// assert_eq!(expand("plain.bin", 10).unwrap(), ["plain.bin"]);
+/// assert_eq!(expand("{literal}", 10).unwrap(), ["{literal}"]);
+/// ```
+///
+/// ```
+/// use torseed::expand::expand;
+///
+/// for bad in ["{1..}", "{a..z}", "{1..5..0}", "{1..5..x}", "part-{1..3", "{1..2..3..4}"] {
+///     assert!(expand(bad, 10).is_err(), "{bad}");
+/// }
+/// assert!(expand("{1..11}", 10).unwrap_err().contains("more than 10"));
+/// assert!(expand("{0..99999999999999}{0..99999999999999}", 10).is_err());
+/// ```
+pub fn expand(template: &str, max: usize) -> Result<Vec<String>, String> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut literal = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        literal.push_str(&rest[..open]);
+        let body_start = open + 1;
+        let close = rest[body_start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed '{{' in {template:?}"))?;
+        let body = &rest[body_start..body_start + close];
+        rest = &rest[body_start + close + 1..];
+        match expand_group(body, max)? {
+            Some(items) => {
+                groups.push(vec![std::mem::take(&mut literal)]);
+                groups.push(items);
+            }
+            None => {
+                literal.push('{');
+                literal.push_str(body);
+                literal.push('}');
+            }
+        }
+    }
+    literal.push_str(rest);
+    groups.push(vec![literal]);
+
+    let count = groups
+        .iter()
+        .try_fold(1usize, |count, items| count.checked_mul(items.len()))
+        .filter(|&count| count <= max)
+        .ok_or_else(|| format!("{template:?} expands to more than {max} URLs"))?;
+    let mut expanded = Vec::with_capacity(count);
+    expanded.push(String::new());
+    for items in groups {
+        expanded = expanded
+            .iter()
+            .flat_map(|prefix| items.iter().map(move |item| format!("{prefix}{item}")))
+            .collect();
+    }
+    Ok(expanded)
+}
+
+/// The items of one brace group, or `None` when it is neither a range nor a
+/// list. Ranges longer than `max` fail before anything is generated.
+fn expand_group(body: &str, max: usize) -> Result<Option<Vec<String>>, String> {
+    if body.contains(',') {
+        return Ok(Some(body.split(',').map(str::to_string).collect()));
+    }
+    let parts: Vec<&str> = body.split("..").collect();
+    let (start, end, step) = match parts[..] {
+        [_] => return Ok(None),
+        [start, end] => (start, end, "1"),
+        [start, end, step] => (start, end, step),
+        _ => return Err(format!("Invalid range {{{body}}}: expected {{start..end}} or {{start..end..step}}")),
+    };
+    let number = |text: &str| {
+        text.parse::<u64>()
+            .map_err(|_| format!("Invalid range {{{body}}}: {text:?} is not a non-negative integer"))
+    };
+    let (first, last, step) = (number(start)?, number(end)?, number(step)?);
+    if step == 0 {
+        return Err(format!("Invalid range {{{body}}}: the step must not be zero"));
+    }
+    let padded = |text: &str| text.len() > 1 && text.starts_with('0');
+    let width = if padded(start) || padded(end) { start.len().max(end.len()) } else { 0 };
+
+    let count = first.abs_diff(last) / step + 1;
+    if count > max as u64 {
+        return Err(format!("{{{body}}} expands to more than {max} URLs"));
+    }
+    let items = (0..count)
+        .map(|index| {
+            let value = if first <= last { first + index * step } else { first - index * step };
+            format!("{value:0width$}")
+        })
+        .collect();
+    Ok(Some(items))
+}
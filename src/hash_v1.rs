@@ -1,4 +1,4 @@
-use sha1::{Digest, Sha1};
+use crate::digest::Sha1;
 
 /// Streaming SHA-1 piece hasher for BitTorrent v1.
 pub struct V1Hasher {
@@ -18,6 +18,22 @@ impl V1Hasher {
         }
     }
 
+    /// Continues hashing after `pieces` were completed by an earlier run.
+    #[cfg(feature = "http")]
+    pub(crate) fn resume(piece_length: usize, pieces: Vec<u8>) -> Self {
+        Self {
+            pieces,
+            ..Self::new(piece_length)
+        }
+    }
+
+    /// Completed piece hashes. Only a complete checkpoint at a piece boundary.
+    #[cfg(feature = "http")]
+    pub(crate) fn snapshot(&self) -> &[u8] {
+        debug_assert_eq!(self.current_len, 0, "snapshot taken mid-piece");
+        &self.pieces
+    }
+
     pub fn update(&mut self, mut data: &[u8]) {
         while !data.is_empty() {
             let remaining = self.piece_length - self.current_len;
@@ -40,10 +56,8 @@ impl V1Hasher {
     }
 
     fn flush_piece(&mut self) {
-        let hasher = std::mem::take(&mut self.hasher);
-        let digest = hasher.finalize();
-        self.pieces.extend_from_slice(&digest);
-        self.hasher = Sha1::new();
+        let hasher = std::mem::replace(&mut self.hasher, Sha1::new());
+        self.pieces.extend_from_slice(&hasher.finalize());
         self.current_len = 0;
     }
 }
@@ -1,157 +1,278 @@
-use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use rayon::prelude::*;
 
-use anyhow::Result;
-use sha2::{Digest, Sha256};
-use tempfile::tempfile;
+use crate::digest::{sha256, sha256_pair};
 
 const LEAF_SIZE: usize = 16 * 1024;
+/// Leaves buffered before hashing them in parallel (4 MiB of data).
+const BATCH_LEAVES: usize = 256;
+const BATCH_SIZE: usize = LEAF_SIZE * BATCH_LEAVES;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct V2Summary {
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde"))]
     pub pieces_root: [u8; 32],
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde"))]
+    pub piece_layers: Vec<u8>,
+}
+
+/// State of a `V2Hasher` at a piece boundary, enough to continue hashing.
+#[cfg(feature = "http")]
+#[derive(Debug, Clone)]
+pub(crate) struct V2Snapshot {
+    pub subtrees: Vec<(u32, [u8; 32])>,
+    pub leaves: usize,
     pub piece_layers: Vec<u8>,
 }
 
 pub struct V2Hasher {
     buffer: Vec<u8>,
-    leaf_writer: BufWriter<std::fs::File>,
-    leaf_count: usize,
-    total_bytes: u64,
+    leaves_per_piece: usize,
+    tree: MerkleStack,
+    piece: MerkleStack,
+    piece_layers: Vec<u8>,
 }
 
 impl V2Hasher {
-    pub fn new() -> Result<Self> {
-        let temp = tempfile()?;
-        Ok(Self {
-            buffer: Vec::with_capacity(LEAF_SIZE),
-            leaf_writer: BufWriter::new(temp),
-            leaf_count: 0,
-            total_bytes: 0,
-        })
-    }
-
-    pub fn update(&mut self, mut data: &[u8]) -> Result<()> {
-        self.total_bytes += data.len() as u64;
-
-        while !data.is_empty() {
-            let needed = LEAF_SIZE - self.buffer.len();
-            let take = needed.min(data.len());
-            self.buffer.extend_from_slice(&data[..take]);
-            data = &data[take..];
-
-            if self.buffer.len() == LEAF_SIZE {
-                self.flush_leaf()?;
-            }
+    pub fn new(piece_length: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(BATCH_SIZE),
+            leaves_per_piece: piece_length.div_ceil(LEAF_SIZE).max(1),
+            tree: MerkleStack::default(),
+            piece: MerkleStack::default(),
+            piece_layers: Vec::new(),
         }
+    }
 
-        Ok(())
+    /// Continues hashing from a snapshot taken by an earlier run.
+    #[cfg(feature = "http")]
+    pub(crate) fn resume(piece_length: usize, snapshot: V2Snapshot) -> Self {
+        let mut hasher = Self::new(piece_length);
+        hasher.tree = MerkleStack {
+            subtrees: snapshot.subtrees,
+            len: snapshot.leaves,
+        };
+        hasher.piece_layers = snapshot.piece_layers;
+        hasher
     }
 
-    pub fn finalize(mut self, piece_length: usize) -> Result<V2Summary> {
+    /// Flushes buffered leaves and captures the hasher state. Must be called on
+    /// a piece boundary so no partial piece subtree is lost.
+    #[cfg(feature = "http")]
+    pub(crate) fn snapshot(&mut self) -> V2Snapshot {
         if !self.buffer.is_empty() {
-            self.flush_leaf()?;
+            self.flush_batch();
         }
-
-        if self.leaf_count == 0 {
-            let digest: [u8; 32] = Sha256::digest(&[]).into();
-            self.write_leaf(&digest)?;
+        debug_assert!(self.piece.is_empty(), "snapshot taken mid-piece");
+        V2Snapshot {
+            subtrees: self.tree.subtrees.clone(),
+            leaves: self.tree.len,
+            piece_layers: self.piece_layers.clone(),
         }
+    }
 
-        let mut file = self.leaf_writer.into_inner()?;
-        file.seek(SeekFrom::Start(0))?;
-        let leaves = read_leaves(&mut file, self.leaf_count)?;
-
-        let piece_count = if self.total_bytes == 0 {
-            0
-        } else {
-            ((self.total_bytes + piece_length as u64 - 1) / piece_length as u64) as usize
-        };
+    /// Feeds the next bytes of the file. Any split of the input into calls
+    /// gives the same hashes.
+    ///
+    /// ```
+    /// use torseed::V2Hasher;
+    ///
+    /// let data: Vec<u8> = (0..5_000_000u32).map(|i| (i % 251) as u8).collect();
+    /// let whole = {
+    ///     let mut hasher = V2Hasher::new(65_536);
+    ///     hasher.update(&data);
+    ///     hasher.finalize()
+    /// };
+    /// // Chunk sizes that straddle 16 KiB leaves and 64 KiB pieces.
+    /// for chunk in [1, 8_191, 16_385, 65_535, 4_194_305] {
+    ///     let mut hasher = V2Hasher::new(65_536);
+    ///     for part in data.chunks(chunk) {
+    ///         hasher.update(part);
+    ///     }
+    ///     let split = hasher.finalize();
+    ///     assert_eq!(split.pieces_root, whole.pieces_root);
+    ///     assert_eq!(split.piece_layers, whole.piece_layers);
+    /// }
+    /// ```
+    pub fn update(&mut self, mut data: &[u8]) {
+        // A started batch is completed first so leaves stay in order.
+        if !self.buffer.is_empty() {
+            let take = (BATCH_SIZE - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < BATCH_SIZE {
+                return;
+            }
+            self.flush_batch();
+        }
+        // At least a batch of whole leaves is hashed straight from the input.
+        let whole = data.len() - data.len() % LEAF_SIZE;
+        if whole >= BATCH_SIZE {
+            self.push_leaves(&data[..whole]);
+            data = &data[whole..];
+        }
+        self.buffer.extend_from_slice(data);
+    }
 
-        let piece_layers = if piece_count == 0 {
-            Vec::new()
-        } else {
-            build_piece_layers(&leaves, piece_length, piece_count)
-        };
-        let pieces_root = merkle_root(&leaves);
+    pub fn finalize(mut self) -> V2Summary {
+        if !self.buffer.is_empty() {
+            self.flush_batch();
+        }
+        if !self.piece.is_empty() {
+            self.piece_layers.extend_from_slice(&self.piece.root());
+        }
 
-        Ok(V2Summary {
-            pieces_root,
-            piece_layers,
-        })
+        V2Summary {
+            pieces_root: self.tree.root(),
+            piece_layers: self.piece_layers,
+        }
     }
 
-    fn flush_leaf(&mut self) -> Result<()> {
-        let digest: [u8; 32] = Sha256::digest(&self.buffer).into();
-        self.write_leaf(&digest)?;
+    /// Hashes the buffered leaves and empties the buffer, keeping its
+    /// allocation.
+    fn flush_batch(&mut self) {
+        let buffer = std::mem::take(&mut self.buffer);
+        self.push_leaves(&buffer);
+        self.buffer = buffer;
         self.buffer.clear();
-        Ok(())
     }
 
-    fn write_leaf(&mut self, digest: &[u8]) -> Result<()> {
-        self.leaf_writer.write_all(digest)?;
-        self.leaf_count += 1;
-        Ok(())
+    /// Hashes `data` as consecutive leaves across the rayon pool. Only the
+    /// last leaf of the file may be partial.
+    fn push_leaves(&mut self, data: &[u8]) {
+        let digests: Vec<[u8; 32]> = data.par_chunks(LEAF_SIZE).map(sha256).collect();
+        for digest in digests {
+            self.push_leaf(digest);
+        }
+    }
+
+    fn push_leaf(&mut self, digest: [u8; 32]) {
+        self.tree.push(digest);
+        self.piece.push(digest);
+        if self.piece.len() == self.leaves_per_piece {
+            self.piece_layers.extend_from_slice(&self.piece.root());
+            self.piece = MerkleStack::default();
+        }
     }
+}
 
+/// Incremental merkle tree that keeps one perfect subtree per height, so
+/// memory stays logarithmic in the number of leaves. When a level has an odd
+/// number of nodes the last one is paired with itself.
+#[derive(Default)]
+struct MerkleStack {
+    subtrees: Vec<(u32, [u8; 32])>,
+    len: usize,
 }
 
-fn read_leaves(file: &mut std::fs::File, leaf_count: usize) -> Result<Vec<[u8; 32]>> {
-    let mut leaves = Vec::with_capacity(leaf_count);
-    let mut buf = [0u8; 32];
-    for _ in 0..leaf_count {
-        file.read_exact(&mut buf)?;
-        leaves.push(buf);
+impl MerkleStack {
+    fn len(&self) -> usize {
+        self.len
     }
-    Ok(leaves)
-}
 
-fn build_piece_layers(leaves: &[[u8; 32]], piece_length: usize, piece_count: usize) -> Vec<u8> {
-    if leaves.is_empty() || piece_length == 0 || piece_count == 0 {
-        return Vec::new();
+    fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
-    let leaves_per_piece = (piece_length + LEAF_SIZE - 1) / LEAF_SIZE;
-    let mut layers: Vec<u8> = Vec::with_capacity(piece_count * 32);
-    let mut index = 0;
-    for _ in 0..piece_count {
-        if index >= leaves.len() {
-            break;
+    fn push(&mut self, mut node: [u8; 32]) {
+        self.len += 1;
+        let mut height = 0;
+        while let Some(&(top_height, top)) = self.subtrees.last()
+            && top_height == height
+        {
+            self.subtrees.pop();
+            node = sha256_pair(&top, &node);
+            height += 1;
         }
-        let end = (index + leaves_per_piece).min(leaves.len());
-        let root = merkle_root(&leaves[index..end]);
-        layers.extend_from_slice(&root);
-        index = end;
+        self.subtrees.push((height, node));
     }
 
-    layers
+    fn root(&self) -> [u8; 32] {
+        let mut subtrees = self.subtrees.iter().rev();
+        let Some(&(mut height, mut node)) = subtrees.next() else {
+            return sha256(&[]);
+        };
+        for &(top_height, top) in subtrees {
+            while height < top_height {
+                node = sha256_pair(&node, &node);
+                height += 1;
+            }
+            node = sha256_pair(&top, &node);
+            height += 1;
+        }
+        node
+    }
 }
 
-fn merkle_root(nodes: &[[u8; 32]]) -> [u8; 32] {
-    if nodes.is_empty() {
-        return Sha256::digest(&[]).into();
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut level: Vec<[u8; 32]> = nodes.to_vec();
+    const PIECE_LENGTH: usize = 4 * LEAF_SIZE;
 
-    while level.len() > 1 {
-        if level.len() % 2 == 1 {
-            let last = *level.last().unwrap();
-            level.push(last);
+    /// Roots from hashing every leaf up front and folding whole levels, with
+    /// an odd last node paired with itself: the tree the temp-file hasher
+    /// built before `MerkleStack` replaced it.
+    fn level_root(nodes: &[[u8; 32]]) -> [u8; 32] {
+        let mut level = nodes.to_vec();
+        if level.is_empty() {
+            return sha256(&[]);
         }
-
-        let mut next = Vec::with_capacity(level.len() / 2);
-        for chunk in level.chunks_exact(2) {
-            next.push(hash_pair(&chunk[0], &chunk[1]));
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(level[level.len() - 1]);
+            }
+            level = level.chunks(2).map(|pair| sha256_pair(&pair[0], &pair[1])).collect();
         }
-        level = next;
+        level[0]
     }
 
-    level[0]
-}
+    fn hash(length: usize) -> (Vec<u8>, V2Summary) {
+        let data: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+        let mut hasher = V2Hasher::new(PIECE_LENGTH);
+        hasher.update(&data);
+        (data, hasher.finalize())
+    }
 
-fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(left);
-    hasher.update(right);
-    hasher.finalize().into()
+    #[test]
+    fn matches_roots_of_the_previous_implementation() {
+        // Regression snapshots of what the hasher produced before, not
+        // BEP 52 reference values: an odd last node is paired with itself
+        // rather than with zero padding, so roots past a power of two of
+        // leaves differ from other clients.
+        // (length, pieces root, SHA-256 of the piece layers, piece count)
+        let known = [
+            (0, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", 0),
+            (1, "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d", "1406e05881e299367766d313e26c05564ec91bf721d31726bd6e46e60689539a", 1),
+            (16_383, "e08c7d58e58b9318263144a618d6f2b6f6825974decd9e6f9371567354b6f566", "1e531c6445a0f6c6b2caa2c2357dfa115e8d4d64c3a6eb9ecfe6a0501ee9e5e5", 1),
+            (16_384, "4348e3b98e8a327b34ced39c1da9e67cdb4cd5e48e4d7960607a3ae403d35f0c", "095f69c7a25d10faa5067bc8a99ee90c454ddf7c89b72e9fc29de6defbaa5627", 1),
+            (16_385, "9d7887c65d577a0237fb3c0998b87b3a62762d03796889a2caea01db914ccbb8", "bee8ffe45d495d0b1f65ad12a6d34d50341d92cbed3905aa61a168961346b4ba", 1),
+            (65_535, "1849c2c789ddb1b827f6f6871511cf2e4d34348dd89ad13719dc4454245f4214", "eab4947663773953671e8e41b4b092d30a9605a993a507da0c6860b78c56257a", 1),
+            (65_536, "2d6b546231225a7132a38ab354f03e9132e4b9141da89f1784b71ab2fb34fae3", "e05db0a366bd0c7eeb889fa13d0988278dd96d9fff807eec3e1fd362e7feb179", 1),
+            (65_537, "aa5a2eeb6f993e9bfcad7db22c87f196a82f7e2ae4fedc04fd66dea5f3c7720f", "71f2fc502624737dd57998f30ae94027c20d7231d6b3b44209119bf6696a7201", 2),
+            (328_680, "0e5247478e5ac9434a1c32914f287330366496595c0877f6e0adb28664cd50ba", "eaf7fa54c69a7c78813676cd9352455fb7893395b7609169b342179e5ee0f3c9", 6),
+            // One leaf past a rayon batch.
+            (4_194_305, "d2ab565e9f99b65a2406c965375a71bde63e0f22daf55e92278c1c23c01bb6be", "60678b2ce1846b2e04fe726ce58517f62d8eeb4dab4a38fbd9caa79ffca47d6f", 65),
+        ];
+        for (length, root, layers, pieces) in known {
+            let (_, summary) = hash(length);
+            assert_eq!(hex::encode(summary.pieces_root), root, "{length} bytes");
+            assert_eq!(hex::encode(sha256(&summary.piece_layers)), layers, "{length} bytes");
+            assert_eq!(summary.piece_layers.len(), pieces * 32, "{length} bytes");
+        }
+    }
+
+    #[test]
+    fn matches_whole_level_hashing() {
+        for length in [0, 1, 16_384, 65_535, 65_536, 65_537, 328_680, 4_194_305] {
+            let (data, summary) = hash(length);
+            let leaves: Vec<[u8; 32]> = data.chunks(LEAF_SIZE).map(sha256).collect();
+            let layers: Vec<u8> = leaves
+                .chunks(PIECE_LENGTH / LEAF_SIZE)
+                .flat_map(level_root)
+                .collect();
+            assert_eq!(summary.pieces_root, level_root(&leaves), "{length} bytes");
+            assert_eq!(summary.piece_layers, layers, "{length} bytes");
+        }
+    }
 }
@@ -0,0 +1,52 @@
+//! Lowercase hex for binary fields, for use with `#[serde(with = ...)]`.
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(bytes: impl AsRef<[u8]>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+/// Decodes into a `Vec<u8>` or a fixed-size array, rejecting the wrong length.
+pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<Vec<u8>>,
+{
+    let text = String::deserialize(deserializer)?;
+    let bytes = hex::decode(text).map_err(D::Error::custom)?;
+    let len = bytes.len();
+    T::try_from(bytes).map_err(|_| D::Error::custom(format!("unexpected length {len} for hex field")))
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S, T>(bytes: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: AsRef<[u8]>,
+    {
+        match bytes {
+            Some(bytes) => super::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: TryFrom<Vec<u8>>,
+    {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(text) => {
+                let bytes = hex::decode(text).map_err(D::Error::custom)?;
+                let len = bytes.len();
+                T::try_from(bytes)
+                    .map(Some)
+                    .map_err(|_| D::Error::custom(format!("unexpected length {len} for hex field")))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -1,58 +1,575 @@
-use std::time::Duration;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{Context, Result};
-use reqwest::{header, Client, Response, StatusCode};
-use tracing::debug;
+use bytes::Bytes;
+use futures::stream::{FuturesUnordered, StreamExt};
+use reqwest::{header, Client, RequestBuilder, Response, StatusCode};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
 use url::Url;
 
+use crate::error::{Result, TorseedError};
+use crate::netrc::Netrc;
+use crate::progress::{Event, EventSink};
 use crate::util::sanitize_filename;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceMetadata {
     pub url: Url,
     pub content_length: u64,
+    /// Sanitized with [`sanitize_filename`].
     pub filename: String,
+    /// The name the server suggested, when sanitizing changed it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub original_filename: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 
-pub async fn head_source(client: &Client, url: Url) -> Result<SourceMetadata> {
-    let response = client
-        .head(url.as_str())
-        .timeout(Duration::from_secs(15))
-        .send()
+/// Connection reuse settings for the shared [`Client`]. Every field left at
+/// `None` keeps reqwest's default.
+///
+/// Keeping more idle connections per host lets a large webseed check on a few
+/// CDNs skip most TLS handshakes, at the cost of open sockets and memory; a
+/// longer idle timeout keeps them useful across phases (webseed checks, then
+/// tracker lists, then the download) but holds them open on servers that may
+/// have dropped them already. TCP keepalive probes detect such dead
+/// connections and keep NAT mappings alive during long idle gaps, for a few
+/// extra packets.
+///
+/// ```
+/// use std::time::Duration;
+/// use torseed::http::PoolOptions;
+///
+/// let options = PoolOptions {
+///     max_idle_per_host: Some(64),
+///     idle_timeout: Some(Duration::from_secs(30)),
+///     tcp_keepalive: Some(Duration::from_secs(15)),
+/// };
+/// let client = options.apply(reqwest::Client::builder()).build();
+/// assert!(client.is_ok());
+/// assert!(PoolOptions::default().apply(reqwest::Client::builder()).build().is_ok());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PoolOptions {
+    /// Idle connections kept per host; unlimited by default.
+    pub max_idle_per_host: Option<usize>,
+    /// How long an idle connection is kept; 90 seconds by default.
+    pub idle_timeout: Option<Duration>,
+    /// Interval of TCP keepalive probes; off by default.
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl PoolOptions {
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if let Some(max) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(interval) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(interval);
+        }
+        builder
+    }
+}
+
+/// Where outbound connections of the shared [`Client`] leave the machine, for
+/// multihomed hosts whose traffic must use a particular link.
+///
+/// ```
+/// use torseed::http::BindOptions;
+///
+/// let local = BindOptions { address: Some("127.0.0.1".parse().unwrap()), interface: None };
+/// assert!(local.check().is_ok());
+/// assert!(local.apply(reqwest::Client::builder()).build().is_ok());
+///
+/// let foreign = BindOptions { address: Some("192.0.2.1".parse().unwrap()), interface: None };
+/// assert!(foreign.check().unwrap_err().to_string().contains("not an address of this machine"));
+/// let missing = BindOptions { address: None, interface: Some("no-such-if0".to_string()) };
+/// assert!(missing.check().is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BindOptions {
+    /// Source address for every connection.
+    pub address: Option<IpAddr>,
+    /// Network interface to send through (`SO_BINDTODEVICE`); Linux only.
+    pub interface: Option<String>,
+}
+
+impl BindOptions {
+    /// Fails unless the address belongs to this machine and the interface
+    /// exists, so a typo surfaces before the first request instead of as a
+    /// connect error midway.
+    pub fn check(&self) -> Result<()> {
+        if let Some(address) = self.address {
+            std::net::UdpSocket::bind((address, 0)).map_err(|err| {
+                TorseedError::InvalidInput(format!("Cannot bind to {address}: not an address of this machine ({err})"))
+            })?;
+        }
+        if let Some(interface) = &self.interface {
+            if !cfg!(target_os = "linux") {
+                return Err(TorseedError::InvalidInput("--interface is only supported on Linux".to_string()));
+            }
+            let known = !interface.is_empty()
+                && !interface.contains('/')
+                && std::path::Path::new("/sys/class/net").join(interface).exists();
+            if !known {
+                return Err(TorseedError::InvalidInput(format!("No network interface named {interface:?}")));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if let Some(address) = self.address {
+            debug!("Binding outbound connections to {address}");
+            builder = builder.local_address(address);
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = &self.interface {
+            debug!("Sending outbound connections through {interface}");
+            builder = builder.interface(interface);
+        }
+        builder
+    }
+}
+
+/// How the shared [`Client`] follows redirects, for every request it sends:
+/// source probes, the download, webseed checks and tracker list fetches.
+/// Each hop is logged at debug level with the chain so far.
+///
+/// A redirect from `https` down to plain `http` is refused unless
+/// `allow_insecure` is set, so a TLS-protected release URL cannot hand the
+/// payload over to an unauthenticated one.
+///
+/// ```
+/// use torseed::http::{RedirectError, RedirectOptions};
+///
+/// let options = RedirectOptions::default();
+/// let url = |url: &str| url.parse::<url::Url>().unwrap();
+/// let chain = [url("https://example.com/stable"), url("https://cdn.example.com/v1.2.iso")];
+/// assert!(options.check(&chain, &url("https://mirror.example.net/v1.2.iso")).is_ok());
+/// assert!(matches!(
+///     options.check(&chain, &url("http://mirror.example.net/v1.2.iso")),
+///     Err(RedirectError::Insecure { .. })
+/// ));
+/// let lenient = RedirectOptions { allow_insecure: true, ..options };
+/// assert!(lenient.check(&chain, &url("http://mirror.example.net/v1.2.iso")).is_ok());
+///
+/// let capped = RedirectOptions { max_redirects: 1, ..options };
+/// assert!(capped.check(&chain[..1], &chain[1]).is_ok());
+/// assert!(matches!(capped.check(&chain, &url("https://cdn.example.com/")), Err(RedirectError::TooMany(1))));
+/// ```
+///
+/// The client enforces the options through [`RedirectOptions::policy`]:
+///
+/// ```
+/// use torseed::http::RedirectOptions;
+///
+/// let options = RedirectOptions { max_redirects: 3, ..Default::default() };
+/// let client = reqwest::Client::builder().redirect(options.policy()).build().unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectOptions {
+    /// Redirects followed per request before failing it; 10 by default.
+    pub max_redirects: usize,
+    /// Follow redirects from `https` to plain `http`.
+    pub allow_insecure: bool,
+}
+
+impl Default for RedirectOptions {
+    fn default() -> Self {
+        Self {
+            max_redirects: 10,
+            allow_insecure: false,
+        }
+    }
+}
+
+/// Why [`RedirectOptions`] refused to follow a redirect.
+#[derive(Debug, thiserror::Error)]
+pub enum RedirectError {
+    #[error("Gave up after {0} redirects")]
+    TooMany(usize),
+    #[error("Refusing to follow a redirect from {from} down to plain HTTP at {to}")]
+    Insecure { from: String, to: String },
+}
+
+impl RedirectOptions {
+    /// Whether to follow a redirect to `next` after requesting `previous`,
+    /// which starts with the original URL.
+    pub fn check(&self, previous: &[Url], next: &Url) -> std::result::Result<(), RedirectError> {
+        if previous.len() > self.max_redirects {
+            return Err(RedirectError::TooMany(self.max_redirects));
+        }
+        if !self.allow_insecure
+            && next.scheme() == "http"
+            && let Some(from) = previous.iter().rev().find(|url| url.scheme() == "https")
+        {
+            return Err(RedirectError::Insecure {
+                from: from.to_string(),
+                to: next.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn policy(self) -> reqwest::redirect::Policy {
+        reqwest::redirect::Policy::custom(move |attempt| {
+            let chain: Vec<&str> = attempt
+                .previous()
+                .iter()
+                .chain([attempt.url()])
+                .map(Url::as_str)
+                .collect();
+            debug!("Redirect {}: {}", attempt.status(), chain.join(" -> "));
+            match self.check(attempt.previous(), attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(err) => attempt.error(err),
+            }
+        })
+    }
+}
+
+/// Settings shared by every request sent through this module: retry and
+/// stall limits, per-host limits for probes, netrc credentials and where
+/// file names come from. Start from [`HttpOptions::default`] and pass the
+/// same value, or clones of it, to every call of one run: clones share the
+/// per-host limiters and the hosts allowed the netrc `default` entry.
+///
+/// ```no_run
+/// # async fn run() -> torseed::Result<()> {
+/// use std::time::Duration;
+/// use torseed::http::{self, FilenameSource, HttpOptions};
+/// use torseed::TorrentBuilder;
+///
+/// let client = reqwest::Client::new();
+/// let options = HttpOptions::default()
+///     .max_retry_wait(Duration::from_secs(10))
+///     .host_limits(2, Duration::from_millis(250))
+///     .filename_order(&[FilenameSource::Path]);
+/// let url = "https://example.com/release.iso".parse().unwrap();
+/// let source = http::head_source(&client, &options, url).await?;
+/// let torrent = TorrentBuilder::new(source)
+///     .http_options(options)
+///     .trackers(["udp://tracker.example.org:1337/announce"])
+///     .build(&client)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    max_retry_wait: Duration,
+    pub(crate) read_timeout: Duration,
+    host_concurrency: usize,
+    host_delay: Duration,
+    host_limiters: Arc<Mutex<HashMap<String, Arc<HostLimiter>>>>,
+    netrc: Option<Arc<Netrc>>,
+    netrc_default_hosts: Arc<Mutex<Vec<String>>>,
+    filename_order: Vec<FilenameSource>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            max_retry_wait: Duration::from_secs(60),
+            read_timeout: Duration::from_secs(60),
+            host_concurrency: 4,
+            host_delay: Duration::ZERO,
+            host_limiters: Arc::default(),
+            netrc: None,
+            netrc_default_hosts: Arc::default(),
+            filename_order: DEFAULT_FILENAME_ORDER.to_vec(),
+        }
+    }
+}
+
+impl HttpOptions {
+    /// Limits how long a single request may wait in total when a server
+    /// answers 429 Too Many Requests or 503 Service Unavailable with
+    /// Retry-After. A request whose next wait would exceed the limit returns
+    /// the error answer instead. Zero disables retrying; the default is one
+    /// minute.
+    pub fn max_retry_wait(mut self, limit: Duration) -> Self {
+        self.max_retry_wait = limit;
+        self
+    }
+
+    /// Fails a payload download with [`TorseedError::Stream`] once it goes
+    /// this long without receiving a chunk. There is no limit on the download
+    /// as a whole: a slow transfer runs for as long as data keeps arriving,
+    /// while a stalled connection fails within `timeout`. The wait for the
+    /// response headers is left to the client's own timeouts. The default is
+    /// one minute.
+    ///
+    /// ```no_run
+    /// # async fn run(source: torseed::SourceMetadata) -> torseed::Result<()> {
+    /// use std::time::Duration;
+    /// use torseed::http::HttpOptions;
+    /// use torseed::TorrentBuilder;
+    ///
+    /// // Give up on a source that goes quiet for ten seconds.
+    /// let options = HttpOptions::default().read_timeout(Duration::from_secs(10));
+    /// let torrent = TorrentBuilder::new(source)
+    ///     .http_options(options)
+    ///     .trackers(["udp://tracker.example.org:1337/announce"])
+    ///     .build(&reqwest::Client::new())
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Limits the probe-style requests (HEAD probes of sources and webseeds,
+    /// tracker list fetches) sent to one host: at most `concurrency` at a
+    /// time, started at least `delay` apart. Payload downloads are not
+    /// limited. The default is 4 at a time, no delay.
+    pub fn host_limits(mut self, concurrency: usize, delay: Duration) -> Self {
+        self.host_concurrency = concurrency.max(1);
+        self.host_delay = delay;
+        self.host_limiters = Arc::default();
+        self
+    }
+
+    /// Sends basic auth from `netrc` with every request to a host it has a
+    /// `machine` entry for; `None` sends no credentials. Requests that
+    /// already carry an Authorization header keep it, and reqwest drops the
+    /// header when a redirect leaves the host.
+    pub fn netrc(mut self, netrc: Option<Netrc>) -> Self {
+        self.netrc = netrc.map(Arc::new);
+        self
+    }
+
+    /// Lets the netrc `default` entry go to the host of `url`, a source the
+    /// user named. Other hosts, webseed mirrors among them, only ever get
+    /// credentials from their own `machine` entry. Applies to every clone.
+    pub fn allow_netrc_default(&self, url: &Url) {
+        if let Some(host) = url.host_str() {
+            let mut hosts = self.netrc_default_hosts.lock().unwrap_or_else(|err| err.into_inner());
+            if !hosts.iter().any(|known| known.eq_ignore_ascii_case(host)) {
+                hosts.push(host.to_string());
+            }
+        }
+    }
+
+    /// Sets the order in which probed sources look for their file name.
+    /// Sources left out are not consulted; an empty list restores
+    /// [`DEFAULT_FILENAME_ORDER`].
+    pub fn filename_order(mut self, order: &[FilenameSource]) -> Self {
+        self.filename_order = if order.is_empty() { DEFAULT_FILENAME_ORDER.to_vec() } else { order.to_vec() };
+        self
+    }
+
+    /// Adds the netrc credentials for the host of `request`, if any.
+    fn with_netrc(&self, request: RequestBuilder) -> RequestBuilder {
+        let Some(netrc) = &self.netrc else {
+            return request;
+        };
+        let Some(built) = request.try_clone().and_then(|request| request.build().ok()) else {
+            return request;
+        };
+        let Some(host) = built.url().host_str() else {
+            return request;
+        };
+        if built.headers().contains_key(header::AUTHORIZATION) {
+            return request;
+        }
+        let credentials = netrc.machine(host).or_else(|| {
+            let hosts = self.netrc_default_hosts.lock().unwrap_or_else(|err| err.into_inner());
+            hosts
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(host))
+                .then(|| netrc.default_entry())
+                .flatten()
+        });
+        match credentials {
+            Some(credentials) => {
+                debug!("Sending netrc credentials to {host}");
+                request.basic_auth(&credentials.login, Some(&credentials.password))
+            }
+            None => request,
+        }
+    }
+
+    fn host_limiter(&self, host: String) -> Arc<HostLimiter> {
+        self.host_limiters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(host)
+            .or_insert_with(|| {
+                Arc::new(HostLimiter {
+                    permits: Semaphore::new(self.host_concurrency),
+                    next_start: tokio::sync::Mutex::new(tokio::time::Instant::now()),
+                })
+            })
+            .clone()
+    }
+}
+
+#[derive(Debug)]
+struct HostLimiter {
+    permits: Semaphore,
+    next_start: tokio::sync::Mutex<tokio::time::Instant>,
+}
+
+/// Sends a probe through the limiter of its host. The slot is held until the
+/// response headers arrive, Retry-After waits included.
+pub(crate) async fn send_probe(options: &HttpOptions, request: RequestBuilder) -> reqwest::Result<Response> {
+    let host = request
+        .try_clone()
+        .and_then(|request| request.build().ok())
+        .and_then(|request| request.url().host_str().map(str::to_string));
+    let Some(host) = host else {
+        return send_with_retry(options, request).await;
+    };
+    let limiter = options.host_limiter(host);
+    let _permit = limiter.permits.acquire().await.expect("host semaphore is never closed");
+    {
+        let mut next_start = limiter.next_start.lock().await;
+        tokio::time::sleep_until(*next_start).await;
+        *next_start = tokio::time::Instant::now() + options.host_delay;
+    }
+    send_with_retry(options, request).await
+}
+
+/// Sends `request`, repeating it while the server answers 429 or 503 with a
+/// Retry-After the remaining wait budget allows.
+pub(crate) async fn send_with_retry(options: &HttpOptions, request: RequestBuilder) -> reqwest::Result<Response> {
+    let request = options.with_netrc(request);
+    let budget = options.max_retry_wait;
+    let mut waited = Duration::ZERO;
+    loop {
+        // Requests without a streaming body always clone.
+        let Some(retry) = request.try_clone() else {
+            return request.send().await;
+        };
+        let response = retry.send().await?;
+        let status = response.status();
+        if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+            return Ok(response);
+        }
+        let delay = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, SystemTime::now()));
+        // A zero delay still waits a second, so a misbehaving server cannot spin us.
+        let Some(delay) = delay.map(|delay| delay.max(Duration::from_secs(1))) else {
+            return Ok(response);
+        };
+        if waited + delay > budget {
+            return Ok(response);
+        }
+        info!(
+            "{} answered {status}; retrying in {}",
+            response.url(),
+            humantime::format_duration(delay)
+        );
+        tokio::time::sleep(delay).await;
+        waited += delay;
+    }
+}
+
+/// Parses a Retry-After value, either delta-seconds or an HTTP-date, into the
+/// time left to wait from `now`. A date in the past means no wait.
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use torseed::http::parse_retry_after;
+///
+/// let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+/// assert_eq!(parse_retry_after("5", now), Some(Duration::from_secs(5)));
+/// assert_eq!(parse_retry_after(" 120 ", now), Some(Duration::from_secs(120)));
+/// assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:47 GMT", now), Some(Duration::from_secs(10)));
+/// assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:30 GMT", now), Some(Duration::ZERO));
+/// let later = now + Duration::from_millis(250);
+/// assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:47 GMT", later), Some(Duration::from_secs(10)));
+/// for nonsense in ["", "-1", "1.5", "soon", "Sun, 06 Foo 1994 08:49:47 GMT", "06 Nov 1994 08:49:47"] {
+///     assert_eq!(parse_retry_after(nonsense, now), None, "{nonsense}");
+/// }
+/// ```
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    // IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT", rewritten as RFC 3339.
+    let (_, date) = value.split_once(", ")?;
+    let mut parts = date.split(' ');
+    let (day, month, year, time, zone) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = MONTHS.iter().position(|name| *name == month)? + 1;
+    if zone != "GMT" || parts.next().is_some() {
+        return None;
+    }
+    let at = humantime::parse_rfc3339(&format!("{year}-{month:02}-{day}T{time}Z")).ok()?;
+    // Dates have whole-second precision; round up rather than retry early.
+    let wait = at.duration_since(now).unwrap_or_default();
+    Some(Duration::from_secs(wait.as_secs() + u64::from(wait.subsec_nanos() > 0)))
+}
+
+/// Reads the size and file name of `url` with a HEAD request, falling back
+/// to a one-byte GET when HEAD is not allowed.
+///
+/// Content-Disposition names that are not UTF-8 are read as ISO-8859-1.
+///
+/// ```no_run
+/// # async fn run() -> torseed::Result<()> {
+/// use torseed::http::{self, HttpOptions};
+///
+/// let url = "https://example.com/download?id=42".parse().unwrap();
+/// let source = http::head_source(&reqwest::Client::new(), &HttpOptions::default(), url).await?;
+/// println!("{} is {} bytes", source.filename, source.content_length);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn head_source(client: &Client, options: &HttpOptions, url: Url) -> Result<SourceMetadata> {
+    let response = send_probe(options, client.head(url.as_str()).timeout(Duration::from_secs(15)))
         .await
-        .with_context(|| format!("HEAD request failed for {url}"))?;
+        .map_err(|err| metadata_error(&url, format!("HEAD request failed for {url}"), Some(err)))?;
 
     if response.status() == StatusCode::METHOD_NOT_ALLOWED {
-        return fetch_via_get(client, url).await;
+        return fetch_via_get(client, options, url).await;
     }
 
     let status = response.status();
-    let response = response
-        .error_for_status()
-        .with_context(|| format!("HEAD request returned error status {} for {url}", status))?;
+    let response = response.error_for_status().map_err(|err| {
+        metadata_error(&url, format!("HEAD request returned error status {status} for {url}"), Some(err))
+    })?;
 
-    build_metadata(url, &response)
+    build_metadata(url, &response, &options.filename_order)
 }
 
-async fn fetch_via_get(client: &Client, url: Url) -> Result<SourceMetadata> {
+async fn fetch_via_get(client: &Client, options: &HttpOptions, url: Url) -> Result<SourceMetadata> {
     debug!("Falling back to GET metadata for {url}");
-    let response = client
+    let request = client
         .get(url.as_str())
         .header(header::RANGE, "bytes=0-0")
-        .timeout(Duration::from_secs(20))
-        .send()
+        .timeout(Duration::from_secs(20));
+    let response = send_probe(options, request)
         .await
-        .with_context(|| format!("GET fallback failed for {url}"))?;
+        .map_err(|err| metadata_error(&url, format!("GET fallback failed for {url}"), Some(err)))?;
 
     let status = response.status();
-    let response = response
-        .error_for_status()
-        .with_context(|| format!("GET fallback returned error status {} for {url}", status))?;
+    let response = response.error_for_status().map_err(|err| {
+        metadata_error(&url, format!("GET fallback returned error status {status} for {url}"), Some(err))
+    })?;
 
-    build_metadata(url, &response)
+    build_metadata(url, &response, &options.filename_order)
 }
 
-fn build_metadata(url: Url, response: &Response) -> Result<SourceMetadata> {
+fn build_metadata(url: Url, response: &Response, order: &[FilenameSource]) -> Result<SourceMetadata> {
     let headers = response.headers();
 
     let content_length = headers
@@ -60,49 +577,588 @@ fn build_metadata(url: Url, response: &Response) -> Result<SourceMetadata> {
         .and_then(|len| len.to_str().ok())
         .and_then(|len| len.parse::<u64>().ok());
 
-    let content_length = content_length
-        .or_else(|| parse_content_range(headers.get(header::CONTENT_RANGE)))
-        .with_context(|| format!("Missing Content-Length header for {url}"))?;
+    let Some(content_length) = content_length.or_else(|| parse_content_range(headers.get(header::CONTENT_RANGE)))
+    else {
+        return Err(metadata_error(&url, format!("Missing Content-Length header for {url}"), None));
+    };
 
-    let filename = infer_filename(&url, headers.get(header::CONTENT_DISPOSITION))?;
+    let disposition = headers
+        .get(header::CONTENT_DISPOSITION)
+        .map(|value| decode_header_text(value.as_bytes()));
+    let (suggested, from) = infer_filename(&url, response.url(), disposition.as_deref(), order);
+    match from {
+        Some(from) => debug!("File name {suggested:?} for {url} taken from the {}", from.describe()),
+        None => debug!("No file name found for {url}; using {suggested:?}, derived from the URL"),
+    }
+    let filename = sanitize_filename(&suggested);
+    let header_string = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
 
     Ok(SourceMetadata {
         url,
         content_length,
+        original_filename: (filename != suggested).then_some(suggested),
         filename,
+        etag: header_string(header::ETAG),
+        last_modified: header_string(header::LAST_MODIFIED),
     })
 }
 
-pub async fn stream(client: &Client, url: &Url) -> Result<Response> {
-    let response = client
+pub async fn stream(client: &Client, options: &HttpOptions, url: &Url) -> Result<Response> {
+    send_stream(options, url, stream_request(client, url)).await
+}
+
+/// Streams the source starting at `offset`. A non-zero offset sends a Range
+/// request guarded by If-Range so a changed source cannot be spliced in, and
+/// a `Content-Range` total other than the expected length fails with
+/// [`TorseedError::Resume`], since the source is then not the one the
+/// earlier bytes came from.
+///
+/// ```no_run
+/// # async fn run(source: torseed::SourceMetadata) -> torseed::Result<()> {
+/// use torseed::http::{self, HttpOptions};
+///
+/// // The first MiB is already on disk.
+/// let response = http::stream_from(&reqwest::Client::new(), &HttpOptions::default(), &source, 1 << 20).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn stream_from(
+    client: &Client,
+    options: &HttpOptions,
+    source: &SourceMetadata,
+    offset: u64,
+) -> Result<Response> {
+    resume_stream(options, source, stream_request(client, &source.url), offset).await
+}
+
+/// Like [`stream_from`], but asks for HTTP/3 first. HTTP/3 only exists for
+/// `https` URLs; when the QUIC handshake or request fails without an HTTP
+/// answer, or no answer arrives within [`HTTP3_ATTEMPT_TIMEOUT`], the
+/// download falls back to HTTP/2 or HTTP/1.1 over TCP.
+///
+/// ```no_run
+/// # async fn run(source: torseed::SourceMetadata) -> torseed::Result<()> {
+/// use torseed::http::{self, HttpOptions};
+///
+/// let response = http::stream_from_http3(&reqwest::Client::new(), &HttpOptions::default(), &source, 0).await?;
+/// println!("Downloading over {}", http::protocol_name(response.version()));
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "http3")]
+pub async fn stream_from_http3(
+    client: &Client,
+    options: &HttpOptions,
+    source: &SourceMetadata,
+    offset: u64,
+) -> Result<Response> {
+    if source.url.scheme() != "https" {
+        debug!("Not trying HTTP/3 for {}: it needs https", source.url);
+        return stream_from(client, options, source, offset).await;
+    }
+    let request = stream_request(client, &source.url).version(reqwest::Version::HTTP_3);
+    let attempt = resume_stream(options, source, request, offset);
+    let reason = match tokio::time::timeout(HTTP3_ATTEMPT_TIMEOUT, attempt).await {
+        Ok(Err(TorseedError::Stream { source: Some(err), .. })) if err.status().is_none() => err.to_string(),
+        Ok(result) => return result,
+        Err(_) => "no answer in time".to_string(),
+    };
+    tracing::warn!("HTTP/3 failed for {} ({reason}); falling back to HTTP/2 or HTTP/1.1", source.url);
+    stream_from(client, options, source, offset).await
+}
+
+/// How long [`stream_from_http3`] waits for an HTTP/3 answer before falling
+/// back. A server without QUIC usually just drops the UDP packets.
+#[cfg(feature = "http3")]
+pub const HTTP3_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Name of the HTTP version a response came over, as shown to people.
+pub fn protocol_name(version: reqwest::Version) -> &'static str {
+    match version {
+        reqwest::Version::HTTP_09 => "HTTP/0.9",
+        reqwest::Version::HTTP_10 => "HTTP/1.0",
+        reqwest::Version::HTTP_11 => "HTTP/1.1",
+        reqwest::Version::HTTP_2 => "HTTP/2",
+        reqwest::Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP",
+    }
+}
+
+fn stream_request(client: &Client, url: &Url) -> RequestBuilder {
+    client
         .get(url.clone())
         .header(header::ACCEPT_ENCODING, "identity")
-        .timeout(Duration::from_secs(900))
-        .send()
+}
+
+async fn send_stream(options: &HttpOptions, url: &Url, request: RequestBuilder) -> Result<Response> {
+    let response = send_with_retry(options, request)
         .await
-        .with_context(|| format!("GET request failed for {url}"))?;
+        .map_err(|err| stream_error(url, format!("GET request failed for {url}"), Some(err)))?;
 
     let status = response.status();
     response
         .error_for_status()
-        .with_context(|| format!("GET request returned error status {} for {url}", status))
+        .map_err(|err| stream_error(url, format!("GET request returned error status {status} for {url}"), Some(err)))
+}
+
+async fn resume_stream(
+    options: &HttpOptions,
+    source: &SourceMetadata,
+    mut request: RequestBuilder,
+    offset: u64,
+) -> Result<Response> {
+    let url = &source.url;
+    if offset == 0 {
+        return send_stream(options, url, request).await;
+    }
+
+    request = request.header(header::RANGE, format!("bytes={offset}-"));
+    if let Some(validator) = source.etag.as_ref().or(source.last_modified.as_ref()) {
+        request = request.header(header::IF_RANGE, validator);
+    }
+    let response = send_with_retry(options, request)
+        .await
+        .map_err(|err| stream_error(url, format!("GET request failed for {url}"), Some(err)))?;
+
+    let status = response.status();
+    if status != StatusCode::PARTIAL_CONTENT {
+        return Err(stream_error(
+            url,
+            format!("Expected 206 Partial Content when resuming {url} at byte {offset}, got {status}"),
+            None,
+        ));
+    }
+    if let Some(total) = parse_content_range(response.headers().get(header::CONTENT_RANGE))
+        && total != source.content_length
+    {
+        return Err(TorseedError::Resume(format!(
+            "{url} now has {total} bytes according to Content-Range, not the {} bytes hashing started with; \
+             the source changed, delete the resume file to start over",
+            source.content_length
+        )));
+    }
+    Ok(response)
+}
+
+/// Fetches `length` bytes starting at `offset` with a Range request.
+pub async fn fetch_range(client: &Client, options: &HttpOptions, url: &Url, offset: u64, length: u64) -> Result<Bytes> {
+    let end = offset + length.max(1) - 1;
+    let request = client
+        .get(url.clone())
+        .header(header::ACCEPT_ENCODING, "identity")
+        .header(header::RANGE, format!("bytes={offset}-{end}"))
+        .timeout(Duration::from_secs(60));
+    let response = send_with_retry(options, request)
+        .await
+        .map_err(|err| stream_error(url, format!("Range request failed for {url}"), Some(err)))?;
+
+    let status = response.status();
+    if status != StatusCode::PARTIAL_CONTENT {
+        return Err(stream_error(
+            url,
+            format!("Expected 206 Partial Content for bytes {offset}-{end} of {url}, got {status}"),
+            None,
+        ));
+    }
+    response
+        .bytes()
+        .await
+        .map_err(|err| stream_error(url, format!("Error while reading bytes {offset}-{end} of {url}"), Some(err)))
+}
+
+/// Outcome of probing one candidate webseed.
+#[derive(Debug, Clone)]
+pub struct WebseedCheck {
+    pub url: Url,
+    /// Why the mirror was left out; `None` when it was accepted.
+    pub rejection: Option<WebseedRejection>,
+    /// Probed for accepted mirrors only.
+    pub ranges: RangeSupport,
+}
+
+/// Whether a webseed honors Range requests. Clients fetch pieces with them,
+/// so a mirror that ignores them makes every piece cost a whole download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum RangeSupport {
+    Yes,
+    No,
+    /// Not probed, or the probe failed.
+    #[default]
+    Unknown,
+}
+
+impl RangeSupport {
+    /// `yes`, `no` or `unknown`, as shown in summaries and JSON.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Yes => "yes",
+            Self::No => "no",
+            Self::Unknown => "unknown",
+        }
+    }
 }
 
-fn infer_filename(url: &Url, disposition: Option<&header::HeaderValue>) -> Result<String> {
-    if let Some(value) = disposition.and_then(|hv| hv.to_str().ok()) {
-        if let Some(name) = parse_content_disposition(value) {
-            return Ok(sanitize_filename(&name));
+/// Asks `url` for its first byte. A 206 answer shows Range support and a
+/// plain 200 shows it is ignored; any other answer falls back on what the
+/// server's `Accept-Ranges` header claims. Shares the per-host limits of
+/// other probes.
+///
+/// ```no_run
+/// # async fn run() {
+/// use torseed::http::{self, HttpOptions, RangeSupport};
+///
+/// let url = "https://mirror.example.net/release.iso".parse().unwrap();
+/// if http::probe_ranges(&reqwest::Client::new(), &HttpOptions::default(), &url).await == RangeSupport::No {
+///     println!("{url} ignores Range requests");
+/// }
+/// # }
+/// ```
+pub async fn probe_ranges(client: &Client, options: &HttpOptions, url: &Url) -> RangeSupport {
+    let request = client
+        .get(url.as_str())
+        .header(header::RANGE, "bytes=0-0")
+        .header(header::ACCEPT_ENCODING, "identity")
+        .timeout(Duration::from_secs(15));
+    let response = match send_probe(options, request).await {
+        Ok(response) => response,
+        Err(err) => {
+            debug!("Range probe of {url} failed: {err}");
+            return RangeSupport::Unknown;
+        }
+    };
+    // A 200 is dropped unread; only its status matters.
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => RangeSupport::Yes,
+        StatusCode::OK => RangeSupport::No,
+        status => {
+            let accept = response
+                .headers()
+                .get(header::ACCEPT_RANGES)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_ascii_lowercase());
+            debug!("Range probe of {url} answered {status}, Accept-Ranges {accept:?}");
+            match accept.as_deref() {
+                Some("bytes") => RangeSupport::Yes,
+                Some("none") => RangeSupport::No,
+                _ => RangeSupport::Unknown,
+            }
         }
     }
+}
 
-    let path = url
-        .path_segments()
-        .and_then(|mut segments| segments.next_back())
-        .filter(|segment| !segment.is_empty())
-        .map(|segment| segment.to_string())
-        .unwrap_or_else(|| url.domain().unwrap_or("download").to_string());
+/// Why [`verify_webseeds`] left a mirror out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum WebseedRejection {
+    /// The mirror serves a different number of bytes than the source.
+    LengthMismatch { expected: u64, actual: u64 },
+    /// The mirror answered with an error status.
+    HttpStatus { status: u16 },
+    Timeout,
+    /// The host name did not resolve.
+    Dns { message: String },
+    /// The connection was refused or broke off, including TLS failures.
+    Connect { message: String },
+    /// Left out by `--require-ranges`: the mirror does not honor Range
+    /// requests, or could not be shown to.
+    Ranges { support: RangeSupport },
+    /// Anything else, such as a response without a usable length.
+    Other { message: String },
+}
 
-    Ok(sanitize_filename(&path))
+impl WebseedRejection {
+    fn from_error(err: &TorseedError) -> Self {
+        let TorseedError::Metadata {
+            source: Some(source), ..
+        } = err
+        else {
+            return Self::Other {
+                message: err.to_string(),
+            };
+        };
+        if let Some(status) = source.status() {
+            return Self::HttpStatus {
+                status: status.as_u16(),
+            };
+        }
+        if source.is_timeout() {
+            return Self::Timeout;
+        }
+        // reqwest does not classify resolver failures; hyper's connector
+        // labels them in the error chain.
+        let mut chain = std::iter::successors(Some(source as &dyn std::error::Error), |err| err.source());
+        let message = chain
+            .clone()
+            .last()
+            .map_or_else(|| source.to_string(), ToString::to_string);
+        if chain.any(|err| err.to_string() == "dns error") {
+            return Self::Dns { message };
+        }
+        if source.is_connect() {
+            return Self::Connect { message };
+        }
+        Self::Other {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for WebseedRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LengthMismatch { expected, actual } => {
+                write!(f, "length mismatch: serves {actual} bytes, expected {expected}")
+            }
+            Self::HttpStatus { status } => {
+                let code = *status;
+                let status = StatusCode::from_u16(code).map_or_else(|_| code.to_string(), |status| status.to_string());
+                write!(f, "HTTP {status}")
+            }
+            Self::Timeout => f.write_str("timed out"),
+            Self::Dns { message } => write!(f, "DNS lookup failed: {message}"),
+            Self::Connect { message } => write!(f, "connection failed: {message}"),
+            Self::Ranges {
+                support: RangeSupport::Unknown,
+            } => f.write_str("Range support unknown"),
+            Self::Ranges { .. } => f.write_str("ignores Range requests"),
+            Self::Other { message } => f.write_str(message),
+        }
+    }
+}
+
+/// Probes extra mirrors concurrently and checks that each serves exactly
+/// `expected_length` bytes, reporting each outcome to `events`. Accepted
+/// mirrors are then checked with [`probe_ranges`]. Results keep
+/// the order of `urls`, accepted or not. Probes still in flight are dropped
+/// when `cancel` fires.
+pub async fn verify_webseeds(
+    client: &Client,
+    options: &HttpOptions,
+    expected_length: u64,
+    urls: Vec<Url>,
+    events: &EventSink,
+    cancel: &CancellationToken,
+) -> Result<Vec<WebseedCheck>> {
+    let mut checks = Vec::with_capacity(urls.len());
+    let mut tasks = FuturesUnordered::new();
+    for (index, url) in urls.into_iter().enumerate() {
+        let (client, options) = (client.clone(), options.clone());
+        tasks.push(async move {
+            let result = head_source(&client, &options, url.clone()).await;
+            let ranges = match &result {
+                Ok(meta) if meta.content_length == expected_length => probe_ranges(&client, &options, &url).await,
+                _ => RangeSupport::Unknown,
+            };
+            (index, url, result, ranges)
+        });
+    }
+
+    let mut now = Instant::now();
+    while let Some((index, url, result, ranges)) = cancel
+        .run_until_cancelled(tasks.next())
+        .await
+        .ok_or(TorseedError::Cancelled)?
+    {
+        let rejection = match result {
+            Ok(meta) if meta.content_length == expected_length => None,
+            Ok(meta) => Some(WebseedRejection::LengthMismatch {
+                expected: expected_length,
+                actual: meta.content_length,
+            }),
+            Err(err) => Some(WebseedRejection::from_error(&err)),
+        };
+        match &rejection {
+            None => events.emit(Event::WebseedVerified { url: url.clone() }),
+            Some(rejection) => {
+                debug!("Rejected webseed {url}: {rejection}");
+                events.emit(Event::WebseedRejected {
+                    url: url.clone(),
+                    reason: rejection.to_string(),
+                });
+            }
+        }
+        checks.push((index, WebseedCheck { url, rejection, ranges }));
+        if now.elapsed() > Duration::from_secs(10) {
+            info!("Checked {} webseeds", checks.len());
+            now = Instant::now();
+        }
+    }
+
+    checks.sort_by_key(|(index, _)| *index);
+    Ok(checks.into_iter().map(|(_, check)| check).collect())
+}
+
+/// Where the file name of a source may come from. See [`infer_filename`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FilenameSource {
+    /// The `filename*=` or `filename=` of Content-Disposition.
+    Disposition,
+    /// The last path segment of the URL a redirect ended at.
+    Redirect,
+    /// A `filename=`, `file=` or similar query parameter.
+    Query,
+    /// The last non-empty path segment of the requested URL.
+    Path,
+    /// The host name, unless it is an IP address.
+    Host,
+}
+
+impl FilenameSource {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Disposition => "Content-Disposition header",
+            Self::Redirect => "redirect target",
+            Self::Query => "query string",
+            Self::Path => "URL path",
+            Self::Host => "host name",
+        }
+    }
+}
+
+/// The order [`head_source`] tries [`FilenameSource`]s in unless
+/// [`HttpOptions::filename_order`] changes it.
+pub const DEFAULT_FILENAME_ORDER: [FilenameSource; 5] = [
+    FilenameSource::Disposition,
+    FilenameSource::Redirect,
+    FilenameSource::Query,
+    FilenameSource::Path,
+    FilenameSource::Host,
+];
+
+/// Query parameters that name the file, e.g. `?file=release.iso`.
+/// `response-content-disposition` is how S3-style presigned URLs carry it.
+const FILENAME_QUERY_PARAMS: [&str; 4] = ["filename", "file", "name", "response-content-disposition"];
+
+/// Picks a file name for a download of `requested` that ended at `landed`
+/// after redirects, trying `order` in turn and returning the source that
+/// answered. The redirect step only applies when `landed` differs from
+/// `requested`. When nothing fits, the name is derived from a hash of the
+/// URL so different sources do not collide. Not yet sanitized.
+///
+/// ```
+/// use torseed::http::{infer_filename, FilenameSource, DEFAULT_FILENAME_ORDER};
+///
+/// let url = |url: &str| url.parse::<url::Url>().unwrap();
+/// let infer = |requested: &str, landed: &str, disposition: Option<&str>| {
+///     infer_filename(&url(requested), &url(landed), disposition, &DEFAULT_FILENAME_ORDER)
+/// };
+/// let download = "https://example.com/download?id=1234";
+///
+/// // Content-Disposition wins, including RFC 5987 names.
+/// let disposition = Some("attachment; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf");
+/// assert_eq!(infer(download, download, disposition), ("résumé.pdf".into(), Some(FilenameSource::Disposition)));
+/// // RFC 5987 names in legacy charsets are decoded; an unknown charset falls back to `filename`.
+/// let shift_jis = Some("attachment; filename*=Shift_JIS''%83e%83X%83g.txt");
+/// assert_eq!(infer(download, download, shift_jis).0, "テスト.txt");
+/// let unknown = Some("attachment; filename=\"plain.pdf\"; filename*=x-klingon''r%E9sum%E9.pdf");
+/// assert_eq!(infer(download, download, unknown).0, "plain.pdf");
+/// // Then the path a redirect landed on.
+/// let landed = "https://cdn.example.com/pool/tool-1.2.tar.gz?sig=abc";
+/// assert_eq!(infer(download, landed, None), ("tool-1.2.tar.gz".into(), Some(FilenameSource::Redirect)));
+/// // Then a query parameter naming the file.
+/// let query = "https://example.com/get?file=dir%2Fdata.zip&id=7";
+/// assert_eq!(infer(query, query, None), ("data.zip".into(), Some(FilenameSource::Query)));
+/// let presigned = "https://s3.example.com/obj?response-content-disposition=attachment%3B%20filename%3D%22a.iso%22";
+/// assert_eq!(infer(presigned, presigned, None), ("a.iso".into(), Some(FilenameSource::Query)));
+/// // Then the last non-empty path segment.
+/// assert_eq!(infer(download, download, None), ("download".into(), Some(FilenameSource::Path)));
+/// let folder = "https://example.com/files/";
+/// assert_eq!(infer(folder, folder, None), ("files".into(), Some(FilenameSource::Path)));
+/// // Then the host.
+/// let root = "https://example.com/";
+/// assert_eq!(infer(root, root, None), ("example.com".into(), Some(FilenameSource::Host)));
+/// // And finally a hash of the URL, which differs between URLs.
+/// let (name, from) = infer("http://10.0.0.1/", "http://10.0.0.1/", None);
+/// assert!(name.starts_with("download-") && from.is_none(), "{name}");
+/// assert_ne!(name, infer("http://10.0.0.2/", "http://10.0.0.2/", None).0);
+///
+/// // Sources left out of the order are skipped.
+/// let (name, from) = infer_filename(&url(download), &url(landed), None, &[FilenameSource::Path]);
+/// assert_eq!((name.as_str(), from), ("download", Some(FilenameSource::Path)));
+/// ```
+pub fn infer_filename(
+    requested: &Url,
+    landed: &Url,
+    disposition: Option<&str>,
+    order: &[FilenameSource],
+) -> (String, Option<FilenameSource>) {
+    for &source in order {
+        let name = match source {
+            FilenameSource::Disposition => disposition.and_then(parse_content_disposition),
+            FilenameSource::Redirect if landed != requested => last_segment(landed),
+            FilenameSource::Redirect => None,
+            FilenameSource::Query => requested
+                .query_pairs()
+                .find(|(key, _)| FILENAME_QUERY_PARAMS.iter().any(|param| key.eq_ignore_ascii_case(param)))
+                .and_then(|(key, value)| {
+                    if key.eq_ignore_ascii_case("response-content-disposition") {
+                        parse_content_disposition(&value)
+                    } else {
+                        Some(value.into_owned())
+                    }
+                })
+                .and_then(|value| value.rsplit(['/', '\\']).next().map(str::to_string))
+                .filter(|name| !name.is_empty()),
+            FilenameSource::Path => last_segment(requested),
+            FilenameSource::Host => requested.domain().map(str::to_string),
+        };
+        if let Some(name) = name {
+            return (name, Some(source));
+        }
+    }
+    let hash = <sha1::Sha1 as sha1::Digest>::digest(requested.as_str().as_bytes());
+    (format!("download-{}", &hex::encode(hash)[..12]), None)
+}
+
+fn last_segment(url: &Url) -> Option<String> {
+    url.path_segments()?
+        .rev()
+        .find(|segment| !segment.is_empty())
+        .map(|segment| percent_encoding::percent_decode_str(segment).decode_utf8_lossy().into_owned())
+}
+
+fn metadata_error(url: &Url, message: String, source: Option<reqwest::Error>) -> TorseedError {
+    TorseedError::Metadata {
+        url: url.clone(),
+        message,
+        source,
+    }
+}
+
+pub(crate) fn stream_error(url: &Url, message: String, source: Option<reqwest::Error>) -> TorseedError {
+    TorseedError::Stream {
+        url: url.clone(),
+        message,
+        source,
+    }
+}
+
+/// Decodes the raw bytes of a header value. RFC 9110 reads bytes outside
+/// ASCII as ISO-8859-1, but many servers send UTF-8, so valid UTF-8 is taken
+/// as such and anything else as ISO-8859-1.
+///
+/// ```
+/// use torseed::http::decode_header_text;
+///
+/// assert_eq!(decode_header_text(b"attachment; filename=plain.txt"), "attachment; filename=plain.txt");
+/// assert_eq!(decode_header_text(b"filename=\"caf\xc3\xa9.txt\""), "filename=\"café.txt\"");
+/// assert_eq!(decode_header_text(b"filename=\"caf\xe9.txt\""), "filename=\"café.txt\"");
+/// assert_eq!(decode_header_text(b"filename=\"\xc6sop \xbd.txt\""), "filename=\"Æsop ½.txt\"");
+/// ```
+pub fn decode_header_text(bytes: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => Cow::Owned(bytes.iter().map(|&byte| char::from(byte)).collect()),
+    }
 }
 
 fn parse_content_disposition(header_value: &str) -> Option<String> {
@@ -121,13 +1177,19 @@ fn parse_content_disposition(header_value: &str) -> Option<String> {
     filename
 }
 
+/// Decodes an RFC 5987 `charset'language'value`. Any charset the WHATWG
+/// Encoding Standard knows is accepted, so legacy names such as `Shift_JIS`
+/// or `windows-1251` work too; an unknown charset or bytes invalid in it give
+/// `None`.
 fn parse_rfc5987(value: &str) -> Option<String> {
-    let mut sections = value.splitn(3, '\'');
-    let _charset = sections.next()?;
+    let mut sections = value.trim().splitn(3, '\'');
+    let charset = sections.next()?;
     let _lang = sections.next();
     let encoded = sections.next()?;
-    let decoded = percent_encoding::percent_decode_str(encoded).decode_utf8().ok()?;
-    Some(decoded.to_string())
+    let bytes: Vec<u8> = percent_encoding::percent_decode_str(encoded).collect();
+    let encoding = encoding_rs::Encoding::for_label_no_replacement(charset.trim().as_bytes())?;
+    let decoded = encoding.decode_without_bom_handling_and_without_replacement(&bytes)?;
+    Some(decoded.into_owned())
 }
 
 fn strip_quotes(value: &str) -> Option<String> {
@@ -155,3 +1217,99 @@ fn parse_content_range(value: Option<&header::HeaderValue>) -> Option<u64> {
     }
     total.parse::<u64>().ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{response, serve};
+
+    fn redirect(location: &str) -> Vec<u8> {
+        format!("HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .into_bytes()
+    }
+
+    fn redirecting_client(max_redirects: usize) -> Client {
+        let options = RedirectOptions { max_redirects, allow_insecure: false };
+        Client::builder().redirect(options.policy()).build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_legacy_disposition_names_as_latin_1() {
+        let dispositions: [&[u8]; 3] = [
+            b"attachment; filename=\"Caf\xe9 menu?.pdf\"",
+            b"attachment; filename=\"Caf\xc3\xa9 menu?.pdf\"",
+            b"attachment; filename=\"x.pdf\"; filename*=iso-8859-1''Caf%E9%20menu%3F.pdf",
+        ];
+        for disposition in dispositions {
+            let mut answer = b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n".to_vec();
+            answer.extend_from_slice(b"Content-Disposition: ");
+            answer.extend_from_slice(disposition);
+            answer.extend_from_slice(b"\r\n\r\n");
+            let url = serve(vec![answer]).await;
+            let source = head_source(&Client::new(), &HttpOptions::default(), url).await.unwrap();
+            assert_eq!(source.filename, "Café menu_.pdf");
+            assert_eq!(source.original_filename.as_deref(), Some("Café menu?.pdf"));
+        }
+    }
+
+    #[tokio::test]
+    async fn resuming_needs_the_same_total_length() {
+        let mut answer = b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 100-199/5000\r\n".to_vec();
+        answer.extend_from_slice(b"Content-Length: 100\r\nConnection: close\r\n\r\n");
+        answer.extend_from_slice(&[0; 100]);
+        let source = SourceMetadata {
+            url: serve(vec![answer]).await,
+            content_length: 200,
+            filename: "data.bin".to_string(),
+            original_filename: None,
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        };
+        let result = stream_from(&Client::new(), &HttpOptions::default(), &source, 100).await;
+        let Err(TorseedError::Resume(message)) = result else { panic!("{result:?}") };
+        assert!(message.contains("5000 bytes"), "{message}");
+    }
+
+    #[cfg(feature = "http3")]
+    #[tokio::test]
+    async fn http3_falls_back_for_plain_http() {
+        // Plain `http` has no QUIC variant.
+        let source = SourceMetadata {
+            url: serve(vec![response(Some(3), b"abc")]).await,
+            content_length: 3,
+            filename: "data.bin".to_string(),
+            original_filename: None,
+            etag: None,
+            last_modified: None,
+        };
+        let streamed = stream_from_http3(&Client::new(), &HttpOptions::default(), &source, 0).await.unwrap();
+        assert_eq!(protocol_name(streamed.version()), "HTTP/1.1");
+        assert_eq!(&streamed.bytes().await.unwrap()[..], b"abc");
+    }
+
+    #[tokio::test]
+    async fn probes_range_support() {
+        let mut ranged = b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-0/3\r\n".to_vec();
+        ranged.extend_from_slice(b"Content-Length: 1\r\nConnection: close\r\n\r\na");
+        let url = serve(vec![ranged, response(Some(3), b"abc")]).await;
+        let client = Client::new();
+        let options = HttpOptions::default();
+        assert_eq!(probe_ranges(&client, &options, &url).await, RangeSupport::Yes);
+        assert_eq!(probe_ranges(&client, &options, &url).await, RangeSupport::No);
+    }
+
+    #[tokio::test]
+    async fn redirect_chains_stop_at_the_cap() {
+        // Three hops before the answer.
+        let chain = || vec![redirect("/2"), redirect("/1"), redirect("/0"), response(Some(3), b"abc")];
+        let options = HttpOptions::default();
+
+        let url = serve(chain()).await;
+        let source = head_source(&redirecting_client(3), &options, url).await.unwrap();
+        assert_eq!(source.content_length, 3);
+
+        let url = serve(chain()).await;
+        let err = head_source(&redirecting_client(2), &options, url).await.unwrap_err();
+        assert!(!err.is_transient(), "{err}");
+    }
+}
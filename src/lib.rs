@@ -0,0 +1,134 @@
+//! Create hybrid (v1 + v2) BitTorrent metainfo from HTTP sources.
+//!
+//! The payload is streamed once and never written to disk: both hashers are
+//! fed from the same download, and the source URL becomes a webseed so
+//! clients can fetch from it directly. [`TorrentBuilder`] is the entry point;
+//! the modules below expose the individual steps for callers that need more
+//! control.
+//!
+#![cfg_attr(feature = "http", doc = "```no_run")]
+#![cfg_attr(not(feature = "http"), doc = "```ignore")]
+//! # async fn run() -> anyhow::Result<()> {
+//! use torseed::{build_magnets, MagnetOptions, TorrentBuilder};
+//!
+//! let client = reqwest::Client::new();
+//! let url = "https://example.com/release.iso".parse()?;
+//! let torrent = TorrentBuilder::from_url(&client, url)
+//!     .await?
+//!     .trackers(["udp://tracker.example.org:1337/announce"])
+//!     .build(&client)
+//!     .await?;
+//!
+//! let magnets = build_magnets(
+//!     &torrent.input.name,
+//!     Some(torrent.input.length),
+//!     &torrent.input.announce_tiers.concat(),
+//!     &torrent.input.webseeds,
+//!     torrent.metainfo.infohash_v1,
+//!     torrent.metainfo.infohash_v2,
+//!     &MagnetOptions::default(),
+//! );
+//! println!("{}", magnets[0]);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Downloading, tracker lists and the types built on them sit behind the
+//! `http` feature. The default `cli` feature adds it along with the binary's
+//! own dependencies, so library users usually want `default-features = false,
+//! features = ["http"]`. Without `http` the crate keeps only what needs no
+//! network: the hashers, [`metainfo`], [`magnet`] and [`util`], without
+//! reqwest or Tokio.
+//!
+//! ```
+//! use torseed::{build_magnets, metainfo, BuildInput, MagnetOptions, V1Hasher, V2Hasher};
+//!
+//! let data = vec![0u8; 40_000];
+//! let (mut v1, mut v2) = (V1Hasher::new(16_384), V2Hasher::new(16_384));
+//! v1.update(&data);
+//! v2.update(&data);
+//! let input = BuildInput {
+//!     name: "data.bin".to_string(),
+//!     length: data.len() as u64,
+//!     piece_length: 16_384,
+//!     pieces: v1.finalize(),
+//!     announce_tiers: vec![vec!["udp://tracker.example.org:1337/announce".to_string()]],
+//!     webseeds: Vec::new(),
+//!     creation_date: 1_700_000_000,
+//!     created_by: String::new(),
+//!     name_utf8: false,
+//!     v2: Some(v2.finalize()),
+//!     files: Vec::new(),
+//! };
+//! let torrent = metainfo::build(&input)?;
+//! let magnets = build_magnets(
+//!     &input.name,
+//!     Some(input.length),
+//!     &input.announce_tiers.concat(),
+//!     &input.webseeds,
+//!     torrent.infohash_v1,
+//!     torrent.infohash_v2,
+//!     &MagnetOptions::default(),
+//! );
+//! assert!(magnets[0].starts_with("magnet:?xt=urn:btih:"));
+//! # Ok::<(), torseed::TorseedError>(())
+//! ```
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "http")]
+mod builder;
+#[cfg(feature = "http")]
+pub mod cache;
+pub mod checksum;
+#[cfg(feature = "http")]
+pub mod convert;
+mod digest;
+mod error;
+pub mod expand;
+pub mod hash_v1;
+pub mod hash_v2;
+#[cfg(feature = "serde")]
+mod hex_serde;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod magnet;
+pub mod metainfo;
+pub mod netrc;
+pub mod passkey;
+pub mod pieces;
+#[cfg(feature = "http")]
+pub mod progress;
+#[cfg(feature = "http")]
+mod resume;
+#[cfg(feature = "http")]
+pub mod summary;
+#[cfg(all(test, feature = "http"))]
+mod testing;
+#[cfg(feature = "http")]
+pub mod tracker_client;
+#[cfg(feature = "http")]
+pub mod trackers;
+#[cfg(feature = "http")]
+pub mod transmission;
+pub mod util;
+#[cfg(feature = "http")]
+pub mod verify;
+
+#[cfg(feature = "http")]
+pub use builder::{FilePart, Torrent, TorrentBuilder};
+pub use error::{Result, TorseedError};
+pub use hash_v1::V1Hasher;
+pub use hash_v2::{V2Hasher, V2Summary};
+#[cfg(feature = "http")]
+pub use http::{head_source, verify_webseeds, SourceMetadata, WebseedCheck, WebseedRejection};
+pub use magnet::{build_magnets, MagnetOptions};
+pub use metainfo::{BuildFile, BuildInput, Metainfo, ParsedTorrent};
+#[cfg(feature = "http")]
+pub use progress::{Event, EventSink};
+#[cfg(feature = "http")]
+pub use summary::BuildSummary;
+#[cfg(feature = "http")]
+pub use trackers::{gather_trackers, GatheredTrackers, TrackerOptions};
+#[cfg(feature = "http")]
+pub use tokio_util::sync::CancellationToken;
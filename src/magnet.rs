@@ -1,4 +1,10 @@
-use percent_encoding::{percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use data_encoding::BASE32_NOPAD;
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::error::{Result, TorseedError};
+use crate::passkey;
 
 const MAGNET_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
     .remove(b'-')
@@ -6,41 +12,205 @@ const MAGNET_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
     .remove(b'.')
     .remove(b'~');
 
+/// Encoding of the v1 infohash in `urn:btih:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum HashFormat {
+    /// 40 hexadecimal characters.
+    Hex,
+    /// 32 base32 characters, for older clients and indexers.
+    Base32,
+    /// One magnet of each form.
+    Both,
+}
+
+/// How hybrid torrents are represented as magnets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum MagnetStyle {
+    /// One btih magnet and one btmh magnet.
+    Separate,
+    /// A single magnet carrying both xt parameters.
+    Combined,
+    /// The combined magnet followed by the separate ones.
+    Both,
+}
+
+/// Knobs controlling which parameters end up in generated magnets.
+#[derive(Debug, Clone)]
+pub struct MagnetOptions {
+    /// Maximum number of `tr=` parameters, taken from the front of the
+    /// tracker list. Zero produces tracker-free magnets.
+    pub max_trackers: usize,
+    pub hash_format: HashFormat,
+    pub style: MagnetStyle,
+    /// Published .torrent URL emitted as `xs=`.
+    pub torrent_url: Option<String>,
+    /// Direct download URL emitted as `as=`.
+    pub direct_source: Option<String>,
+    /// Peer address hints emitted as `x.pe=` parameters.
+    pub peers: Vec<String>,
+    /// Normalized BEP 53 file selection emitted as `so=`.
+    pub select_only: Option<String>,
+    /// Keep trackers whose announce URL carries a passkey (see
+    /// [`passkey::has_passkey`]); they are left out by default, since
+    /// magnets get shared.
+    pub passkey_trackers: bool,
+}
+
+impl Default for MagnetOptions {
+    /// The CLI defaults: up to 30 trackers, hex btih, one combined magnet.
+    fn default() -> Self {
+        Self {
+            max_trackers: 30,
+            hash_format: HashFormat::Hex,
+            style: MagnetStyle::Combined,
+            torrent_url: None,
+            direct_source: None,
+            peers: Vec::new(),
+            select_only: None,
+            passkey_trackers: false,
+        }
+    }
+}
+
+/// Builds one magnet per requested infohash form. `exact_length` becomes the
+/// `xl=` parameter and should be `None` for multi-file torrents.
+///
+/// ```
+/// use torseed::magnet::{build_magnets, HashFormat, MagnetOptions, MagnetStyle};
+///
+/// let options = MagnetOptions {
+///     max_trackers: 1,
+///     hash_format: HashFormat::Hex,
+///     style: MagnetStyle::Separate,
+///     ..MagnetOptions::default()
+/// };
+/// let magnets = build_magnets(
+///     "file.bin",
+///     Some(1024),
+///     &["udp://tracker.example.org:1337/announce".to_string()],
+///     &[],
+///     Some([0xab; 20]),
+///     None,
+///     &options,
+/// );
+/// assert_eq!(
+///     magnets,
+///     [format!(
+///         "magnet:?xt=urn:btih:{}&dn=file.bin&xl=1024&tr=udp%3A%2F%2Ftracker.example.org%3A1337%2Fannounce",
+///         "ab".repeat(20)
+///     )]
+/// );
+/// ```
 pub fn build_magnets(
     name: &str,
+    exact_length: Option<u64>,
     trackers: &[String],
     webseeds: &[String],
     infohash_v1: Option<[u8; 20]>,
     infohash_v2: Option<[u8; 32]>,
+    options: &MagnetOptions,
 ) -> Vec<String> {
+    let btih_hashes: Vec<String> = infohash_v1
+        .map(|hash| {
+            let mut encoded = Vec::new();
+            if matches!(options.hash_format, HashFormat::Hex | HashFormat::Both) {
+                encoded.push(hex::encode(hash));
+            }
+            if matches!(options.hash_format, HashFormat::Base32 | HashFormat::Both) {
+                encoded.push(BASE32_NOPAD.encode(&hash));
+            }
+            encoded
+        })
+        .unwrap_or_default();
+
     let mut magnets = Vec::new();
-    if let Some(hash) = infohash_v1 {
-        magnets.push(build_btih(name, trackers, webseeds, &hash));
+    if let Some(v2) = &infohash_v2
+        && !btih_hashes.is_empty()
+        && matches!(options.style, MagnetStyle::Combined | MagnetStyle::Both)
+    {
+        for btih in &btih_hashes {
+            magnets.push(build_hybrid(name, exact_length, trackers, webseeds, btih, v2, options));
+        }
+        if options.style == MagnetStyle::Combined {
+            return magnets;
+        }
+    }
+
+    for btih in &btih_hashes {
+        magnets.push(build_btih(name, exact_length, trackers, webseeds, btih, options));
     }
     if let Some(hash) = infohash_v2 {
-        magnets.push(build_btmh(name, trackers, webseeds, &hash));
+        magnets.push(build_btmh(name, exact_length, trackers, webseeds, &hash, options));
     }
     magnets
 }
 
-fn build_btih(name: &str, trackers: &[String], webseeds: &[String], hash: &[u8; 20]) -> String {
-    let mut magnet = format!("magnet:?xt=urn:btih:{}", hex::encode(hash));
-    append_common(&mut magnet, name, trackers, webseeds);
+/// Builds a BEP 52 hybrid magnet with both the btih and btmh exact topics.
+fn build_hybrid(
+    name: &str,
+    exact_length: Option<u64>,
+    trackers: &[String],
+    webseeds: &[String],
+    btih: &str,
+    btmh: &[u8; 32],
+    options: &MagnetOptions,
+) -> String {
+    let mut magnet = format!("magnet:?xt=urn:btih:{btih}&xt=urn:btmh:1220");
+    magnet.push_str(&hex::encode(btmh));
+    append_common(&mut magnet, name, exact_length, trackers, webseeds, options);
     magnet
 }
 
-fn build_btmh(name: &str, trackers: &[String], webseeds: &[String], hash: &[u8; 32]) -> String {
+fn build_btih(
+    name: &str,
+    exact_length: Option<u64>,
+    trackers: &[String],
+    webseeds: &[String],
+    hash: &str,
+    options: &MagnetOptions,
+) -> String {
+    let mut magnet = format!("magnet:?xt=urn:btih:{hash}");
+    append_common(&mut magnet, name, exact_length, trackers, webseeds, options);
+    magnet
+}
+
+fn build_btmh(
+    name: &str,
+    exact_length: Option<u64>,
+    trackers: &[String],
+    webseeds: &[String],
+    hash: &[u8; 32],
+    options: &MagnetOptions,
+) -> String {
     let mut magnet = String::from("magnet:?xt=urn:btmh:1220");
     magnet.push_str(&hex::encode(hash));
-    append_common(&mut magnet, name, trackers, webseeds);
+    append_common(&mut magnet, name, exact_length, trackers, webseeds, options);
     magnet
 }
 
-fn append_common(magnet: &mut String, name: &str, trackers: &[String], webseeds: &[String]) {
+/// Appends the shared parameters in a fixed order: dn, xl, tr, ws, xs, as, x.pe, so.
+fn append_common(
+    magnet: &mut String,
+    name: &str,
+    exact_length: Option<u64>,
+    trackers: &[String],
+    webseeds: &[String],
+    options: &MagnetOptions,
+) {
     magnet.push_str("&dn=");
     magnet.push_str(&encode_component(name));
 
-    for tracker in trackers {
+    if let Some(length) = exact_length {
+        magnet.push_str("&xl=");
+        magnet.push_str(&length.to_string());
+    }
+
+    let shareable = trackers
+        .iter()
+        .filter(|tracker| options.passkey_trackers || !passkey::has_passkey(tracker));
+    for tracker in shareable.take(options.max_trackers) {
         magnet.push_str("&tr=");
         magnet.push_str(&encode_component(tracker));
     }
@@ -49,8 +219,446 @@ fn append_common(magnet: &mut String, name: &str, trackers: &[String], webseeds:
         magnet.push_str("&ws=");
         magnet.push_str(&encode_component(ws));
     }
+
+    if let Some(url) = &options.torrent_url {
+        magnet.push_str("&xs=");
+        magnet.push_str(&encode_component(url));
+    }
+
+    if let Some(url) = &options.direct_source {
+        magnet.push_str("&as=");
+        magnet.push_str(&encode_component(url));
+    }
+
+    for peer in &options.peers {
+        magnet.push_str("&x.pe=");
+        magnet.push_str(&encode_component(peer));
+    }
+
+    if let Some(select) = &options.select_only {
+        magnet.push_str("&so=");
+        magnet.push_str(select);
+    }
+}
+
+/// Parses a BEP 53 select-only spec such as `0,2-4` and returns it with
+/// ranges sorted, merged and checked against the torrent's file count.
+pub fn parse_select_spec(spec: &str, file_count: usize) -> Result<String> {
+    if file_count < 2 {
+        return Err(invalid("--magnet-select only applies to multi-file torrents"));
+    }
+
+    let mut ranges = Vec::new();
+    for part in spec.split(',').map(str::trim) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (parse_file_index(start, part)?, parse_file_index(end, part)?),
+            None => {
+                let index = parse_file_index(part, part)?;
+                (index, index)
+            }
+        };
+        if start > end {
+            return Err(TorseedError::InvalidInput(format!("Descending file range {part:?} in --magnet-select")));
+        }
+        if end >= file_count {
+            return Err(TorseedError::InvalidInput(format!(
+                "File index {end} in --magnet-select is out of range (torrent has {file_count} files)"
+            )));
+        }
+        ranges.push((start, end));
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let parts: Vec<String> = merged
+        .into_iter()
+        .map(|(start, end)| if start == end { start.to_string() } else { format!("{start}-{end}") })
+        .collect();
+    Ok(parts.join(","))
+}
+
+fn parse_file_index(value: &str, part: &str) -> Result<usize> {
+    value
+        .trim()
+        .parse()
+        .map_err(|err| TorseedError::InvalidInput(format!("Invalid file index in --magnet-select entry {part:?}: {err}")))
+}
+
+/// Validates a `host:port` peer hint. Hosts may be IPv4 addresses, bracketed
+/// IPv6 addresses or DNS names.
+pub fn parse_peer(value: &str) -> Result<String, String> {
+    let (host, port) = value
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected HOST:PORT, got {value:?}"))?;
+    let port: u16 = port.parse().map_err(|_| format!("invalid port in {value:?}"))?;
+    if port == 0 {
+        return Err(format!("port must be non-zero in {value:?}"));
+    }
+
+    if let Some(inner) = host.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| format!("unterminated IPv6 literal in {value:?}"))?;
+        inner
+            .parse::<Ipv6Addr>()
+            .map_err(|_| format!("invalid IPv6 address in {value:?}"))?;
+    } else if host.contains(':') {
+        return Err(format!("IPv6 peer addresses must be bracketed, got {value:?}"));
+    } else if host.parse::<Ipv4Addr>().is_err() && !is_hostname(host) {
+        return Err(format!("invalid host in {value:?}"));
+    }
+
+    Ok(format!("{host}:{port}"))
+}
+
+fn is_hostname(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty() && label.len() <= 63 && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn invalid(message: &str) -> TorseedError {
+    TorseedError::InvalidInput(message.to_string())
 }
 
 fn encode_component(value: &str) -> String {
     percent_encode(value.as_bytes(), MAGNET_ENCODE_SET).to_string()
 }
+
+/// Components of a parsed magnet URI.
+#[derive(Debug, Clone, Default)]
+pub struct ParsedMagnet {
+    pub name: Option<String>,
+    pub infohash_v1: Option<[u8; 20]>,
+    pub infohash_v2: Option<[u8; 32]>,
+    pub trackers: Vec<String>,
+    pub webseeds: Vec<String>,
+    /// `xl=`, the payload size of a single-file torrent.
+    pub exact_length: Option<u64>,
+    /// `so=`, the BEP 53 file selection as written.
+    pub select_only: Option<String>,
+    /// `xs=`, where the .torrent is published.
+    pub torrent_url: Option<String>,
+    /// `as=`, a direct download of the payload.
+    pub direct_source: Option<String>,
+}
+
+impl ParsedMagnet {
+    /// Infohash used by trackers: v1 when present, otherwise the truncated v2 hash.
+    pub fn tracker_infohash(&self) -> Option<[u8; 20]> {
+        self.infohash_v1.or_else(|| {
+            self.infohash_v2.map(|hash| {
+                let mut truncated = [0u8; 20];
+                truncated.copy_from_slice(&hash[..20]);
+                truncated
+            })
+        })
+    }
+}
+
+pub fn parse_magnet(uri: &str) -> Result<ParsedMagnet> {
+    let query = uri
+        .strip_prefix("magnet:?")
+        .ok_or_else(|| invalid("Magnet URI must start with magnet:?"))?;
+
+    let mut magnet = ParsedMagnet::default();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode_str(value)
+            .decode_utf8()
+            .map_err(|_| TorseedError::InvalidInput(format!("Magnet parameter {name} is not valid UTF-8")))?
+            .into_owned();
+        // Parameters may carry a numeric suffix such as `tr.1`.
+        let base = name.split_once('.').map_or(name, |(base, _)| base);
+        match base {
+            "xt" => parse_exact_topic(&value, &mut magnet)?,
+            "dn" => magnet.name = Some(value),
+            "tr" => magnet.trackers.push(value),
+            "ws" => magnet.webseeds.push(value),
+            "xl" => {
+                let length = value
+                    .parse()
+                    .map_err(|_| TorseedError::InvalidInput(format!("Magnet xl {value:?} is not a byte count")))?;
+                magnet.exact_length = Some(length);
+            }
+            "so" => magnet.select_only = Some(value),
+            "xs" => magnet.torrent_url = Some(value),
+            "as" => magnet.direct_source = Some(value),
+            _ => {}
+        }
+    }
+
+    if magnet.infohash_v1.is_none() && magnet.infohash_v2.is_none() {
+        return Err(invalid("Magnet URI has no urn:btih or urn:btmh exact topic"));
+    }
+    Ok(magnet)
+}
+
+fn parse_exact_topic(value: &str, magnet: &mut ParsedMagnet) -> Result<()> {
+    if let Some(hash) = value.strip_prefix("urn:btih:") {
+        let bytes = match hash.len() {
+            40 => hex::decode(hash).map_err(|err| TorseedError::InvalidInput(format!("Invalid hex btih infohash: {err}")))?,
+            32 => BASE32_NOPAD
+                .decode(hash.to_ascii_uppercase().as_bytes())
+                .map_err(|err| TorseedError::InvalidInput(format!("Invalid base32 btih infohash: {err}")))?,
+            len => return Err(TorseedError::InvalidInput(format!("btih infohash has unexpected length {len}"))),
+        };
+        magnet.infohash_v1 = Some(bytes.try_into().map_err(|_| invalid("btih infohash is not 20 bytes"))?);
+    } else if let Some(multihash) = value.strip_prefix("urn:btmh:") {
+        let hash = multihash
+            .strip_prefix("1220")
+            .ok_or_else(|| invalid("btmh multihash is not SHA-256"))?;
+        let bytes = hex::decode(hash).map_err(|err| TorseedError::InvalidInput(format!("Invalid hex btmh infohash: {err}")))?;
+        magnet.infohash_v2 = Some(bytes.try_into().map_err(|_| invalid("btmh infohash is not 32 bytes"))?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_spec_merges_and_sorts_ranges() {
+        let cases = [
+            ("0", "0"),
+            ("4,0,2", "0,2,4"),
+            ("0,1,2", "0-2"),
+            ("3-5,0-1", "0-1,3-5"),
+            ("0-3,2-6", "0-6"),
+            ("0-2,3-4", "0-4"),
+            ("5,5,5", "5"),
+            ("2-2", "2"),
+            ("1-4,2-3", "1-4"),
+            (" 7 , 1 - 2 ", "1-2,7"),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(parse_select_spec(spec, 10).unwrap(), expected, "{spec}");
+        }
+    }
+
+    #[test]
+    fn select_spec_rejects_bad_input() {
+        for (spec, file_count, message) in [
+            ("0", 1, "only applies to multi-file torrents"),
+            ("0", 0, "only applies to multi-file torrents"),
+            ("10", 10, "out of range"),
+            ("0-10", 10, "out of range"),
+            ("4-2", 10, "Descending"),
+            ("", 10, "Invalid file index"),
+            ("1,,2", 10, "Invalid file index"),
+            ("a", 10, "Invalid file index"),
+            ("-1", 10, "Invalid file index"),
+            ("1-", 10, "Invalid file index"),
+            ("1.5", 10, "Invalid file index"),
+            ("1-2-3", 10, "Invalid file index"),
+        ] {
+            let err = parse_select_spec(spec, file_count).unwrap_err();
+            assert!(matches!(err, TorseedError::InvalidInput(_)), "{spec}: {err:?}");
+            assert!(err.to_string().contains(message), "{spec}: {err}");
+        }
+    }
+
+    #[test]
+    fn parses_btih_in_hex_and_base32() {
+        let hash = [0xab; 20];
+        for encoded in [
+            hex::encode(hash),
+            hex::encode_upper(hash),
+            BASE32_NOPAD.encode(&hash),
+            BASE32_NOPAD.encode(&hash).to_ascii_lowercase(),
+        ] {
+            let magnet = parse_magnet(&format!("magnet:?xt=urn:btih:{encoded}")).unwrap();
+            assert_eq!(magnet.infohash_v1, Some(hash), "{encoded}");
+            assert_eq!(magnet.infohash_v2, None, "{encoded}");
+            assert_eq!(magnet.tracker_infohash(), Some(hash), "{encoded}");
+        }
+    }
+
+    #[test]
+    fn parses_btmh_and_hybrid_topics() {
+        let v2 = [0xcd; 32];
+        let magnet = parse_magnet(&format!("magnet:?xt=urn:btmh:1220{}", hex::encode(v2))).unwrap();
+        assert_eq!((magnet.infohash_v1, magnet.infohash_v2), (None, Some(v2)));
+        assert_eq!(magnet.tracker_infohash(), Some([0xcd; 20]));
+
+        let hybrid = format!("magnet:?xt=urn:btih:{}&xt=urn:btmh:1220{}", hex::encode([0xab; 20]), hex::encode(v2));
+        let magnet = parse_magnet(&hybrid).unwrap();
+        assert_eq!((magnet.infohash_v1, magnet.infohash_v2), (Some([0xab; 20]), Some(v2)));
+        assert_eq!(magnet.tracker_infohash(), Some([0xab; 20]));
+    }
+
+    #[test]
+    fn decodes_and_collects_parameters() {
+        let uri = format!(
+            "magnet:?xt=urn:btih:{}&&dn=first&dn=My%20File%2B1+2.iso&tr=udp%3A%2F%2Fa.example%3A1%2Fannounce\
+             &tr.1=http://b.example/announce?k=1&tr.2=udp%3A%2F%2Fa.example%3A1%2Fannounce\
+             &ws=https%3A%2F%2Fexample.com%2Fa%20b.iso&xl=1024&so=0,2-4\
+             &xs=https%3A%2F%2Fexample.com%2Fx.torrent%3Fa%3D1%26b%3D2&as=https%3A%2F%2Fexample.com%2Fa%20b.iso\
+             &x.pe=10.0.0.1:6881&kt=ignored&novalue",
+            hex::encode([1u8; 20])
+        );
+        let magnet = parse_magnet(&uri).unwrap();
+        assert_eq!(magnet.name.as_deref(), Some("My File+1+2.iso"));
+        assert_eq!(
+            magnet.trackers,
+            [
+                "udp://a.example:1/announce",
+                "http://b.example/announce?k=1",
+                "udp://a.example:1/announce"
+            ]
+        );
+        assert_eq!(magnet.webseeds, ["https://example.com/a b.iso"]);
+        assert_eq!(magnet.exact_length, Some(1024));
+        assert_eq!(magnet.select_only.as_deref(), Some("0,2-4"));
+        assert_eq!(magnet.torrent_url.as_deref(), Some("https://example.com/x.torrent?a=1&b=2"));
+        assert_eq!(magnet.direct_source.as_deref(), Some("https://example.com/a b.iso"));
+    }
+
+    #[test]
+    fn later_exact_topics_replace_earlier_ones() {
+        let uri = format!("magnet:?xt=urn:btih:{}&xt=urn:btih:{}", hex::encode([1u8; 20]), hex::encode([2u8; 20]));
+        assert_eq!(parse_magnet(&uri).unwrap().infohash_v1, Some([2; 20]));
+    }
+
+    #[test]
+    fn rejects_malformed_magnets() {
+        let v1 = hex::encode([0xab; 20]);
+        for (uri, message) in [
+            (format!("xt=urn:btih:{v1}"), "must start with magnet:?"),
+            (format!("magnet:xt=urn:btih:{v1}"), "must start with magnet:?"),
+            ("magnet:?dn=file.bin&tr=udp%3A%2F%2Fa.example%3A1".to_string(), "no urn:btih or urn:btmh"),
+            ("magnet:?xt=urn:sha1:ABCDEF".to_string(), "no urn:btih or urn:btmh"),
+            (format!("magnet:?xt=urn:btih:{}", &v1[..39]), "unexpected length 39"),
+            (format!("magnet:?xt=urn:btih:{}zz", &v1[..38]), "Invalid hex btih"),
+            (format!("magnet:?xt=urn:btih:{}", "1".repeat(32)), "Invalid base32 btih"),
+            (format!("magnet:?xt=urn:btmh:1114{}", hex::encode([0xcd; 20])), "not SHA-256"),
+            (format!("magnet:?xt=urn:btmh:1220{}", hex::encode([0xcd; 31])), "not 32 bytes"),
+            (format!("magnet:?xt=urn:btmh:1220{}", "g".repeat(64)), "Invalid hex btmh"),
+            (format!("magnet:?xt=urn:btih:{v1}&dn=%ff"), "dn is not valid UTF-8"),
+            (format!("magnet:?xt=urn:btih:{v1}&xl=-1"), "not a byte count"),
+        ] {
+            let err = parse_magnet(&uri).unwrap_err();
+            assert!(matches!(err, TorseedError::InvalidInput(_)), "{uri}: {err:?}");
+            assert!(err.to_string().contains(message), "{uri}: {err}");
+        }
+    }
+
+    const V1: [u8; 20] = [0xab; 20];
+    const V2: [u8; 32] = [0xcd; 32];
+
+    #[test]
+    fn btih_encodings_round_trip() {
+        for (format, expected) in [
+            (HashFormat::Hex, vec![hex::encode(V1)]),
+            (HashFormat::Base32, vec![BASE32_NOPAD.encode(&V1)]),
+            (HashFormat::Both, vec![hex::encode(V1), BASE32_NOPAD.encode(&V1)]),
+        ] {
+            let options = MagnetOptions {
+                hash_format: format,
+                ..MagnetOptions::default()
+            };
+            let built = build_magnets("file.bin", None, &[], &[], Some(V1), None, &options);
+            assert_eq!(built.len(), expected.len(), "{format:?}");
+            for (magnet, encoded) in built.iter().zip(&expected) {
+                assert!(magnet.starts_with(&format!("magnet:?xt=urn:btih:{encoded}&")), "{magnet}");
+                assert_eq!(parse_magnet(magnet).unwrap().infohash_v1, Some(V1), "{magnet}");
+            }
+        }
+        assert_eq!(BASE32_NOPAD.encode(&V1), "VOV2XK5LVOV2XK5LVOV2XK5LVOV2XK5L");
+    }
+
+    fn topics(magnet: &str) -> Vec<&str> {
+        magnet
+            .trim_start_matches("magnet:?")
+            .split('&')
+            .filter_map(|pair| pair.strip_prefix("xt="))
+            .collect()
+    }
+
+    #[test]
+    fn hybrid_styles_carry_each_topic_once() {
+        let btih = format!("urn:btih:{}", hex::encode(V1));
+        let btmh = format!("urn:btmh:1220{}", hex::encode(V2));
+        for (style, expected) in [
+            (MagnetStyle::Combined, vec![vec![&btih, &btmh]]),
+            (MagnetStyle::Separate, vec![vec![&btih], vec![&btmh]]),
+            (MagnetStyle::Both, vec![vec![&btih, &btmh], vec![&btih], vec![&btmh]]),
+        ] {
+            let options = MagnetOptions {
+                style,
+                ..MagnetOptions::default()
+            };
+            let built = build_magnets("file.bin", Some(1024), &[], &[], Some(V1), Some(V2), &options);
+            let built_topics: Vec<Vec<&str>> = built.iter().map(|magnet| topics(magnet)).collect();
+            assert_eq!(built_topics, expected, "{style:?}");
+            for magnet in &built {
+                let parsed = parse_magnet(magnet).unwrap();
+                let has_v1 = magnet.contains(&btih);
+                let has_v2 = magnet.contains(&btmh);
+                assert_eq!(parsed.infohash_v1, has_v1.then_some(V1), "{magnet}");
+                assert_eq!(parsed.infohash_v2, has_v2.then_some(V2), "{magnet}");
+            }
+        }
+    }
+
+    #[test]
+    fn combined_style_falls_back_without_both_hashes() {
+        let options = MagnetOptions::default();
+        let v1_only = build_magnets("file.bin", None, &[], &[], Some(V1), None, &options);
+        let v2_only = build_magnets("file.bin", None, &[], &[], None, Some(V2), &options);
+        assert_eq!((v1_only.len(), v2_only.len()), (1, 1));
+        assert_eq!(topics(&v1_only[0]), [format!("urn:btih:{}", hex::encode(V1))]);
+        assert_eq!(topics(&v2_only[0]), [format!("urn:btmh:1220{}", hex::encode(V2))]);
+    }
+
+    #[test]
+    fn common_parameters_round_trip() {
+        let trackers = [
+            "udp://tracker.example.org:1337/announce".to_string(),
+            "https://tracker.example.org/announce?a=1&b=two words".to_string(),
+        ];
+        let webseeds = ["https://example.com/files/my file & more.iso".to_string()];
+        let options = MagnetOptions {
+            style: MagnetStyle::Both,
+            torrent_url: Some("https://example.com/t/my file.torrent?x=1&y=2".to_string()),
+            direct_source: Some("https://example.com/files/my file & more.iso".to_string()),
+            select_only: Some("0,2-4".to_string()),
+            ..MagnetOptions::default()
+        };
+        let built = build_magnets("my file & more.iso", Some(1024), &trackers, &webseeds, Some(V1), Some(V2), &options);
+        assert_eq!(built.len(), 3);
+        for magnet in &built {
+            assert!(!magnet.contains(' '), "{magnet}");
+            // Only the separators are left unescaped: dn, xl, two tr, ws, xs, as and so.
+            assert_eq!(magnet.split('&').count(), topics(magnet).len() + 8, "{magnet}");
+            let parsed = parse_magnet(magnet).unwrap();
+            assert_eq!(parsed.name.as_deref(), Some("my file & more.iso"));
+            assert_eq!(parsed.exact_length, Some(1024));
+            assert_eq!(parsed.trackers, trackers);
+            assert_eq!(parsed.webseeds, webseeds);
+            assert_eq!(parsed.torrent_url, options.torrent_url);
+            assert_eq!(parsed.direct_source, options.direct_source);
+            assert_eq!(parsed.select_only, options.select_only);
+        }
+    }
+
+    #[test]
+    fn leaves_out_unset_parameters() {
+        let built = build_magnets("file.bin", None, &[], &[], Some(V1), None, &MagnetOptions::default());
+        assert_eq!(built, [format!("magnet:?xt=urn:btih:{}&dn=file.bin", hex::encode(V1))]);
+        let parsed = parse_magnet(&built[0]).unwrap();
+        assert_eq!(parsed.exact_length, None);
+        assert_eq!((parsed.torrent_url, parsed.direct_source, parsed.select_only), (None, None, None));
+    }
+}
+
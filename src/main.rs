@@ -1,192 +1,3015 @@
-mod hash_v1;
-mod hash_v2;
-mod http;
-mod magnet;
-mod metainfo;
-mod trackers;
-mod util;
+mod bars;
+mod color;
+mod events;
+mod notify;
+mod publish;
+mod qbittorrent;
+mod qr;
+mod seed;
+mod serve;
 
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
 use std::fs;
+use std::future::Future;
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use data_encoding::BASE32_NOPAD;
-use futures::StreamExt;
-use hash_v1::V1Hasher;
-use hash_v2::V2Hasher;
-use magnet::build_magnets;
-use metainfo::{build as build_metainfo, BuildInput};
+use futures::stream::{self, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Client;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio::time::Instant;
-use tracing::{info, warn};
+use torseed::cache::DownloadCache;
+use torseed::checksum::{self, Checksum, ChecksumAlgorithm, DigestHeaderPolicy};
+use torseed::magnet::{self, build_magnets, HashFormat, MagnetOptions, MagnetStyle};
+use torseed::metainfo::{self, BuildInput, FileLayout, MetaVersion, ParsedTorrent};
+use torseed::netrc::Netrc;
+use torseed::pieces::{self, PiecesFormat};
+use torseed::progress::{Event, EventSink, TransferStats};
+use torseed::summary::{self, BuildSummary, RunReport, RunTimings, WebseedReport, WebtorrentReport};
+use torseed::tracker_client::{self, AnnounceEvent, AnnounceParams, CheckOptions, ScrapeOptions, ScrapeOutcome};
+use torseed::trackers::{self, DedupeMode, GatheredTrackers, NewtrackonEndpoint, TrackerOptions, TrackerOrder};
+use torseed::transmission::{AddOutcome, TransmissionRpc};
+use torseed::util::{self, sanitize_filename, ByteUnits, QueryStrip, TemplateValues};
+use torseed::http::{HttpOptions, SourceMetadata};
+use torseed::{convert, expand, http, verify, CancellationToken, FilePart, TorrentBuilder, TorseedError};
+use tracing::{debug, info, warn, Instrument};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 use url::Url;
 
-use crate::util::{choose_piece_length, format_bytes, sanitize_filename};
+/// Library events buffered for the log task.
+const EVENT_QUEUE_DEPTH: usize = 64;
+/// Spacing of progress lines while hashing.
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(15);
 
 #[derive(Debug, Parser)]
-#[command(name = "torseed", version, about = "Create hybrid BitTorrent torrents from HTTP sources")]
+#[command(
+    name = "torseed",
+    version,
+    about = "Create hybrid BitTorrent torrents from HTTP sources",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_help = EXIT_CODES_HELP
+)]
 struct Cli {
-    /// Primary HTTP/HTTPS URL to fetch and hash
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Log more: -v adds torseed debug output, -vv debug for everything, -vvv trace
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only log warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also append debug-level logs to this file
+    #[arg(long, value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Colorize logs and the summary; auto honors NO_COLOR and only colors terminals
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = clap::ColorChoice::Auto, global = true)]
+    color: clap::ColorChoice,
+
+    /// Show sizes in powers of 1000 (KB, MB, GB) instead of 1024 (KiB, MiB, GiB)
+    #[arg(long, global = true)]
+    si: bool,
+
+    /// Print passkey-bearing tracker URLs in full and keep them in magnets (they are redacted and left out by default)
+    #[arg(long, global = true)]
+    no_redact: bool,
+
+    /// Longest total wait per request when a server answers 429 or 503 with Retry-After; 0 never retries
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "60s",
+        value_parser = humantime::parse_duration,
+        global = true
+    )]
+    max_retry_wait: Duration,
+
+    /// Fail a download that receives nothing for this long; the transfer as a whole has no time limit
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "60s",
+        value_parser = humantime::parse_duration,
+        global = true
+    )]
+    read_timeout: Duration,
+
+    /// Most probes (HEAD checks, tracker list fetches) in flight to one host at a time
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u16).range(1..),
+        global = true
+    )]
+    per_host_concurrency: u16,
+
+    /// Least time between the starts of two probes to the same host
+    #[arg(long, value_name = "DURATION", default_value = "0s", value_parser = humantime::parse_duration, global = true)]
+    per_host_delay: Duration,
+
+    /// Idle connections kept open per host for reuse (default: unlimited); fewer saves sockets, more saves handshakes
+    #[arg(long, value_name = "N", global = true)]
+    pool_max_idle_per_host: Option<usize>,
+
+    /// How long an idle connection stays open for reuse (default: 90s)
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, global = true)]
+    pool_idle_timeout: Option<Duration>,
+
+    /// Send TCP keepalive probes at this interval, to notice dead connections (default: off)
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, global = true)]
+    tcp_keepalive: Option<Duration>,
+
+    /// Send HTTP basic auth from ~/.netrc to the hosts it lists; its default entry only goes to source hosts
+    #[arg(long, global = true)]
+    netrc: bool,
+
+    /// Like --netrc, reading this file instead of ~/.netrc
+    #[arg(long, value_name = "PATH", global = true)]
+    netrc_file: Option<PathBuf>,
+
+    /// Source address for all HTTP connections (downloads, probes, tracker lists), for multihomed hosts
+    #[arg(long, value_name = "IP", global = true)]
+    bind_address: Option<IpAddr>,
+
+    /// Send all HTTP connections through this network interface (Linux only)
+    #[arg(long, value_name = "NAME", global = true)]
+    interface: Option<String>,
+
+    /// Redirects followed per request before giving up
+    #[arg(long, value_name = "N", default_value_t = 10, global = true)]
+    max_redirects: usize,
+
+    /// Follow redirects from https down to plain http, which are refused by default
+    #[arg(long, global = true)]
+    allow_insecure_redirect: bool,
+
+    /// Where to look for a source's file name, in order (comma-separated); a URL hash is the last resort
+    #[arg(
+        long,
+        value_enum,
+        value_name = "SOURCES",
+        value_delimiter = ',',
+        default_value = "disposition,redirect,query,path,host",
+        global = true
+    )]
+    filename_from: Vec<http::FilenameSource>,
+
+    #[command(flatten)]
+    create: CreateArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Query trackers for swarm statistics of a torrent or magnet
+    Scrape(ScrapeArgs),
+    /// Rebuild a .torrent from a magnet link that carries webseeds
+    FromMagnet(FromMagnetArgs),
+    /// Print the contents of a .torrent file
+    Inspect(InspectArgs),
+    /// Check that a URL still serves the payload of a torrent
+    Verify(VerifyArgs),
+    /// Change trackers, webseeds or comment of a torrent without rehashing
+    Edit(EditArgs),
+    /// Replace the trackers of existing torrents with a freshly gathered list
+    RefreshTrackers(RefreshTrackersArgs),
+    /// Print the magnet links of an existing .torrent
+    Magnet(MagnetArgs),
+    /// Upgrade a v1 torrent to hybrid by re-hashing its webseed
+    Convert(ConvertArgs),
+    /// Create torrents for every URL in a list, gathering trackers once
+    Batch(BatchArgs),
+    /// Serve a torrent's payload over HTTP as a webseed
+    Serve(ServeArgs),
+    /// Seed a single-file torrent to peers until a stop condition is met
+    Seed(SeedArgs),
+}
+
+#[derive(Debug, Args)]
+struct SeedArgs {
+    /// Torrent to seed
+    #[arg(value_name = "TORRENT")]
+    torrent: PathBuf,
+
+    /// Payload file on disk
+    #[arg(long, value_name = "PATH")]
+    file: PathBuf,
+
+    /// TCP port to accept peers on and announce
+    #[arg(long, default_value_t = 6881)]
+    port: u16,
+
+    /// Stop after seeding this long (e.g. 2h)
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    seed_time: Option<Duration>,
+
+    /// Stop once uploaded bytes reach this multiple of the payload size
+    #[arg(long, value_name = "RATIO")]
+    seed_ratio: Option<f64>,
+
+    /// Maximum number of peers served at once
+    #[arg(long, value_name = "N", default_value_t = 50)]
+    max_peers: usize,
+
+    /// Hash only N random pieces from disk before seeding instead of all of them
+    #[arg(long, value_name = "N")]
+    check_pieces: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+struct ServeArgs {
+    /// Torrent whose payload is served
+    #[arg(value_name = "TORRENT")]
+    torrent: PathBuf,
+
+    /// Directory containing the payload, as saved next to the torrent name
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    root: PathBuf,
+
+    /// Address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Hash N random pieces from disk at startup and refuse to serve on a mismatch
+    #[arg(long, value_name = "N")]
+    check_pieces: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+struct BatchArgs {
+    /// File with one URL per line, optionally followed by an output path; # starts a comment
+    #[arg(value_name = "LIST")]
+    list: PathBuf,
+
+    /// Number of entries downloaded and hashed at the same time
+    #[arg(short, long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+
+    /// Directory for entries without an explicit output path
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Piece length such as 262144 or 256KiB (power of two, at least 16 KiB)
+    #[arg(long, value_name = "SIZE", value_parser = util::parse_piece_length, conflicts_with = "target_pieces")]
+    piece_length: Option<usize>,
+
+    /// Pick the smallest piece length giving at most N pieces
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    target_pieces: Option<u64>,
+
+    /// Do not write a <torrent-stem>.magnet file per entry
+    #[arg(long)]
+    no_magnet_file: bool,
+
+    #[command(flatten)]
+    magnet_format: MagnetFormatArgs,
+
+    #[command(flatten)]
+    tracker: TrackerArgs,
+
+    #[command(flatten)]
+    created_by: CreatedByArgs,
+}
+
+#[derive(Debug, Args)]
+struct ConvertArgs {
+    /// v1 torrent file to upgrade; it is not modified
+    #[arg(value_name = "TORRENT")]
+    file: PathBuf,
+
+    /// Download the payload from this URL instead of the torrent's webseeds
+    #[arg(long, value_name = "URL")]
+    from: Option<String>,
+
+    /// Output path for the hybrid torrent [default: <stem>.hybrid.torrent]
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    created_by: CreatedByArgs,
+}
+
+#[derive(Debug, Args)]
+struct MagnetArgs {
+    /// Torrent file to read
+    #[arg(value_name = "TORRENT")]
+    file: PathBuf,
+
+    /// Write the magnet links to this file instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Gather a new tracker list instead of using the torrent's own
+    #[arg(long)]
+    fresh_trackers: bool,
+
+    #[command(flatten)]
+    magnet_format: MagnetFormatArgs,
+
+    #[command(flatten)]
+    tracker: TrackerArgs,
+}
+
+#[derive(Debug, Args)]
+struct RefreshTrackersArgs {
+    /// Torrent files to update in place
+    #[arg(value_name = "TORRENT", required = true)]
+    files: Vec<PathBuf>,
+
+    /// Keep the existing announce tiers and append gathered trackers after them
+    #[arg(long)]
+    merge: bool,
+
+    /// Report what would change without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Do not rewrite the <torrent-stem>.magnet files
+    #[arg(long)]
+    no_magnet_file: bool,
+
+    #[command(flatten)]
+    tracker: TrackerArgs,
+}
+
+#[derive(Debug, Args)]
+struct EditArgs {
+    /// Torrent file to edit
+    #[arg(value_name = "TORRENT")]
+    file: PathBuf,
+
+    /// Write the edited torrent here instead of replacing the file (which keeps a .bak copy)
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Tracker announce URL to append as a new tier (repeatable)
+    #[arg(long, value_name = "URL")]
+    add_tracker: Vec<String>,
+
+    /// Tracker announce URL to remove from every tier (repeatable)
+    #[arg(long, value_name = "URL")]
+    remove_tracker: Vec<String>,
+
+    /// Webseed URL to append (repeatable)
+    #[arg(long, value_name = "URL")]
+    add_webseed: Vec<String>,
+
+    /// Webseed URL to remove (repeatable)
+    #[arg(long, value_name = "URL")]
+    remove_webseed: Vec<String>,
+
+    /// New comment; an empty string removes it
+    #[arg(long, value_name = "TEXT")]
+    set_comment: Option<String>,
+
+    /// New creation date as a Unix timestamp
+    #[arg(long, value_name = "UNIX_TIME")]
+    set_creation_date: Option<i64>,
+
+    #[command(flatten)]
+    created_by: CreatedByArgs,
+
+    /// Not supported: the name is part of the info dictionary
+    #[arg(long, value_name = "NAME", hide = true)]
+    set_name: Option<String>,
+
+    /// Not supported: the private flag is part of the info dictionary
+    #[arg(long, value_name = "0|1", hide = true)]
+    set_private: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct VerifyArgs {
+    /// Torrent file to check against
+    #[arg(value_name = "TORRENT")]
+    torrent: PathBuf,
+
+    /// HTTP/HTTPS URL serving the payload
     #[arg(value_name = "URL")]
-    primary_url: String,
+    url: String,
+
+    /// Check only N randomly chosen pieces using Range requests
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pieces: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+struct InspectArgs {
+    /// Torrent file to decode
+    #[arg(value_name = "TORRENT")]
+    file: PathBuf,
+
+    /// Print the contents as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Also print the magnet link derived from the file
+    #[arg(long)]
+    magnet: bool,
+}
+
+#[derive(Debug, Args)]
+struct FromMagnetArgs {
+    /// Magnet URI with at least one ws= webseed
+    #[arg(value_name = "MAGNET")]
+    magnet: String,
+
+    /// Optional output path for the torrent file
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct CreateArgs {
+    /// Primary HTTP/HTTPS URL to fetch and hash
+    #[arg(value_name = "URL", required_unless_present = "multi")]
+    primary_url: Option<String>,
+
+    /// Build one multi-file torrent from every URL a brace template expands to, e.g.
+    /// "https://host/data/part-{000..127}.bin"; ranges take an optional step ({0..100..10}), lists use commas
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all = ["primary_url", "resume", "checksums", "magnet_select", "magnet_as", "ascii_names"]
+    )]
+    multi: Option<String>,
+
+    /// Refuse a --multi template that expands to more URLs than this
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    max_expanded_urls: usize,
 
     /// Additional HTTP(S) URLs to include as webseeds
     #[arg(value_name = "WEBSEED", num_args = 0..)]
     extra_urls: Vec<String>,
 
-    /// Optional output path for the torrent file
-    #[arg(short, long, value_name = "FILE")]
-    output: Option<PathBuf>,
+    /// Fail instead of leaving out webseeds that do not serve the source
+    #[arg(long)]
+    require_all_webseeds: bool,
+
+    /// Fail before downloading unless at least N webseeds, counting the primary URL, pass verification
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    min_webseeds: Option<u64>,
+
+    /// Leave out mirrors that do not answer a one-byte Range request with 206 Partial Content
+    #[arg(long)]
+    require_ranges: bool,
+
+    /// Write webseeds and magnets without their query strings; downloads still use the full URLs
+    #[arg(long, conflicts_with = "strip_query_params")]
+    strip_query: bool,
+
+    /// Like --strip-query, but only remove these query parameters (comma-separated, any case)
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    strip_query_params: Vec<String>,
+
+    /// Optional output path for the torrent file
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Restrict the torrent name and output filename to ASCII letters, digits, '.', '_' and '-'
+    #[arg(long)]
+    ascii_names: bool,
+
+    /// Also write the name as name.utf-8 for older clients; this changes the infohash
+    #[arg(long)]
+    name_utf8: bool,
+
+    /// Write one torrent per listed flavor (hybrid, v1, v2) from the same hashing pass, named
+    /// <stem>.<flavor>.torrent; the first listed is the primary output
+    #[arg(long, value_name = "FLAVORS", value_delimiter = ',', conflicts_with = "match_torrent")]
+    emit: Vec<MetaVersion>,
+
+    /// Also write <stem>.webtorrent.torrent and its magnets for browser clients, announcing only to the ws:// and
+    /// wss:// trackers; the info dictionary, and so the infohash, is unchanged
+    #[arg(long)]
+    webtorrent: bool,
+
+    /// Also write the piece hashes to this file
+    #[arg(long, value_name = "PATH")]
+    pieces_out: Option<PathBuf>,
+
+    /// Format of the --pieces-out file
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = PiecesFormat::Binary, requires = "pieces_out")]
+    pieces_format: PiecesFormat,
+
+    /// Also compute these whole-file digests while hashing (comma-separated)
+    #[arg(long, value_enum, value_name = "ALGOS", value_delimiter = ',')]
+    checksums: Vec<ChecksumAlgorithm>,
+
+    /// Write the --checksums digests to this file in sha256sum-compatible format
+    #[arg(long, value_name = "PATH", requires = "checksums")]
+    checksums_out: Option<PathBuf>,
+
+    /// What to do when the bytes served disagree with the server's own Digest, Repr-Digest or Content-MD5 header
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = DigestHeaderPolicy::Enforce)]
+    digest_header: DigestHeaderPolicy,
+
+    /// Refuse sources larger than this (e.g. 50GiB), whether the server says so up front or the stream runs over
+    #[arg(long, value_name = "SIZE", value_parser = util::parse_size)]
+    max_download_size: Option<u64>,
+
+    /// When the source sends more than its declared length, hash only the declared bytes instead of failing
+    #[arg(long)]
+    truncate_overlong: bool,
+
+    /// Keep a copy of the download in this directory and hash it instead of the network while the source's
+    /// ETag, Last-Modified and length stay the same
+    #[arg(long, value_name = "DIR", env = "TORSEED_CACHE")]
+    cache: Option<PathBuf>,
+
+    /// Download from the source even when --cache or TORSEED_CACHE is set
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Before reusing a cached copy, compare samples of it with ranged requests to the source
+    #[arg(long, requires = "cache")]
+    revalidate: bool,
+
+    /// Also write the downloaded payload to this file; unlike --cache, a failed write stops the download
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["multi", "resume"])]
+    save: Option<PathBuf>,
+
+    /// Download the payload over HTTP/3 (QUIC) when the server offers it, else fall back to HTTP/2 or 1.1
+    #[cfg(feature = "http3")]
+    #[arg(long)]
+    http3: bool,
+
+    /// Piece length such as 262144 or 256KiB (power of two, at least 16 KiB)
+    #[arg(long, value_name = "SIZE", value_parser = util::parse_piece_length, conflicts_with = "target_pieces")]
+    piece_length: Option<usize>,
+
+    /// Pick the smallest piece length giving at most N pieces
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    target_pieces: Option<u64>,
+
+    /// Recreate this single-file torrent from the URL, keeping its infohash: every piece must match, and
+    /// only the trackers, webseeds, creation date and creator change
+    #[arg(
+        long = "match",
+        value_name = "TORRENT",
+        conflicts_with_all = ["multi", "piece_length", "target_pieces", "ascii_names", "name_utf8"]
+    )]
+    match_torrent: Option<PathBuf>,
+
+    /// Checkpoint hashing progress to this file and resume from it after a restart
+    #[arg(long, value_name = "STATE_FILE")]
+    resume: Option<PathBuf>,
+
+    /// Reuse the announce tiers of an existing .torrent, placed before gathered trackers
+    #[arg(long, value_name = "TORRENT")]
+    trackers_from: Option<PathBuf>,
+
+    #[command(flatten)]
+    tracker: TrackerArgs,
+
+    /// Probe trackers and drop the ones that do not respond
+    #[arg(long)]
+    check_trackers: bool,
+
+    /// Timeout for a single tracker probe
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = humantime::parse_duration)]
+    tracker_check_timeout: Duration,
+
+    /// Maximum number of tracker probes in flight
+    #[arg(long, value_name = "N", default_value_t = 32)]
+    tracker_check_concurrency: usize,
+
+    /// Overall time budget for tracker probing
+    #[arg(long, value_name = "DURATION", default_value = "30s", value_parser = humantime::parse_duration)]
+    tracker_check_deadline: Duration,
+
+    #[command(flatten)]
+    magnet_format: MagnetFormatArgs,
+
+    /// Peer address hint (host:port) added to magnets as x.pe (repeatable)
+    #[arg(long = "peer", value_name = "HOST:PORT", value_parser = magnet::parse_peer)]
+    peers: Vec<String>,
+
+    /// URL where the .torrent will be published, added to magnets as xs=
+    #[arg(long, value_name = "URL")]
+    torrent_url: Option<String>,
+
+    /// Advertise the primary URL as a direct download source (as=) in magnets
+    #[arg(long)]
+    magnet_as: bool,
+
+    /// Write magnet links to this file instead of <torrent-stem>.magnet
+    #[arg(long, value_name = "PATH", conflicts_with = "no_magnet_file")]
+    magnet_file: Option<PathBuf>,
+
+    /// Do not write a magnet file
+    #[arg(long)]
+    no_magnet_file: bool,
+
+    /// Append to the magnet file instead of overwriting it
+    #[arg(long, conflicts_with = "no_magnet_file")]
+    append_magnets: bool,
+
+    /// Name the torrent from a template such as "{name}-{infohash7}-{date}.torrent"; placeholders:
+    /// name, infohash_v1, infohash_v2, infohash7, date, size
+    #[arg(long, value_name = "TEMPLATE", value_parser = util::OutputTemplate::parse, conflicts_with = "output")]
+    output_template: Option<util::OutputTemplate>,
+
+    /// Overwrite existing torrent and magnet files
+    #[arg(long, conflicts_with = "backup")]
+    force: bool,
+
+    /// Rename existing torrent and magnet files to <name>.bak instead of refusing to write
+    #[arg(long)]
+    backup: bool,
+
+    /// Preselect files in magnets by index, e.g. 0,2-4 (BEP 53 so=)
+    #[arg(long, value_name = "SPEC")]
+    magnet_select: Option<String>,
+
+    /// Print the magnet link as a QR code in the terminal
+    #[arg(long)]
+    qr: bool,
+
+    /// Also write the QR code as a PNG image
+    #[arg(long, value_name = "PATH")]
+    qr_png: Option<PathBuf>,
+
+    /// Maximum number of trackers in the QR magnet; fewer are used if it does not fit
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    qr_trackers: usize,
+
+    /// Copy the primary magnet link to the system clipboard
+    #[arg(long)]
+    copy: bool,
+
+    /// Print a JSON run report instead of the prose summary; logs go to stderr
+    #[arg(long, conflicts_with_all = ["qr", "scrape_after"])]
+    json: bool,
+
+    /// Also write the JSON run report to this file
+    #[arg(long, value_name = "FILE")]
+    summary_out: Option<PathBuf>,
+
+    /// Scrape the trackers for the new infohash after writing the torrent
+    #[arg(long)]
+    scrape_after: bool,
+
+    #[command(flatten)]
+    scrape: ScrapeOptionsArgs,
+
+    /// Announce the new infohash once to the first trackers after writing the torrent
+    #[arg(long)]
+    announce_once: bool,
+
+    /// Number of trackers to announce to with --announce-once
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    announce_trackers: usize,
+
+    /// Port to announce; when omitted a stopped event follows so no stale peer is left behind
+    #[arg(long, value_name = "PORT")]
+    announce_port: Option<u16>,
+
+    /// Upload the finished torrent to this URL; a failed upload fails the run (repeatable)
+    #[arg(long, value_name = "URL")]
+    publish: Vec<String>,
+
+    /// Upload the magnet links as text to this URL (repeatable)
+    #[arg(long, value_name = "URL")]
+    publish_magnet: Vec<String>,
+
+    /// How --publish and --publish-magnet send their upload
+    #[arg(long, value_enum, value_name = "METHOD", default_value_t = publish::PublishMethod::Put)]
+    publish_method: publish::PublishMethod,
+
+    /// Header added to publish uploads, e.g. "Authorization: Bearer TOKEN" (repeatable)
+    #[arg(long, value_name = "HEADER", value_parser = publish::parse_header)]
+    publish_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Add the finished torrent to qBittorrent through its WebUI at this URL
+    #[arg(long, value_name = "URL")]
+    qbittorrent: Option<String>,
+
+    /// qBittorrent WebUI user name; omit when the WebUI lets this host bypass authentication
+    #[arg(long, value_name = "USER", env = "TORSEED_QBITTORRENT_USER")]
+    qbittorrent_user: Option<String>,
+
+    /// qBittorrent WebUI password
+    #[arg(long, value_name = "PASS", env = "TORSEED_QBITTORRENT_PASS", hide_env_values = true)]
+    qbittorrent_pass: Option<String>,
+
+    /// Session cookie of an existing WebUI login (e.g. "SID=..."), used instead of logging in
+    #[arg(long, value_name = "COOKIE", env = "TORSEED_QBITTORRENT_COOKIE", hide_env_values = true)]
+    qbittorrent_cookie: Option<String>,
+
+    /// Directory holding the payload, passed to qBittorrent as the save path
+    #[arg(long, value_name = "DIR")]
+    qbittorrent_savepath: Option<String>,
+
+    /// Category for the torrent in qBittorrent
+    #[arg(long, value_name = "NAME")]
+    qbittorrent_category: Option<String>,
+
+    /// Add the finished torrent to Transmission through this RPC URL (e.g. http://host:9091/transmission/rpc)
+    #[arg(long, value_name = "URL")]
+    transmission: Option<String>,
+
+    /// Transmission RPC user name
+    #[arg(long, value_name = "USER", env = "TORSEED_TRANSMISSION_USER")]
+    transmission_user: Option<String>,
+
+    /// Transmission RPC password
+    #[arg(long, value_name = "PASS", env = "TORSEED_TRANSMISSION_PASS", hide_env_values = true)]
+    transmission_pass: Option<String>,
+
+    /// Directory holding the payload, passed to Transmission as the download dir
+    #[arg(long, value_name = "DIR")]
+    transmission_download_dir: Option<String>,
+
+    /// Fail the run when an integration such as --qbittorrent or --transmission fails
+    #[arg(long)]
+    strict_integrations: bool,
+
+    #[command(flatten)]
+    notify: NotifyArgs,
+
+    #[command(flatten)]
+    progress: ProgressArgs,
+
+    #[command(flatten)]
+    created_by: CreatedByArgs,
+}
+
+/// Flags for structured progress output.
+#[derive(Debug, Args)]
+struct ProgressArgs {
+    /// Progress output: a bar or log lines, or JSON events on stderr
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = events::ProgressFormat::Human)]
+    progress: events::ProgressFormat,
+
+    /// Log progress lines instead of drawing a progress bar on a terminal
+    #[arg(long)]
+    no_progress: bool,
+
+    /// Write JSON progress events to this open file descriptor instead of stderr
+    #[arg(long, value_name = "FD")]
+    progress_fd: Option<u32>,
+
+    /// Minimum spacing between JSON hash_progress events
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = humantime::parse_duration)]
+    progress_interval: Duration,
+
+    /// Also emit a JSON piece_completed event for every v1 piece
+    #[arg(long)]
+    progress_pieces: bool,
+}
+
+impl ProgressArgs {
+    /// Bars are drawn only for human output on an interactive stdout that
+    /// does not carry a `--json` report.
+    fn wants_bars(&self, json_report: bool) -> bool {
+        self.progress == events::ProgressFormat::Human
+            && !self.no_progress
+            && !json_report
+            && io::stdout().is_terminal()
+    }
+
+    fn open(&self, bars: Option<bars::Bars>) -> Result<events::ProgressOutput> {
+        match (self.progress, bars) {
+            (events::ProgressFormat::Json, _) => {
+                events::JsonEvents::open(self.progress_fd, self.progress_interval, self.progress_pieces)
+                    .map(events::ProgressOutput::Json)
+            }
+            (events::ProgressFormat::Human, Some(bars)) => Ok(events::ProgressOutput::Bars(bars)),
+            (events::ProgressFormat::Human, None) => Ok(events::ProgressOutput::Log),
+        }
+    }
+}
+
+/// Webhooks told about the outcome of a run.
+#[derive(Debug, Clone, Args)]
+struct NotifyArgs {
+    /// POST a JSON summary to this URL after a successful run (repeatable)
+    #[arg(long, value_name = "URL")]
+    notify: Vec<String>,
+
+    /// Also notify the --notify URLs when the run fails
+    #[arg(long, requires = "notify")]
+    notify_on_failure: bool,
+
+    /// Sign notifications with HMAC-SHA256 of the body in an X-Torseed-Signature header
+    #[arg(long, value_name = "SECRET", env = "TORSEED_NOTIFY_SECRET", hide_env_values = true)]
+    notify_secret: Option<String>,
+}
+
+impl NotifyArgs {
+    fn endpoints(&self) -> Result<Vec<Url>> {
+        self.notify.iter().map(|value| parse_url(value)).collect()
+    }
+}
+
+/// Flags setting the "created by" field of the torrents written.
+#[derive(Debug, Args)]
+struct CreatedByArgs {
+    /// Creator string to write into the torrent [default for new torrents: torseed <version>]
+    #[arg(long, value_name = "TEXT", conflicts_with = "no_created_by")]
+    created_by: Option<String>,
+
+    /// Leave the "created by" field out of the torrent; the infohash is the same either way
+    #[arg(long)]
+    no_created_by: bool,
+}
+
+impl CreatedByArgs {
+    /// The value to write, `Some("")` to leave the field out, or `None` to
+    /// keep the default.
+    fn value(&self) -> Option<String> {
+        if self.no_created_by { Some(String::new()) } else { self.created_by.clone() }
+    }
+}
+
+/// Flags shaping the magnet links themselves.
+#[derive(Debug, Args)]
+struct MagnetFormatArgs {
+    /// Maximum number of trackers embedded in magnet links; 0 relies on DHT only
+    #[arg(long, value_name = "N", default_value_t = 30)]
+    magnet_trackers: usize,
+
+    /// Encoding of the v1 infohash in btih magnets
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = HashFormat::Hex)]
+    magnet_hash_format: HashFormat,
+
+    /// How hybrid torrents are written as magnets
+    #[arg(long, value_enum, value_name = "STYLE", default_value_t = MagnetStyle::Combined)]
+    magnet_style: MagnetStyle,
+}
+
+impl MagnetFormatArgs {
+    fn to_options(&self) -> MagnetOptions {
+        MagnetOptions {
+            max_trackers: self.magnet_trackers,
+            hash_format: self.magnet_hash_format,
+            style: self.magnet_style,
+            passkey_trackers: !trackers::redaction_enabled(),
+            ..MagnetOptions::default()
+        }
+    }
+}
+
+/// Flags controlling how the announce list is gathered.
+#[derive(Debug, Args)]
+struct TrackerArgs {
+    /// Additional tracker announce URL to place first in the list (repeatable)
+    #[arg(long = "tracker", value_name = "URL")]
+    trackers: Vec<String>,
+
+    /// I2P tracker announce URL, placed in its own tier (repeatable)
+    #[arg(long = "i2p-tracker", value_name = "URL")]
+    i2p_trackers: Vec<String>,
+
+    /// Maximum number of trackers to embed; 0 uses only --tracker and --trackers-from entries
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    max_trackers: usize,
+
+    /// Ordering of trackers fetched from remote lists
+    #[arg(long, value_enum, value_name = "ORDER", default_value_t = TrackerOrder::Shuffle)]
+    tracker_order: TrackerOrder,
+
+    /// Only keep gathered trackers using these schemes (comma separated)
+    #[arg(
+        long,
+        value_name = "SCHEMES",
+        value_delimiter = ',',
+        value_parser = clap::builder::PossibleValuesParser::new(trackers::SUPPORTED_SCHEMES)
+    )]
+    tracker_schemes: Vec<String>,
+
+    /// Exclude trackers by host suffix or URL glob (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    tracker_exclude: Vec<String>,
+
+    /// File with tracker exclusion patterns, one per line
+    #[arg(long, value_name = "FILE")]
+    tracker_exclude_file: Option<PathBuf>,
+
+    /// newtrackon API endpoint to fetch trackers from
+    #[arg(long, value_enum, value_name = "ENDPOINT", default_value_t = NewtrackonEndpoint::Stable)]
+    newtrackon: NewtrackonEndpoint,
+
+    /// Collapse trackers that share a host, keeping the preferred scheme
+    #[arg(long, value_enum, value_name = "MODE")]
+    dedupe_trackers: Option<DedupeMode>,
+
+    /// Scheme preference for --dedupe-trackers, most preferred first
+    #[arg(
+        long,
+        value_name = "SCHEMES",
+        value_delimiter = ',',
+        default_value = "udp,https,http",
+        value_parser = clap::builder::PossibleValuesParser::new(trackers::SUPPORTED_SCHEMES)
+    )]
+    dedupe_prefer: Vec<String>,
+
+    /// Emit one announce-list tier per source class: user, curated, everything else
+    #[arg(long)]
+    tier_by_source: bool,
+
+    /// Timeout for fetching a single remote tracker list
+    #[arg(long, value_name = "DURATION", default_value = "8s", value_parser = humantime::parse_duration)]
+    tracker_fetch_timeout: Duration,
+
+    /// Overall time budget for fetching remote tracker lists
+    #[arg(long, value_name = "DURATION", default_value = "15s", value_parser = humantime::parse_duration)]
+    tracker_fetch_deadline: Duration,
+
+    /// Write the final tracker list to FILE, one URL per line with a blank line between tiers
+    #[arg(long, value_name = "FILE")]
+    dump_trackers: Option<PathBuf>,
+}
+
+impl TrackerArgs {
+    fn to_options(&self, imported_tiers: Vec<Vec<String>>) -> Result<TrackerOptions> {
+        let mut exclude = self.tracker_exclude.clone();
+        if let Some(path) = &self.tracker_exclude_file {
+            exclude.extend(trackers::load_exclude_file(path)?);
+        }
+        Ok(TrackerOptions {
+            imported_tiers,
+            user_trackers: self.trackers.clone(),
+            i2p_trackers: self.i2p_trackers.clone(),
+            max_trackers: self.max_trackers,
+            order: self.tracker_order,
+            schemes: self.tracker_schemes.clone(),
+            exclude,
+            newtrackon: self.newtrackon,
+            dedupe: self.dedupe_trackers,
+            dedupe_prefer: self.dedupe_prefer.clone(),
+            tier_by_source: self.tier_by_source,
+            fetch_timeout: self.tracker_fetch_timeout,
+            fetch_deadline: self.tracker_fetch_deadline,
+        })
+    }
+
+    /// Writes `gathered` to the --dump-trackers file, if one was given.
+    fn dump(&self, gathered: &GatheredTrackers) -> Result<()> {
+        let Some(path) = &self.dump_trackers else {
+            return Ok(());
+        };
+        let list = trackers::format_tracker_list(gathered, std::time::SystemTime::now());
+        util::write_atomic(path, list.as_bytes())
+            .with_context(|| format!("Failed to write tracker list to {}", path.display()))?;
+        info!("Tracker list written to {}", path.display());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args)]
+struct ScrapeArgs {
+    /// Torrent file or magnet URI to scrape
+    #[arg(value_name = "TORRENT|MAGNET")]
+    target: String,
+
+    #[command(flatten)]
+    options: ScrapeOptionsArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+struct ScrapeOptionsArgs {
+    /// Timeout for a single tracker scrape
+    #[arg(long, value_name = "DURATION", default_value = "8s", value_parser = humantime::parse_duration)]
+    scrape_timeout: Duration,
+
+    /// Maximum number of tracker scrapes in flight
+    #[arg(long, value_name = "N", default_value_t = 32)]
+    scrape_concurrency: usize,
+}
+
+impl ScrapeOptionsArgs {
+    fn to_options(&self) -> ScrapeOptions {
+        ScrapeOptions {
+            timeout: self.scrape_timeout,
+            concurrency: self.scrape_concurrency,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let creating = cli.command.is_none();
+    let bars = (creating && cli.create.progress.wants_bars(cli.create.json)).then(bars::Bars::new);
+    // With --json stdout carries only the report, so logs move to stderr.
+    if let Err(err) = init_tracing(&cli, creating && cli.create.json, bars.as_ref()) {
+        eprintln!("Error: {err:?}");
+        return ExitCode::FAILURE;
+    }
+
+    let cancel = CancellationToken::new();
+    spawn_interrupt_handler(cancel.clone());
+    let log_file = cli.log_file.clone();
+    match run(cli, bars, &cancel).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            let class = FailureClass::of(&err);
+            eprintln!("Error: {err:?}");
+            eprintln!("Exit status {}: {}", class.exit_code(), class.description());
+            if let Some(path) = log_file {
+                eprintln!("Debug log: {}", path.display());
+            }
+            ExitCode::from(class.exit_code())
+        }
+    }
+}
+
+async fn run(cli: Cli, bars: Option<bars::Bars>, cancel: &CancellationToken) -> Result<()> {
+    let pool = http::PoolOptions {
+        max_idle_per_host: cli.pool_max_idle_per_host,
+        idle_timeout: cli.pool_idle_timeout,
+        tcp_keepalive: cli.tcp_keepalive,
+    };
+    let redirects = http::RedirectOptions {
+        max_redirects: cli.max_redirects,
+        allow_insecure: cli.allow_insecure_redirect,
+    };
+    let bind = http::BindOptions {
+        address: cli.bind_address,
+        interface: cli.interface,
+    };
+    bind.check()?;
+    let client = build_client(&pool, &bind, redirects, cli.read_timeout)?;
+    trackers::set_redaction(!cli.no_redact);
+    let netrc_path = match cli.netrc_file {
+        Some(path) => Some(path),
+        None if cli.netrc => Some(Netrc::default_path().context("Cannot find the home directory for --netrc")?),
+        None => None,
+    };
+    let netrc = match netrc_path {
+        Some(path) => Some(Netrc::load(&path)?),
+        None => None,
+    };
+    let http_options = HttpOptions::default()
+        .max_retry_wait(cli.max_retry_wait)
+        .read_timeout(cli.read_timeout)
+        .host_limits(usize::from(cli.per_host_concurrency), cli.per_host_delay)
+        .filename_order(&cli.filename_from)
+        .netrc(netrc);
+    let units = if cli.si { ByteUnits::Si } else { ByteUnits::Binary };
+
+    match cli.command {
+        Some(Command::Scrape(args)) => run_scrape(&client, args, cancel).await,
+        Some(Command::FromMagnet(args)) => run_from_magnet(&client, &http_options, args, units, cancel).await,
+        Some(Command::Inspect(args)) => run_inspect(&args, units),
+        Some(Command::Verify(args)) => run_verify(&client, &http_options, args, units, cancel).await,
+        Some(Command::Edit(args)) => run_edit(args),
+        Some(Command::RefreshTrackers(args)) => run_refresh_trackers(&client, &http_options, args, cancel).await,
+        Some(Command::Magnet(args)) => run_magnet(&client, &http_options, args, cancel).await,
+        Some(Command::Convert(args)) => run_convert(&client, &http_options, args, units, cancel).await,
+        Some(Command::Batch(args)) => run_batch(&client, &http_options, args, units, cancel).await,
+        Some(Command::Serve(args)) => run_serve(args, cancel).await,
+        Some(Command::Seed(args)) => run_seed(&client, args, units, cancel).await,
+        None => {
+            let started = Instant::now();
+            let source = cli.create.primary_url.clone();
+            let notify = cli.create.notify.clone();
+            let progress = cli.create.progress.open(bars)?;
+            let reporting = Reporting {
+                progress: progress.clone(),
+                log_file: cli.log_file.as_deref(),
+                palette: color::Palette::new(color::enabled(cli.color, io::stdout().is_terminal())),
+                units,
+            };
+            let result = create(&client, &http_options, cli.create, reporting, cancel).await;
+            if let events::ProgressOutput::Json(progress) = &progress {
+                match &result {
+                    Ok(()) => progress.write("done", json!({ "elapsed_secs": started.elapsed().as_secs_f64() })),
+                    Err(err) => {
+                        let class = FailureClass::of(err);
+                        progress.write(
+                            "error",
+                            json!({
+                                "message": format!("{err:#}"),
+                                "exit_code": class.exit_code(),
+                                "category": class.description(),
+                            }),
+                        )
+                    }
+                }
+            }
+            if let Err(err) = &result
+                && notify.notify_on_failure
+            {
+                let payload = json!({
+                    "status": "failure",
+                    "schema": summary::REPORT_SCHEMA,
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "source": source,
+                    "error": format!("{err:#}"),
+                    "timings": { "total_secs": started.elapsed().as_secs_f64() },
+                });
+                // Invalid URLs already failed the run; there is nothing to send to.
+                let endpoints = notify.endpoints().unwrap_or_default();
+                notify::notify(&client, &endpoints, notify.notify_secret.as_deref(), &payload).await;
+            }
+            result
+        }
+    }
+}
+
+/// Cancels `cancel` on the first Ctrl-C so the run can unwind and keep its
+/// resume state; a second Ctrl-C exits immediately.
+fn spawn_interrupt_handler(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        warn!("Interrupted; stopping (press Ctrl-C again to exit immediately)");
+        cancel.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            util::remove_partial_writes();
+            std::process::exit(i32::from(EXIT_CANCELLED));
+        }
+    });
+}
+
+/// Exit status after Ctrl-C, following the shell's 128 + SIGINT convention.
+const EXIT_CANCELLED: u8 = 130;
+
+const EXIT_CODES_HELP: &str = "\
+Exit status:
+    0    success
+    1    any other failure
+    2    invalid arguments or input
+    3    source unreachable or answered with a client error (4xx); retrying will not help
+    4    download broke off, size mismatch or inconsistent hashing
+    5    no usable trackers could be gathered
+    6    reading or writing a file failed
+    7    verification found a mismatch, a webseed failed --require-all-webseeds, or too few passed --min-webseeds
+    8    transient network failure (timeout, connection error, 5xx, 429); worth retrying
+    130  interrupted";
+
+/// What kind of failure ended the run. Each class has its own exit status,
+/// listed in [`EXIT_CODES_HELP`], so scripts can decide whether to retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureClass {
+    Other,
+    InvalidInput,
+    Source,
+    Stream,
+    Trackers,
+    Io,
+    Mismatch,
+    Transient,
+    Cancelled,
+}
+
+impl FailureClass {
+    /// Classifies by the first library error in the chain. Network failures
+    /// that might pass on a retry are reported as transient whichever step
+    /// they hit.
+    fn of(err: &anyhow::Error) -> Self {
+        let Some(err) = err.chain().find_map(|cause| cause.downcast_ref::<TorseedError>()) else {
+            return Self::Other;
+        };
+        if err.is_transient() {
+            return Self::Transient;
+        }
+        match err {
+            TorseedError::Cancelled => Self::Cancelled,
+            TorseedError::InvalidInput(_) => Self::InvalidInput,
+            TorseedError::Metadata { .. } => Self::Source,
+            TorseedError::Stream { .. } | TorseedError::Hashing(_) | TorseedError::Resume(_) => Self::Stream,
+            TorseedError::Trackers(_) => Self::Trackers,
+            TorseedError::Io { .. } => Self::Io,
+            TorseedError::Mismatch(_) => Self::Mismatch,
+            _ => Self::Other,
+        }
+    }
+
+    fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::InvalidInput => 2,
+            Self::Source => 3,
+            Self::Stream => 4,
+            Self::Trackers => 5,
+            Self::Io => 6,
+            Self::Mismatch => 7,
+            Self::Transient => 8,
+            Self::Cancelled => EXIT_CANCELLED,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::Other => "failed",
+            Self::InvalidInput => "invalid arguments",
+            Self::Source => "source unreachable or rejected",
+            Self::Stream => "download or hashing failed",
+            Self::Trackers => "tracker gathering failed",
+            Self::Io => "file I/O failed",
+            Self::Mismatch => "verification failed",
+            Self::Transient => "transient network failure",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// How a `create` run reports back, from flags outside [`CreateArgs`].
+struct Reporting<'a> {
+    progress: events::ProgressOutput,
+    log_file: Option<&'a Path>,
+    palette: color::Palette,
+    units: ByteUnits,
+}
+
+async fn create(
+    client: &Client,
+    http_options: &HttpOptions,
+    cli: CreateArgs,
+    reporting: Reporting<'_>,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let started = Instant::now();
+    let (events, event_log) = spawn_event_log(reporting.progress.clone(), reporting.units);
+    // A multi-file source stands in as its webseed directory, name and total
+    // size; everything up to the build treats it like a single source.
+    let (mut primary_meta, parts) = match &cli.multi {
+        Some(template) => {
+            let spinner = reporting.progress.spinner("Probing parts");
+            let multi = probe_parts(client, http_options, template, cli.max_expanded_urls, cancel).await?;
+            spinner.finish_and_clear();
+            (multi.meta, Some(multi.parts))
+        }
+        None => {
+            let primary_url = cli.primary_url.as_deref().context("Missing source URL")?;
+            let primary_url = parse_url(primary_url)?;
+            info!("Primary URL: {}", primary_url);
+            http_options.allow_netrc_default(&primary_url);
+            let meta = until_cancelled(cancel, http::head_source(client, http_options, primary_url.clone()))
+                .await?
+                .with_context(|| format!("Failed to fetch metadata for {primary_url}"))?;
+            (meta, None)
+        }
+    };
+    // The builder enforces the cap too; checking here fails before trackers
+    // are gathered and mirrors probed.
+    if let Some(limit) = cli.max_download_size
+        && primary_meta.content_length > limit
+    {
+        return Err(TorseedError::InvalidInput(format!(
+            "{} is {}, over --max-download-size {}",
+            primary_meta.url,
+            reporting.units.format(primary_meta.content_length),
+            reporting.units.format(limit)
+        ))
+        .into());
+    }
+    if cli.ascii_names {
+        primary_meta.filename = util::sanitize_ascii_filename(&primary_meta.filename);
+    }
+    // A matched torrent keeps the reference's name, which also names the outputs.
+    let reference = match &cli.match_torrent {
+        Some(path) => {
+            let bytes = fs::read(path).with_context(|| format!("Failed to read torrent file {}", path.display()))?;
+            let parsed = metainfo::parse(&bytes).with_context(|| format!("Invalid torrent file {}", path.display()))?;
+            primary_meta.filename = sanitize_filename(&parsed.name_lossy());
+            primary_meta.original_filename = None;
+            Some(bytes)
+        }
+        None => None,
+    };
+
+    // Torrents are currently single-file, so any selection is rejected here
+    // before the download starts rather than after hashing.
+    let select_only = match cli.magnet_select.as_deref() {
+        Some(spec) => Some(magnet::parse_select_spec(spec, 1)?),
+        None => None,
+    };
+
+    let torrent_url = cli
+        .torrent_url
+        .as_deref()
+        .map(|value| parse_url(value).map(|url| url.to_string()))
+        .transpose()?;
+
+    let publish_targets = cli
+        .publish
+        .iter()
+        .map(|value| parse_url(value))
+        .collect::<Result<Vec<_>>>()?;
+    let publish_magnet_targets = cli
+        .publish_magnet
+        .iter()
+        .map(|value| parse_url(value))
+        .collect::<Result<Vec<_>>>()?;
+    let publish_headers: HeaderMap = cli.publish_header.into_iter().collect();
+    let qbittorrent = cli
+        .qbittorrent
+        .as_deref()
+        .map(|value| {
+            parse_url(value).map(|url| qbittorrent::WebUi {
+                url,
+                username: cli.qbittorrent_user.clone(),
+                password: cli.qbittorrent_pass.clone(),
+                cookie: cli.qbittorrent_cookie.clone(),
+            })
+        })
+        .transpose()?;
+    let transmission = cli
+        .transmission
+        .as_deref()
+        .map(|value| {
+            parse_url(value).map(|url| {
+                let rpc = TransmissionRpc::new(url);
+                match &cli.transmission_user {
+                    Some(user) => rpc.basic_auth(user, cli.transmission_pass.clone().unwrap_or_default()),
+                    None => rpc,
+                }
+            })
+        })
+        .transpose()?;
+    let notify_endpoints = cli.notify.endpoints()?;
+
+    let mut webseeds: Vec<String> = Vec::new();
+    webseeds.push(primary_meta.url.to_string());
+
+    let mut extra_urls: Vec<Url> = Vec::new();
+    for value in cli.extra_urls {
+        let url = parse_url(&value)?;
+        extra_urls.push(url);
+    }
+
+    // Webseeds are verified, and --require-all-webseeds and --min-webseeds
+    // enforced, before anything is downloaded: a torrent that could not be
+    // published must fail before the expensive part of the run.
+    let spinner = reporting.progress.spinner("Checking webseeds");
+    // A multi-file webseed is a directory; its first part stands in for it.
+    let range_probe_url = match parts.as_deref() {
+        Some([first, ..]) => &first.source.url,
+        _ => &primary_meta.url,
+    };
+    let (primary_ranges, webseed_checks) = tokio::join!(
+        until_cancelled(cancel, http::probe_ranges(client, http_options, range_probe_url)),
+        http::verify_webseeds(client, http_options, primary_meta.content_length, extra_urls, &events, cancel),
+    );
+    let (primary_ranges, mut webseed_checks) = (primary_ranges?, webseed_checks?);
+    spinner.finish_and_clear();
+    if primary_ranges != http::RangeSupport::Yes {
+        warn!(
+            "{} may not honor Range requests (ranges: {}); clients could fetch whole files from it",
+            primary_meta.url,
+            primary_ranges.name()
+        );
+    }
+    if cli.require_ranges {
+        for check in &mut webseed_checks {
+            if check.rejection.is_none() && check.ranges != http::RangeSupport::Yes {
+                let rejection = http::WebseedRejection::Ranges { support: check.ranges };
+                warn!("Skipping webseed {}: {rejection}", check.url);
+                check.rejection = Some(rejection);
+            }
+        }
+    }
+    let rejected: Vec<String> = webseed_checks
+        .iter()
+        .filter_map(|check| Some(format!("{} ({})", check.url, check.rejection.as_ref()?)))
+        .collect();
+    if cli.require_all_webseeds && !rejected.is_empty() {
+        return Err(TorseedError::Mismatch(format!(
+            "{} of {} webseeds rejected: {}",
+            rejected.len(),
+            webseed_checks.len(),
+            rejected.join("; ")
+        ))
+        .into());
+    }
+    for check in &webseed_checks {
+        if check.rejection.is_none() {
+            webseeds.push(check.url.to_string());
+        }
+    }
+    if let Some(min) = cli.min_webseeds
+        && (webseeds.len() as u64) < min
+    {
+        let rejected = if rejected.is_empty() {
+            "no mirrors were rejected".to_string()
+        } else {
+            format!("rejected: {}", rejected.join("; "))
+        };
+        return Err(TorseedError::Mismatch(format!(
+            "Only {} of the required {min} webseeds verified, counting the primary URL; {rejected}",
+            webseeds.len()
+        ))
+        .into());
+    }
+    let query_strip = if cli.strip_query {
+        QueryStrip::All
+    } else if !cli.strip_query_params.is_empty() {
+        QueryStrip::Params(cli.strip_query_params.clone())
+    } else {
+        QueryStrip::Keep
+    };
+    let stored_url = |url: &str| Url::parse(url).map_or_else(|_| url.to_string(), |url| query_strip.apply(&url).into());
+    let mut stored_webseeds: Vec<String> = webseeds.iter().map(|url| stored_url(url)).collect();
+    check_stripped_webseeds(client, http_options, primary_meta.content_length, &webseeds, &stored_webseeds, cancel).await?;
+
+    let imported_tiers = match &cli.trackers_from {
+        Some(path) => {
+            let bytes = fs::read(path)
+                .with_context(|| format!("Failed to read torrent file {}", path.display()))?;
+            metainfo::read_announce_tiers(&bytes)
+                .with_context(|| format!("Failed to read trackers from {}", path.display()))?
+        }
+        None => Vec::new(),
+    };
+
+    let tracker_options = cli.tracker.to_options(imported_tiers)?;
+    let spinner = reporting.progress.spinner("Gathering trackers");
+    let mut gathered = trackers::gather_trackers(client, http_options, &tracker_options, cancel)
+        .await
+        .context("Failed to gather tracker list")?;
+    spinner.finish_and_clear();
+
+    if cli.check_trackers {
+        let check_options = CheckOptions {
+            timeout: cli.tracker_check_timeout,
+            concurrency: cli.tracker_check_concurrency,
+            deadline: cli.tracker_check_deadline,
+        };
+        let (alive, report) =
+            until_cancelled(cancel, tracker_client::check_trackers(client, &gathered.all(), &check_options)).await?;
+        info!(
+            "Tracker check: {} alive, {} dead, {} unchecked",
+            report.alive,
+            report.dead,
+            report.unchecked
+        );
+        if alive.is_empty() {
+            return Err(TorseedError::Trackers("No trackers passed the liveness check".to_string()).into());
+        }
+        let alive: HashSet<String> = alive.into_iter().collect();
+        gathered.retain(|tracker| alive.contains(tracker));
+        gathered.check = Some(report);
+    }
+    cli.tracker.dump(&gathered)?;
+    let trackers = gathered.all();
+    events.emit(Event::TrackersGathered {
+        trackers: trackers.len(),
+        tiers: gathered.tiers.len(),
+    });
+    // Browser clients time out stepping through trackers they cannot reach,
+    // so the WebTorrent copy lists the WebSocket ones alone.
+    let webtorrent_tiers = cli.webtorrent.then(|| trackers::webtorrent_tiers(&gathered.tiers));
+    if webtorrent_tiers.as_ref().is_some_and(Vec::is_empty) {
+        return Err(TorseedError::InvalidInput(
+            "--webtorrent needs at least one ws:// or wss:// tracker; add one with --tracker".to_string(),
+        )
+        .into());
+    }
+
+    let setup_elapsed = started.elapsed();
+    // Templates naming the torrent by its infohash can only be expanded once
+    // it is built; the placeholder path below is replaced then.
+    let deferred_template = cli.output_template.as_ref().filter(|template| template.needs_infohash());
+    let early_values = TemplateValues {
+        name: &primary_meta.filename,
+        infohash_v1: None,
+        infohash_v2: None,
+        creation_date: std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64),
+        size: primary_meta.content_length,
+    };
+    let template = cli.output_template.as_ref().map(|template| (template, &early_values));
+    let mut output_path = compute_output_path(cli.output, template, &primary_meta.filename);
+    let mut magnet_path = (!cli.no_magnet_file)
+        .then(|| cli.magnet_file.clone().unwrap_or_else(|| magnet_output_path(&output_path)));
+    // Checked before hashing so a clash does not cost a full download.
+    let overwrite = OverwritePolicy::from_flags(cli.force, cli.backup);
+    let mut emit: Vec<MetaVersion> = Vec::new();
+    for &version in &cli.emit {
+        if !emit.contains(&version) {
+            emit.push(version);
+        }
+    }
+    if deferred_template.is_none() {
+        overwrite.check_outputs(&torrent_paths(&output_path, &emit), magnet_path.as_deref(), cli.append_magnets)?;
+        if cli.webtorrent {
+            let (path, magnet) = webtorrent_paths(&output_path, magnet_path.is_some());
+            overwrite.check_outputs(&[path], magnet.as_deref(), cli.append_magnets)?;
+        }
+    }
+
+    let builder = match parts {
+        Some(parts) => TorrentBuilder::from_parts(primary_meta.filename.clone(), parts),
+        None => TorrentBuilder::new(primary_meta.clone()),
+    };
+    let mut builder = builder
+        .announce_tiers(gathered.tiers.clone())
+        .webseeds(stored_webseeds.clone())
+        .http_options(http_options.clone())
+        .events(events.clone())
+        .cancel_token(cancel.clone());
+    if let Some(piece_length) = cli.piece_length {
+        builder = builder.piece_length(piece_length);
+    }
+    if let Some(target) = cli.target_pieces {
+        builder = builder.target_pieces(target);
+    }
+    if let Some(path) = &cli.resume {
+        builder = builder.resume_file(path);
+    }
+    if !cli.checksums.is_empty() {
+        builder = builder.checksums(cli.checksums.iter().copied());
+    }
+    builder = builder
+        .digest_header(cli.digest_header)
+        .name_utf8(cli.name_utf8)
+        .truncate_overlong(cli.truncate_overlong);
+    if let Some(limit) = cli.max_download_size {
+        builder = builder.max_download_size(limit);
+    }
+    match cli.cache.as_ref().filter(|_| !cli.no_cache) {
+        Some(_) if cli.multi.is_some() => {
+            warn!("--cache only applies to single-URL sources; not caching --multi parts")
+        }
+        Some(dir) => builder = builder.cache(DownloadCache::new(dir).revalidate(cli.revalidate)),
+        None => {}
+    }
+    if let Some(path) = &cli.save {
+        builder = builder.save_payload(path);
+    }
+    if let Some(reference) = reference {
+        builder = builder.match_torrent(reference);
+    }
+    if let Some(created_by) = cli.created_by.value() {
+        builder = builder.created_by(created_by);
+    }
+    #[cfg(feature = "http3")]
+    {
+        builder = builder.http3(cli.http3);
+    }
+    let torrent = builder.build(client).await?;
+    if let Some(path) = &cli.match_torrent {
+        info!("All {} pieces match {}", torrent.input.pieces.len() / 20, path.display());
+    }
+    let mut build_summary = BuildSummary::new(&torrent, &gathered, Vec::new(), Vec::new());
+    let (mut build_input, mut metainfo, transfer, checksums) =
+        (torrent.input, torrent.metainfo, torrent.transfer, torrent.checksums);
+    // The mirrors were verified against the HEAD size; when the download
+    // served a different one, they are checked again against that, including
+    // those skipped for not matching the HEAD size.
+    let served_length = transfer.served_length.unwrap_or(primary_meta.content_length);
+    if cli.multi.is_none() && cli.match_torrent.is_none() && served_length != primary_meta.content_length {
+        primary_meta.content_length = served_length;
+        let candidates: Vec<Url> = webseed_checks
+            .iter()
+            .filter(|check| matches!(check.rejection, None | Some(http::WebseedRejection::LengthMismatch { .. })))
+            .map(|check| check.url.clone())
+            .collect();
+        if !candidates.is_empty() {
+            warn!(
+                "Checking {} webseeds again against the corrected size of {}",
+                candidates.len(),
+                reporting.units.format(served_length)
+            );
+        }
+        let rechecked = http::verify_webseeds(client, http_options, served_length, candidates, &events, cancel).await?;
+        let mut dropped = Vec::new();
+        let mut changed = false;
+        for mut recheck in rechecked {
+            if cli.require_ranges && recheck.rejection.is_none() && recheck.ranges != http::RangeSupport::Yes {
+                recheck.rejection = Some(http::WebseedRejection::Ranges { support: recheck.ranges });
+            }
+            let index = webseeds.iter().position(|url| url.as_str() == recheck.url.as_str());
+            match (&recheck.rejection, index) {
+                (Some(_), Some(index)) => {
+                    webseeds.remove(index);
+                    dropped.push(stored_webseeds.remove(index));
+                    changed = true;
+                }
+                (None, None) => {
+                    info!("Adding webseed {}: it serves the corrected size", recheck.url);
+                    webseeds.push(recheck.url.to_string());
+                    stored_webseeds.push(stored_url(recheck.url.as_str()));
+                    changed = true;
+                }
+                _ => {}
+            }
+            if let Some(check) = webseed_checks.iter_mut().find(|check| check.url == recheck.url) {
+                *check = recheck;
+            }
+        }
+        if cli.require_all_webseeds && !dropped.is_empty() {
+            return Err(TorseedError::Mismatch(format!(
+                "{} webseeds do not serve the corrected size of {} bytes: {}",
+                dropped.len(),
+                served_length,
+                dropped.join(", ")
+            ))
+            .into());
+        }
+        if let Some(min) = cli.min_webseeds
+            && (webseeds.len() as u64) < min
+        {
+            return Err(TorseedError::Mismatch(format!(
+                "Only {} of the required {min} webseeds serve the corrected size, counting the primary URL",
+                webseeds.len()
+            ))
+            .into());
+        }
+        if changed {
+            build_input.webseeds = stored_webseeds.clone();
+            metainfo = metainfo::build(&build_input)?;
+        }
+    }
+    if let Some(template) = deferred_template {
+        let values = TemplateValues {
+            name: &build_input.name,
+            infohash_v1: metainfo.infohash_v1,
+            infohash_v2: metainfo.infohash_v2,
+            creation_date: build_input.creation_date,
+            size: build_input.length,
+        };
+        output_path = compute_output_path(None, Some((template, &values)), &build_input.name);
+        if cli.magnet_file.is_none() && magnet_path.is_some() {
+            magnet_path = Some(magnet_output_path(&output_path));
+        }
+        overwrite.check_outputs(&torrent_paths(&output_path, &emit), magnet_path.as_deref(), cli.append_magnets)?;
+        if cli.webtorrent {
+            let (path, magnet) = webtorrent_paths(&output_path, magnet_path.is_some());
+            overwrite.check_outputs(&[path], magnet.as_deref(), cli.append_magnets)?;
+        }
+    }
+    let webtorrent_paths = cli.webtorrent.then(|| webtorrent_paths(&output_path, magnet_path.is_some()));
+    // Every --emit flavor is built from the same hashes; the first stands in
+    // for the torrent from here on, in uploads, clients and the report.
+    let mut variants = Vec::new();
+    for (&version, path) in emit.iter().zip(torrent_paths(&output_path, &emit)) {
+        variants.push((version, path, metainfo::build_version(&build_input, version)?));
+    }
+    if let Some((_, path, primary)) = variants.first() {
+        output_path = path.clone();
+        metainfo = primary.clone();
+        build_summary.infohash_v1 = metainfo.infohash_v1;
+        build_summary.infohash_v2 = metainfo.infohash_v2;
+    }
+    // Trackers sit outside the info dictionary, so the primary flavor built
+    // with other tiers keeps its infohashes.
+    let mut webtorrent = match (webtorrent_tiers, webtorrent_paths) {
+        (Some(tiers), Some((path, magnet_path))) => {
+            let input = BuildInput {
+                announce_tiers: tiers.clone(),
+                ..build_input.clone()
+            };
+            Some(Webtorrent {
+                path,
+                magnet_path,
+                metainfo: metainfo::build_version(&input, meta_version(&metainfo))?,
+                trackers: tiers.concat(),
+                magnets: Vec::new(),
+            })
+        }
+        _ => None,
+    };
+
+    // Past this point the outputs are written as a set; an interrupt during
+    // the build must not leave a torrent without its magnet file.
+    ensure_not_cancelled(cancel)?;
+    overwrite.prepare(&output_path)?;
+    write_torrent(&output_path, &metainfo.torrent)?;
+    events.emit(Event::TorrentWritten {
+        path: output_path.clone(),
+    });
+    for (_, path, variant) in variants.iter().skip(1) {
+        overwrite.prepare(path)?;
+        write_torrent(path, &variant.torrent)?;
+        events.emit(Event::TorrentWritten { path: path.clone() });
+    }
+    if let Some(webtorrent) = &webtorrent {
+        overwrite.prepare(&webtorrent.path)?;
+        write_torrent(&webtorrent.path, &webtorrent.metainfo.torrent)?;
+        events.emit(Event::TorrentWritten {
+            path: webtorrent.path.clone(),
+        });
+    }
+    drop(events);
+    let _ = event_log.await;
+
+    if let Some(path) = &cli.pieces_out {
+        pieces::write_pieces(
+            path,
+            cli.pieces_format,
+            build_input.piece_length,
+            &build_input.pieces,
+            build_input.v2.as_ref(),
+        )?;
+        info!("Piece hashes written to {}", path.display());
+    }
+    if let Some(path) = &cli.checksums_out {
+        util::write_atomic(path, checksum::sums_file(&checksums, &build_input.name).as_bytes())
+            .with_context(|| format!("Failed to write checksums to {}", path.display()))?;
+        info!("Checksums written to {}", path.display());
+    }
+
+    let magnet_options = MagnetOptions {
+        torrent_url,
+        direct_source: cli.magnet_as.then(|| stored_url(primary_meta.url.as_str())),
+        peers: cli.peers,
+        select_only,
+        ..cli.magnet_format.to_options()
+    };
+    let magnets_for = |metainfo: &metainfo::Metainfo| {
+        build_magnets(
+            &build_input.name,
+            Some(build_input.length),
+            &trackers,
+            &stored_webseeds,
+            metainfo.infohash_v1,
+            metainfo.infohash_v2,
+            &magnet_options,
+        )
+    };
+    // Grouped by flavor, in --emit order.
+    let magnets: Vec<String> = if variants.is_empty() {
+        magnets_for(&metainfo)
+    } else {
+        variants.iter().flat_map(|(_, _, variant)| magnets_for(variant)).collect()
+    };
+
+    if let Some(path) = &magnet_path {
+        if !cli.append_magnets {
+            overwrite.prepare(path)?;
+        }
+        write_magnet_file(path, &magnets, cli.append_magnets)?;
+    }
+    if let Some(webtorrent) = &mut webtorrent {
+        webtorrent.magnets = build_magnets(
+            &build_input.name,
+            Some(build_input.length),
+            &webtorrent.trackers,
+            &stored_webseeds,
+            webtorrent.metainfo.infohash_v1,
+            webtorrent.metainfo.infohash_v2,
+            &magnet_options,
+        );
+        if let Some(path) = &webtorrent.magnet_path {
+            if !cli.append_magnets {
+                overwrite.prepare(path)?;
+            }
+            write_magnet_file(path, &webtorrent.magnets, cli.append_magnets)?;
+        }
+    }
+
+    // Uploads happen only once the local files are safely written, so a
+    // failed upload never costs the torrent itself.
+    let torrent_filename = output_path
+        .file_name()
+        .map_or_else(|| format!("{}.torrent", build_input.name), |name| name.to_string_lossy().into_owned());
+    let mut published = publish::publish(
+        client,
+        &publish_targets,
+        cli.publish_method,
+        &publish_headers,
+        &publish::Upload {
+            field: "torrent",
+            filename: &torrent_filename,
+            content_type: "application/x-bittorrent",
+            bytes: &metainfo.torrent,
+        },
+    )
+    .await;
+    let magnet_text = format!("{}\n", magnets.join("\n"));
+    published.extend(
+        publish::publish(
+            client,
+            &publish_magnet_targets,
+            cli.publish_method,
+            &publish_headers,
+            &publish::Upload {
+                field: "magnet",
+                filename: &magnet_output_path(Path::new(&torrent_filename)).to_string_lossy(),
+                content_type: "text/plain; charset=utf-8",
+                bytes: magnet_text.as_bytes(),
+            },
+        )
+        .await,
+    );
+
+    let qbittorrent_added = match &qbittorrent {
+        Some(webui) => Some(
+            qbittorrent::add_torrent(
+                client,
+                webui,
+                &torrent_filename,
+                &metainfo.torrent,
+                &qbittorrent::AddOptions {
+                    save_path: cli.qbittorrent_savepath.as_deref(),
+                    category: cli.qbittorrent_category.as_deref(),
+                },
+            )
+            .await,
+        ),
+        None => None,
+    };
+    let transmission_added = match transmission {
+        Some(mut rpc) => Some(
+            rpc.torrent_add(client, &metainfo.torrent, cli.transmission_download_dir.as_deref())
+                .await
+                .map_err(anyhow::Error::from),
+        ),
+        None => None,
+    };
+
+    let timings = Timings {
+        total: started.elapsed(),
+        setup: setup_elapsed,
+        transfer,
+    };
+    build_summary.webseeds = WebseedReport::from_checks(&webseeds[0], primary_ranges, &webseed_checks);
+    for report in &mut build_summary.webseeds {
+        let stored = stored_url(&report.url);
+        if stored != report.url {
+            report.fetched_url = Some(std::mem::replace(&mut report.url, stored));
+        }
+    }
+    build_summary.magnets = magnets.clone();
+    let report = RunReport {
+        schema: summary::REPORT_SCHEMA,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        build: build_summary,
+        torrent_path: output_path.display().to_string(),
+        magnet_path: magnet_path.as_ref().map(|path| path.display().to_string()),
+        pieces_path: cli.pieces_out.as_ref().map(|path| path.display().to_string()),
+        webtorrent: webtorrent.as_ref().map(|webtorrent| WebtorrentReport {
+            torrent_path: webtorrent.path.display().to_string(),
+            magnet_path: webtorrent.magnet_path.as_ref().map(|path| path.display().to_string()),
+            trackers: webtorrent.trackers.clone(),
+            magnets: webtorrent.magnets.clone(),
+        }),
+        timings: RunTimings {
+            total_secs: timings.total.as_secs_f64(),
+            setup_secs: timings.setup.as_secs_f64(),
+            transfer_secs: timings.transfer.elapsed.as_secs_f64(),
+            transfer_bytes: timings.transfer.bytes,
+        },
+    }
+    .to_json();
+    if let Some(path) = &cli.summary_out {
+        util::write_atomic(path, format!("{report:#}\n").as_bytes())
+            .with_context(|| format!("Failed to write the run report to {}", path.display()))?;
+    }
+
+    if cli.json {
+        println!("{report:#}");
+    } else {
+        let summary = Summary {
+            output_path: &output_path,
+            build_input: &build_input,
+            renamed_from: primary_meta.original_filename.as_deref(),
+            matched: cli.match_torrent.as_deref(),
+            metainfo: &metainfo,
+            variants: &variants,
+            trackers: &gathered,
+            webseeds: &webseeds,
+            stored_webseeds: &stored_webseeds,
+            primary_ranges,
+            webseed_checks: &webseed_checks,
+            checksums: &checksums,
+            magnets: &magnets,
+            magnet_path: magnet_path.as_deref(),
+            webtorrent: webtorrent.as_ref(),
+            peers: &magnet_options.peers,
+            published: &published,
+            qbittorrent: qbittorrent_added.as_ref(),
+            transmission: transmission_added.as_ref(),
+            log_file: reporting.log_file,
+            timings,
+        };
+        print!("{}", render_summary(&summary, reporting.palette, reporting.units));
+    }
+
+    if cli.qr || cli.qr_png.is_some() {
+        let max_trackers = cli.qr_trackers.min(magnet_options.max_trackers);
+        let fitted = qr::fit_magnet(max_trackers, |count| {
+            let options = MagnetOptions {
+                max_trackers: count,
+                ..magnet_options.clone()
+            };
+            build_magnets(
+                &build_input.name,
+                Some(build_input.length),
+                &trackers,
+                &stored_webseeds,
+                metainfo.infohash_v1,
+                metainfo.infohash_v2,
+                &options,
+            )
+            .swap_remove(0)
+        });
+        match fitted {
+            Some((uri, code, count)) => {
+                if count < max_trackers {
+                    info!("QR magnet limited to {count} trackers to fit the QR payload");
+                }
+                if cli.qr {
+                    println!("{}", qr::render_terminal(&code));
+                    println!("QR magnet: {uri}");
+                }
+                if let Some(path) = &cli.qr_png {
+                    qr::write_png(&code, path)?;
+                    info!("QR code written to {}", path.display());
+                }
+            }
+            None => warn!("Magnet link is too long for a QR code even without trackers; skipping QR output"),
+        }
+    }
+
+    if cli.copy {
+        copy_magnet(&magnets[0]);
+    }
+
+    if cli.announce_once {
+        if let Some(infohash) = metainfo.infohash_v1 {
+            until_cancelled(
+                cancel,
+                announce_once(client, &trackers, infohash, cli.announce_trackers, cli.announce_port),
+            )
+            .await?;
+        } else {
+            warn!("Skipping --announce-once: torrent has no v1 infohash");
+        }
+    }
+
+    if cli.scrape_after {
+        if let Some(infohash) = metainfo.infohash_v1 {
+            let results = until_cancelled(
+                cancel,
+                tracker_client::scrape_trackers(client, &trackers, infohash, &cli.scrape.to_options()),
+            )
+            .await?;
+            print_scrape_results(&results);
+        } else {
+            warn!("Skipping --scrape-after: torrent has no v1 infohash");
+        }
+    }
+
+    let failed = published.iter().filter(|result| !result.succeeded()).count();
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} publish uploads failed", published.len());
+    }
+    if cli.strict_integrations {
+        if let Some(Err(err)) = qbittorrent_added {
+            return Err(err.context("Failed to add the torrent to qBittorrent"));
+        }
+        if let Some(Err(err)) = transmission_added {
+            return Err(err.context("Failed to add the torrent to Transmission"));
+        }
+    }
+
+    if !notify_endpoints.is_empty() {
+        let mut payload = report;
+        payload["status"] = json!("success");
+        notify::notify(client, &notify_endpoints, cli.notify.notify_secret.as_deref(), &payload).await;
+    }
+    Ok(())
+}
+
+/// Rebuilds a torrent from a magnet by downloading the payload from its first
+/// reachable webseed, then checks the result against the magnet's infohashes.
+async fn run_from_magnet(
+    client: &Client,
+    http_options: &HttpOptions,
+    args: FromMagnetArgs,
+    units: ByteUnits,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let magnet = magnet::parse_magnet(&args.magnet)?;
+    if magnet.infohash_v1.is_none() && magnet.infohash_v2.is_none() {
+        anyhow::bail!("Magnet URI has no btih or btmh infohash");
+    }
+    if magnet.webseeds.is_empty() {
+        anyhow::bail!("Magnet URI has no ws= webseeds; the payload can only be fetched over HTTP");
+    }
+
+    let mut source = None;
+    for webseed in &magnet.webseeds {
+        let url = match parse_url(webseed) {
+            Ok(url) => url,
+            Err(err) => {
+                warn!("Skipping webseed {webseed}: {err:#}");
+                continue;
+            }
+        };
+        match until_cancelled(cancel, http::head_source(client, http_options, url)).await? {
+            Ok(meta) => {
+                source = Some(meta);
+                break;
+            }
+            Err(err) => warn!("Skipping webseed {webseed}: {err:#}"),
+        }
+    }
+    let source = source.context("None of the magnet's webseeds are reachable")?;
+    info!("Downloading from {}", source.url);
+
+    let (events, event_log) = spawn_event_log(events::ProgressOutput::Log, units);
+    let mut builder = TorrentBuilder::new(source)
+        .webseeds(magnet.webseeds.clone())
+        .http_options(http_options.clone())
+        .events(events.clone())
+        .cancel_token(cancel.clone());
+    if let Some(name) = &magnet.name {
+        builder = builder.name(name.clone());
+    }
+    if !magnet.trackers.is_empty() {
+        builder = builder.trackers(magnet.trackers.clone());
+    }
+    let torrent = builder.build(client).await?;
+    let (build_input, metainfo) = (torrent.input, torrent.metainfo);
+
+    if let Some(expected) = magnet.infohash_v1
+        && metainfo.infohash_v1 != Some(expected)
+    {
+        anyhow::bail!(
+            "v1 infohash mismatch: magnet has {}, webseed content hashes to {}",
+            hex::encode(expected),
+            metainfo.infohash_v1.map(hex::encode).unwrap_or_else(|| "nothing".to_string())
+        );
+    }
+    if let Some(expected) = magnet.infohash_v2
+        && metainfo.infohash_v2 != Some(expected)
+    {
+        anyhow::bail!(
+            "v2 infohash mismatch: magnet has {}, webseed content hashes to {}",
+            hex::encode(expected),
+            metainfo.infohash_v2.map(hex::encode).unwrap_or_else(|| "nothing".to_string())
+        );
+    }
+
+    let output_path = compute_output_path(args.output, None, &build_input.name);
+    ensure_not_cancelled(cancel)?;
+    write_torrent(&output_path, &metainfo.torrent)?;
+    events.emit(Event::TorrentWritten {
+        path: output_path.clone(),
+    });
+    drop(events);
+    let _ = event_log.await;
+    println!("Torrent written to {}", output_path.display());
+    println!("Infohash verified against the magnet");
+    Ok(())
+}
+
+/// Places the magnet on the clipboard. Failure (for example on a headless
+/// machine) is only a warning since the torrent has already been written.
+fn copy_magnet(magnet_uri: &str) {
+    if !std::io::stdout().is_terminal() {
+        info!("Skipping --copy: output is not a terminal");
+        return;
+    }
+    match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(magnet_uri)) {
+        Ok(()) => info!("Magnet link copied to clipboard"),
+        Err(err) => warn!("Could not copy magnet link to clipboard: {err}"),
+    }
+}
+
+/// Performs a single started announce to the first `count` trackers. Without an
+/// explicit port the announce is immediately followed by a stopped event so the
+/// swarm does not keep advertising a peer that will never answer.
+async fn announce_once(client: &Client, trackers: &[String], infohash: [u8; 20], count: usize, port: Option<u16>) {
+    const ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    let peer_id = tracker_client::throwaway_peer_id();
+    let mut params = AnnounceParams {
+        infohash,
+        peer_id,
+        port: port.unwrap_or(6881),
+        uploaded: 0,
+        left: 0,
+        event: AnnounceEvent::Started,
+    };
+
+    let targets: Vec<&String> = trackers.iter().filter(|t| !trackers::is_i2p(t)).take(count).collect();
+    let started = futures::future::join_all(
+        targets
+            .iter()
+            .map(|tracker| tracker_client::announce(client, tracker, &params, ANNOUNCE_TIMEOUT)),
+    )
+    .await;
+
+    let mut accepted = Vec::new();
+    for (tracker, result) in targets.iter().zip(started) {
+        match result {
+            Ok(interval) => {
+                info!("Announce accepted by {} (interval {interval}s)", trackers::for_display(tracker));
+                accepted.push(*tracker);
+            }
+            Err(err) => warn!("Announce to {} failed: {:#}", trackers::for_display(tracker), anyhow::Error::from(err)),
+        }
+    }
+    info!("Announce accepted by {} of {} trackers", accepted.len(), targets.len());
+
+    if port.is_none() {
+        params.event = AnnounceEvent::Stopped;
+        futures::future::join_all(
+            accepted
+                .iter()
+                .map(|tracker| tracker_client::announce(client, tracker, &params, ANNOUNCE_TIMEOUT)),
+        )
+        .await;
+    }
+}
+
+async fn run_scrape(client: &Client, args: ScrapeArgs, cancel: &CancellationToken) -> Result<()> {
+    let (infohash, trackers) = if args.target.starts_with("magnet:") {
+        let magnet = magnet::parse_magnet(&args.target)?;
+        let infohash = magnet
+            .tracker_infohash()
+            .context("Magnet URI has no usable infohash")?;
+        (infohash, magnet.trackers)
+    } else {
+        let bytes = fs::read(&args.target).with_context(|| format!("Failed to read torrent file {}", args.target))?;
+        let infohash = metainfo::tracker_infohash(&bytes)?;
+        let trackers: Vec<String> = metainfo::read_announce_tiers(&bytes)?.into_iter().flatten().collect();
+        (infohash, trackers)
+    };
+
+    if trackers.is_empty() {
+        anyhow::bail!("No trackers to scrape in {}", args.target);
+    }
+
+    info!("Scraping {} trackers for {}", trackers.len(), hex::encode(infohash));
+    let results = until_cancelled(
+        cancel,
+        tracker_client::scrape_trackers(client, &trackers, infohash, &args.options.to_options()),
+    )
+    .await?;
+    print_scrape_results(&results);
+    Ok(())
+}
+
+async fn run_verify(
+    client: &Client,
+    http_options: &HttpOptions,
+    args: VerifyArgs,
+    units: ByteUnits,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let bytes =
+        fs::read(&args.torrent).with_context(|| format!("Failed to read torrent file {}", args.torrent.display()))?;
+    let torrent =
+        metainfo::parse(&bytes).with_context(|| format!("Invalid torrent file {}", args.torrent.display()))?;
+    let url = parse_url(&args.url)?;
+    http_options.allow_netrc_default(&url);
+    let source = until_cancelled(cancel, http::head_source(client, http_options, url))
+        .await?
+        .with_context(|| format!("Failed to fetch metadata for {}", args.url))?;
+
+    let report = match args.pieces {
+        Some(count) => {
+            let count = usize::try_from(count).unwrap_or(usize::MAX);
+            verify::verify_sample(client, http_options, &torrent, &source, count, cancel).await?
+        }
+        None => {
+            let (events, event_log) = spawn_event_log(events::ProgressOutput::Log, units);
+            let report = verify::verify_source(client, http_options, &torrent, &source, &events, cancel).await?;
+            drop(events);
+            let _ = event_log.await;
+            report
+        }
+    };
+
+    println!("Checked {} of {} pieces", report.pieces_checked, report.pieces_total);
+    match report.mismatch {
+        None => {
+            println!("PASS: {} matches {}", source.url, args.torrent.display());
+            Ok(())
+        }
+        Some(mismatch) => {
+            println!("FAIL: {mismatch}");
+            let message = format!("{} does not match {}: {mismatch}", source.url, args.torrent.display());
+            Err(TorseedError::Mismatch(message).into())
+        }
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    init_tracing();
+async fn run_convert(
+    client: &Client,
+    http_options: &HttpOptions,
+    args: ConvertArgs,
+    units: ByteUnits,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let bytes = fs::read(&args.file).with_context(|| format!("Failed to read torrent file {}", args.file.display()))?;
+    let torrent = metainfo::parse(&bytes).with_context(|| format!("Invalid torrent file {}", args.file.display()))?;
+    let output_path = args.output.unwrap_or_else(|| args.file.with_extension("hybrid.torrent"));
+    if output_path == args.file {
+        anyhow::bail!("Refusing to overwrite the original torrent {}", args.file.display());
+    }
 
-    let cli = Cli::parse();
-    let client = build_client()?;
+    let source = match &args.from {
+        Some(from) => {
+            let url = parse_url(from)?;
+            http_options.allow_netrc_default(&url);
+            until_cancelled(cancel, http::head_source(client, http_options, url))
+                .await?
+                .with_context(|| format!("Failed to fetch metadata for {from}"))?
+        }
+        None => until_cancelled(cancel, convert::find_source(client, http_options, &torrent)).await??,
+    };
 
-    let primary_url = parse_url(&cli.primary_url)?;
-    info!("Primary URL: {}", primary_url);
+    let (events, event_log) = spawn_event_log(events::ProgressOutput::Log, units);
+    let converted = convert::to_hybrid(client, http_options, &torrent, &source, &events, cancel).await;
+    drop(events);
+    let _ = event_log.await;
+    let mut converted = converted.with_context(|| format!("Failed to convert {}", args.file.display()))?;
+    if let Some(created_by) = args.created_by.value() {
+        let edits = metainfo::RootEdits {
+            created_by: Some(created_by),
+            ..metainfo::RootEdits::default()
+        };
+        converted.metainfo.torrent = metainfo::edit(&converted.metainfo.torrent, &edits)?;
+    }
 
-    let primary_meta = http::head_source(&client, primary_url.clone())
-        .await
-        .with_context(|| format!("Failed to fetch metadata for {primary_url}"))?;
+    ensure_not_cancelled(cancel)?;
+    write_torrent(&output_path, &converted.metainfo.torrent)?;
+    println!("Hybrid torrent written to {}", output_path.display());
+    let written = metainfo::parse(&converted.metainfo.torrent)?;
+    if let Some(infohash) = written.infohash_v1() {
+        println!("v1 infohash: {}", hex::encode(infohash));
+    }
+    if let Some(infohash) = written.infohash_v2() {
+        println!("v2 infohash: {}", hex::encode(infohash));
+    }
+    Ok(())
+}
 
-    let mut webseeds: Vec<String> = Vec::new();
-    webseeds.push(primary_meta.url.to_string());
+/// One line of a batch list.
+struct BatchEntry {
+    line: usize,
+    url: String,
+    output: Option<PathBuf>,
+}
 
-    let mut extra_urls: Vec<Url> = Vec::new();
-    for value in cli.extra_urls {
-        let url = parse_url(&value)?;
-        extra_urls.push(url);
+/// Outcome of one batch entry, in list order.
+struct BatchOutcome {
+    entry: BatchEntry,
+    result: Result<PathBuf>,
+}
+
+fn read_batch_list(path: &Path) -> Result<Vec<BatchEntry>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read batch list {}", path.display()))?;
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (url, output) = match line.split_once(char::is_whitespace) {
+            Some((url, output)) => (url, Some(PathBuf::from(output.trim()))),
+            None => (line, None),
+        };
+        entries.push(BatchEntry {
+            line: index + 1,
+            url: url.to_string(),
+            output,
+        });
     }
+    Ok(entries)
+}
 
-    let extra_webseeds = verify_webseeds(&client, primary_meta.content_length, extra_urls).await;
-    for url in extra_webseeds {
-        webseeds.push(url.to_string());
+/// Creates one torrent and magnet file per list entry. Trackers are gathered
+/// once and every source is probed before hashing starts, so output name
+/// collisions are reported before anything is downloaded. Failed entries do
+/// not stop the others; the run fails at the end if any entry did.
+async fn run_batch(
+    client: &Client,
+    http_options: &HttpOptions,
+    args: BatchArgs,
+    units: ByteUnits,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let entries = read_batch_list(&args.list)?;
+    if entries.is_empty() {
+        anyhow::bail!("Batch list {} has no entries", args.list.display());
     }
+    let jobs = usize::from(args.jobs);
 
-    let trackers = trackers::gather_trackers(&client)
+    let options = args.tracker.to_options(Vec::new())?;
+    let gathered = trackers::gather_trackers(client, http_options, &options, cancel)
         .await
         .context("Failed to gather tracker list")?;
+    args.tracker.dump(&gathered)?;
+    let trackers = gathered.all();
+    info!("Gathered {} trackers for {} entries", trackers.len(), entries.len());
 
-    let piece_length = choose_piece_length(primary_meta.content_length);
-    info!(
-        "Using v1 piece length {} KiB ({} pieces)",
-        piece_length / 1024,
-        (primary_meta.content_length + piece_length as u64 - 1) / piece_length as u64
-    );
-
-    let mut v1_hasher = V1Hasher::new(piece_length);
-    let mut v2_hasher = V2Hasher::new().context("Failed to initialize v2 hasher")?;
-    let mut total_bytes: u64 = 0;
+    let probes: Vec<_> = until_cancelled(
+        cancel,
+        stream::iter(entries.iter().map(|entry| async move {
+            let url = parse_url(&entry.url)?;
+            http_options.allow_netrc_default(&url);
+            http::head_source(client, http_options, url)
+                .await
+                .with_context(|| format!("Failed to fetch metadata for {}", entry.url))
+        }))
+        .buffered(jobs)
+        .collect(),
+    )
+    .await?;
 
-    let response = http::stream(&client, &primary_meta.url)
-        .await
-        .with_context(|| format!("Failed to stream data from {}", primary_meta.url))?;
+    let mut outcomes = Vec::new();
+    let mut planned = Vec::new();
+    let mut claimed: HashMap<PathBuf, usize> = HashMap::new();
+    let mut collisions = Vec::new();
+    for (entry, probe) in entries.into_iter().zip(probes) {
+        let source = match probe {
+            Ok(source) => source,
+            Err(err) => {
+                outcomes.push(BatchOutcome {
+                    entry,
+                    result: Err(err),
+                });
+                continue;
+            }
+        };
+        let output_path = match &entry.output {
+            Some(path) => path.clone(),
+            None => args.output_dir.join(compute_output_path(None, None, &source.filename)),
+        };
+        let mut paths = vec![output_path.clone()];
+        if !args.no_magnet_file {
+            paths.push(magnet_output_path(&output_path));
+        }
+        for path in paths {
+            if let Some(first) = claimed.insert(path.clone(), entry.line) {
+                collisions.push(format!(
+                    "lines {first} and {} both write {}",
+                    entry.line,
+                    path.display()
+                ));
+            }
+        }
+        planned.push((entry, source, output_path));
+    }
+    if !collisions.is_empty() {
+        anyhow::bail!("Output paths collide: {}", collisions.join("; "));
+    }
 
-    let mut stream = response.bytes_stream();
-    let mut last_log = Instant::now();
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk.with_context(|| "Error while reading HTTP stream")?;
-        total_bytes += chunk.len() as u64;
-        v1_hasher.update(&chunk);
-        v2_hasher
-            .update(&chunk)
-            .context("Failed while hashing for v2")?;
+    let magnet_options = args.magnet_format.to_options();
+    let runs = planned.into_iter().map(|(entry, source, output_path)| {
+        let span = tracing::info_span!("batch", entry = %source.filename);
+        let (trackers, tiers, magnet_options) = (&trackers, &gathered.tiers, &magnet_options);
+        let (piece_length, target_pieces, write_magnet) = (args.piece_length, args.target_pieces, !args.no_magnet_file);
+        let created_by = args.created_by.value();
+        async move {
+            let (events, event_log) = spawn_event_log(events::ProgressOutput::Log, units);
+            let mut builder = TorrentBuilder::new(source)
+                .announce_tiers(tiers.clone())
+                .http_options(http_options.clone())
+                .events(events.clone())
+                .cancel_token(cancel.clone());
+            if let Some(piece_length) = piece_length {
+                builder = builder.piece_length(piece_length);
+            }
+            if let Some(target) = target_pieces {
+                builder = builder.target_pieces(target);
+            }
+            if let Some(created_by) = created_by {
+                builder = builder.created_by(created_by);
+            }
+            let result = async {
+                let torrent = builder.build(client).await?;
+                ensure_not_cancelled(cancel)?;
+                write_torrent(&output_path, &torrent.metainfo.torrent)?;
+                events.emit(Event::TorrentWritten {
+                    path: output_path.clone(),
+                });
+                if write_magnet {
+                    let magnets = build_magnets(
+                        &torrent.input.name,
+                        Some(torrent.input.length),
+                        trackers,
+                        &torrent.input.webseeds,
+                        torrent.metainfo.infohash_v1,
+                        torrent.metainfo.infohash_v2,
+                        magnet_options,
+                    );
+                    write_magnet_file(&magnet_output_path(&output_path), &magnets, false)?;
+                }
+                info!("Torrent written to {}", output_path.display());
+                Ok(output_path)
+            }
+            .await;
+            drop(events);
+            let _ = event_log.await;
+            BatchOutcome { entry, result }
+        }
+        .instrument(span)
+    });
+    outcomes.extend(stream::iter(runs).buffer_unordered(jobs).collect::<Vec<_>>().await);
+    outcomes.sort_by_key(|outcome| outcome.entry.line);
 
-        if last_log.elapsed() > Duration::from_secs(15) {
-            let pct = (total_bytes as f64 / primary_meta.content_length as f64) * 100.0;
-            info!("Hashed {:.1}% ({} / {})", pct, format_bytes(total_bytes), format_bytes(primary_meta.content_length));
-            last_log = Instant::now();
+    let failed = outcomes.iter().filter(|outcome| outcome.result.is_err()).count();
+    println!("{:<5} {:<6} RESULT", "LINE", "STATUS");
+    for outcome in &outcomes {
+        match &outcome.result {
+            Ok(path) => println!("{:<5} {:<6} {}", outcome.entry.line, "ok", path.display()),
+            Err(err) => println!("{:<5} {:<6} {}: {err:#}", outcome.entry.line, "failed", outcome.entry.url),
         }
     }
+    println!("{} of {} entries succeeded", outcomes.len() - failed, outcomes.len());
 
-    if total_bytes != primary_meta.content_length {
-        warn!(
-            "Streamed size mismatch: expected {} bytes, got {} bytes",
-            primary_meta.content_length,
-            total_bytes
+    ensure_not_cancelled(cancel)?;
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} batch entries failed", outcomes.len());
+    }
+    Ok(())
+}
+
+/// Serves the payload until Ctrl-C, which is the normal way to stop and so
+/// exits successfully.
+async fn run_serve(args: ServeArgs, cancel: &CancellationToken) -> Result<()> {
+    let bytes =
+        fs::read(&args.torrent).with_context(|| format!("Failed to read torrent file {}", args.torrent.display()))?;
+    let torrent =
+        metainfo::parse(&bytes).with_context(|| format!("Invalid torrent file {}", args.torrent.display()))?;
+    let payload = serve::Payload::open(&torrent, &args.root)?;
+    if let Some(count) = args.check_pieces {
+        payload.spot_check(&torrent, count, cancel)?;
+    }
+
+    let listener = tokio::net::TcpListener::bind(args.listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", args.listen))?;
+    let address = listener.local_addr().context("Failed to read the listening address")?;
+    info!("Serving {} file(s) of {} on http://{address}", payload.len(), torrent.name_lossy());
+    for path in payload.url_paths() {
+        println!("http://{address}{path}");
+    }
+    serve::run(listener, Arc::new(payload), cancel.clone()).await;
+    info!("Server stopped");
+    Ok(())
+}
+
+/// Seeds until Ctrl-C or a stop condition; both end the run successfully.
+async fn run_seed(
+    client: &Client,
+    args: SeedArgs,
+    units: ByteUnits,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let bytes =
+        fs::read(&args.torrent).with_context(|| format!("Failed to read torrent file {}", args.torrent.display()))?;
+    let torrent =
+        metainfo::parse(&bytes).with_context(|| format!("Invalid torrent file {}", args.torrent.display()))?;
+    let options = seed::SeedOptions {
+        port: args.port,
+        seed_time: args.seed_time,
+        seed_ratio: args.seed_ratio,
+        max_peers: args.max_peers,
+        check_pieces: args.check_pieces,
+    };
+    let stats = seed::run(client, &torrent, args.file, &options, cancel).await?;
+    println!("{}", stats.line(units));
+    Ok(())
+}
+
+/// Rewrites the root dictionary of a torrent, keeping its info dictionary and
+/// therefore its infohashes unchanged.
+fn run_edit(args: EditArgs) -> Result<()> {
+    if args.set_name.is_some() || args.set_private.is_some() {
+        anyhow::bail!(
+            "The name and private flag live in the info dictionary; changing them changes the infohash \
+             and creates a different torrent. Create a new torrent instead."
         );
     }
+    let mut webseeds = Vec::new();
+    for value in &args.add_webseed {
+        webseeds.push(parse_url(value)?.to_string());
+    }
+    let edits = metainfo::RootEdits {
+        announce_tiers: None,
+        add_trackers: args.add_tracker,
+        remove_trackers: args.remove_tracker,
+        add_webseeds: webseeds,
+        remove_webseeds: args.remove_webseed,
+        comment: args.set_comment,
+        creation_date: args.set_creation_date,
+        created_by: args.created_by.value(),
+    };
 
-    let pieces = v1_hasher.finalize();
-    let v2_summary = match v2_hasher.finalize(piece_length) {
-        Ok(summary) => Some(summary),
-        Err(err) => {
-            warn!("Falling back to v1-only torrent: {err}");
-            None
+    let bytes = fs::read(&args.file).with_context(|| format!("Failed to read torrent file {}", args.file.display()))?;
+    let edited = metainfo::edit(&bytes, &edits).with_context(|| format!("Failed to edit {}", args.file.display()))?;
+
+    let output_path = match args.output {
+        Some(path) => path,
+        None => {
+            let mut backup = args.file.clone().into_os_string();
+            backup.push(".bak");
+            fs::copy(&args.file, &backup)
+                .with_context(|| format!("Failed to back up {}", args.file.display()))?;
+            args.file
         }
     };
+    write_torrent(&output_path, &edited)?;
+    println!("Torrent written to {}", output_path.display());
+    Ok(())
+}
+
+/// Gathers trackers once and rewrites the announce list of every file,
+/// keeping each info dictionary intact.
+async fn run_refresh_trackers(
+    client: &Client,
+    http_options: &HttpOptions,
+    args: RefreshTrackersArgs,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let options = args.tracker.to_options(Vec::new())?;
+    let gathered = trackers::gather_trackers(client, http_options, &options, cancel)
+        .await
+        .context("Failed to gather tracker list")?;
+    args.tracker.dump(&gathered)?;
 
-    let creation_date = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs() as i64;
+    for path in &args.files {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read torrent file {}", path.display()))?;
+        let torrent = metainfo::parse(&bytes).with_context(|| format!("Invalid torrent file {}", path.display()))?;
 
-    let output_path = compute_output_path(cli.output, &primary_meta.filename);
-    let created_by = format!("torseed {}", env!("CARGO_PKG_VERSION"));
+        let tiers = if args.merge {
+            let mut tiers = torrent.announce_tiers.clone();
+            let existing: HashSet<String> = tiers.iter().flatten().cloned().collect();
+            for tier in &gathered.tiers {
+                let fresh: Vec<String> = tier.iter().filter(|t| !existing.contains(*t)).cloned().collect();
+                if !fresh.is_empty() {
+                    tiers.push(fresh);
+                }
+            }
+            tiers
+        } else {
+            gathered.tiers.clone()
+        };
+        let before: HashSet<&String> = torrent.announce_tiers.iter().flatten().collect();
+        let after: HashSet<&String> = tiers.iter().flatten().collect();
+        let added = after.difference(&before).count();
+        let removed = before.difference(&after).count();
 
-    let build_input = BuildInput {
-        name: sanitize_filename(&primary_meta.filename),
-        length: primary_meta.content_length,
-        piece_length: u32::try_from(piece_length).context("piece length overflow")?,
-        pieces,
-        trackers: trackers.clone(),
-        webseeds: webseeds.clone(),
-        creation_date,
-        created_by,
-        v2: v2_summary,
-    };
+        let verb = if args.dry_run { "would gain" } else { "gained" };
+        println!("{}: {verb} {added} trackers, lost {removed} ({} total)", path.display(), after.len());
+        if args.dry_run {
+            continue;
+        }
 
-    let metainfo = build_metainfo(&build_input)?;
+        let edits = metainfo::RootEdits {
+            announce_tiers: Some(tiers.clone()),
+            ..metainfo::RootEdits::default()
+        };
+        let edited = metainfo::edit(&bytes, &edits).with_context(|| format!("Failed to edit {}", path.display()))?;
+        ensure_not_cancelled(cancel)?;
+        write_torrent(path, &edited)?;
 
-    write_torrent(&output_path, &metainfo.torrent)?;
+        if !args.no_magnet_file {
+            let length = match torrent.layout {
+                FileLayout::Single { length } => Some(length),
+                FileLayout::Multi { .. } => None,
+            };
+            let magnets = build_magnets(
+                &torrent.name_lossy(),
+                length,
+                &tiers.concat(),
+                &torrent.webseeds,
+                torrent.infohash_v1(),
+                torrent.infohash_v2(),
+                &MagnetOptions {
+                    passkey_trackers: !trackers::redaction_enabled(),
+                    ..MagnetOptions::default()
+                },
+            );
+            write_magnet_file(&magnet_output_path(path), &magnets, false)?;
+        }
+    }
+    Ok(())
+}
+
+async fn run_magnet(
+    client: &Client,
+    http_options: &HttpOptions,
+    args: MagnetArgs,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let bytes = fs::read(&args.file).with_context(|| format!("Failed to read torrent file {}", args.file.display()))?;
+    let torrent = metainfo::parse(&bytes).with_context(|| format!("Invalid torrent file {}", args.file.display()))?;
 
+    let trackers = if args.fresh_trackers {
+        let options = args.tracker.to_options(Vec::new())?;
+        let gathered = trackers::gather_trackers(client, http_options, &options, cancel)
+            .await
+            .context("Failed to gather tracker list")?;
+        args.tracker.dump(&gathered)?;
+        gathered.all()
+    } else {
+        torrent.announce_tiers.concat()
+    };
+    let length = match torrent.layout {
+        FileLayout::Single { length } => Some(length),
+        FileLayout::Multi { .. } => None,
+    };
     let magnets = build_magnets(
-        &build_input.name,
+        &torrent.name_lossy(),
+        length,
         &trackers,
-        &webseeds,
-        metainfo.infohash_v1,
-        metainfo.infohash_v2,
+        &torrent.webseeds,
+        torrent.infohash_v1(),
+        torrent.infohash_v2(),
+        &args.magnet_format.to_options(),
     );
 
-    let magnet_path = magnet_output_path(&output_path);
-    write_magnet_file(&magnet_path, &magnets)?;
+    match &args.output {
+        Some(path) => {
+            write_magnet_file(path, &magnets, false)?;
+            println!("Magnet links written to {}", path.display());
+        }
+        None => {
+            for magnet_uri in &magnets {
+                println!("{magnet_uri}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_inspect(args: &InspectArgs, units: ByteUnits) -> Result<()> {
+    let bytes = fs::read(&args.file).with_context(|| format!("Failed to read torrent file {}", args.file.display()))?;
+    let torrent = metainfo::parse(&bytes).with_context(|| format!("Invalid torrent file {}", args.file.display()))?;
+    let magnet = args.magnet.then(|| {
+        let length = match torrent.layout {
+            FileLayout::Single { length } => Some(length),
+            FileLayout::Multi { .. } => None,
+        };
+        let options = MagnetOptions {
+            passkey_trackers: !trackers::redaction_enabled(),
+            ..MagnetOptions::default()
+        };
+        let trackers: Vec<String> = torrent.announce_tiers.concat();
+        build_magnets(
+            &torrent.name_lossy(),
+            length,
+            &trackers,
+            &torrent.webseeds,
+            torrent.infohash_v1(),
+            torrent.infohash_v2(),
+            &options,
+        )
+        .swap_remove(0)
+    });
 
-    print_summary(
-        &output_path,
-        &build_input,
-        &metainfo,
-        &trackers,
-        &webseeds,
-        &magnets,
-        &magnet_path,
-    );
+    if args.json {
+        let mut value = inspect_json(&torrent);
+        if let Some(magnet) = magnet {
+            value["magnet"] = magnet.into();
+        }
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
 
+    println!("Name: {}", torrent.name_lossy());
+    println!("Version: {}", torrent.version());
+    println!("Size: {} ({} bytes)", units.format(torrent.length()), torrent.length());
+    if let FileLayout::Multi { files } = &torrent.layout {
+        println!("Files: {}", files.len());
+        for file in files {
+            println!("  {} ({})", display_path(&file.path), units.format(file.length));
+        }
+    }
+    println!("Piece length: {} KiB", torrent.piece_length / 1024);
+    if let Some(pieces) = &torrent.pieces {
+        println!("Pieces: {}", pieces.len() / 20);
+    }
+    if let Some(date) = torrent.creation_date {
+        println!("Created: {}", format_timestamp(date));
+    }
+    if let Some(created_by) = &torrent.created_by {
+        println!("Created by: {created_by}");
+    }
+    if let Some(comment) = &torrent.comment {
+        println!("Comment: {comment}");
+    }
+    println!("Private: {}", if torrent.private { "yes" } else { "no" });
+    if let Some(v1) = torrent.infohash_v1() {
+        println!("v1 infohash (hex): {}", hex::encode(v1));
+    }
+    if let Some(v2) = torrent.infohash_v2() {
+        println!("v2 infohash (sha256 hex): {}", hex::encode(v2));
+    }
+    println!("Trackers: {} in {} tier(s)", torrent.announce_tiers.concat().len(), torrent.announce_tiers.len());
+    for (index, tier) in torrent.announce_tiers.iter().enumerate() {
+        println!("  Tier {}: {}", index + 1, tier.join(", "));
+    }
+    println!("Webseeds: {}", torrent.webseeds.len());
+    for webseed in &torrent.webseeds {
+        println!("  {webseed}");
+    }
+    if let Some(magnet) = magnet {
+        println!("magnet: {magnet}");
+    }
     Ok(())
 }
 
-fn init_tracing() {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
+fn inspect_json(torrent: &ParsedTorrent) -> serde_json::Value {
+    let files = match &torrent.layout {
+        FileLayout::Single { .. } => serde_json::Value::Null,
+        FileLayout::Multi { files } => files
+            .iter()
+            .map(|file| json!({ "path": display_path(&file.path), "length": file.length }))
+            .collect(),
+    };
+    json!({
+        "name": torrent.name_lossy(),
+        "version": torrent.version().to_string(),
+        "length": torrent.length(),
+        "files": files,
+        "piece_length": torrent.piece_length,
+        "pieces": torrent.pieces.as_ref().map(|pieces| pieces.len() / 20),
+        "creation_date": torrent.creation_date,
+        "created_by": torrent.created_by,
+        "comment": torrent.comment,
+        "private": torrent.private,
+        "infohash_v1": torrent.infohash_v1().map(hex::encode),
+        "infohash_v2": torrent.infohash_v2().map(hex::encode),
+        "announce_tiers": torrent.announce_tiers,
+        "webseeds": torrent.webseeds,
+    })
+}
+
+fn display_path(components: &[Vec<u8>]) -> String {
+    components
+        .iter()
+        .map(|component| String::from_utf8_lossy(component))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// RFC 3339 in UTC, or the raw value when it is before the epoch.
+fn format_timestamp(timestamp: i64) -> String {
+    match u64::try_from(timestamp) {
+        Ok(secs) => humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs)).to_string(),
+        Err(_) => timestamp.to_string(),
+    }
+}
+
+fn print_scrape_results(results: &[(String, ScrapeOutcome)]) {
+    let mut responsive = 0;
+    for (tracker, outcome) in results {
+        let tracker = trackers::for_display(tracker);
+        match outcome {
+            ScrapeOutcome::Stats(stats) => {
+                responsive += 1;
+                println!(
+                    "{tracker}: seeders {} leechers {} completed {}",
+                    stats.seeders, stats.leechers, stats.completed
+                );
+            }
+            ScrapeOutcome::Unsupported(reason) => println!("{tracker}: unsupported ({reason})"),
+            ScrapeOutcome::Unresponsive(reason) => println!("{tracker}: unresponsive ({reason})"),
+        }
+    }
+    println!("Scrape: {} of {} trackers responded", responsive, results.len());
+}
+
+/// Sets up console logging, filtered by RUST_LOG when set and by -v/-q
+/// otherwise, plus an unfiltered debug log when `--log-file` is given. The
+/// file is opened for appending so external rotation can truncate or move it.
+fn init_tracing(cli: &Cli, to_stderr: bool, bars: Option<&bars::Bars>) -> Result<()> {
+    let default_filter = match (cli.quiet, cli.verbose) {
+        (true, _) => "warn",
+        (false, 0) => "info",
+        (false, 1) => "info,torseed=debug",
+        (false, 2) => "debug",
+        (false, _) => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    let writer = if to_stderr {
+        BoxMakeWriter::new(io::stderr)
+    } else if let Some(bars) = bars {
+        let bars = bars.clone();
+        BoxMakeWriter::new(move || bars.log_writer())
+    } else {
+        BoxMakeWriter::new(io::stdout)
+    };
+    let terminal = if to_stderr { io::stderr().is_terminal() } else { io::stdout().is_terminal() };
+    let console = tracing_subscriber::fmt::layer()
         .with_target(false)
-        .init();
+        .with_ansi(color::enabled(cli.color, terminal))
+        .with_writer(writer)
+        .with_filter(filter);
+
+    let file = match &cli.log_file {
+        Some(path) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Arc::new(file))
+                .with_filter(LevelFilter::DEBUG);
+            Some(layer)
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry().with(console).with(file).init();
+    Ok(())
+}
+
+/// HEADs the webseeds whose stored form lost query parameters and warns
+/// about those that no longer serve the source: the mirror may need the
+/// token that was stripped. They stay in the torrent either way.
+async fn check_stripped_webseeds(
+    client: &Client,
+    http_options: &HttpOptions,
+    length: u64,
+    fetched: &[String],
+    stored: &[String],
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let checks = fetched
+        .iter()
+        .zip(stored)
+        .filter(|(fetched, stored)| fetched != stored)
+        .filter_map(|(_, stored)| Url::parse(stored).ok())
+        .map(|url| async move { (http::head_source(client, http_options, url.clone()).await, url) });
+    for (result, url) in until_cancelled(cancel, futures::future::join_all(checks)).await? {
+        match result {
+            Ok(meta) if meta.content_length == length => debug!("Stripped webseed {url} still serves the source"),
+            Ok(meta) => warn!(
+                "Stripped webseed {url} serves {} bytes, expected {length}; the mirror may need the removed query",
+                meta.content_length
+            ),
+            Err(err) => {
+                warn!("Stripped webseed {url} failed a HEAD check ({err}); the mirror may need the removed query")
+            }
+        }
+    }
+    Ok(())
 }
 
-fn build_client() -> Result<Client> {
-    Client::builder()
+/// The one client every request of a run goes through, so idle connections
+/// are reused across webseed checks, tracker lists and the download.
+fn build_client(
+    pool: &http::PoolOptions,
+    bind: &http::BindOptions,
+    redirects: http::RedirectOptions,
+    read_timeout: Duration,
+) -> Result<Client> {
+    bind.apply(pool.apply(Client::builder()))
         .user_agent(format!("torseed/{}", env!("CARGO_PKG_VERSION")))
-        .redirect(reqwest::redirect::Policy::limited(10))
+        .redirect(redirects.policy())
+        .read_timeout(read_timeout)
         .build()
         .context("Failed to build HTTP client")
 }
 
+/// The parts of a `--multi` source and the torrent they form.
+struct MultiSource {
+    /// The webseed directory as `url`, the torrent name as `filename` and the
+    /// total size as `content_length`.
+    meta: SourceMetadata,
+    parts: Vec<FilePart>,
+}
+
+/// Expands `template`, probes every part and lays them out as one torrent.
+/// The directory the parts share becomes the torrent name and its parent the
+/// webseed, so clients find each part at webseed + name + path (BEP 19). Any
+/// part that cannot be probed fails the run: a torrent missing parts would
+/// be useless.
+async fn probe_parts(
+    client: &Client,
+    http_options: &HttpOptions,
+    template: &str,
+    max_urls: usize,
+    cancel: &CancellationToken,
+) -> Result<MultiSource> {
+    let urls = expand::expand(template, max_urls)
+        .map_err(TorseedError::InvalidInput)?
+        .iter()
+        .map(|url| parse_url(url))
+        .collect::<Result<Vec<_>>>()?;
+    info!("Probing {} parts of {template}", urls.len());
+
+    let first = &urls[0];
+    if let Some(other) = urls.iter().find(|url| url.origin() != first.origin()) {
+        return Err(TorseedError::InvalidInput(format!(
+            "All --multi parts must be on one host: {first} and {other} differ"
+        ))
+        .into());
+    }
+    let segments: Vec<Vec<String>> = urls
+        .iter()
+        .map(|url| {
+            url.path_segments()
+                .into_iter()
+                .flatten()
+                .map(|segment| percent_encoding::percent_decode_str(segment).decode_utf8_lossy().into_owned())
+                .collect()
+        })
+        .collect();
+    // Directories shared by every part; the last path segment is always a file.
+    let shared = segments
+        .iter()
+        .map(|path| path.len().saturating_sub(1))
+        .min()
+        .map_or(0, |depth| {
+            (0..depth)
+                .take_while(|&index| segments.iter().all(|path| path[index] == segments[0][index]))
+                .count()
+        });
+    if shared == 0 {
+        return Err(TorseedError::InvalidInput(format!(
+            "--multi parts must share a directory to name the torrent after, e.g. https://host/data/part-{{1..9}}.bin; \
+             {first} has none"
+        ))
+        .into());
+    }
+    let name = segments[0][shared - 1].clone();
+    let mut webseed = first.clone();
+    webseed.set_query(None);
+    webseed.set_fragment(None);
+    webseed
+        .path_segments_mut()
+        .map_err(|()| anyhow::anyhow!("{first} cannot be a webseed"))?
+        .clear()
+        .extend(&first.path_segments().into_iter().flatten().collect::<Vec<_>>()[..shared - 1])
+        .push("");
+    for component in std::iter::once(&name).chain(segments.iter().flat_map(|path| &path[shared..])) {
+        if sanitize_filename(component) != *component {
+            return Err(TorseedError::InvalidInput(format!(
+                "--multi path component {component:?} is not a safe file name; \
+                 clients could not find it on the webseed"
+            ))
+            .into());
+        }
+    }
+
+    urls.iter().for_each(|url| http_options.allow_netrc_default(url));
+    let metas = until_cancelled(
+        cancel,
+        futures::future::try_join_all(urls.iter().map(|url| http::head_source(client, http_options, url.clone()))),
+    )
+    .await?
+    .context("Failed to probe a --multi part; not building a torrent with parts missing")?;
+    let parts: Vec<FilePart> = metas
+        .into_iter()
+        .zip(&segments)
+        .map(|(source, path)| FilePart {
+            path: path[shared..].to_vec(),
+            source,
+        })
+        .collect();
+    if !parts.is_sorted_by(|a, b| a.path <= b.path) {
+        warn!("--multi parts are stored sorted by path, not in template order; zero-pad numbers to keep them aligned");
+    }
+    let content_length = parts.iter().map(|part| part.source.content_length).sum();
+    info!("{} parts, {} in total, served from {webseed}", parts.len(), util::format_bytes(content_length));
+    Ok(MultiSource {
+        meta: SourceMetadata {
+            url: webseed,
+            content_length,
+            filename: name,
+            original_filename: None,
+            etag: None,
+            last_modified: None,
+        },
+        parts,
+    })
+}
+
+/// Runs `future` to completion unless `cancel` fires first.
+async fn until_cancelled<T>(cancel: &CancellationToken, future: impl Future<Output = T>) -> Result<T> {
+    cancel
+        .run_until_cancelled(future)
+        .await
+        .ok_or_else(|| TorseedError::Cancelled.into())
+}
+
+fn ensure_not_cancelled(cancel: &CancellationToken) -> Result<()> {
+    if cancel.is_cancelled() {
+        return Err(TorseedError::Cancelled.into());
+    }
+    Ok(())
+}
+
 fn parse_url(input: &str) -> Result<Url> {
     let url = Url::parse(input).with_context(|| format!("Invalid URL: {input}"))?;
     match url.scheme() {
@@ -195,105 +3018,416 @@ fn parse_url(input: &str) -> Result<Url> {
     }
 }
 
-async fn verify_webseeds(client: &Client, expected_length: u64, urls: Vec<Url>) -> Vec<Url> {
-    use futures::stream::FuturesUnordered;
-
-    let mut verified = Vec::new();
-    let mut tasks = FuturesUnordered::new();
-    for url in urls {
-        let client = client.clone();
-        tasks.push(async move {
-            match http::head_source(&client, url.clone()).await {
-                Ok(meta) => Some((url, meta)),
-                Err(err) => {
-                    warn!("Skipping webseed {url}: {err}");
-                    None
+/// Starts the task that turns library events into log output: a progress
+/// line every `PROGRESS_LOG_INTERVAL` while hashing, plus webseed outcomes.
+/// The task ends once every clone of the returned sink is dropped. It logs
+/// within the caller's span so concurrent batch entries stay distinguishable.
+/// Progress lines are replaced by a bar or JSON events when `output` asks
+/// for them.
+fn spawn_event_log(output: events::ProgressOutput, units: ByteUnits) -> (EventSink, JoinHandle<()>) {
+    let (sender, mut receiver) = mpsc::channel(EVENT_QUEUE_DEPTH);
+    let mut sink = EventSink::new(sender);
+    let (json, bars) = match output {
+        events::ProgressOutput::Log => (None, None),
+        events::ProgressOutput::Bars(bars) => (None, Some(bars)),
+        events::ProgressOutput::Json(json) => (Some(json), None),
+    };
+    if json.as_ref().is_some_and(|json| json.pieces) {
+        sink = sink.with_piece_events();
+    }
+    let task = async move {
+        let mut last_progress = Instant::now();
+        let mut last_json_progress: Option<Instant> = None;
+        let mut hash_bar: Option<indicatif::ProgressBar> = None;
+        while let Some(event) = receiver.recv().await {
+            if let Some(json) = &json {
+                match &event {
+                    // The final totals are always written so the stream ends at 100%.
+                    Event::BytesHashed(progress)
+                        if last_json_progress.is_some_and(|at| at.elapsed() < json.hash_interval)
+                            && progress.expected != Some(progress.hashed) => {}
+                    Event::BytesHashed(_) => {
+                        json.library_event(&event);
+                        last_json_progress = Some(Instant::now());
+                    }
+                    _ => json.library_event(&event),
                 }
             }
-        });
+            match event {
+                Event::MetadataResolved(source) => {
+                    last_progress = Instant::now();
+                    if let Some(bar) = hash_bar.take() {
+                        bar.finish_and_clear();
+                    }
+                    hash_bar = bars.as_ref().map(|bars| bars.hash_bar(Some(source.content_length)));
+                }
+                Event::BytesHashed(progress) => {
+                    if let Some(bar) = &hash_bar {
+                        bars::update_hash_bar(bar, &progress, units);
+                    } else if json.is_none() && last_progress.elapsed() > PROGRESS_LOG_INTERVAL {
+                        info!("{}", progress.line(units));
+                        last_progress = Instant::now();
+                    }
+                }
+                Event::WebseedVerified { url } => debug!("Verified webseed {url}"),
+                Event::WebseedRejected { url, reason } => warn!("Skipping webseed {url}: {reason}"),
+                Event::PieceLayerFinalized { .. } | Event::TorrentBuilt { .. } => {
+                    if let Some(bar) = hash_bar.take() {
+                        bar.finish_and_clear();
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(bar) = hash_bar {
+            bar.finish_and_clear();
+        }
+    };
+    let handle = tokio::spawn(task.instrument(tracing::Span::current()));
+    (sink, handle)
+}
+
+/// What `create` does about output files that already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OverwritePolicy {
+    Refuse,
+    Overwrite,
+    Backup,
+}
+
+impl OverwritePolicy {
+    fn from_flags(force: bool, backup: bool) -> Self {
+        match (force, backup) {
+            (true, _) => Self::Overwrite,
+            (false, true) => Self::Backup,
+            (false, false) => Self::Refuse,
+        }
     }
 
-    let mut now = Instant::now();
-    while let Some(result) = tasks.next().await {
-        if let Some((url, meta)) = result {
-            if meta.content_length == expected_length {
-                verified.push(url);
-            } else {
-                warn!(
-                    "Skipping webseed {} (length mismatch: {} vs {expected_length})",
-                    url,
-                    meta.content_length
-                );
-            }
+    /// Checks the torrents and, unless appending to it, the magnet file.
+    fn check_outputs(self, torrents: &[PathBuf], magnet: Option<&Path>, append_magnets: bool) -> Result<()> {
+        for torrent in torrents {
+            self.check(torrent)?;
         }
-        if now.elapsed() > Duration::from_secs(10) {
-            info!("Checked {} webseeds", verified.len());
-            now = Instant::now();
+        match magnet {
+            Some(magnet) if !append_magnets => self.check(magnet),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if `path` exists and may not be replaced.
+    fn check(self, path: &Path) -> Result<()> {
+        let exists = path
+            .try_exists()
+            .with_context(|| format!("Failed to check whether {} exists", path.display()))?;
+        if exists && self == Self::Refuse {
+            anyhow::bail!(
+                "{} already exists; pass --force to overwrite it, --backup to keep the old file as .bak, \
+                 or choose another path with --output",
+                path.display()
+            );
         }
+        Ok(())
     }
 
-    verified
+    /// Makes way for writing `path`, moving an existing file aside with
+    /// `--backup`.
+    fn prepare(self, path: &Path) -> Result<()> {
+        if self != Self::Backup || !path.exists() {
+            return Ok(());
+        }
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        let backup = PathBuf::from(backup);
+        fs::rename(path, &backup)
+            .with_context(|| format!("Failed to back up {} to {}", path.display(), backup.display()))?;
+        info!("Moved existing {} to {}", path.display(), backup.display());
+        Ok(())
+    }
 }
 
-fn compute_output_path(cli_value: Option<PathBuf>, filename: &str) -> PathBuf {
+/// The torrent path: `--output` as given, else the expanded
+/// `--output-template`, else `<filename>.torrent`.
+fn compute_output_path(
+    cli_value: Option<PathBuf>,
+    template: Option<(&util::OutputTemplate, &TemplateValues<'_>)>,
+    filename: &str,
+) -> PathBuf {
     if let Some(path) = cli_value {
         return path;
     }
+    if let Some((template, values)) = template {
+        return template.expand(values);
+    }
     let sanitized = sanitize_filename(filename);
     PathBuf::from(format!("{sanitized}.torrent"))
 }
 
-fn write_torrent(path: &PathBuf, bytes: &[u8]) -> Result<()> {
+/// Where `create` writes torrents: `path` itself, or one
+/// `<stem>.<flavor>.torrent` per `--emit` flavor.
+fn torrent_paths(path: &Path, emit: &[MetaVersion]) -> Vec<PathBuf> {
+    if emit.is_empty() {
+        return vec![path.to_path_buf()];
+    }
+    emit.iter().map(|version| path.with_extension(format!("{version}.torrent"))).collect()
+}
+
+/// Where `--webtorrent` writes its torrent, `<stem>.webtorrent.torrent`, and
+/// its magnets when magnets are written at all.
+fn webtorrent_paths(output_path: &Path, magnets: bool) -> (PathBuf, Option<PathBuf>) {
+    let path = output_path.with_extension("webtorrent.torrent");
+    let magnet = magnets.then(|| magnet_output_path(&path));
+    (path, magnet)
+}
+
+/// The flavor `metainfo` was built as.
+fn meta_version(metainfo: &metainfo::Metainfo) -> MetaVersion {
+    match (metainfo.infohash_v1, metainfo.infohash_v2) {
+        (Some(_), Some(_)) => MetaVersion::Hybrid,
+        (None, Some(_)) => MetaVersion::V2,
+        _ => MetaVersion::V1,
+    }
+}
+
+fn write_torrent(path: &Path, bytes: &[u8]) -> Result<()> {
     if let Some(parent) = path.parent() {
         if !parent.as_os_str().is_empty() {
             std::fs::create_dir_all(parent)
                 .with_context(|| format!("Failed to create parent directories for {}", path.display()))?;
         }
     }
-    std::fs::write(path, bytes)
+    util::write_atomic(path, bytes)
         .with_context(|| format!("Failed to write torrent file to {}", path.display()))
 }
 
-fn print_summary(
-    output_path: &PathBuf,
-    build_input: &BuildInput,
-    metainfo: &metainfo::Metainfo,
-    trackers: &[String],
-    webseeds: &[String],
-    magnets: &[String],
-    magnet_path: &Path,
-) {
-    println!("Torrent written to {}", output_path.display());
+/// The `--webtorrent` copy of the torrent.
+struct Webtorrent {
+    path: PathBuf,
+    magnet_path: Option<PathBuf>,
+    metainfo: metainfo::Metainfo,
+    /// The WebSocket trackers it announces to.
+    trackers: Vec<String>,
+    magnets: Vec<String>,
+}
+
+/// Everything reported after a successful run.
+struct Summary<'a> {
+    output_path: &'a Path,
+    build_input: &'a BuildInput,
+    /// The server-suggested name, when it had to be sanitized.
+    renamed_from: Option<&'a str>,
+    /// The reference torrent recreated with `--match`.
+    matched: Option<&'a Path>,
+    metainfo: &'a metainfo::Metainfo,
+    /// Torrents written for `--emit`, the first being `metainfo`.
+    variants: &'a [(MetaVersion, PathBuf, metainfo::Metainfo)],
+    trackers: &'a GatheredTrackers,
+    webseeds: &'a [String],
+    /// `webseeds` as written into the torrent, after `--strip-query`.
+    stored_webseeds: &'a [String],
+    /// Range support of the first webseed, the source itself.
+    primary_ranges: http::RangeSupport,
+    webseed_checks: &'a [http::WebseedCheck],
+    checksums: &'a [Checksum],
+    magnets: &'a [String],
+    magnet_path: Option<&'a Path>,
+    webtorrent: Option<&'a Webtorrent>,
+    peers: &'a [String],
+    published: &'a [publish::PublishResult],
+    qbittorrent: Option<&'a Result<()>>,
+    transmission: Option<&'a Result<AddOutcome>>,
+    log_file: Option<&'a Path>,
+    timings: Timings,
+}
 
+/// Wall-clock breakdown of a run.
+struct Timings {
+    total: Duration,
+    /// HEAD requests, webseed checks and tracker gathering before streaming.
+    setup: Duration,
+    transfer: TransferStats,
+}
+
+/// Width of the label column in the summary.
+const SUMMARY_LABEL_WIDTH: usize = 26;
+
+/// Formats the summary printed after a run. Magnet links are never styled so
+/// copying them from the terminal cannot pick up escape codes.
+fn render_summary(summary: &Summary<'_>, palette: color::Palette, units: ByteUnits) -> String {
+    let Summary {
+        output_path,
+        build_input,
+        renamed_from,
+        matched,
+        metainfo,
+        variants,
+        trackers,
+        webseeds,
+        stored_webseeds,
+        primary_ranges,
+        webseed_checks,
+        checksums,
+        magnets,
+        magnet_path,
+        webtorrent,
+        peers,
+        published,
+        qbittorrent,
+        transmission,
+        log_file,
+        ref timings,
+    } = *summary;
+
+    let mut out = String::new();
+    let mut field = |label: &str, value: &dyn std::fmt::Display| {
+        let label = format!("{label}:");
+        let _ = writeln!(out, "{label:<SUMMARY_LABEL_WIDTH$} {value}");
+    };
+
+    field("Torrent written to", &palette.path(output_path.display()));
+    if let Some(original) = renamed_from {
+        field("Name", &format!("{} (renamed from {original:?})", build_input.name));
+    }
+    if let Some(reference) = matched {
+        field("Matches", &palette.success(format!("{} (every piece identical)", reference.display())));
+    }
     if let Some(v1) = metainfo.infohash_v1 {
-        println!("v1 infohash (hex): {}", hex::encode(v1));
-        println!("v1 infohash (base32): {}", BASE32_NOPAD.encode(&v1));
+        field("v1 infohash (hex)", &palette.hash(hex::encode(v1)));
+        field("v1 infohash (base32)", &palette.hash(BASE32_NOPAD.encode(&v1)));
     }
     if let Some(v2) = metainfo.infohash_v2 {
-        println!("v2 infohash (sha256 hex): {}", hex::encode(v2));
+        field("v2 infohash (sha256 hex)", &palette.hash(hex::encode(v2)));
+    }
+    for (version, path, variant) in variants {
+        let hashes = [("v1", variant.infohash_v1.map(hex::encode)), ("v2", variant.infohash_v2.map(hex::encode))]
+            .into_iter()
+            .filter_map(|(label, hash)| Some(format!("{label} {}", palette.hash(hash?))))
+            .collect::<Vec<_>>();
+        field(&format!("{version} torrent"), &format!("{} ({})", palette.path(path.display()), hashes.join(", ")));
     }
 
     for magnet_uri in magnets {
-        println!("magnet: {}", magnet_uri);
+        field("magnet", magnet_uri);
+    }
+    if let Some(path) = magnet_path {
+        field("Magnet links written to", &palette.path(path.display()));
+    }
+    if let Some(webtorrent) = webtorrent {
+        field("WebTorrent torrent", &palette.path(webtorrent.path.display()));
+        field("WebTorrent trackers", &format!("{} (ws/wss only)", webtorrent.trackers.len()));
+        for magnet_uri in &webtorrent.magnets {
+            field("WebTorrent magnet", magnet_uri);
+        }
+        if let Some(path) = &webtorrent.magnet_path {
+            field("WebTorrent magnets written to", &palette.path(path.display()));
+        }
+    }
+    if !peers.is_empty() {
+        field("Peer hints (x.pe)", &peers.join(", "));
+    }
+    for result in published {
+        match &result.outcome {
+            Ok(status) => {
+                let status = palette.success(format!("HTTP {status}"));
+                field("Published", &format!("{} ({status})", result.target))
+            }
+            Err(err) => field("Publishing failed", &palette.warning(format!("{}: {err:#}", result.target))),
+        }
+    }
+    match qbittorrent {
+        Some(Ok(())) => field("qBittorrent", &palette.success("added")),
+        Some(Err(err)) => field("qBittorrent", &palette.warning(format!("failed: {err:#}"))),
+        None => {}
+    }
+    match transmission {
+        Some(Ok(AddOutcome::Added(torrent))) => field(
+            "Transmission",
+            &palette.success(format!("added as torrent {} ({})", torrent.id, torrent.name)),
+        ),
+        Some(Ok(AddOutcome::Duplicate(Some(torrent)))) => field(
+            "Transmission",
+            &format!("already present as torrent {} ({})", torrent.id, torrent.name),
+        ),
+        Some(Ok(AddOutcome::Duplicate(None))) => field("Transmission", &"already present"),
+        Some(Err(err)) => field("Transmission", &palette.warning(format!("failed: {err:#}"))),
+        None => {}
     }
-    println!("Magnet links written to {}", magnet_path.display());
 
     let pieces = build_input.pieces.len() / 20;
-    println!(
-        "File size: {} ({} bytes)",
-        format_bytes(build_input.length),
-        build_input.length
+    field(
+        "File size",
+        &format!("{} ({} bytes)", units.format(build_input.length), build_input.length),
     );
-    println!(
-        "Piece length: {} KiB",
-        build_input.piece_length / 1024
+    field("Piece length", &format!("{} KiB", build_input.piece_length / 1024));
+    field("Pieces", &pieces);
+    match build_input.created_by.as_str() {
+        "" => field("Created by", &"omitted"),
+        created_by => field("Created by", &created_by),
+    }
+    for checksum in checksums {
+        field(checksum.algorithm.name(), &palette.hash(checksum.hex()));
+    }
+    let all_trackers = trackers.all();
+    field("Trackers", &format!("{} in {} tier(s)", all_trackers.len(), trackers.tiers.len()));
+    let i2p_count = all_trackers.iter().filter(|t| trackers::is_i2p(t)).count();
+    if i2p_count > 0 {
+        field("I2P trackers", &i2p_count);
+    }
+    for origin in &trackers.origins {
+        field(&format!("  {}", origin.source), &origin.count);
+    }
+    let schemes: Vec<String> = trackers::count_by_scheme(&all_trackers)
+        .into_iter()
+        .map(|(scheme, count)| format!("{scheme}={count}"))
+        .collect();
+    field("Tracker schemes", &schemes.join(", "));
+    if trackers.collapsed > 0 {
+        field("Duplicate hosts collapsed", &trackers.collapsed);
+    }
+    if let Some(report) = &trackers.check {
+        let dead = format!("{} dead", report.dead);
+        let dead = if report.dead > 0 { palette.warning(dead) } else { dead };
+        field(
+            "Tracker check",
+            &format!("{} alive, {dead}, {} unchecked", report.alive, report.unchecked),
+        );
+    }
+    field("Webseeds", &webseeds.len());
+    for (fetched, stored) in webseeds.iter().zip(stored_webseeds).filter(|(fetched, stored)| fetched != stored) {
+        field("Stored webseed", &format!("{stored} (fetched {fetched})"));
+    }
+    let accepted = webseed_checks.iter().filter(|check| check.rejection.is_none());
+    let ranges = std::iter::once((webseeds[0].as_str(), primary_ranges))
+        .chain(accepted.map(|check| (check.url.as_str(), check.ranges)));
+    for (url, ranges) in ranges.filter(|(_, ranges)| *ranges != http::RangeSupport::Yes) {
+        field("Webseed ranges", &palette.warning(format!("{url} (ranges: {})", ranges.name())));
+    }
+    for check in webseed_checks {
+        if let Some(rejection) = &check.rejection {
+            field("Skipped webseed", &palette.warning(format!("{} ({rejection})", check.url)));
+        }
+    }
+    field(
+        "Stats",
+        &format!(
+            "{:.1}s elapsed ({:.1}s setup, {} streamed in {:.1}s), average {}",
+            timings.total.as_secs_f64(),
+            timings.setup.as_secs_f64(),
+            units.format(timings.transfer.bytes),
+            timings.transfer.elapsed.as_secs_f64(),
+            units.format_rate(timings.transfer.average_rate())
+        ),
     );
-    println!("Pieces: {}", pieces);
-    println!("Trackers: {}", trackers.len());
-    println!("Webseeds: {}", webseeds.len());
+    if let Some(protocol) = timings.transfer.protocol {
+        field("Protocol", &protocol);
+    }
+    if let Some(path) = log_file {
+        field("Debug log", &path.display());
+    }
+    out
 }
 
-fn write_magnet_file(path: &Path, magnets: &[String]) -> Result<()> {
+fn write_magnet_file(path: &Path, magnets: &[String], append: bool) -> Result<()> {
     if let Some(parent) = path.parent() {
         if !parent.as_os_str().is_empty() {
             fs::create_dir_all(parent)
@@ -301,15 +3435,201 @@ fn write_magnet_file(path: &Path, magnets: &[String]) -> Result<()> {
         }
     }
 
-    let mut contents = magnets.join("\n");
-    contents.push('\n');
-    fs::write(path, contents).with_context(|| format!("Failed to write magnet file to {}", path.display()))
+    // Appends rewrite the whole file so it is still replaced atomically.
+    let mut contents = if append {
+        match fs::read(path) {
+            Ok(existing) => existing,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read magnet file {}", path.display()));
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    contents.extend_from_slice(magnets.join("\n").as_bytes());
+    contents.push(b'\n');
+    util::write_atomic(path, &contents)
+        .with_context(|| format!("Failed to write magnet file to {}", path.display()))
 }
 
+/// Default magnet file: `<torrent-stem>.magnet` next to the torrent.
 fn magnet_output_path(output_path: &Path) -> PathBuf {
-    let dir = output_path
-        .parent()
-        .filter(|p| !p.as_os_str().is_empty())
-        .unwrap_or_else(|| Path::new("."));
-    dir.join(".magnet")
+    output_path.with_extension("magnet")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn url() -> Url {
+        "http://127.0.0.1/data.bin".parse().unwrap()
+    }
+
+    /// A connection refused by a closed local port.
+    async fn connect_error() -> reqwest::Error {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        Client::new().get(format!("http://{address}/")).send().await.unwrap_err()
+    }
+
+    /// A 404 turned into an error by `error_for_status`.
+    async fn not_found() -> reqwest::Error {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let _ = stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await;
+        });
+        let response = Client::new().get(format!("http://{address}/")).send().await.unwrap();
+        response.error_for_status().unwrap_err()
+    }
+
+    fn class(err: TorseedError) -> (FailureClass, u8) {
+        let class = FailureClass::of(&anyhow::Error::from(err));
+        (class, class.exit_code())
+    }
+
+    #[tokio::test]
+    async fn failures_map_to_exit_codes() {
+        let message = || "failed".to_string();
+        let io = || io::Error::other("disk full");
+        let cases = [
+            (TorseedError::InvalidInput(message()), FailureClass::InvalidInput, 2),
+            (
+                TorseedError::Metadata {
+                    url: url(),
+                    message: message(),
+                    source: None,
+                },
+                FailureClass::Source,
+                3,
+            ),
+            (
+                TorseedError::Metadata {
+                    url: url(),
+                    message: message(),
+                    source: Some(not_found().await),
+                },
+                FailureClass::Source,
+                3,
+            ),
+            (
+                TorseedError::Stream {
+                    url: url(),
+                    message: message(),
+                    source: None,
+                },
+                FailureClass::Stream,
+                4,
+            ),
+            (TorseedError::Hashing(message()), FailureClass::Stream, 4),
+            (TorseedError::Resume(message()), FailureClass::Stream, 4),
+            (TorseedError::Trackers(message()), FailureClass::Trackers, 5),
+            (
+                TorseedError::Io {
+                    context: message(),
+                    source: io(),
+                },
+                FailureClass::Io,
+                6,
+            ),
+            (TorseedError::Mismatch(message()), FailureClass::Mismatch, 7),
+            (
+                TorseedError::Metadata {
+                    url: url(),
+                    message: message(),
+                    source: Some(connect_error().await),
+                },
+                FailureClass::Transient,
+                8,
+            ),
+            (
+                TorseedError::Stream {
+                    url: url(),
+                    message: message(),
+                    source: Some(connect_error().await),
+                },
+                FailureClass::Transient,
+                8,
+            ),
+            (TorseedError::Cancelled, FailureClass::Cancelled, EXIT_CANCELLED),
+            (
+                TorseedError::Encode {
+                    what: "torrent",
+                    message: message(),
+                },
+                FailureClass::Other,
+                1,
+            ),
+            (TorseedError::Decode(message()), FailureClass::Other, 1),
+            (
+                TorseedError::Rpc {
+                    message: message(),
+                    source: None,
+                },
+                FailureClass::Other,
+                1,
+            ),
+        ];
+        for (err, expected, code) in cases {
+            let description = format!("{err:?}");
+            assert_eq!(class(err), (expected, code), "{description}");
+        }
+    }
+
+    #[test]
+    fn plain_errors_are_other() {
+        let err = anyhow::anyhow!("Something else went wrong");
+        assert_eq!(FailureClass::of(&err), FailureClass::Other);
+        assert_eq!(FailureClass::of(&err).exit_code(), 1);
+    }
+
+    #[test]
+    fn library_errors_are_found_under_context() {
+        let err = anyhow::Error::from(TorseedError::Mismatch("differs".to_string())).context("Failed to verify");
+        assert_eq!(FailureClass::of(&err), FailureClass::Mismatch);
+    }
+
+    fn fixture(name: &str) -> ParsedTorrent {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+        metainfo::parse(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn inspect_json_describes_other_creators() {
+        let value = inspect_json(&fixture("mktorrent_multi.torrent"));
+        assert_eq!(value["version"], "v1");
+        assert_eq!(value["length"], 40_100);
+        assert_eq!(
+            value["files"],
+            json!([{ "path": "disc1/a.bin", "length": 40_000 }, { "path": "b.txt", "length": 100 }])
+        );
+        assert_eq!(value["pieces"], 2);
+        assert_eq!(value["private"], true);
+        assert_eq!(value["created_by"], "mktorrent 1.1");
+        assert_eq!(value["infohash_v2"], serde_json::Value::Null);
+        assert_eq!(value["webseeds"], json!(["https://mirror.example.org/album/"]));
+
+        let value = inspect_json(&fixture("qbittorrent_hybrid.torrent"));
+        assert_eq!(value["version"], "hybrid");
+        assert_eq!(value["files"], serde_json::Value::Null);
+        assert_eq!(value["infohash_v1"], "448ad5057ce086b5af0c3691944c603e6bc0fd67");
+        assert_eq!(
+            value["infohash_v2"],
+            "752f38ba8e1de5fb34f0196ffc53302536c9127850e8d7669ccdd6285137e099"
+        );
+
+        let value = inspect_json(&fixture("legacy_cp1251.torrent"));
+        assert_eq!(value["name"], "\u{fffd}".repeat(6));
+        assert_eq!(value["creation_date"], serde_json::Value::Null);
+        assert_eq!(value["announce_tiers"], json!([["http://tracker.example.org/announce"]]));
+    }
+
 }
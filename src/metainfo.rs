@@ -1,67 +1,171 @@
 use std::borrow::Cow;
 use std::collections::BTreeMap;
+use std::fmt;
 
-use anyhow::{anyhow, bail, Context, Result};
+use bendy::decoding::{Decoder, FromBencode, Object};
 use bendy::encoding::ToBencode;
 use bendy::value::Value;
 use sha1::{Digest as Sha1DigestTrait, Sha1};
 use sha2::Sha256;
 
+use crate::error::{Result, TorseedError};
 use crate::hash_v2::V2Summary;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BuildInput {
     pub name: String,
     pub length: u64,
     pub piece_length: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde"))]
     pub pieces: Vec<u8>,
-    pub trackers: Vec<String>,
+    /// Announce-list tiers; the first tracker of the first tier becomes `announce`.
+    pub announce_tiers: Vec<Vec<String>>,
     pub webseeds: Vec<String>,
     pub creation_date: i64,
+    /// Left out of the torrent when empty. Outside the info dictionary, so
+    /// it never changes the infohashes.
     pub created_by: String,
+    /// Also write the name under `name.utf-8`, which some older clients read
+    /// instead of `name`. Part of the info dictionary, so it changes the
+    /// infohashes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name_utf8: bool,
     pub v2: Option<V2Summary>,
+    /// Files of a multi-file torrent, in order. Empty for a single file named
+    /// `name`, described by `length` and `v2`. Every file but the last is
+    /// padded to a piece boundary (BEP 47), and `pieces` covers the padding.
+    pub files: Vec<BuildFile>,
 }
 
+/// One file of a multi-file [`BuildInput`].
+///
+/// ```
+/// use torseed::metainfo::{self, BuildFile, BuildInput, FileEntry, FileLayout};
+/// use torseed::V2Summary;
+///
+/// let file = |name: &str, length| BuildFile {
+///     path: vec![name.to_string()],
+///     length,
+///     v2: Some(V2Summary { pieces_root: [length as u8; 32], piece_layers: Vec::new() }),
+/// };
+/// let input = BuildInput {
+///     name: "data".to_string(),
+///     length: 30_000,
+///     piece_length: 16_384,
+///     pieces: vec![7; 60],
+///     announce_tiers: vec![vec!["udp://a.example:1/announce".to_string()]],
+///     webseeds: vec!["https://example.com/".to_string()],
+///     creation_date: 1_700_000_000,
+///     created_by: "torseed".to_string(),
+///     name_utf8: false,
+///     v2: None,
+///     files: vec![file("a.bin", 10_000), file("b.bin", 20_000)],
+/// };
+/// let parsed = metainfo::parse(&metainfo::build(&input)?.torrent)?;
+/// let entry = |path: &[&str], length| FileEntry {
+///     path: path.iter().map(|part| part.as_bytes().to_vec()).collect(),
+///     length,
+/// };
+/// assert_eq!(
+///     parsed.layout,
+///     FileLayout::Multi {
+///         files: vec![entry(&["a.bin"], 10_000), entry(&[".pad", "6384"], 6_384), entry(&["b.bin"], 20_000)]
+///     }
+/// );
+/// assert_eq!(parsed.v2.unwrap().files.len(), 2);
+/// # Ok::<(), torseed::TorseedError>(())
+/// ```
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BuildFile {
+    /// Path components below the torrent name.
+    pub path: Vec<String>,
+    pub length: u64,
+    /// `None` for an empty file, which has no v2 hashes.
+    pub v2: Option<V2Summary>,
+}
+
+/// The encoded torrent and its infohashes. With the `serde` feature only the
+/// infohashes are serialized; `torrent` is left out and deserializes empty.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Metainfo {
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub torrent: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde::option"))]
     pub infohash_v1: Option<[u8; 20]>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde::option"))]
     pub infohash_v2: Option<[u8; 32]>,
 }
 
 type Dict = BTreeMap<Cow<'static, [u8]>, Value<'static>>;
 
 pub fn build(input: &BuildInput) -> Result<Metainfo> {
-    if input.trackers.is_empty() {
-        bail!("At least one tracker is required");
-    }
+    let version = if has_v2(input) { MetaVersion::Hybrid } else { MetaVersion::V1 };
+    build_version(input, version)
+}
 
-    let info_full = build_info_full(input)?;
-    let info_v1 = build_info_v1(input)?;
-    let info_v2 = build_info_v2(input)?;
+/// Builds `input` as a torrent of one protocol version: `v1` keeps only the
+/// v1 keys of the info dictionary, `v2` only the v2 keys, and `hybrid` both.
+/// All three come from the same hashes, so one pass over the payload is
+/// enough for every flavor; `v2` and `hybrid` need the input's v2 hashes.
+/// Each infohash covers the info dictionary as written, and the v2 piece
+/// layers go in the root dictionary, outside it.
+///
+/// ```
+/// use torseed::metainfo::{self, BuildInput, MetaVersion};
+/// use torseed::V2Summary;
+///
+/// let input = BuildInput {
+///     name: "data.bin".to_string(),
+///     length: 40_000,
+///     piece_length: 16_384,
+///     pieces: vec![7; 60],
+///     announce_tiers: vec![vec!["udp://a.example:1/announce".to_string()]],
+///     webseeds: Vec::new(),
+///     creation_date: 1_700_000_000,
+///     created_by: "torseed".to_string(),
+///     name_utf8: false,
+///     v2: Some(V2Summary { pieces_root: [1; 32], piece_layers: vec![2; 96] }),
+///     files: Vec::new(),
+/// };
+/// let mut infohashes = Vec::new();
+/// for version in [MetaVersion::Hybrid, MetaVersion::V1, MetaVersion::V2] {
+///     let built = metainfo::build_version(&input, version)?;
+///     let parsed = metainfo::parse(&built.torrent)?;
+///     assert_eq!(parsed.version(), version);
+///     assert_eq!((built.infohash_v1, built.infohash_v2), (parsed.infohash_v1(), parsed.infohash_v2()));
+///     infohashes.push((built.infohash_v1, built.infohash_v2));
+/// }
+/// assert!(infohashes[1].1.is_none() && infohashes[2].0.is_none());
+/// assert_ne!(infohashes[0].0, infohashes[1].0);
+/// assert_ne!(infohashes[0].1, infohashes[2].1);
+///
+/// let v1_only = BuildInput { v2: None, ..input };
+/// assert!(metainfo::build_version(&v1_only, MetaVersion::V2).is_err());
+/// # Ok::<(), torseed::TorseedError>(())
+/// ```
+pub fn build_version(input: &BuildInput, version: MetaVersion) -> Result<Metainfo> {
+    if input.announce_tiers.iter().all(Vec::is_empty) {
+        return Err(TorseedError::InvalidInput("At least one tracker is required".to_string()));
+    }
+    if version != MetaVersion::V1 && !has_v2(input) {
+        return Err(TorseedError::InvalidInput(format!(
+            "A {version} torrent needs v2 hashes, which this input lacks"
+        )));
+    }
 
-    let infohash_v1 = Some(
-        Sha1::digest(
-            &info_v1
-                .to_bencode()
-                .map_err(|err| anyhow!("Failed to encode v1 info dictionary: {err}"))?,
-        )
-        .into(),
-    );
-    let infohash_v2 = if input.v2.is_some() {
-        Some(
-            Sha256::digest(
-                &info_v2
-                    .to_bencode()
-                    .map_err(|err| anyhow!("Failed to encode v2 info dictionary: {err}"))?,
-            )
-            .into(),
-        )
-    } else {
-        None
+    let info = match version {
+        MetaVersion::V1 => build_info_v1(input)?,
+        MetaVersion::V2 => build_info_v2(input)?,
+        MetaVersion::Hybrid => build_info_full(input)?,
     };
+    let encoded = info.to_bencode().map_err(|err| encode_error("info dictionary", err))?;
+    let infohash_v1 = (version != MetaVersion::V2).then(|| Sha1::digest(&encoded).into());
+    let infohash_v2 = (version != MetaVersion::V1).then(|| Sha256::digest(&encoded).into());
 
-    let torrent = build_torrent_root(input, info_full)?;
+    let torrent = build_torrent_root(input, info, version)?;
 
     Ok(Metainfo {
         torrent,
@@ -70,23 +174,32 @@ pub fn build(input: &BuildInput) -> Result<Metainfo> {
     })
 }
 
-fn build_torrent_root(input: &BuildInput, info: Value<'static>) -> Result<Vec<u8>> {
+fn build_torrent_root(input: &BuildInput, info: Value<'static>, version: MetaVersion) -> Result<Vec<u8>> {
     let mut root: Dict = BTreeMap::new();
-    root.insert(key("announce"), bytes(input.trackers[0].clone()));
+    let primary = input
+        .announce_tiers
+        .iter()
+        .flatten()
+        .next()
+        .ok_or_else(|| TorseedError::InvalidInput("At least one tracker is required".to_string()))?;
+    root.insert(key("announce"), bytes(primary.clone()));
 
-    let announce_tier: Vec<Value<'static>> = input
-        .trackers
+    let tiers: Vec<Value<'static>> = input
+        .announce_tiers
         .iter()
-        .map(|t| bytes(t.clone()))
+        .filter(|tier| !tier.is_empty())
+        .map(|tier| Value::List(tier.iter().map(|t| bytes(t.clone())).collect()))
         .collect();
-    root.insert(
-        key("announce-list"),
-        Value::List(vec![Value::List(announce_tier)]),
-    );
+    root.insert(key("announce-list"), Value::List(tiers));
 
-    root.insert(key("created by"), bytes(input.created_by.clone()));
+    if !input.created_by.is_empty() {
+        root.insert(key("created by"), bytes(input.created_by.clone()));
+    }
     root.insert(key("creation date"), Value::Integer(input.creation_date));
     root.insert(key("info"), info);
+    if version != MetaVersion::V1 {
+        root.insert(key("piece layers"), build_piece_layers(input));
+    }
 
     let webseed_list: Vec<Value<'static>> = input
         .webseeds
@@ -97,13 +210,18 @@ fn build_torrent_root(input: &BuildInput, info: Value<'static>) -> Result<Vec<u8
 
     Value::Dict(root)
         .to_bencode()
-        .map_err(|err| anyhow!("Failed to encode root dictionary: {err}"))
+        .map_err(|err| encode_error("root dictionary", err))
+}
+
+/// Multi-file inputs always carry v2 hashes, per file.
+fn has_v2(input: &BuildInput) -> bool {
+    input.v2.is_some() || !input.files.is_empty()
 }
 
 fn build_info_full(input: &BuildInput) -> Result<Value<'static>> {
     let mut dict = info_v1_map(input)?;
-    if let Some(v2) = &input.v2 {
-        dict.extend(info_v2_map(input, v2)?);
+    if has_v2(input) {
+        dict.extend(info_v2_map(input)?);
     }
     Ok(Value::Dict(dict))
 }
@@ -113,16 +231,20 @@ fn build_info_v1(input: &BuildInput) -> Result<Value<'static>> {
 }
 
 fn build_info_v2(input: &BuildInput) -> Result<Value<'static>> {
-    match &input.v2 {
-        Some(v2) => Ok(Value::Dict(info_v2_map(input, v2)?)),
-        None => Ok(Value::Dict(BTreeMap::new())),
-    }
+    Ok(Value::Dict(info_v2_map(input)?))
 }
 
 fn info_v1_map(input: &BuildInput) -> Result<Dict> {
     let mut dict = BTreeMap::new();
-    dict.insert(key("length"), Value::Integer(i64_from_u64(input.length)?));
+    if input.files.is_empty() {
+        dict.insert(key("length"), Value::Integer(i64_from_u64(input.length)?));
+    } else {
+        dict.insert(key("files"), build_file_list(input)?);
+    }
     dict.insert(key("name"), bytes(input.name.clone()));
+    if input.name_utf8 {
+        dict.insert(key("name.utf-8"), bytes(input.name.clone()));
+    }
     dict.insert(
         key("piece length"),
         Value::Integer(i64::from(input.piece_length)),
@@ -131,7 +253,30 @@ fn info_v1_map(input: &BuildInput) -> Result<Dict> {
     Ok(dict)
 }
 
-fn info_v2_map(input: &BuildInput, v2: &V2Summary) -> Result<Dict> {
+/// The v1 `files` list, with a pad file after every file that does not end on
+/// a piece boundary, except the last.
+fn build_file_list(input: &BuildInput) -> Result<Value<'static>> {
+    let piece_length = u64::from(input.piece_length);
+    let mut list = Vec::new();
+    for (index, file) in input.files.iter().enumerate() {
+        let mut entry = BTreeMap::new();
+        entry.insert(key("length"), Value::Integer(i64_from_u64(file.length)?));
+        entry.insert(key("path"), Value::List(file.path.iter().map(|part| bytes(part.clone())).collect()));
+        list.push(Value::Dict(entry));
+
+        let padding = (piece_length - file.length % piece_length) % piece_length;
+        if padding > 0 && index + 1 < input.files.len() {
+            let mut pad = BTreeMap::new();
+            pad.insert(key("attr"), bytes("p"));
+            pad.insert(key("length"), Value::Integer(i64_from_u64(padding)?));
+            pad.insert(key("path"), Value::List(vec![bytes(".pad"), bytes(padding.to_string())]));
+            list.push(Value::Dict(pad));
+        }
+    }
+    Ok(Value::List(list))
+}
+
+fn info_v2_map(input: &BuildInput) -> Result<Dict> {
     let mut dict = BTreeMap::new();
     dict.insert(key("meta version"), Value::Integer(2));
     dict.insert(key("name"), bytes(input.name.clone()));
@@ -139,12 +284,14 @@ fn info_v2_map(input: &BuildInput, v2: &V2Summary) -> Result<Dict> {
         key("piece length"),
         Value::Integer(i64::from(input.piece_length)),
     );
-    dict.insert(key("file tree"), build_file_tree(input, v2)?);
-    dict.insert(key("piece layers"), build_piece_layers(v2));
+    dict.insert(key("file tree"), build_file_tree(input)?);
     Ok(dict)
 }
 
-fn build_file_tree(input: &BuildInput, v2: &V2Summary) -> Result<Value<'static>> {
+fn build_file_tree(input: &BuildInput) -> Result<Value<'static>> {
+    let Some(v2) = &input.v2 else {
+        return build_multi_file_tree(input);
+    };
     let mut leaf = BTreeMap::new();
     leaf.insert(key("length"), Value::Integer(i64_from_u64(input.length)?));
     leaf.insert(key("pieces root"), bytes(v2.pieces_root.to_vec()));
@@ -158,12 +305,703 @@ fn build_file_tree(input: &BuildInput, v2: &V2Summary) -> Result<Value<'static>>
     Ok(Value::Dict(tree))
 }
 
-fn build_piece_layers(v2: &V2Summary) -> Value<'static> {
+/// `name` → path components → file leaf. Pad files exist only in v1.
+fn build_multi_file_tree(input: &BuildInput) -> Result<Value<'static>> {
+    let mut root = BTreeMap::new();
+    for file in &input.files {
+        let mut leaf = BTreeMap::new();
+        leaf.insert(key("length"), Value::Integer(i64_from_u64(file.length)?));
+        if let Some(v2) = &file.v2 {
+            leaf.insert(key("pieces root"), bytes(v2.pieces_root.to_vec()));
+        }
+        let mut node = &mut root;
+        for part in &file.path {
+            let child = node
+                .entry(key(part))
+                .or_insert_with(|| Value::Dict(BTreeMap::new()));
+            let Value::Dict(child) = child else {
+                unreachable!("file tree nodes are dictionaries");
+            };
+            node = child;
+        }
+        if !node.is_empty() {
+            return Err(TorseedError::InvalidInput(format!("Conflicting file path {}", file.path.join("/"))));
+        }
+        node.insert(Cow::Owned(Vec::new()), Value::Dict(leaf));
+    }
+
+    let mut tree = BTreeMap::new();
+    tree.insert(key(&input.name), Value::Dict(root));
+    Ok(Value::Dict(tree))
+}
+
+/// Piece layers by pieces root. Files of a multi-file torrent that fit in
+/// one piece have none (BEP 52).
+fn build_piece_layers(input: &BuildInput) -> Value<'static> {
     let mut dict = BTreeMap::new();
-    dict.insert(Cow::Owned(v2.pieces_root.to_vec()), bytes(v2.piece_layers.clone()));
+    if let Some(v2) = &input.v2 {
+        dict.insert(Cow::Owned(v2.pieces_root.to_vec()), bytes(v2.piece_layers.clone()));
+    }
+    for file in &input.files {
+        if let Some(v2) = &file.v2
+            && file.length > u64::from(input.piece_length)
+        {
+            dict.insert(Cow::Owned(v2.pieces_root.to_vec()), bytes(v2.piece_layers.clone()));
+        }
+    }
     Value::Dict(dict)
 }
 
+/// Reads the announce tiers from an encoded torrent, preferring `announce-list`
+/// and falling back to the single `announce` URL.
+pub fn read_announce_tiers(torrent: &[u8]) -> Result<Vec<Vec<String>>> {
+    let root = match Value::from_bencode(torrent) {
+        Ok(Value::Dict(root)) => root,
+        Ok(_) => return Err(decode_error("Torrent is not a bencoded dictionary")),
+        Err(err) => return Err(decode_error(format!("Failed to decode torrent: {err}"))),
+    };
+
+    let mut tiers = Vec::new();
+    if let Some(value) = root.get(b"announce-list".as_slice()) {
+        let Value::List(list) = value else {
+            return Err(decode_error("announce-list is not a list"));
+        };
+        for tier in list {
+            let Value::List(entries) = tier else {
+                return Err(decode_error("announce-list tier is not a list"));
+            };
+            let urls: Vec<String> = entries.iter().filter_map(utf8_bytes).collect();
+            if !urls.is_empty() {
+                tiers.push(urls);
+            }
+        }
+    }
+
+    if tiers.is_empty()
+        && let Some(url) = root.get(b"announce".as_slice()).and_then(utf8_bytes)
+    {
+        tiers.push(vec![url]);
+    }
+
+    if tiers.is_empty() {
+        return Err(decode_error("Torrent has no announce or announce-list trackers"));
+    }
+    Ok(tiers)
+}
+
+/// Returns the raw, undecoded bytes of the `info` dictionary so infohashes can
+/// be recomputed exactly as the original creator encoded them.
+pub fn info_dict_bytes(torrent: &[u8]) -> Result<&[u8]> {
+    let mut decoder = Decoder::new(torrent);
+    let root = decoder
+        .next_object()
+        .map_err(|err| decode_error(format!("Failed to decode torrent: {err}")))?
+        .ok_or_else(|| decode_error("Torrent file is empty"))?;
+    let Object::Dict(mut dict) = root else {
+        return Err(decode_error("Torrent is not a bencoded dictionary"));
+    };
+    while let Some((name, value)) = dict
+        .next_pair()
+        .map_err(|err| decode_error(format!("Failed to decode torrent: {err}")))?
+    {
+        if name == b"info" {
+            let Object::Dict(info) = value else {
+                return Err(decode_error("Torrent info is not a dictionary"));
+            };
+            return info
+                .into_raw()
+                .map_err(|err| decode_error(format!("Failed to decode info dictionary: {err}")));
+        }
+    }
+    Err(decode_error("Torrent has no info dictionary"))
+}
+
+/// Infohash to use when talking to trackers: the v1 SHA-1 for v1 and hybrid
+/// torrents, the truncated v2 SHA-256 for v2-only torrents.
+pub fn tracker_infohash(torrent: &[u8]) -> Result<[u8; 20]> {
+    let info = info_dict_bytes(torrent)?;
+    let has_pieces = match Value::from_bencode(info) {
+        Ok(Value::Dict(dict)) => dict.contains_key(b"pieces".as_slice()),
+        _ => return Err(decode_error("Torrent info is not a dictionary")),
+    };
+    if has_pieces {
+        Ok(Sha1::digest(info).into())
+    } else {
+        let digest = Sha256::digest(info);
+        let mut truncated = [0u8; 20];
+        truncated.copy_from_slice(&digest[..20]);
+        Ok(truncated)
+    }
+}
+
+/// A decoded `.torrent` file, the inverse of [`build`].
+///
+/// Optional keys that are missing decode to `None` or an empty list. Names
+/// and paths are kept as raw bytes because older creators wrote them in the
+/// local code page rather than UTF-8.
+#[derive(Debug, Clone)]
+pub struct ParsedTorrent {
+    pub name: Vec<u8>,
+    pub piece_length: u64,
+    pub layout: FileLayout,
+    /// Concatenated SHA-1 piece hashes; `None` for v2-only torrents.
+    pub pieces: Option<Vec<u8>>,
+    pub announce: Option<String>,
+    /// `announce-list` tiers, or the `announce` URL alone when the list is
+    /// absent.
+    pub announce_tiers: Vec<Vec<String>>,
+    pub webseeds: Vec<String>,
+    pub creation_date: Option<i64>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    /// BEP 27 `private` flag.
+    pub private: bool,
+    /// The `info` dictionary exactly as encoded in the file.
+    pub info: Vec<u8>,
+    pub v2: Option<ParsedV2>,
+}
+
+/// Which BitTorrent protocol versions a torrent supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum MetaVersion {
+    V1,
+    V2,
+    Hybrid,
+}
+
+impl fmt::Display for MetaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::V1 => "v1",
+            Self::V2 => "v2",
+            Self::Hybrid => "hybrid",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileLayout {
+    Single { length: u64 },
+    Multi { files: Vec<FileEntry> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    /// Path components below the torrent name.
+    pub path: Vec<Vec<u8>>,
+    pub length: u64,
+}
+
+/// BEP 52 structures of a v2 or hybrid torrent.
+#[derive(Debug, Clone)]
+pub struct ParsedV2 {
+    pub files: Vec<V2File>,
+    /// Piece layer per pieces root. Files no longer than one piece have none.
+    pub piece_layers: BTreeMap<[u8; 32], Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V2File {
+    /// Path components from the file tree root, starting with the torrent
+    /// name for single-file torrents.
+    pub path: Vec<Vec<u8>>,
+    pub length: u64,
+    /// Absent for empty files.
+    pub pieces_root: Option<[u8; 32]>,
+}
+
+impl ParsedTorrent {
+    /// The name for display, with invalid UTF-8 replaced.
+    pub fn name_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    pub fn version(&self) -> MetaVersion {
+        match (&self.pieces, &self.v2) {
+            (Some(_), Some(_)) => MetaVersion::Hybrid,
+            (None, Some(_)) => MetaVersion::V2,
+            _ => MetaVersion::V1,
+        }
+    }
+
+    /// Total payload size.
+    pub fn length(&self) -> u64 {
+        match &self.layout {
+            FileLayout::Single { length } => *length,
+            FileLayout::Multi { files } => files.iter().map(|file| file.length).sum(),
+        }
+    }
+
+    /// SHA-1 of the info dictionary, for torrents with v1 pieces.
+    pub fn infohash_v1(&self) -> Option<[u8; 20]> {
+        self.pieces.as_ref().map(|_| Sha1::digest(&self.info).into())
+    }
+
+    /// SHA-256 of the info dictionary, for v2 and hybrid torrents.
+    pub fn infohash_v2(&self) -> Option<[u8; 32]> {
+        self.v2.as_ref().map(|_| Sha256::digest(&self.info).into())
+    }
+}
+
+/// Decodes a `.torrent` file.
+///
+/// ```
+/// use torseed::metainfo;
+///
+/// // As written by mktorrent: no creation date, a single url-list string.
+/// let torrent = b"d8:announce26:udp://tracker.example:80/a7:comment2:hi\
+///     4:infod6:lengthi5e4:name4:file12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae\
+///     8:url-list24:https://example.com/filee";
+/// let parsed = metainfo::parse(torrent)?;
+/// assert_eq!(parsed.name, b"file");
+/// assert_eq!(parsed.length(), 5);
+/// assert_eq!(parsed.announce_tiers, [["udp://tracker.example:80/a"]]);
+/// assert_eq!(parsed.webseeds, ["https://example.com/file"]);
+/// assert_eq!(parsed.creation_date, None);
+/// assert!(parsed.v2.is_none());
+/// # Ok::<(), torseed::TorseedError>(())
+/// ```
+///
+/// Parsing a torrent from [`build`] gives back its inputs:
+///
+/// ```
+/// use torseed::metainfo::{self, BuildInput, FileLayout};
+///
+/// let input = BuildInput {
+///     name: "data.bin".to_string(),
+///     length: 40_000,
+///     piece_length: 16_384,
+///     pieces: vec![7; 60],
+///     announce_tiers: vec![vec!["udp://a.example:1/announce".to_string()], vec!["https://b.example/announce".to_string()]],
+///     webseeds: vec!["https://example.com/data.bin".to_string()],
+///     creation_date: 1_700_000_000,
+///     created_by: "torseed".to_string(),
+///     name_utf8: false,
+///     v2: None,
+///     files: Vec::new(),
+/// };
+/// let parsed = metainfo::parse(&metainfo::build(&input)?.torrent)?;
+/// assert_eq!(parsed.name_lossy(), input.name);
+/// assert_eq!(parsed.layout, FileLayout::Single { length: 40_000 });
+/// assert_eq!(parsed.piece_length, 16_384);
+/// assert_eq!(parsed.pieces.as_deref(), Some(&input.pieces[..]));
+/// assert_eq!(parsed.announce_tiers, input.announce_tiers);
+/// assert_eq!(parsed.webseeds, input.webseeds);
+/// assert_eq!(parsed.creation_date, Some(input.creation_date));
+/// assert_eq!(parsed.created_by.as_deref(), Some("torseed"));
+/// # Ok::<(), torseed::TorseedError>(())
+/// ```
+pub fn parse(torrent: &[u8]) -> Result<ParsedTorrent> {
+    let root = match Value::from_bencode(torrent) {
+        Ok(Value::Dict(root)) => root,
+        Ok(_) => return Err(decode_error("Torrent is not a bencoded dictionary")),
+        Err(err) => return Err(decode_error(format!("Failed to decode torrent: {err}"))),
+    };
+    let info_bytes = info_dict_bytes(torrent)?.to_vec();
+    let Some(Value::Dict(info)) = root.get(b"info".as_slice()) else {
+        return Err(decode_error("Torrent info is not a dictionary"));
+    };
+
+    let name = match info.get(b"name".as_slice()) {
+        Some(Value::Bytes(name)) => name.to_vec(),
+        _ => return Err(decode_error("Torrent info has no name")),
+    };
+    let piece_length = match info.get(b"piece length".as_slice()) {
+        Some(value) => non_negative(value, "piece length")?,
+        None => return Err(decode_error("Torrent info has no piece length")),
+    };
+    let pieces = match info.get(b"pieces".as_slice()) {
+        Some(Value::Bytes(pieces)) if pieces.len() % 20 == 0 => Some(pieces.to_vec()),
+        Some(_) => return Err(decode_error("pieces is not a multiple of 20 bytes")),
+        None => None,
+    };
+
+    let v2 = match info.get(b"meta version".as_slice()) {
+        Some(Value::Integer(2)) => Some(parse_v2(&root, info)?),
+        Some(Value::Integer(version)) => {
+            return Err(decode_error(format!("Unsupported meta version {version}")));
+        }
+        Some(_) => return Err(decode_error("meta version is not an integer")),
+        None => None,
+    };
+    if pieces.is_none() && v2.is_none() {
+        return Err(decode_error("Torrent has neither v1 pieces nor a v2 file tree"));
+    }
+
+    let layout = match (info.get(b"length".as_slice()), info.get(b"files".as_slice()), &v2) {
+        (Some(length), _, _) => FileLayout::Single {
+            length: non_negative(length, "length")?,
+        },
+        (None, Some(Value::List(entries)), _) => FileLayout::Multi {
+            files: entries.iter().map(parse_file_entry).collect::<Result<_>>()?,
+        },
+        (None, Some(_), _) => return Err(decode_error("files is not a list")),
+        (None, None, Some(v2)) => match v2.files.as_slice() {
+            [file] if file.path == [name.clone()] => FileLayout::Single { length: file.length },
+            files => FileLayout::Multi {
+                files: files
+                    .iter()
+                    .map(|file| FileEntry {
+                        path: file.path.iter().skip(1).cloned().collect(),
+                        length: file.length,
+                    })
+                    .collect(),
+            },
+        },
+        (None, None, None) => return Err(decode_error("Torrent info has neither length nor files")),
+    };
+
+    let announce = root.get(b"announce".as_slice()).and_then(utf8_bytes);
+    let mut announce_tiers = Vec::new();
+    if let Some(Value::List(list)) = root.get(b"announce-list".as_slice()) {
+        for tier in list {
+            if let Value::List(entries) = tier {
+                let urls: Vec<String> = entries.iter().filter_map(utf8_bytes).collect();
+                if !urls.is_empty() {
+                    announce_tiers.push(urls);
+                }
+            }
+        }
+    }
+    if announce_tiers.is_empty()
+        && let Some(url) = &announce
+    {
+        announce_tiers.push(vec![url.clone()]);
+    }
+
+    let webseeds = match root.get(b"url-list".as_slice()) {
+        Some(Value::List(urls)) => urls.iter().filter_map(utf8_bytes).collect(),
+        Some(value) => utf8_bytes(value).into_iter().collect(),
+        None => Vec::new(),
+    };
+    let creation_date = match root.get(b"creation date".as_slice()) {
+        Some(Value::Integer(timestamp)) => Some(*timestamp),
+        _ => None,
+    };
+    let private = match info.get(b"private".as_slice()) {
+        Some(Value::Integer(flag)) => *flag == 1,
+        Some(_) => return Err(decode_error("private is not an integer")),
+        None => false,
+    };
+    let text = |name: &str| match root.get(name.as_bytes()) {
+        Some(Value::Bytes(data)) => Some(String::from_utf8_lossy(data).into_owned()),
+        _ => None,
+    };
+
+    Ok(ParsedTorrent {
+        name,
+        piece_length,
+        layout,
+        pieces,
+        announce,
+        announce_tiers,
+        webseeds,
+        creation_date,
+        comment: text("comment"),
+        created_by: text("created by"),
+        private,
+        info: info_bytes,
+        v2,
+    })
+}
+
+fn parse_file_entry(value: &Value<'_>) -> Result<FileEntry> {
+    let Value::Dict(entry) = value else {
+        return Err(decode_error("files entry is not a dictionary"));
+    };
+    let length = match entry.get(b"length".as_slice()) {
+        Some(length) => non_negative(length, "file length")?,
+        None => return Err(decode_error("files entry has no length")),
+    };
+    let Some(Value::List(components)) = entry.get(b"path".as_slice()) else {
+        return Err(decode_error("files entry has no path list"));
+    };
+    let path = components
+        .iter()
+        .map(|component| match component {
+            Value::Bytes(data) => Ok(data.to_vec()),
+            _ => Err(decode_error("files path component is not a byte string")),
+        })
+        .collect::<Result<_>>()?;
+    Ok(FileEntry { path, length })
+}
+
+/// Reads the file tree and piece layers. Piece layers belong in the root
+/// dictionary, but are also accepted inside `info` where earlier torseed
+/// versions put them.
+fn parse_v2(root: &BTreeMap<Cow<'_, [u8]>, Value<'_>>, info: &BTreeMap<Cow<'_, [u8]>, Value<'_>>) -> Result<ParsedV2> {
+    let Some(Value::Dict(tree)) = info.get(b"file tree".as_slice()) else {
+        return Err(decode_error("v2 torrent has no file tree"));
+    };
+    let mut files = Vec::new();
+    walk_file_tree(tree, &mut Vec::new(), &mut files)?;
+
+    let mut piece_layers = BTreeMap::new();
+    let layers = root
+        .get(b"piece layers".as_slice())
+        .or_else(|| info.get(b"piece layers".as_slice()));
+    match layers {
+        Some(Value::Dict(layers)) => {
+            for (root_hash, layer) in layers {
+                let root_hash: [u8; 32] = root_hash
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| decode_error("piece layers key is not a 32-byte pieces root"))?;
+                let Value::Bytes(layer) = layer else {
+                    return Err(decode_error("piece layer is not a byte string"));
+                };
+                piece_layers.insert(root_hash, layer.to_vec());
+            }
+        }
+        Some(_) => return Err(decode_error("piece layers is not a dictionary")),
+        None => {}
+    }
+    Ok(ParsedV2 { files, piece_layers })
+}
+
+fn walk_file_tree(
+    tree: &BTreeMap<Cow<'_, [u8]>, Value<'_>>,
+    path: &mut Vec<Vec<u8>>,
+    files: &mut Vec<V2File>,
+) -> Result<()> {
+    for (component, node) in tree {
+        let Value::Dict(node) = node else {
+            return Err(decode_error("file tree node is not a dictionary"));
+        };
+        if component.is_empty() {
+            let length = match node.get(b"length".as_slice()) {
+                Some(length) => non_negative(length, "file tree length")?,
+                None => return Err(decode_error("file tree entry has no length")),
+            };
+            let pieces_root = match node.get(b"pieces root".as_slice()) {
+                Some(Value::Bytes(hash)) => Some(
+                    hash.as_ref()
+                        .try_into()
+                        .map_err(|_| decode_error("pieces root is not 32 bytes"))?,
+                ),
+                Some(_) => return Err(decode_error("pieces root is not a byte string")),
+                None => None,
+            };
+            files.push(V2File {
+                path: path.clone(),
+                length,
+                pieces_root,
+            });
+        } else {
+            path.push(component.to_vec());
+            walk_file_tree(node, path, files)?;
+            path.pop();
+        }
+    }
+    Ok(())
+}
+
+fn non_negative(value: &Value<'_>, name: &str) -> Result<u64> {
+    match value {
+        Value::Integer(value) => u64::try_from(*value).map_err(|_| decode_error(format!("{name} is negative"))),
+        _ => Err(decode_error(format!("{name} is not an integer"))),
+    }
+}
+
+/// Changes to the root dictionary of an existing torrent. Nothing here can
+/// touch the `info` dictionary, so infohashes survive an edit.
+#[derive(Debug, Clone, Default)]
+pub struct RootEdits {
+    /// Replaces the announce list before the other tracker edits apply.
+    pub announce_tiers: Option<Vec<Vec<String>>>,
+    /// Appended to the announce list, one tier each, unless already present.
+    pub add_trackers: Vec<String>,
+    pub remove_trackers: Vec<String>,
+    pub add_webseeds: Vec<String>,
+    pub remove_webseeds: Vec<String>,
+    /// `Some("")` removes the comment.
+    pub comment: Option<String>,
+    pub creation_date: Option<i64>,
+    /// `Some("")` removes the `created by` field.
+    pub created_by: Option<String>,
+}
+
+/// Applies `edits` and re-encodes the torrent around the original `info`
+/// bytes, so the info dictionary is carried through byte for byte even
+/// when its creator encoded it non-canonically. The result is decoded again
+/// and rejected if the infohash changed.
+///
+/// ```
+/// use torseed::metainfo::{self, RootEdits};
+///
+/// let torrent = b"d8:announce14:udp://a.ex:1/a\
+///     4:infod6:lengthi5e4:name4:file12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+/// let edits = RootEdits {
+///     add_trackers: vec!["udp://b.ex:1/a".to_string()],
+///     add_webseeds: vec!["https://example.com/file".to_string()],
+///     ..RootEdits::default()
+/// };
+/// let edited = metainfo::parse(&metainfo::edit(torrent, &edits)?)?;
+/// assert_eq!(edited.announce_tiers, [["udp://a.ex:1/a"], ["udp://b.ex:1/a"]]);
+/// assert_eq!(edited.webseeds, ["https://example.com/file"]);
+/// assert_eq!(edited.infohash_v1(), metainfo::parse(torrent)?.infohash_v1());
+/// # Ok::<(), torseed::TorseedError>(())
+/// ```
+pub fn edit(torrent: &[u8], edits: &RootEdits) -> Result<Vec<u8>> {
+    let original = parse(torrent)?;
+    let mut root = match Value::from_bencode(torrent) {
+        Ok(Value::Dict(root)) => root,
+        Ok(_) => return Err(decode_error("Torrent is not a bencoded dictionary")),
+        Err(err) => return Err(decode_error(format!("Failed to decode torrent: {err}"))),
+    };
+
+    let mut tiers = edits.announce_tiers.clone().unwrap_or_else(|| original.announce_tiers.clone());
+    for tier in &mut tiers {
+        tier.retain(|tracker| !edits.remove_trackers.contains(tracker));
+    }
+    tiers.retain(|tier| !tier.is_empty());
+    for tracker in &edits.add_trackers {
+        if !tiers.iter().flatten().any(|existing| existing == tracker) {
+            tiers.push(vec![tracker.clone()]);
+        }
+    }
+    match tiers.first().and_then(|tier| tier.first()) {
+        Some(primary) => {
+            root.insert(key("announce"), bytes(primary.clone()));
+            let list = tiers
+                .iter()
+                .map(|tier| Value::List(tier.iter().map(|tracker| bytes(tracker.clone())).collect()))
+                .collect();
+            root.insert(key("announce-list"), Value::List(list));
+        }
+        None => {
+            root.remove(b"announce".as_slice());
+            root.remove(b"announce-list".as_slice());
+        }
+    }
+
+    let mut webseeds = original.webseeds.clone();
+    webseeds.retain(|webseed| !edits.remove_webseeds.contains(webseed));
+    for webseed in &edits.add_webseeds {
+        if !webseeds.contains(webseed) {
+            webseeds.push(webseed.clone());
+        }
+    }
+    if webseeds.is_empty() {
+        root.remove(b"url-list".as_slice());
+    } else {
+        let list = webseeds.iter().map(|webseed| bytes(webseed.clone())).collect();
+        root.insert(key("url-list"), Value::List(list));
+    }
+
+    match edits.comment.as_deref() {
+        Some("") => {
+            root.remove(b"comment".as_slice());
+        }
+        Some(comment) => {
+            root.insert(key("comment"), bytes(comment));
+        }
+        None => {}
+    }
+    if let Some(timestamp) = edits.creation_date {
+        root.insert(key("creation date"), Value::Integer(timestamp));
+    }
+    match edits.created_by.as_deref() {
+        Some("") => {
+            root.remove(b"created by".as_slice());
+        }
+        Some(created_by) => {
+            root.insert(key("created by"), bytes(created_by));
+        }
+        None => {}
+    }
+
+    let mut encoded = vec![b'd'];
+    for (name, value) in &root {
+        encoded.extend_from_slice(name.len().to_string().as_bytes());
+        encoded.push(b':');
+        encoded.extend_from_slice(name);
+        if name.as_ref() == b"info" {
+            encoded.extend_from_slice(&original.info);
+        } else {
+            let value = value.to_bencode().map_err(|err| encode_error("root dictionary", err))?;
+            encoded.extend_from_slice(&value);
+        }
+    }
+    encoded.push(b'e');
+
+    let edited = parse(&encoded)?;
+    if edited.info != original.info
+        || edited.infohash_v1() != original.infohash_v1()
+        || edited.infohash_v2() != original.infohash_v2()
+    {
+        return Err(TorseedError::Encode {
+            what: "edited torrent",
+            message: "the info dictionary changed; refusing to write".to_string(),
+        });
+    }
+    Ok(encoded)
+}
+
+/// Wraps the `info` dictionary of `reference`, byte for byte, in a root
+/// carrying the trackers, webseeds, creation date and creator of `input`.
+/// Everything else in `input` is ignored; the caller is responsible for it
+/// describing the same payload. Other root fields of the reference, such as
+/// its comment and v2 piece layers, are kept.
+///
+/// ```
+/// use torseed::metainfo::{self, BuildInput};
+///
+/// let reference = b"d8:announce14:udp://a.ex:1/a7:comment8:official\
+///     4:infod6:lengthi5e4:name4:file12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa7:privatei1eee";
+/// let input = BuildInput {
+///     name: "file".to_string(),
+///     length: 5,
+///     piece_length: 16_384,
+///     pieces: vec![b'a'; 20],
+///     announce_tiers: vec![vec!["udp://mine.example:1/announce".to_string()]],
+///     webseeds: vec!["https://mirror.example/file".to_string()],
+///     creation_date: 1_700_000_000,
+///     created_by: "torseed".to_string(),
+///     name_utf8: false,
+///     v2: None,
+///     files: Vec::new(),
+/// };
+/// let rewrapped = metainfo::rewrap(reference, &input)?;
+/// let parsed = metainfo::parse(&rewrapped.torrent)?;
+/// let original = metainfo::parse(reference)?;
+/// assert_eq!(parsed.info, original.info);
+/// assert_eq!(rewrapped.infohash_v1, original.infohash_v1());
+/// assert!(parsed.private);
+/// assert_eq!(parsed.announce_tiers, [["udp://mine.example:1/announce"]]);
+/// assert_eq!(parsed.webseeds, ["https://mirror.example/file"]);
+/// assert_eq!(parsed.comment.as_deref(), Some("official"));
+/// assert_eq!(parsed.creation_date, Some(1_700_000_000));
+/// # Ok::<(), torseed::TorseedError>(())
+/// ```
+pub fn rewrap(reference: &[u8], input: &BuildInput) -> Result<Metainfo> {
+    if input.announce_tiers.iter().all(Vec::is_empty) {
+        return Err(TorseedError::InvalidInput("At least one tracker is required".to_string()));
+    }
+    let original = parse(reference)?;
+    let edits = RootEdits {
+        announce_tiers: Some(input.announce_tiers.clone()),
+        add_webseeds: input.webseeds.clone(),
+        remove_webseeds: original.webseeds.clone(),
+        creation_date: Some(input.creation_date),
+        created_by: Some(input.created_by.clone()),
+        ..RootEdits::default()
+    };
+    Ok(Metainfo {
+        torrent: edit(reference, &edits)?,
+        infohash_v1: original.infohash_v1(),
+        infohash_v2: original.infohash_v2(),
+    })
+}
+
+fn utf8_bytes(value: &Value<'_>) -> Option<String> {
+    match value {
+        Value::Bytes(data) => String::from_utf8(data.to_vec()).ok(),
+        _ => None,
+    }
+}
+
 fn bytes(data: impl Into<Vec<u8>>) -> Value<'static> {
     Value::Bytes(Cow::Owned(data.into()))
 }
@@ -173,5 +1011,204 @@ fn key(input: &str) -> Cow<'static, [u8]> {
 }
 
 fn i64_from_u64(value: u64) -> Result<i64> {
-    i64::try_from(value).context("value exceeds i64 range")
+    i64::try_from(value).map_err(|_| TorseedError::InvalidInput(format!("value {value} exceeds i64 range")))
+}
+
+fn encode_error(what: &'static str, err: impl std::fmt::Display) -> TorseedError {
+    TorseedError::Encode {
+        what,
+        message: err.to_string(),
+    }
+}
+
+fn decode_error(message: impl Into<String>) -> TorseedError {
+    TorseedError::Decode(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{V1Hasher, V2Hasher};
+
+    fn input(length: usize) -> BuildInput {
+        let data: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+        let (mut v1, mut v2) = (V1Hasher::new(16_384), V2Hasher::new(16_384));
+        v1.update(&data);
+        v2.update(&data);
+        BuildInput {
+            name: "data.bin".to_string(),
+            length: length as u64,
+            piece_length: 16_384,
+            pieces: v1.finalize(),
+            announce_tiers: vec![vec!["udp://a.example:1/announce".to_string()]],
+            webseeds: vec!["https://example.com/data.bin".to_string()],
+            creation_date: 1_700_000_000,
+            created_by: "torseed".to_string(),
+            name_utf8: false,
+            v2: Some(v2.finalize()),
+            files: Vec::new(),
+        }
+    }
+
+    fn root_keys(torrent: &[u8]) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let Ok(Value::Dict(root)) = Value::from_bencode(torrent) else {
+            panic!("torrent is not a dictionary");
+        };
+        let Some(Value::Dict(info)) = root.get(b"info".as_slice()) else {
+            panic!("torrent has no info dictionary");
+        };
+        (
+            root.keys().map(|key| key.to_vec()).collect(),
+            info.keys().map(|key| key.to_vec()).collect(),
+        )
+    }
+
+    #[test]
+    fn infohashes_cover_the_info_dictionary_as_written() {
+        let input = input(100_000);
+        for version in [MetaVersion::Hybrid, MetaVersion::V1, MetaVersion::V2] {
+            let built = build_version(&input, version).unwrap();
+            let info = info_dict_bytes(&built.torrent).unwrap();
+            let sha1: [u8; 20] = Sha1::digest(info).into();
+            let sha256: [u8; 32] = Sha256::digest(info).into();
+            assert_eq!(built.infohash_v1, (version != MetaVersion::V2).then_some(sha1), "{version}");
+            assert_eq!(built.infohash_v2, (version != MetaVersion::V1).then_some(sha256), "{version}");
+        }
+    }
+
+    #[test]
+    fn piece_layers_sit_at_the_root() {
+        let input = input(100_000);
+        for version in [MetaVersion::Hybrid, MetaVersion::V2] {
+            let built = build_version(&input, version).unwrap();
+            let (root, info) = root_keys(&built.torrent);
+            assert!(root.contains(&b"piece layers".to_vec()), "{version}");
+            assert!(!info.contains(&b"piece layers".to_vec()), "{version}");
+            let parsed = parse(&built.torrent).unwrap();
+            assert_eq!(parsed.infohash_v2(), built.infohash_v2);
+        }
+        let built = build_version(&input, MetaVersion::V1).unwrap();
+        let (root, info) = root_keys(&built.torrent);
+        assert!(!root.contains(&b"piece layers".to_vec()));
+        assert!(!info.contains(&b"meta version".to_vec()));
+    }
+
+    #[test]
+    fn v1_input_builds_a_v1_torrent() {
+        let input = BuildInput { v2: None, ..input(40_000) };
+        let built = build(&input).unwrap();
+        assert!(built.infohash_v2.is_none());
+        assert_eq!(parse(&built.torrent).unwrap().version(), MetaVersion::V1);
+    }
+
+    fn fixture(name: &str) -> ParsedTorrent {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+        parse(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    fn tiers(tiers: &[&[&str]]) -> Vec<Vec<String>> {
+        tiers.iter().map(|tier| tier.iter().map(|url| url.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn parses_mktorrent_multi_file() {
+        let torrent = fixture("mktorrent_multi.torrent");
+        assert_eq!(torrent.version(), MetaVersion::V1);
+        assert_eq!(torrent.name_lossy(), "album");
+        let FileLayout::Multi { files } = &torrent.layout else {
+            panic!("expected a multi-file layout");
+        };
+        let files: Vec<_> = files.iter().map(|file| (file.path.join(&b'/'), file.length)).collect();
+        assert_eq!(files, [(b"disc1/a.bin".to_vec(), 40_000), (b"b.txt".to_vec(), 100)]);
+        assert_eq!(torrent.length(), 40_100);
+        assert_eq!((torrent.piece_length, torrent.pieces.as_ref().map(Vec::len)), (32_768, Some(40)));
+        assert!(torrent.private);
+        assert_eq!(torrent.comment.as_deref(), Some("Test album"));
+        assert_eq!(torrent.created_by.as_deref(), Some("mktorrent 1.1"));
+        assert_eq!(torrent.creation_date, Some(1_600_000_000));
+        assert_eq!(
+            torrent.announce_tiers,
+            tiers(&[&["http://tracker.example.org:6969/announce"], &["udp://backup.example.org:1337/announce"]])
+        );
+        assert_eq!(torrent.webseeds, ["https://mirror.example.org/album/"]);
+        assert_eq!(
+            torrent.infohash_v1().map(hex::encode).as_deref(),
+            Some("6982a9f2f99537e3ed101ab80977107074f724fc")
+        );
+        assert_eq!(torrent.infohash_v2(), None);
+    }
+
+    #[test]
+    fn parses_transmission_single_file() {
+        let torrent = fixture("transmission_single.torrent");
+        assert_eq!(torrent.version(), MetaVersion::V1);
+        assert_eq!(torrent.name_lossy(), "image.iso");
+        assert!(matches!(torrent.layout, FileLayout::Single { length: 50_000 }));
+        assert_eq!(torrent.pieces.as_ref().map(|pieces| pieces.len() / 20), Some(4));
+        assert!(!torrent.private);
+        assert_eq!(torrent.created_by.as_deref(), Some("Transmission/4.0.5 (a6fe2a64aa)"));
+        assert_eq!(torrent.announce_tiers, tiers(&[&["udp://tracker.example.org:1337/announce"]]));
+        assert!(torrent.webseeds.is_empty());
+        assert_eq!(
+            torrent.infohash_v1().map(hex::encode).as_deref(),
+            Some("4cfc2a27621f74d024cc9c39fc1bda0ec0244b69")
+        );
+    }
+
+    #[test]
+    fn parses_qbittorrent_hybrid() {
+        let torrent = fixture("qbittorrent_hybrid.torrent");
+        assert_eq!(torrent.version(), MetaVersion::Hybrid);
+        assert!(matches!(torrent.layout, FileLayout::Single { length: 40_000 }));
+        let v2 = torrent.v2.as_ref().unwrap();
+        assert_eq!(v2.files.len(), 1);
+        let root = v2.files[0].pieces_root.unwrap();
+        assert_eq!(v2.piece_layers.get(&root).map(Vec::len), Some(3 * 32));
+        assert_eq!(torrent.created_by.as_deref(), Some("qBittorrent v4.6.2"));
+        // Without an announce-list the announce URL forms the only tier.
+        assert_eq!(torrent.announce_tiers, tiers(&[&["udp://tracker.example.org:1337/announce"]]));
+        assert_eq!(
+            torrent.infohash_v1().map(hex::encode).as_deref(),
+            Some("448ad5057ce086b5af0c3691944c603e6bc0fd67")
+        );
+        assert_eq!(
+            torrent.infohash_v2().map(hex::encode).as_deref(),
+            Some("752f38ba8e1de5fb34f0196ffc53302536c9127850e8d7669ccdd6285137e099")
+        );
+    }
+
+    #[test]
+    fn parses_legacy_code_page_names() {
+        let torrent = fixture("legacy_cp1251.torrent");
+        // "Музыка" in Windows-1251, kept as written.
+        assert_eq!(torrent.name, b"\xcc\xf3\xe7\xfb\xea\xe0");
+        assert_eq!(torrent.name_lossy(), "\u{fffd}".repeat(6));
+        assert_eq!(torrent.created_by, None);
+        assert_eq!(torrent.creation_date, None);
+        assert_eq!(
+            torrent.infohash_v1().map(hex::encode).as_deref(),
+            Some("79d952579de840b4b99db7516a2b9c994f1c19f1")
+        );
+    }
+
+    #[test]
+    fn malformed_keys_are_named() {
+        let info = |body: &str| format!("d4:info{body}e").into_bytes();
+        for (torrent, wording) in [
+            (info("d6:lengthi5e12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae"), "no name"),
+            (info("d6:lengthi5e4:name1:a6:pieces20:aaaaaaaaaaaaaaaaaaaae"), "no piece length"),
+            (info("d6:lengthi5e4:name1:a12:piece lengthi16384e6:pieces3:abce"), "pieces"),
+            (
+                info("d6:lengthi5e4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa7:private1:xe"),
+                "private",
+            ),
+            (info("d5:filesl1:xe4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae"), "files entry"),
+            (info("d12:meta versioni3e4:name1:a12:piece lengthi16384ee"), "meta version 3"),
+            (b"li1ee".to_vec(), "not a bencoded dictionary"),
+        ] {
+            let err = parse(&torrent).unwrap_err().to_string();
+            assert!(err.contains(wording), "{err}");
+        }
+    }
+
 }
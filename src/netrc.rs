@@ -0,0 +1,255 @@
+//! Credentials from a `.netrc` file, the format curl, wget and pip read for
+//! HTTP basic auth.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, TorseedError};
+
+/// A login and password for one host. `Debug` leaves the password out.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub login: String,
+    pub password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("login", &self.login)
+            .field("password", &"<hidden>")
+            .finish()
+    }
+}
+
+/// The entries of a netrc file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Netrc {
+    machines: Vec<(String, Credentials)>,
+    default: Option<Credentials>,
+}
+
+impl Netrc {
+    /// Parses netrc text: `machine`, `default`, `login`, `password`,
+    /// `account` and `macdef` tokens separated by whitespace, each value on
+    /// its keyword's line and optionally double-quoted, and `#` comments.
+    /// Entries without a login are ignored; a missing password is empty.
+    /// Errors name the line.
+    ///
+    /// ```
+    /// use torseed::netrc::Netrc;
+    ///
+    /// let netrc = Netrc::parse(
+    ///     "# internal mirrors\n\
+    ///      machine mirror.example.com login builder password \"s3cret pass\"\n\
+    ///      machine other.example.com\n  login ops\n  password hunter2\n\
+    ///      macdef init\ncd /pub\n\n\
+    ///      default login anonymous password guest@example.com\n",
+    /// )
+    /// .unwrap();
+    /// let mirror = netrc.machine("MIRROR.example.com").unwrap();
+    /// assert_eq!((mirror.login.as_str(), mirror.password.as_str()), ("builder", "s3cret pass"));
+    /// assert_eq!(netrc.machine("other.example.com").unwrap().password, "hunter2");
+    /// assert_eq!(netrc.machine("cdn.example.net"), None);
+    /// assert_eq!(netrc.default_entry().unwrap().login, "anonymous");
+    /// assert!(!format!("{mirror:?}").contains("s3cret"));
+    ///
+    /// let err = Netrc::parse("machine a.example login x\nmachine b.example\n  password y\n  login\n").unwrap_err();
+    /// assert_eq!(err.to_string(), "line 4: login needs a value");
+    /// let err = Netrc::parse("login x password y\n").unwrap_err();
+    /// assert_eq!(err.to_string(), "line 1: login outside a machine or default entry");
+    /// let err = Netrc::parse("machine a.example\nlogin x\nport 21\n").unwrap_err();
+    /// assert_eq!(err.to_string(), "line 3: unexpected token \"port\"");
+    /// assert!(Netrc::parse("machine a.example password \"open").is_err());
+    /// ```
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut netrc = Self::default();
+        let mut tokens = Tokens::new(text);
+        let mut entry: Option<Entry> = None;
+        while let Some((line, token)) = tokens.next()? {
+            match token.as_str() {
+                "machine" => {
+                    netrc.finish(entry.take());
+                    let host = tokens.value(line, "machine")?;
+                    entry = Some(Entry::new(Some(host)));
+                }
+                "default" => {
+                    netrc.finish(entry.take());
+                    entry = Some(Entry::new(None));
+                }
+                "login" | "password" | "account" => {
+                    let value = tokens.value(line, &token)?;
+                    let Some(entry) = entry.as_mut() else {
+                        return Err(parse_error(line, format!("{token} outside a machine or default entry")));
+                    };
+                    match token.as_str() {
+                        "login" => entry.login = Some(value),
+                        "password" => entry.password = Some(value),
+                        _ => {}
+                    }
+                }
+                "macdef" => {
+                    tokens.value(line, "macdef")?;
+                    tokens.skip_macro();
+                }
+                _ => return Err(parse_error(line, format!("unexpected token {token:?}"))),
+            }
+        }
+        netrc.finish(entry);
+        Ok(netrc)
+    }
+
+    /// Reads and parses the file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| TorseedError::io(format!("Failed to read netrc file {}", path.display()), err))?;
+        Self::parse(&text)
+            .map_err(|err| TorseedError::InvalidInput(format!("Invalid netrc file {}, {err}", path.display())))
+    }
+
+    /// `~/.netrc`, or `_netrc` in the user profile on Windows, as curl looks
+    /// them up.
+    pub fn default_path() -> Option<PathBuf> {
+        let name = if cfg!(windows) { "_netrc" } else { ".netrc" };
+        std::env::home_dir().map(|home| home.join(name))
+    }
+
+    /// The first `machine` entry for `host`, ignoring case.
+    pub fn machine(&self, host: &str) -> Option<&Credentials> {
+        self.machines
+            .iter()
+            .find(|(machine, _)| machine.eq_ignore_ascii_case(host))
+            .map(|(_, credentials)| credentials)
+    }
+
+    /// The `default` entry, which curl uses for every host without a
+    /// `machine` entry.
+    pub fn default_entry(&self) -> Option<&Credentials> {
+        self.default.as_ref()
+    }
+
+    fn finish(&mut self, entry: Option<Entry>) {
+        let Some(Entry { host, login: Some(login), password }) = entry else {
+            return;
+        };
+        let credentials = Credentials {
+            login,
+            password: password.unwrap_or_default(),
+        };
+        match host {
+            Some(host) => self.machines.push((host, credentials)),
+            None => {
+                self.default.get_or_insert(credentials);
+            }
+        }
+    }
+}
+
+/// A `machine` (with a host) or `default` entry being parsed.
+struct Entry {
+    host: Option<String>,
+    login: Option<String>,
+    password: Option<String>,
+}
+
+impl Entry {
+    fn new(host: Option<String>) -> Self {
+        Self {
+            host,
+            login: None,
+            password: None,
+        }
+    }
+}
+
+fn parse_error(line: usize, message: String) -> TorseedError {
+    TorseedError::InvalidInput(format!("line {line}: {message}"))
+}
+
+/// Whitespace-separated tokens with their 1-based line numbers.
+struct Tokens<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { rest: text, line: 1 }
+    }
+
+    fn next(&mut self) -> Result<Option<(usize, String)>> {
+        loop {
+            let trimmed = self.rest.trim_start_matches(|c: char| c.is_whitespace() && c != '\n');
+            if let Some(rest) = trimmed.strip_prefix('\n') {
+                self.line += 1;
+                self.rest = rest;
+            } else if trimmed.starts_with('#') {
+                self.rest = trimmed.find('\n').map_or("", |end| &trimmed[end..]);
+            } else {
+                self.rest = trimmed;
+                break;
+            }
+        }
+        if self.rest.is_empty() {
+            return Ok(None);
+        }
+        let line = self.line;
+        if let Some(quoted) = self.rest.strip_prefix('"') {
+            let mut token = String::new();
+            let mut chars = quoted.char_indices();
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        self.rest = &quoted[index + 1..];
+                        return Ok(Some((line, token)));
+                    }
+                    '\\' => match chars.next() {
+                        Some((_, 'n')) => token.push('\n'),
+                        Some((_, 'r')) => token.push('\r'),
+                        Some((_, 't')) => token.push('\t'),
+                        Some((_, escaped)) => token.push(escaped),
+                        None => break,
+                    },
+                    '\n' => {
+                        self.line += 1;
+                        token.push(c);
+                    }
+                    _ => token.push(c),
+                }
+            }
+            return Err(parse_error(line, "unterminated quoted value".to_string()));
+        }
+        let end = self.rest.find(char::is_whitespace).unwrap_or(self.rest.len());
+        let token = self.rest[..end].to_string();
+        self.rest = &self.rest[end..];
+        Ok(Some((line, token)))
+    }
+
+    /// The value following `keyword`, which must be on the same line.
+    fn value(&mut self, line: usize, keyword: &str) -> Result<String> {
+        let missing = || parse_error(line, format!("{keyword} needs a value"));
+        let at_line_end = self
+            .rest
+            .trim_start_matches(|c: char| c.is_whitespace() && c != '\n')
+            .starts_with(['\n', '#']);
+        if at_line_end {
+            return Err(missing());
+        }
+        self.next()?.map(|(_, value)| value).ok_or_else(missing)
+    }
+
+    /// Skips a macro body: everything up to the next empty line.
+    fn skip_macro(&mut self) {
+        let mut lines = self.rest.split_inclusive('\n');
+        let mut consumed = lines.next().map_or(0, str::len);
+        self.line += 1;
+        for line in lines {
+            consumed += line.len();
+            self.line += 1;
+            if line.trim().is_empty() {
+                break;
+            }
+        }
+        self.rest = &self.rest[consumed..];
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use url::Url;
+
+/// Header carrying the HMAC-SHA256 of the body when `--notify-secret` is set.
+const SIGNATURE_HEADER: &str = "X-Torseed-Signature";
+/// Per-attempt limit for one delivery.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(15);
+/// Deliveries are tried this many times before giving up.
+const NOTIFY_ATTEMPTS: u32 = 3;
+
+/// POSTs `payload` to every endpoint concurrently. Failures are retried with
+/// a short backoff and then only logged: a webhook never fails the run.
+pub async fn notify(client: &Client, endpoints: &[Url], secret: Option<&str>, payload: &Value) {
+    let body = payload.to_string();
+    let signature =
+        secret.map(|secret| format!("sha256={}", hex::encode(hmac_sha256(secret.as_bytes(), body.as_bytes()))));
+    futures::future::join_all(endpoints.iter().map(|endpoint| {
+        let body = &body;
+        let signature = signature.as_deref();
+        async move {
+            let mut attempt = 1;
+            loop {
+                match deliver(client, endpoint, body, signature).await {
+                    Ok(()) => {
+                        info!("Notified {endpoint}");
+                        break;
+                    }
+                    Err(err) if attempt < NOTIFY_ATTEMPTS => {
+                        warn!("Notifying {endpoint} failed (attempt {attempt}): {err:#}; retrying");
+                        tokio::time::sleep(Duration::from_secs(u64::from(attempt))).await;
+                        attempt += 1;
+                    }
+                    Err(err) => {
+                        warn!("Giving up notifying {endpoint}: {err:#}");
+                        break;
+                    }
+                }
+            }
+        }
+    }))
+    .await;
+}
+
+async fn deliver(client: &Client, endpoint: &Url, body: &str, signature: Option<&str>) -> Result<()> {
+    let mut request = client
+        .post(endpoint.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .timeout(NOTIFY_TIMEOUT);
+    if let Some(signature) = signature {
+        request = request.header(SIGNATURE_HEADER, signature);
+    }
+    let response = request.send().await.context("Request failed")?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Server answered {status}");
+    }
+    Ok(())
+}
+
+/// HMAC-SHA256 per RFC 2104, so receivers can check the payload with any
+/// standard library.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
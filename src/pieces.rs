@@ -0,0 +1,57 @@
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::error::{Result, TorseedError};
+use crate::hash_v2::V2Summary;
+use crate::util;
+
+/// Layout of the `--pieces-out` sidecar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PiecesFormat {
+    /// Raw concatenated 20-byte SHA-1 hashes, as in the `pieces` field.
+    Binary,
+    /// Hex v1 piece hashes plus the v2 pieces root and piece layer.
+    Json,
+}
+
+/// The piece hashes as a JSON document.
+pub fn pieces_json(piece_length: u32, pieces: &[u8], v2: Option<&V2Summary>) -> serde_json::Value {
+    let v1: Vec<String> = pieces.chunks(20).map(hex::encode).collect();
+    let v2 = v2.map(|summary| {
+        json!({
+            "pieces_root": hex::encode(summary.pieces_root),
+            "piece_layers": summary.piece_layers.chunks(32).map(hex::encode).collect::<Vec<_>>(),
+        })
+    });
+    json!({
+        "piece_length": piece_length,
+        "v1": v1,
+        "v2": v2,
+    })
+}
+
+pub fn write_pieces(
+    path: &Path,
+    format: PiecesFormat,
+    piece_length: u32,
+    pieces: &[u8],
+    v2: Option<&V2Summary>,
+) -> Result<()> {
+    let contents = match format {
+        PiecesFormat::Binary => pieces.to_vec(),
+        PiecesFormat::Json => {
+            let mut text = serde_json::to_string_pretty(&pieces_json(piece_length, pieces, v2)).map_err(|err| {
+                TorseedError::Encode {
+                    what: "piece hashes",
+                    message: err.to_string(),
+                }
+            })?;
+            text.push('\n');
+            text.into_bytes()
+        }
+    };
+    util::write_atomic(path, &contents)
+        .map_err(|err| TorseedError::io(format!("Failed to write piece hashes to {}", path.display()), err))
+}
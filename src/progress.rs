@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use url::Url;
+
+use crate::http::SourceMetadata;
+use crate::util::ByteUnits;
+
+/// Span over which the instantaneous rate in progress lines is averaged.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+/// Minimum spacing between recorded samples, to keep the window small.
+const SAMPLE_SPACING: Duration = Duration::from_millis(250);
+
+/// Something that happened while building a torrent, for GUIs and services
+/// that want structured progress instead of log lines.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
+    /// An HTTP source was probed and is about to be downloaded.
+    MetadataResolved(SourceMetadata),
+    /// Running hashing totals, sent a few times per second and once at the end.
+    BytesHashed(HashProgress),
+    /// A v1 piece is complete. Only sent to sinks that opt in with
+    /// [`EventSink::with_piece_events`], as there can be thousands per second.
+    PieceHashed { index: usize, hash: [u8; 20] },
+    /// A mirror serves the same length as the primary source.
+    WebseedVerified { url: Url },
+    WebseedRejected { url: Url, reason: String },
+    TrackersGathered { trackers: usize, tiers: usize },
+    /// The v2 merkle tree is complete.
+    PieceLayerFinalized { pieces_root: [u8; 32], pieces: usize },
+    /// The metainfo was encoded.
+    TorrentBuilt {
+        infohash_v1: Option<[u8; 20]>,
+        infohash_v2: Option<[u8; 32]>,
+        size: usize,
+    },
+    /// The .torrent file was saved by the caller.
+    TorrentWritten { path: PathBuf },
+}
+
+/// Fire-and-forget handle for emitting [`Event`]s into a bounded channel.
+/// Events are dropped rather than waited on when the channel is full, so a
+/// slow observer can never stall hashing; `BytesHashed` carries running
+/// totals, so a later one makes up for any lost.
+#[derive(Debug, Clone, Default)]
+pub struct EventSink {
+    sender: Option<mpsc::Sender<Event>>,
+    pieces: bool,
+}
+
+impl EventSink {
+    pub fn new(sender: mpsc::Sender<Event>) -> Self {
+        Self {
+            sender: Some(sender),
+            pieces: false,
+        }
+    }
+
+    /// A sink that discards every event.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Also deliver [`Event::PieceHashed`].
+    pub fn with_piece_events(mut self) -> Self {
+        self.pieces = true;
+        self
+    }
+
+    pub fn wants_piece_events(&self) -> bool {
+        self.sender.is_some() && self.pieces
+    }
+
+    pub fn emit(&self, event: Event) {
+        if matches!(event, Event::PieceHashed { .. }) && !self.pieces {
+            return;
+        }
+        if let Some(sender) = &self.sender {
+            let _ = sender.try_send(event);
+        }
+    }
+}
+
+/// Snapshot of hashing progress.
+#[derive(Debug, Clone, Copy)]
+pub struct HashProgress {
+    /// Bytes hashed so far, including any resumed from a checkpoint.
+    pub hashed: u64,
+    /// Total source length, when known.
+    pub expected: Option<u64>,
+    /// Rate over the last few seconds, in bytes per second.
+    pub rate: Option<f64>,
+    /// Average rate of this run, in bytes per second.
+    pub average_rate: f64,
+    pub eta: Option<Duration>,
+}
+
+impl HashProgress {
+    /// One progress line: percentage, current and average rate, and ETA.
+    pub fn line(&self, units: ByteUnits) -> String {
+        let current = self
+            .rate
+            .map(|rate| units.format_rate(rate))
+            .unwrap_or_else(|| "-".to_string());
+        let eta = self
+            .eta
+            .map(|eta| humantime::format_duration(Duration::from_secs(eta.as_secs())).to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let done = match self.expected {
+            Some(expected) => format!(
+                "{:.1}% ({} / {})",
+                self.hashed as f64 / expected as f64 * 100.0,
+                units.format(self.hashed),
+                units.format(expected)
+            ),
+            None => units.format(self.hashed),
+        };
+        format!(
+            "Hashed {done} at {current}, average {}, ETA {eta}",
+            units.format_rate(self.average_rate)
+        )
+    }
+}
+
+/// Byte counts and timings for one streaming pass over the source.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferStats {
+    /// Bytes actually downloaded in this run (excludes resumed data).
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// HTTP version the payload came over, e.g. `HTTP/2`; `None` for readers.
+    pub protocol: Option<&'static str>,
+    /// Size of the source as its GET response reported it, which wins over
+    /// a differing HEAD size; `None` for readers, parts and cached copies.
+    pub served_length: Option<u64>,
+}
+
+impl TransferStats {
+    pub fn average_rate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+}
+
+/// Tracks download progress and a rolling transfer rate.
+pub(crate) struct Progress {
+    started: Instant,
+    start_bytes: u64,
+    total: u64,
+    expected: u64,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Progress {
+    /// `start_bytes` is where streaming begins, non-zero when resuming.
+    pub fn new(start_bytes: u64, expected: u64) -> Self {
+        let started = Instant::now();
+        Self {
+            started,
+            start_bytes,
+            total: start_bytes,
+            expected,
+            samples: VecDeque::from([(started, start_bytes)]),
+        }
+    }
+
+    pub fn record(&mut self, bytes: u64) {
+        self.total += bytes;
+        let now = Instant::now();
+        if self
+            .samples
+            .back()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= SAMPLE_SPACING)
+        {
+            self.samples.push_back((now, self.total));
+            while self.samples.len() > 2
+                && self
+                    .samples
+                    .front()
+                    .is_some_and(|(at, _)| now.duration_since(*at) > RATE_WINDOW)
+            {
+                self.samples.pop_front();
+            }
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Rate over the last `RATE_WINDOW`, in bytes per second.
+    fn current_rate(&self) -> Option<f64> {
+        let (first_at, first_bytes) = self.samples.front()?;
+        let (last_at, last_bytes) = self.samples.back()?;
+        let secs = last_at.duration_since(*first_at).as_secs_f64();
+        (secs > 0.0).then(|| (last_bytes - first_bytes) as f64 / secs)
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        let rate = self.current_rate().filter(|rate| *rate > 0.0)?;
+        if self.expected == 0 || self.total > self.expected {
+            return None;
+        }
+        Some(Duration::from_secs_f64((self.expected - self.total) as f64 / rate))
+    }
+
+    pub fn snapshot(&self) -> HashProgress {
+        HashProgress {
+            hashed: self.total,
+            expected: (self.expected > 0).then_some(self.expected),
+            rate: self.current_rate(),
+            average_rate: self.stats().average_rate(),
+            eta: self.eta(),
+        }
+    }
+
+    pub fn stats(&self) -> TransferStats {
+        TransferStats {
+            bytes: self.total - self.start_bytes,
+            elapsed: self.started.elapsed(),
+            protocol: None,
+            served_length: None,
+        }
+    }
+}
+
+pub fn format_rate(bytes_per_sec: f64) -> String {
+    ByteUnits::Binary.format_rate(bytes_per_sec)
+}
@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::Client;
+use url::Url;
+
+/// Per-target limit for an upload, including reading the response.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How a file is sent to a `--publish` target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PublishMethod {
+    /// PUT the raw bytes to the URL, WebDAV style.
+    Put,
+    /// POST a multipart/form-data upload with a single file field.
+    Post,
+}
+
+/// One file to upload.
+pub struct Upload<'a> {
+    /// Form field name for multipart uploads.
+    pub field: &'a str,
+    pub filename: &'a str,
+    pub content_type: &'a str,
+    pub bytes: &'a [u8],
+}
+
+/// Outcome of one upload, reported in the summary.
+pub struct PublishResult {
+    pub target: String,
+    pub outcome: Result<u16>,
+}
+
+impl PublishResult {
+    pub fn succeeded(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+/// Parses a `Name: value` header argument.
+pub fn parse_header(value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected a header as 'Name: value', got {value:?}"))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|err| format!("invalid header name: {err}"))?;
+    let value = HeaderValue::from_str(value.trim()).map_err(|err| format!("invalid header value: {err}"))?;
+    Ok((name, value))
+}
+
+/// Uploads `upload` to every target concurrently. Credentials embedded in a
+/// target URL are sent as basic auth and left out of the report.
+pub async fn publish(
+    client: &Client,
+    targets: &[Url],
+    method: PublishMethod,
+    headers: &HeaderMap,
+    upload: &Upload<'_>,
+) -> Vec<PublishResult> {
+    futures::future::join_all(targets.iter().map(|target| async move {
+        let mut url = target.clone();
+        let username = url.username().to_string();
+        let password = url.password().map(str::to_string);
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+        let outcome = send(client, &url, &username, password.as_deref(), method, headers, upload).await;
+        PublishResult {
+            target: url.to_string(),
+            outcome,
+        }
+    }))
+    .await
+}
+
+async fn send(
+    client: &Client,
+    url: &Url,
+    username: &str,
+    password: Option<&str>,
+    method: PublishMethod,
+    headers: &HeaderMap,
+    upload: &Upload<'_>,
+) -> Result<u16> {
+    let request = match method {
+        PublishMethod::Put => client
+            .put(url.clone())
+            .header(CONTENT_TYPE, upload.content_type)
+            .body(upload.bytes.to_vec()),
+        PublishMethod::Post => {
+            let (content_type, body) = multipart_body(&[Part::File(upload)]);
+            client.post(url.clone()).header(CONTENT_TYPE, content_type).body(body)
+        }
+    };
+    let mut request = request.headers(headers.clone()).timeout(PUBLISH_TIMEOUT);
+    if !username.is_empty() {
+        request = request.basic_auth(username, password);
+    }
+    let response = request.send().await.context("Upload failed")?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Server answered {status}");
+    }
+    Ok(status.as_u16())
+}
+
+/// One field of a multipart/form-data body.
+pub enum Part<'a> {
+    Text { name: &'a str, value: &'a str },
+    File(&'a Upload<'a>),
+}
+
+/// Encodes `parts` as multipart/form-data, returning the content type with
+/// its boundary and the body.
+pub fn multipart_body(parts: &[Part<'_>]) -> (String, Vec<u8>) {
+    let boundary = format!("torseed-{:016x}", rand::thread_rng().r#gen::<u64>());
+    let mut body = Vec::new();
+    for part in parts {
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        match part {
+            Part::Text { name, value } => {
+                body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n").as_bytes());
+                body.extend_from_slice(value.as_bytes());
+            }
+            Part::File(upload) => {
+                let filename = upload.filename.replace(['"', '\r', '\n'], "_");
+                body.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{filename}\"\r\nContent-Type: {}\r\n\r\n",
+                        upload.field, upload.content_type
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(upload.bytes);
+            }
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    (format!("multipart/form-data; boundary={boundary}"), body)
+}
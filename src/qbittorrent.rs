@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::header::{CONTENT_TYPE, COOKIE, REFERER, SET_COOKIE};
+use reqwest::{Client, StatusCode};
+use url::Url;
+
+use crate::publish::{self, Part, Upload};
+
+/// Limit for each WebUI API call.
+const API_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How to reach and authenticate against a qBittorrent WebUI.
+pub struct WebUi {
+    pub url: Url,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// A session cookie from an existing login, sent as is.
+    pub cookie: Option<String>,
+}
+
+/// Where and how the torrent is added.
+pub struct AddOptions<'a> {
+    pub save_path: Option<&'a str>,
+    pub category: Option<&'a str>,
+}
+
+/// Logs in if credentials were given and adds `torrent` with hash checking
+/// enabled, so qBittorrent verifies the payload at `save_path` before seeding.
+pub async fn add_torrent(
+    client: &Client,
+    webui: &WebUi,
+    filename: &str,
+    torrent: &[u8],
+    options: &AddOptions<'_>,
+) -> Result<()> {
+    let cookie = match (&webui.cookie, &webui.username) {
+        (Some(cookie), _) => Some(cookie.clone()),
+        (None, Some(username)) => Some(login(client, webui, username).await?),
+        // Without credentials the WebUI must allow this host to bypass
+        // authentication, e.g. for clients on localhost.
+        (None, None) => None,
+    };
+
+    let upload = Upload {
+        field: "torrents",
+        filename,
+        content_type: "application/x-bittorrent",
+        bytes: torrent,
+    };
+    let mut parts = vec![Part::File(&upload), Part::Text {
+        name: "skip_checking",
+        value: "false",
+    }];
+    if let Some(save_path) = options.save_path {
+        parts.push(Part::Text {
+            name: "savepath",
+            value: save_path,
+        });
+    }
+    if let Some(category) = options.category {
+        parts.push(Part::Text {
+            name: "category",
+            value: category,
+        });
+    }
+    let (content_type, body) = publish::multipart_body(&parts);
+
+    let mut request = client
+        .post(endpoint(&webui.url, "torrents/add")?)
+        .header(CONTENT_TYPE, content_type)
+        .header(REFERER, webui.url.as_str())
+        .body(body)
+        .timeout(API_TIMEOUT);
+    if let Some(cookie) = &cookie {
+        request = request.header(COOKIE, cookie);
+    }
+    let response = request.send().await.context("qBittorrent did not answer")?;
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    match status {
+        StatusCode::FORBIDDEN => anyhow::bail!("qBittorrent refused the request: not logged in"),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => anyhow::bail!("qBittorrent rejected the torrent file as invalid"),
+        status if !status.is_success() => anyhow::bail!("qBittorrent answered {status}: {}", text.trim()),
+        _ if text.trim() == "Fails." => anyhow::bail!("qBittorrent could not add the torrent (already added?)"),
+        _ => Ok(()),
+    }
+}
+
+/// Logs in and returns the session cookies to send with later calls.
+/// qBittorrent names the cookie `SID` or, in recent versions,
+/// `QBT_SID_<port>`, so every cookie set by the login is kept.
+async fn login(client: &Client, webui: &WebUi, username: &str) -> Result<String> {
+    let response = client
+        .post(endpoint(&webui.url, "auth/login")?)
+        .header(REFERER, webui.url.as_str())
+        .form(&[("username", username), ("password", webui.password.as_deref().unwrap_or_default())])
+        .timeout(API_TIMEOUT)
+        .send()
+        .await
+        .context("qBittorrent did not answer the login")?;
+    let status = response.status();
+    let cookies: Vec<String> = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next())
+        .map(|pair| pair.trim().to_string())
+        .collect();
+    let text = response.text().await.unwrap_or_default();
+    if status == StatusCode::FORBIDDEN {
+        anyhow::bail!("qBittorrent banned this address after too many failed logins");
+    }
+    if !status.is_success() || text.trim() == "Fails." {
+        anyhow::bail!("qBittorrent login failed for user {username}");
+    }
+    if cookies.is_empty() {
+        anyhow::bail!("qBittorrent accepted the login but sent no session cookie");
+    }
+    Ok(cookies.join("; "))
+}
+
+fn endpoint(base: &Url, method: &str) -> Result<Url> {
+    let mut base = base.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    base.join(&format!("api/v2/{method}"))
+        .context("Invalid qBittorrent URL")
+}
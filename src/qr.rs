@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{Color, EcLevel, QrCode};
+
+/// Pixels per module in PNG output.
+const PNG_MODULE_SIZE: usize = 8;
+/// Light border around the code, in modules, as required by the QR spec.
+const QUIET_ZONE: usize = 4;
+
+/// Finds the magnet with the most trackers, up to `max_trackers`, that still
+/// fits in a single QR code. `build` returns the magnet for a tracker count.
+/// Returns `None` when even the tracker-free magnet is too long.
+pub fn fit_magnet(max_trackers: usize, build: impl Fn(usize) -> String) -> Option<(String, QrCode, usize)> {
+    (0..=max_trackers).rev().find_map(|count| {
+        let uri = build(count);
+        QrCode::with_error_correction_level(uri.as_bytes(), EcLevel::L)
+            .ok()
+            .map(|code| (uri, code, count))
+    })
+}
+
+/// Renders the code with half-block characters, two modules per line, light
+/// on dark so it scans from a typical terminal.
+pub fn render_terminal(code: &QrCode) -> String {
+    code.render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build()
+}
+
+pub fn write_png(code: &QrCode, path: &Path) -> Result<()> {
+    let modules = code.width();
+    let colors = code.to_colors();
+    let side = (modules + 2 * QUIET_ZONE) * PNG_MODULE_SIZE;
+
+    let mut pixels = vec![0xFFu8; side * side];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x0 = (index % modules + QUIET_ZONE) * PNG_MODULE_SIZE;
+        let y0 = (index / modules + QUIET_ZONE) * PNG_MODULE_SIZE;
+        for y in y0..y0 + PNG_MODULE_SIZE {
+            pixels[y * side + x0..y * side + x0 + PNG_MODULE_SIZE].fill(0);
+        }
+    }
+
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let side = u32::try_from(side).context("QR image too large")?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), side, side);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .with_context(|| format!("Failed to write QR image {}", path.display()))?;
+    writer
+        .write_image_data(&pixels)
+        .with_context(|| format!("Failed to write QR image {}", path.display()))
+}
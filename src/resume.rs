@@ -0,0 +1,205 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bendy::decoding::FromBencode;
+use bendy::encoding::ToBencode;
+use bendy::value::Value;
+
+use crate::error::{Result, TorseedError};
+use crate::hash_v1::V1Hasher;
+use crate::hash_v2::{V2Hasher, V2Snapshot};
+use crate::http::SourceMetadata;
+use crate::util;
+
+/// Hashing progress saved by `--resume`, taken at a piece boundary.
+pub struct ResumeState {
+    pub url: String,
+    pub length: u64,
+    pub piece_length: usize,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub offset: u64,
+    pub v1_pieces: Vec<u8>,
+    pub v2: V2Snapshot,
+}
+
+impl ResumeState {
+    /// Refuses to continue when the source or piece length differ from the
+    /// run that wrote the checkpoint.
+    pub fn validate(&self, source: &SourceMetadata, piece_length: usize) -> Result<()> {
+        let problem = if self.url != source.url.as_str() {
+            format!("Resume state is for {}, not {}", self.url, source.url)
+        } else if self.length != source.content_length {
+            format!(
+                "Source length changed since the checkpoint ({} vs {} bytes)",
+                self.length,
+                source.content_length
+            )
+        } else if self.piece_length != piece_length {
+            "Piece length changed since the checkpoint".to_string()
+        } else if self.etag != source.etag || self.last_modified != source.last_modified {
+            "Source ETag/Last-Modified changed since the checkpoint; delete the resume file to start over".to_string()
+        } else if self.offset > self.length || !self.offset.is_multiple_of(piece_length as u64) {
+            format!("Resume state offset {} is not a piece boundary", self.offset)
+        } else {
+            return Ok(());
+        };
+        Err(TorseedError::Resume(problem))
+    }
+}
+
+/// Where and for which source checkpoints are written.
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub source: SourceMetadata,
+    pub piece_length: usize,
+}
+
+impl Checkpoint {
+    /// Atomically replaces the state file. `offset` must be a piece boundary.
+    pub fn save(&self, offset: u64, v1: &V1Hasher, v2: &mut V2Hasher) -> Result<()> {
+        let state = ResumeState {
+            url: self.source.url.to_string(),
+            length: self.source.content_length,
+            piece_length: self.piece_length,
+            etag: self.source.etag.clone(),
+            last_modified: self.source.last_modified.clone(),
+            offset,
+            v1_pieces: v1.snapshot().to_vec(),
+            v2: v2.snapshot(),
+        };
+        let encoded = encode(&state)?;
+
+        util::write_atomic(&self.path, &encoded)
+            .map_err(|err| TorseedError::io(format!("Failed to replace resume state {}", self.path.display()), err))
+    }
+
+    pub fn clear(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads a previously saved state, or `None` when the file does not exist.
+pub fn load(path: &Path) -> Result<Option<ResumeState>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(TorseedError::io(format!("Failed to read {}", path.display()), err)),
+    };
+    decode(&bytes)
+        .map(Some)
+        .map_err(|err| TorseedError::Resume(format!("Invalid resume state in {}: {err}", path.display())))
+}
+
+fn encode(state: &ResumeState) -> Result<Vec<u8>> {
+    let mut dict = BTreeMap::new();
+    dict.insert(key("url"), bytes(state.url.as_bytes().to_vec()));
+    dict.insert(key("length"), integer(state.length)?);
+    dict.insert(key("piece length"), integer(state.piece_length as u64)?);
+    if let Some(etag) = &state.etag {
+        dict.insert(key("etag"), bytes(etag.as_bytes().to_vec()));
+    }
+    if let Some(last_modified) = &state.last_modified {
+        dict.insert(key("last modified"), bytes(last_modified.as_bytes().to_vec()));
+    }
+    dict.insert(key("offset"), integer(state.offset)?);
+    dict.insert(key("v1 pieces"), bytes(state.v1_pieces.clone()));
+    dict.insert(key("v2 leaves"), integer(state.v2.leaves as u64)?);
+    dict.insert(key("v2 piece layers"), bytes(state.v2.piece_layers.clone()));
+    let subtrees = state
+        .v2
+        .subtrees
+        .iter()
+        .map(|(height, hash)| {
+            Value::List(vec![Value::Integer(i64::from(*height)), bytes(hash.to_vec())])
+        })
+        .collect();
+    dict.insert(key("v2 subtrees"), Value::List(subtrees));
+
+    Value::Dict(dict)
+        .to_bencode()
+        .map_err(|err| TorseedError::Encode {
+            what: "resume state",
+            message: err.to_string(),
+        })
+}
+
+fn decode(data: &[u8]) -> Result<ResumeState, String> {
+    let dict = match Value::from_bencode(data) {
+        Ok(Value::Dict(dict)) => dict,
+        Ok(_) => return Err("Resume state is not a dictionary".to_string()),
+        Err(err) => return Err(format!("Failed to decode resume state: {err}")),
+    };
+    let get = |name: &str| dict.get(name.as_bytes()).ok_or_else(|| format!("Missing {name:?}"));
+    let get_bytes = |name: &str| match get(name)? {
+        Value::Bytes(data) => Ok(data.to_vec()),
+        _ => Err(format!("{name:?} is not a byte string")),
+    };
+    let get_int = |name: &str| match get(name)? {
+        Value::Integer(value) => u64::try_from(*value).map_err(|_| format!("{name:?} is negative")),
+        _ => Err(format!("{name:?} is not an integer")),
+    };
+    let get_string = |name: &str| -> Result<Option<String>, String> {
+        match dict.get(name.as_bytes()) {
+            None => Ok(None),
+            Some(Value::Bytes(data)) => Ok(Some(utf8(data.to_vec(), name)?)),
+            Some(_) => Err(format!("{name:?} is not a byte string")),
+        }
+    };
+
+    let Value::List(entries) = get("v2 subtrees")? else {
+        return Err("\"v2 subtrees\" is not a list".to_string());
+    };
+    let mut subtrees = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let Value::List(pair) = entry else {
+            return Err("v2 subtree is not a list".to_string());
+        };
+        let [Value::Integer(height), Value::Bytes(hash)] = pair.as_slice() else {
+            return Err("v2 subtree is not a [height, hash] pair".to_string());
+        };
+        let hash: [u8; 32] = hash
+            .as_ref()
+            .try_into()
+            .map_err(|_| "v2 subtree hash is not 32 bytes".to_string())?;
+        let height = u32::try_from(*height).map_err(|_| format!("v2 subtree height {height} is out of range"))?;
+        subtrees.push((height, hash));
+    }
+
+    Ok(ResumeState {
+        url: utf8(get_bytes("url")?, "url")?,
+        length: get_int("length")?,
+        piece_length: usize::try_from(get_int("piece length")?).map_err(|err| err.to_string())?,
+        etag: get_string("etag")?,
+        last_modified: get_string("last modified")?,
+        offset: get_int("offset")?,
+        v1_pieces: get_bytes("v1 pieces")?,
+        v2: V2Snapshot {
+            subtrees,
+            leaves: usize::try_from(get_int("v2 leaves")?).map_err(|err| err.to_string())?,
+            piece_layers: get_bytes("v2 piece layers")?,
+        },
+    })
+}
+
+fn utf8(data: Vec<u8>, name: &str) -> Result<String, String> {
+    String::from_utf8(data).map_err(|_| format!("{name:?} is not valid UTF-8"))
+}
+
+fn key(name: &str) -> Cow<'static, [u8]> {
+    Cow::Owned(name.as_bytes().to_vec())
+}
+
+fn bytes(data: Vec<u8>) -> Value<'static> {
+    Value::Bytes(Cow::Owned(data))
+}
+
+fn integer(value: u64) -> Result<Value<'static>> {
+    let value = i64::try_from(value).map_err(|_| TorseedError::Encode {
+        what: "resume state",
+        message: format!("{value} exceeds i64"),
+    })?;
+    Ok(Value::Integer(value))
+}
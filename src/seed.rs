@@ -0,0 +1,429 @@
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::{Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use torseed::metainfo::{FileLayout, ParsedTorrent};
+use torseed::tracker_client::{self, AnnounceEvent, AnnounceParams};
+use torseed::trackers;
+use torseed::util::ByteUnits;
+use tracing::{debug, info, warn};
+
+use crate::serve;
+
+const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+const HANDSHAKE_LEN: usize = 68;
+
+const MSG_CHOKE: u8 = 0;
+const MSG_UNCHOKE: u8 = 1;
+const MSG_INTERESTED: u8 = 2;
+const MSG_BITFIELD: u8 = 5;
+const MSG_REQUEST: u8 = 6;
+const MSG_PIECE: u8 = 7;
+
+/// Largest block a peer may request; clients ask for 16 KiB, some for more.
+const MAX_BLOCK: u32 = 128 * 1024;
+/// Largest message accepted from a peer. Bitfields of very large torrents
+/// are the only legitimate messages near this size.
+const MAX_MESSAGE: u32 = 1024 * 1024;
+/// Peers send keep-alives every two minutes; silence beyond this drops them.
+const PEER_TIMEOUT: Duration = Duration::from_secs(180);
+const ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Bounds on the re-announce interval, whatever the trackers ask for.
+const MIN_REANNOUNCE: Duration = Duration::from_secs(60);
+const MAX_REANNOUNCE: Duration = Duration::from_secs(30 * 60);
+/// How often the ratio stop condition is checked.
+const RATIO_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct SeedOptions {
+    pub port: u16,
+    pub seed_time: Option<Duration>,
+    pub seed_ratio: Option<f64>,
+    pub max_peers: usize,
+    /// Pieces to hash before seeding, picked at random; `None` checks all.
+    pub check_pieces: Option<usize>,
+}
+
+/// Totals reported when seeding stops.
+#[derive(Debug, Clone, Copy)]
+pub struct SeedStats {
+    pub uploaded: u64,
+    pub peers: u64,
+    pub elapsed: Duration,
+}
+
+impl SeedStats {
+    pub fn line(&self, units: ByteUnits) -> String {
+        format!(
+            "Uploaded {} to {} peer(s) in {}",
+            units.format(self.uploaded),
+            self.peers,
+            humantime::format_duration(Duration::from_secs(self.elapsed.as_secs()))
+        )
+    }
+}
+
+/// What every peer connection needs to answer requests.
+struct Shared {
+    infohash: [u8; 20],
+    peer_id: [u8; 20],
+    path: PathBuf,
+    length: u64,
+    piece_length: u64,
+    bitfield: Vec<u8>,
+    uploaded: AtomicU64,
+}
+
+impl Shared {
+    fn piece_size(&self, index: u32) -> Option<u64> {
+        let offset = u64::from(index) * self.piece_length;
+        (offset < self.length).then(|| self.piece_length.min(self.length - offset))
+    }
+}
+
+/// Seeds a single-file v1 or hybrid torrent from `path` over the v1 peer
+/// protocol until `cancel` fires or a stop condition is met. Only uploads:
+/// every connecting peer is unchoked and served, nothing is downloaded.
+/// Refuses to start when a checked piece does not match the torrent.
+pub async fn run(
+    client: &Client,
+    torrent: &ParsedTorrent,
+    path: PathBuf,
+    options: &SeedOptions,
+    cancel: &CancellationToken,
+) -> Result<SeedStats> {
+    let FileLayout::Single { length } = torrent.layout else {
+        anyhow::bail!("Only single-file torrents can be seeded");
+    };
+    let (Some(infohash), Some(pieces)) = (torrent.infohash_v1(), &torrent.pieces) else {
+        anyhow::bail!("Seeding needs v1 piece hashes; this is a v2-only torrent");
+    };
+    let on_disk = tokio::fs::metadata(&path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .len();
+    if on_disk != length {
+        anyhow::bail!("{} is {on_disk} bytes, the torrent expects {length}", path.display());
+    }
+
+    // The bitfield claims every piece, so a payload that does not match would
+    // hand peers data they reject.
+    let piece_count = pieces.len() / 20;
+    let checked = {
+        let (path, pieces, piece_length, count, cancel) =
+            (path.clone(), pieces.clone(), torrent.piece_length, options.check_pieces, cancel.clone());
+        tokio::task::spawn_blocking(move || serve::check_pieces(&path, length, piece_length, &pieces, count, &cancel))
+            .await
+            .context("Piece check stopped unexpectedly")??
+    };
+    info!("Checked {checked} of {piece_count} pieces of {}", path.display());
+
+    let mut bitfield = vec![0xffu8; piece_count.div_ceil(8)];
+    if piece_count % 8 != 0
+        && let Some(last) = bitfield.last_mut()
+    {
+        *last = 0xff << (8 - piece_count % 8);
+    }
+    let shared = Arc::new(Shared {
+        infohash,
+        peer_id: tracker_client::throwaway_peer_id(),
+        path,
+        length,
+        piece_length: torrent.piece_length.max(1),
+        bitfield,
+        uploaded: AtomicU64::new(0),
+    });
+
+    let listener = TcpListener::bind(("0.0.0.0", options.port))
+        .await
+        .with_context(|| format!("Failed to listen on port {}", options.port))?;
+    info!("Seeding {} on port {}", torrent.name_lossy(), options.port);
+
+    let started = Instant::now();
+    let stop = cancel.child_token();
+    let announce_trackers: Vec<String> = torrent
+        .announce_tiers
+        .iter()
+        .flatten()
+        .filter(|tracker| !trackers::is_i2p(tracker))
+        .cloned()
+        .collect();
+    let announcer = {
+        let (client, shared, stop, port) = (client.clone(), shared.clone(), stop.clone(), options.port);
+        tokio::spawn(async move { announce_loop(&client, &announce_trackers, &shared, port, &stop).await })
+    };
+
+    let mut peers = JoinSet::new();
+    let mut accepted_peers = 0u64;
+    let mut ratio_check = tokio::time::interval(RATIO_CHECK_INTERVAL);
+    ratio_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let deadline = options.seed_time.map(|seed_time| started + seed_time);
+    loop {
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            _ = sleep_until(deadline) => {
+                info!("Seed time reached");
+                break;
+            }
+            _ = ratio_check.tick() => {
+                let uploaded = shared.uploaded.load(Ordering::Relaxed);
+                if let Some(ratio) = options.seed_ratio
+                    && uploaded as f64 >= ratio * length as f64
+                {
+                    info!("Seed ratio {ratio} reached");
+                    break;
+                }
+            }
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        warn!("Failed to accept peer: {err}");
+                        continue;
+                    }
+                };
+                while peers.try_join_next().is_some() {}
+                if peers.len() >= options.max_peers {
+                    debug!("{peer}: refused, already serving {} peers", peers.len());
+                    continue;
+                }
+                accepted_peers += 1;
+                let shared = shared.clone();
+                peers.spawn(async move {
+                    match serve_peer(stream, peer, &shared).await {
+                        Ok(()) => debug!("{peer}: disconnected"),
+                        Err(err) => debug!("{peer}: dropped: {err:#}"),
+                    }
+                });
+            }
+        }
+    }
+
+    stop.cancel();
+    peers.shutdown().await;
+    let _ = announcer.await;
+    Ok(SeedStats {
+        uploaded: shared.uploaded.load(Ordering::Relaxed),
+        peers: accepted_peers,
+        elapsed: started.elapsed(),
+    })
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Announces `started`, re-announces at the interval the trackers ask for
+/// and sends `stopped` to the trackers that answered last once `stop` fires.
+async fn announce_loop(client: &Client, trackers: &[String], shared: &Shared, port: u16, stop: &CancellationToken) {
+    if trackers.is_empty() {
+        warn!("Torrent has no reachable trackers; peers must find this seed some other way");
+        return;
+    }
+    let mut params = AnnounceParams {
+        infohash: shared.infohash,
+        peer_id: shared.peer_id,
+        port,
+        uploaded: 0,
+        left: 0,
+        event: AnnounceEvent::Started,
+    };
+    let mut answered = Vec::new();
+    loop {
+        params.uploaded = shared.uploaded.load(Ordering::Relaxed);
+        let results = futures::future::join_all(
+            trackers
+                .iter()
+                .map(|tracker| tracker_client::announce(client, tracker, &params, ANNOUNCE_TIMEOUT)),
+        )
+        .await;
+        let mut interval = MAX_REANNOUNCE;
+        answered.clear();
+        for (tracker, result) in trackers.iter().zip(results) {
+            match result {
+                Ok(seconds) => {
+                    if seconds > 0 {
+                        interval = interval.min(Duration::from_secs(u64::from(seconds)));
+                    }
+                    answered.push(tracker);
+                }
+                Err(err) => debug!("Announce to {} failed: {:#}", trackers::for_display(tracker), anyhow::Error::from(err)),
+            }
+        }
+        if params.event == AnnounceEvent::Started {
+            info!("Announced to {} of {} trackers", answered.len(), trackers.len());
+        }
+        params.event = AnnounceEvent::None;
+
+        if stop
+            .run_until_cancelled(tokio::time::sleep(interval.max(MIN_REANNOUNCE)))
+            .await
+            .is_none()
+        {
+            break;
+        }
+    }
+
+    params.event = AnnounceEvent::Stopped;
+    params.uploaded = shared.uploaded.load(Ordering::Relaxed);
+    futures::future::join_all(
+        answered
+            .iter()
+            .map(|tracker| tracker_client::announce(client, tracker, &params, ANNOUNCE_TIMEOUT)),
+    )
+    .await;
+}
+
+/// Runs the upload side of the peer protocol on one connection.
+async fn serve_peer(mut stream: TcpStream, peer: SocketAddr, shared: &Shared) -> Result<()> {
+    let mut handshake = [0u8; HANDSHAKE_LEN];
+    tokio::time::timeout(PEER_TIMEOUT, stream.read_exact(&mut handshake))
+        .await
+        .context("handshake timed out")??;
+    if handshake[0] as usize != PROTOCOL.len() || &handshake[1..20] != PROTOCOL {
+        anyhow::bail!("not a BitTorrent handshake");
+    }
+    if handshake[28..48] != shared.infohash {
+        anyhow::bail!("handshake for a different infohash");
+    }
+
+    let mut reply = Vec::with_capacity(HANDSHAKE_LEN + 5 + shared.bitfield.len());
+    reply.push(PROTOCOL.len() as u8);
+    reply.extend_from_slice(PROTOCOL);
+    reply.extend_from_slice(&[0; 8]);
+    reply.extend_from_slice(&shared.infohash);
+    reply.extend_from_slice(&shared.peer_id);
+    reply.extend_from_slice(&(1 + shared.bitfield.len() as u32).to_be_bytes());
+    reply.push(MSG_BITFIELD);
+    reply.extend_from_slice(&shared.bitfield);
+    stream.write_all(&reply).await?;
+    debug!("{peer}: connected");
+
+    let mut file = File::open(&shared.path).await?;
+    let mut unchoked = false;
+    let mut message = Vec::new();
+    loop {
+        let mut prefix = [0u8; 4];
+        match tokio::time::timeout(PEER_TIMEOUT, stream.read_exact(&mut prefix))
+            .await
+            .context("peer went silent")?
+        {
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_be_bytes(prefix);
+        if len == 0 {
+            continue;
+        }
+        if len > MAX_MESSAGE {
+            anyhow::bail!("message of {len} bytes is too large");
+        }
+        message.resize(len as usize, 0);
+        stream.read_exact(&mut message).await?;
+
+        match message[0] {
+            MSG_INTERESTED if !unchoked => {
+                stream.write_all(&[0, 0, 0, 1, MSG_UNCHOKE]).await?;
+                unchoked = true;
+            }
+            MSG_REQUEST if unchoked => {
+                if message.len() != 13 {
+                    anyhow::bail!("malformed request");
+                }
+                let field = |at: usize| u32::from_be_bytes(message[at..at + 4].try_into().expect("length checked"));
+                let (index, begin, block) = (field(1), field(5), field(9));
+                let valid = block > 0
+                    && block <= MAX_BLOCK
+                    && shared
+                        .piece_size(index)
+                        .is_some_and(|size| u64::from(begin) + u64::from(block) <= size);
+                if !valid {
+                    stream.write_all(&[0, 0, 0, 1, MSG_CHOKE]).await?;
+                    anyhow::bail!("invalid request for piece {index} at {begin} ({block} bytes)");
+                }
+
+                let mut data = vec![0u8; 13 + block as usize];
+                data[..4].copy_from_slice(&(9 + block).to_be_bytes());
+                data[4] = MSG_PIECE;
+                data[5..13].copy_from_slice(&message[1..9]);
+                file.seek(SeekFrom::Start(u64::from(index) * shared.piece_length + u64::from(begin)))
+                    .await?;
+                file.read_exact(&mut data[13..]).await?;
+                stream.write_all(&data).await?;
+                shared.uploaded.fetch_add(u64::from(block), Ordering::Relaxed);
+            }
+            // Choke, have, bitfield, cancel and the rest need no answer from
+            // a seed that answers requests as soon as they arrive.
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use torseed::{metainfo, TorrentBuilder};
+
+    async fn torrent_for(data: &[u8]) -> ParsedTorrent {
+        let torrent = TorrentBuilder::from_reader(std::io::Cursor::new(data.to_vec()), "data.bin", Some(data.len() as u64))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .piece_length(16_384)
+            .build(&Client::new())
+            .await
+            .unwrap();
+        metainfo::parse(&torrent.metainfo.torrent).unwrap()
+    }
+
+    fn write_payload(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("torseed-seed-{name}-{}.bin", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn checks_every_piece_unless_sampling() {
+        let data: Vec<u8> = (0..70_000u32).map(|i| (i % 251) as u8).collect();
+        let torrent = torrent_for(&data).await;
+        let path = write_payload("sample", &data);
+        let pieces = torrent.pieces.as_deref().unwrap();
+        let cancel = CancellationToken::new();
+        let all = serve::check_pieces(&path, 70_000, torrent.piece_length, pieces, None, &cancel).unwrap();
+        let sampled = serve::check_pieces(&path, 70_000, torrent.piece_length, pieces, Some(2), &cancel).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((all, sampled), (5, 2));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_seed_a_corrupt_payload() {
+        let mut data: Vec<u8> = (0..70_000u32).map(|i| (i % 251) as u8).collect();
+        let torrent = torrent_for(&data).await;
+        data[50_000] ^= 0xff;
+        let path = write_payload("corrupt", &data);
+        let options = SeedOptions {
+            port: 0,
+            seed_time: None,
+            seed_ratio: None,
+            max_peers: 1,
+            check_pieces: None,
+        };
+        let result = run(&Client::new(), &torrent, path.clone(), &options, &CancellationToken::new()).await;
+        std::fs::remove_file(&path).unwrap();
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("Piece 3 of"), "{err:#}");
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use rand::seq::index;
+use sha1::{Digest, Sha1};
+use tokio::io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use torseed::metainfo::{FileLayout, ParsedTorrent};
+use torseed::TorseedError;
+use tracing::{debug, info, warn};
+
+/// Largest request head accepted before the connection is dropped.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+/// Idle keep-alive connections are closed after this long.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// Characters escaped when printing URL paths.
+const PATH_ESCAPE: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'?');
+
+/// A payload file on disk and the URL path it is served under.
+struct ServedFile {
+    disk_path: PathBuf,
+    length: u64,
+    etag: String,
+}
+
+/// The files of one torrent, keyed by decoded URL path.
+pub struct Payload {
+    files: HashMap<String, ServedFile>,
+}
+
+impl Payload {
+    /// Maps the torrent's files below `root` and checks that each exists
+    /// with the length the torrent expects. Single-file payloads are served
+    /// at `/<name>`, multi-file ones at `/<name>/<path>`, which is where
+    /// BEP 19 clients look when the webseed URL ends in `/`.
+    pub fn open(torrent: &ParsedTorrent, root: &Path) -> Result<Self> {
+        let name = path_component(&torrent.name)?;
+        let entries: Vec<(Vec<&str>, u64)> = match &torrent.layout {
+            FileLayout::Single { length } => vec![(vec![name], *length)],
+            FileLayout::Multi { files } => files
+                .iter()
+                .map(|file| {
+                    let mut components = vec![name];
+                    for component in &file.path {
+                        components.push(path_component(component)?);
+                    }
+                    Ok((components, file.length))
+                })
+                .collect::<Result<_>>()?,
+        };
+
+        let mut files = HashMap::new();
+        for (components, length) in entries {
+            let disk_path: PathBuf = components.iter().fold(root.to_path_buf(), |path, component| path.join(component));
+            let metadata = fs::metadata(&disk_path)
+                .with_context(|| format!("Payload file {} is missing", disk_path.display()))?;
+            if metadata.len() != length {
+                anyhow::bail!(
+                    "{} is {} bytes, the torrent expects {length}",
+                    disk_path.display(),
+                    metadata.len()
+                );
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            let etag = format!("\"{:x}-{:x}\"", length, modified.as_nanos());
+            files.insert(
+                format!("/{}", components.join("/")),
+                ServedFile {
+                    disk_path,
+                    length,
+                    etag,
+                },
+            );
+        }
+        Ok(Self { files })
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// URL paths of the served files, escaped for display.
+    pub fn url_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .files
+            .keys()
+            .map(|path| utf8_percent_encode(path, PATH_ESCAPE).to_string())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Hashes `count` random v1 pieces from disk. Only single-file torrents
+    /// are checked, since pieces of multi-file torrents span file boundaries.
+    pub fn spot_check(&self, torrent: &ParsedTorrent, count: usize, cancel: &CancellationToken) -> Result<()> {
+        let (FileLayout::Single { length }, Some(pieces)) = (&torrent.layout, &torrent.pieces) else {
+            warn!("Skipping piece spot-check: only single-file v1 or hybrid torrents can be checked");
+            return Ok(());
+        };
+        let file = self.files.values().next().context("Torrent has no files")?;
+        let checked = check_pieces(&file.disk_path, *length, torrent.piece_length, pieces, Some(count), cancel)?;
+        info!("Spot-checked {checked} of {} pieces", pieces.len() / 20);
+        Ok(())
+    }
+}
+
+/// Hashes the v1 pieces of the single file at `path`, all of them or `count`
+/// picked at random, and fails on the first one that does not match
+/// `pieces`. Returns how many pieces were checked.
+pub fn check_pieces(
+    path: &Path,
+    length: u64,
+    piece_length: u64,
+    pieces: &[u8],
+    count: Option<usize>,
+    cancel: &CancellationToken,
+) -> Result<usize> {
+    let piece_length = piece_length.max(1);
+    let total = pieces.len() / 20;
+    let selected = match count {
+        Some(count) => index::sample(&mut rand::thread_rng(), total, count.min(total)).into_vec(),
+        None => (0..total).collect(),
+    };
+    let mut reader = fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut buffer = Vec::new();
+    for &piece in &selected {
+        if cancel.is_cancelled() {
+            return Err(TorseedError::Cancelled.into());
+        }
+        let offset = piece as u64 * piece_length;
+        let size = piece_length.min(length - offset);
+        buffer.resize(size as usize, 0);
+        reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| reader.read_exact(&mut buffer))
+            .with_context(|| format!("Failed to read piece {piece} of {}", path.display()))?;
+        if Sha1::digest(&buffer)[..] != pieces[piece * 20..piece * 20 + 20] {
+            anyhow::bail!("Piece {piece} of {} does not match the torrent", path.display());
+        }
+    }
+    Ok(selected.len())
+}
+
+/// Rejects names that would escape the root directory once joined.
+fn path_component(raw: &[u8]) -> Result<&str> {
+    let component = std::str::from_utf8(raw).context("Torrent path is not valid UTF-8")?;
+    if component.is_empty() || component == "." || component == ".." || component.contains(['/', '\\']) {
+        anyhow::bail!("Refusing to serve unsafe torrent path component {component:?}");
+    }
+    Ok(component)
+}
+
+/// Accepts connections until `cancel` fires, then drops the ones still open.
+pub async fn run(listener: TcpListener, payload: Arc<Payload>, cancel: CancellationToken) {
+    let mut connections = JoinSet::new();
+    while let Some(accepted) = cancel.run_until_cancelled(listener.accept()).await {
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!("Failed to accept connection: {err}");
+                continue;
+            }
+        };
+        let payload = payload.clone();
+        connections.spawn(async move {
+            if let Err(err) = handle_connection(stream, peer, &payload).await {
+                debug!("{peer}: connection closed: {err}");
+            }
+        });
+        while connections.try_join_next().is_some() {}
+    }
+    if !connections.is_empty() {
+        info!("Closing {} open connection(s)", connections.len());
+    }
+    connections.shutdown().await;
+}
+
+/// Byte range selected by a `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    Full,
+    /// Inclusive start and end offsets.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parses a single `bytes=` range. Multiple ranges and malformed headers
+/// fall back to the full file, which RFC 9110 allows.
+fn parse_range(header: &str, length: u64) -> RangeRequest {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    let range = if start.is_empty() {
+        match end.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(suffix) => (length.saturating_sub(suffix), length.saturating_sub(1)),
+            Err(_) => return RangeRequest::Full,
+        }
+    } else {
+        let Ok(start) = start.parse::<u64>() else {
+            return RangeRequest::Full;
+        };
+        let end = match end {
+            "" => length.saturating_sub(1),
+            end => match end.parse::<u64>() {
+                Ok(end) if end >= start => end.min(length.saturating_sub(1)),
+                _ => return RangeRequest::Full,
+            },
+        };
+        (start, end)
+    };
+    if range.0 >= length {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial(range.0, range.1)
+}
+
+/// The parts of a request head the server looks at.
+struct RequestHead {
+    method: String,
+    path: String,
+    range: Option<String>,
+    if_range: Option<String>,
+    keep_alive: bool,
+}
+
+/// Serves requests on one connection until the client closes it, asks to
+/// close, or idles past `READ_TIMEOUT`.
+async fn handle_connection(mut stream: TcpStream, peer: SocketAddr, payload: &Payload) -> io::Result<()> {
+    let mut buffer = Vec::with_capacity(1024);
+    loop {
+        let Some(head) = read_head(&mut stream, &mut buffer).await? else {
+            return Ok(());
+        };
+        let (status, sent) = respond(&mut stream, &head, payload).await?;
+        info!("{peer} \"{} {}\" {status} {sent}", head.method, head.path);
+        if !head.keep_alive {
+            return stream.shutdown().await;
+        }
+    }
+}
+
+/// Reads one request head, leaving any pipelined bytes in `buffer`. Returns
+/// `None` when the client closed the connection between requests.
+async fn read_head(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> io::Result<Option<RequestHead>> {
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut request = httparse::Request::new(&mut headers);
+        let parsed = request
+            .parse(buffer)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if let httparse::Status::Complete(consumed) = parsed {
+            let header = |name: &str| {
+                request
+                    .headers
+                    .iter()
+                    .find(|header| header.name.eq_ignore_ascii_case(name))
+                    .and_then(|header| std::str::from_utf8(header.value).ok())
+                    .map(str::to_string)
+            };
+            let connection = header("connection").unwrap_or_default().to_ascii_lowercase();
+            let has_body = header("content-length").is_some_and(|value| value.trim() != "0")
+                || header("transfer-encoding").is_some();
+            let head = RequestHead {
+                method: request.method.unwrap_or_default().to_string(),
+                path: request.path.unwrap_or_default().to_string(),
+                range: header("range"),
+                if_range: header("if-range"),
+                // Request bodies are never read, so a request carrying one ends the connection.
+                keep_alive: !has_body
+                    && match request.version {
+                        Some(1) => !connection.contains("close"),
+                        _ => connection.contains("keep-alive"),
+                    },
+            };
+            buffer.drain(..consumed);
+            return Ok(Some(head));
+        }
+        if buffer.len() >= MAX_HEAD_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+        }
+
+        let mut chunk = [0u8; 4096];
+        let read = match tokio::time::timeout(READ_TIMEOUT, stream.read(&mut chunk)).await {
+            Ok(read) => read?,
+            Err(_) => return Ok(None),
+        };
+        if read == 0 {
+            return if buffer.is_empty() {
+                Ok(None)
+            } else {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed mid-request"))
+            };
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Writes the response for `head` and returns its status and body size.
+async fn respond(stream: &mut TcpStream, head: &RequestHead, payload: &Payload) -> io::Result<(u16, u64)> {
+    let connection = if head.keep_alive { "keep-alive" } else { "close" };
+    let is_head = match head.method.as_str() {
+        "GET" => false,
+        "HEAD" => true,
+        _ => {
+            let response = format!(
+                "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD\r\nContent-Length: 0\r\nConnection: {connection}\r\n\r\n"
+            );
+            stream.write_all(response.as_bytes()).await?;
+            return Ok((405, 0));
+        }
+    };
+    let path = head.path.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    let Some(file) = payload.files.get(path.as_ref()) else {
+        let response = format!("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: {connection}\r\n\r\n");
+        stream.write_all(response.as_bytes()).await?;
+        return Ok((404, 0));
+    };
+
+    // A stale If-Range validator means the client's partial copy is of a
+    // different file, so it gets the whole thing.
+    let range = match (&head.range, &head.if_range) {
+        (Some(_), Some(validator)) if *validator != file.etag => RangeRequest::Full,
+        (Some(range), _) => parse_range(range, file.length),
+        (None, _) => RangeRequest::Full,
+    };
+    let (status, start, end) = match range {
+        RangeRequest::Full => (200, 0, file.length),
+        RangeRequest::Partial(start, end) => (206, start, end + 1),
+        RangeRequest::Unsatisfiable => {
+            let response = format!(
+                "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\nConnection: {connection}\r\n\r\n",
+                file.length
+            );
+            stream.write_all(response.as_bytes()).await?;
+            return Ok((416, 0));
+        }
+    };
+
+    let mut response = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nETag: {}\r\nConnection: {connection}\r\n",
+        if status == 206 { "Partial Content" } else { "OK" },
+        end - start,
+        file.etag
+    );
+    if status == 206 {
+        response.push_str(&format!("Content-Range: bytes {start}-{}/{}\r\n", end - 1, file.length));
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    if is_head {
+        return Ok((status, 0));
+    }
+
+    let mut reader = tokio::fs::File::open(&file.disk_path).await?;
+    reader.seek(SeekFrom::Start(start)).await?;
+    let sent = io::copy(&mut reader.take(end - start), stream).await?;
+    if sent != end - start {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "payload file shrank while serving"));
+    }
+    Ok((status, sent))
+}
@@ -0,0 +1,429 @@
+//! A self-contained record of one build, for reports and machine-readable
+//! output. With the `serde` feature every type here serializes, binary
+//! fields as lowercase hex.
+
+use serde_json::{json, Value};
+
+use crate::builder::Torrent;
+use crate::checksum::Checksum;
+use crate::http::{RangeSupport, WebseedCheck, WebseedRejection};
+use crate::tracker_client::CheckReport;
+use crate::trackers::{self, GatheredTrackers, SourcePriority, TrackerOrigin};
+
+/// What a run produced: the torrent's key parameters, its infohashes, the
+/// webseeds that were considered and the magnets derived from it.
+///
+#[cfg_attr(feature = "serde", doc = "```")]
+#[cfg_attr(not(feature = "serde"), doc = "```ignore")]
+/// use torseed::checksum::{Checksum, ChecksumAlgorithm};
+/// use torseed::http::{RangeSupport, WebseedRejection};
+/// use torseed::summary::{BuildSummary, TrackerSummary, WebseedReport, WebseedStatus};
+///
+/// let summary = BuildSummary {
+///     name: "data.bin".to_string(),
+///     length: 40_000,
+///     piece_length: 16_384,
+///     pieces: 3,
+///     creation_date: 1_700_000_000,
+///     created_by: "torseed".to_string(),
+///     infohash_v1: Some([0xab; 20]),
+///     infohash_v2: None,
+///     pieces_root: Some([0x01; 32]),
+///     checksums: vec![Checksum { algorithm: ChecksumAlgorithm::Md5, digest: vec![0x02; 16] }],
+///     webseeds: vec![WebseedReport {
+///         url: "https://mirror.example/data.bin".to_string(),
+///         status: WebseedStatus::rejected(WebseedRejection::LengthMismatch { expected: 40_000, actual: 39_000 }),
+///         ranges: RangeSupport::Unknown,
+///         fetched_url: None,
+///     }],
+///     trackers: TrackerSummary {
+///         total: 1,
+///         tiers: 1,
+///         i2p: 0,
+///         origins: Vec::new(),
+///         schemes: vec![("udp".to_string(), 1)],
+///         collapsed: 0,
+///         check: None,
+///     },
+///     magnets: vec!["magnet:?xt=urn:btih:abab".to_string()],
+/// };
+/// let json = serde_json::to_value(&summary).unwrap();
+/// assert_eq!(
+///     json,
+///     serde_json::json!({
+///         "name": "data.bin",
+///         "length": 40000,
+///         "piece_length": 16384,
+///         "pieces": 3,
+///         "creation_date": 1700000000,
+///         "created_by": "torseed",
+///         "infohash_v1": "ab".repeat(20),
+///         "infohash_v2": null,
+///         "pieces_root": "01".repeat(32),
+///         "checksums": [{ "algorithm": "md5", "digest": "02".repeat(16) }],
+///         "webseeds": [
+///             {
+///                 "url": "https://mirror.example/data.bin",
+///                 "status": "rejected",
+///                 "reason": "length mismatch: serves 39000 bytes, expected 40000",
+///                 "rejection": { "kind": "length_mismatch", "expected": 40000, "actual": 39000 },
+///                 "ranges": "unknown",
+///                 "fetched_url": null
+///             }
+///         ],
+///         "trackers": {
+///             "total": 1,
+///             "tiers": 1,
+///             "i2p": 0,
+///             "origins": [],
+///             "schemes": [["udp", 1]],
+///             "collapsed": 0,
+///             "check": null
+///         },
+///         "magnets": ["magnet:?xt=urn:btih:abab"]
+///     })
+/// );
+/// assert_eq!(summary.to_json(), json);
+/// let back: BuildSummary = serde_json::from_value(json).unwrap();
+/// assert_eq!(back.infohash_v1, summary.infohash_v1);
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BuildSummary {
+    pub name: String,
+    pub length: u64,
+    pub piece_length: u32,
+    /// Number of v1 pieces.
+    pub pieces: usize,
+    pub creation_date: i64,
+    pub created_by: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde::option"))]
+    pub infohash_v1: Option<[u8; 20]>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde::option"))]
+    pub infohash_v2: Option<[u8; 32]>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::hex_serde::option"))]
+    pub pieces_root: Option<[u8; 32]>,
+    /// Whole-file digests, when requested.
+    pub checksums: Vec<Checksum>,
+    pub webseeds: Vec<WebseedReport>,
+    pub trackers: TrackerSummary,
+    pub magnets: Vec<String>,
+}
+
+impl BuildSummary {
+    /// `webseeds` lists every candidate with its outcome; the verified ones
+    /// are expected to match the torrent's `url-list`.
+    pub fn new(
+        torrent: &Torrent,
+        trackers: &GatheredTrackers,
+        webseeds: Vec<WebseedReport>,
+        magnets: Vec<String>,
+    ) -> Self {
+        let input = &torrent.input;
+        Self {
+            name: input.name.clone(),
+            length: input.length,
+            piece_length: input.piece_length,
+            pieces: input.pieces.len() / 20,
+            creation_date: input.creation_date,
+            created_by: input.created_by.clone(),
+            infohash_v1: torrent.metainfo.infohash_v1,
+            infohash_v2: torrent.metainfo.infohash_v2,
+            pieces_root: input.v2.as_ref().map(|v2| v2.pieces_root),
+            checksums: torrent.checksums.clone(),
+            webseeds,
+            trackers: TrackerSummary::new(trackers),
+            magnets,
+        }
+    }
+
+    /// The same JSON the `serde` feature produces, available without it.
+    pub fn to_json(&self) -> Value {
+        let trackers = &self.trackers;
+        json!({
+            "name": self.name,
+            "length": self.length,
+            "piece_length": self.piece_length,
+            "pieces": self.pieces,
+            "creation_date": self.creation_date,
+            "created_by": self.created_by,
+            "infohash_v1": self.infohash_v1.map(hex::encode),
+            "infohash_v2": self.infohash_v2.map(hex::encode),
+            "pieces_root": self.pieces_root.map(hex::encode),
+            "checksums": self.checksums.iter().map(|checksum| json!({
+                "algorithm": checksum.algorithm.name(),
+                "digest": checksum.hex(),
+            })).collect::<Vec<_>>(),
+            "webseeds": self.webseeds.iter().map(|webseed| {
+                let mut entry = json!({
+                    "url": webseed.url,
+                    "ranges": webseed.ranges.name(),
+                    "fetched_url": webseed.fetched_url,
+                });
+                match &webseed.status {
+                    WebseedStatus::Primary => entry["status"] = json!("primary"),
+                    WebseedStatus::Verified => entry["status"] = json!("verified"),
+                    WebseedStatus::Rejected { reason, rejection } => {
+                        entry["status"] = json!("rejected");
+                        entry["reason"] = json!(reason);
+                        entry["rejection"] = rejection_json(rejection);
+                    }
+                }
+                entry
+            }).collect::<Vec<_>>(),
+            "trackers": {
+                "total": trackers.total,
+                "tiers": trackers.tiers,
+                "i2p": trackers.i2p,
+                "origins": trackers.origins.iter().map(|origin| json!({
+                    "source": origin.source,
+                    "priority": match origin.priority {
+                        SourcePriority::User => "user",
+                        SourcePriority::Best => "best",
+                        SourcePriority::All => "all",
+                        SourcePriority::Fallback => "fallback",
+                    },
+                    "count": origin.count,
+                })).collect::<Vec<_>>(),
+                "schemes": trackers.schemes,
+                "collapsed": trackers.collapsed,
+                "check": trackers.check.as_ref().map(|check| json!({
+                    "alive": check.alive,
+                    "dead": check.dead,
+                    "unchecked": check.unchecked,
+                })),
+            },
+            "magnets": self.magnets,
+        })
+    }
+}
+
+fn rejection_json(rejection: &WebseedRejection) -> Value {
+    match rejection {
+        WebseedRejection::LengthMismatch { expected, actual } => {
+            json!({ "kind": "length_mismatch", "expected": expected, "actual": actual })
+        }
+        WebseedRejection::HttpStatus { status } => json!({ "kind": "http_status", "status": status }),
+        WebseedRejection::Timeout => json!({ "kind": "timeout" }),
+        WebseedRejection::Dns { message } => json!({ "kind": "dns", "message": message }),
+        WebseedRejection::Connect { message } => json!({ "kind": "connect", "message": message }),
+        WebseedRejection::Ranges { support } => json!({ "kind": "ranges", "support": support.name() }),
+        WebseedRejection::Other { message } => json!({ "kind": "other", "message": message }),
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WebseedReport {
+    /// The URL as written into the torrent.
+    pub url: String,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub status: WebseedStatus,
+    /// Whether the webseed honors Range requests.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ranges: RangeSupport,
+    /// The URL actually requested, when query parameters were stripped from
+    /// the stored one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fetched_url: Option<String>,
+}
+
+impl WebseedReport {
+    /// Reports `primary` followed by every checked mirror, in order.
+    pub fn from_checks(primary: &str, primary_ranges: RangeSupport, checks: &[WebseedCheck]) -> Vec<Self> {
+        let mirrors = checks.iter().map(|check| Self {
+            url: check.url.to_string(),
+            status: match &check.rejection {
+                None => WebseedStatus::Verified,
+                Some(rejection) => WebseedStatus::rejected(rejection.clone()),
+            },
+            ranges: check.ranges,
+            fetched_url: None,
+        });
+        std::iter::once(Self {
+            url: primary.to_string(),
+            status: WebseedStatus::Primary,
+            ranges: primary_ranges,
+            fetched_url: None,
+        })
+        .chain(mirrors)
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "status", rename_all = "lowercase"))]
+pub enum WebseedStatus {
+    /// The URL the payload was downloaded from.
+    Primary,
+    /// A mirror serving the same length.
+    Verified,
+    /// A mirror left out of the torrent; `reason` is `rejection` as text.
+    Rejected { reason: String, rejection: WebseedRejection },
+}
+
+impl WebseedStatus {
+    pub fn rejected(rejection: WebseedRejection) -> Self {
+        Self::Rejected {
+            reason: rejection.to_string(),
+            rejection,
+        }
+    }
+}
+
+/// Counts describing the embedded tracker list.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackerSummary {
+    pub total: usize,
+    pub tiers: usize,
+    pub i2p: usize,
+    pub origins: Vec<TrackerOrigin>,
+    /// Tracker count per URL scheme, sorted by scheme.
+    pub schemes: Vec<(String, usize)>,
+    pub collapsed: usize,
+    pub check: Option<CheckReport>,
+}
+
+impl TrackerSummary {
+    pub fn new(trackers: &GatheredTrackers) -> Self {
+        let all = trackers.all();
+        Self {
+            total: all.len(),
+            tiers: trackers.tiers.len(),
+            i2p: all.iter().filter(|tracker| trackers::is_i2p(tracker)).count(),
+            origins: trackers.origins.clone(),
+            schemes: trackers::count_by_scheme(&all),
+            collapsed: trackers.collapsed,
+            check: trackers.check.clone(),
+        }
+    }
+}
+
+/// Version of the [`RunReport`] JSON layout. Fields may be added without a
+/// bump; renaming or removing one bumps it.
+pub const REPORT_SCHEMA: u32 = 1;
+
+/// The machine-readable result of a `create` run: the build itself plus
+/// where its outputs went and how long it took. Printed by `--json` and
+/// sent to `--notify` webhooks.
+///
+#[cfg_attr(feature = "serde", doc = "```")]
+#[cfg_attr(not(feature = "serde"), doc = "```ignore")]
+/// use torseed::summary::{BuildSummary, RunReport, RunTimings, TrackerSummary};
+///
+/// let report = RunReport {
+///     schema: 1,
+///     version: "0.1.0".to_string(),
+///     build: BuildSummary {
+///         name: "data.bin".to_string(),
+///         length: 40_000,
+///         piece_length: 16_384,
+///         pieces: 3,
+///         creation_date: 1_700_000_000,
+///         created_by: "torseed".to_string(),
+///         infohash_v1: Some([0xab; 20]),
+///         infohash_v2: None,
+///         pieces_root: None,
+///         checksums: Vec::new(),
+///         webseeds: Vec::new(),
+///         trackers: TrackerSummary {
+///             total: 0,
+///             tiers: 0,
+///             i2p: 0,
+///             origins: Vec::new(),
+///             schemes: Vec::new(),
+///             collapsed: 0,
+///             check: None,
+///         },
+///         magnets: Vec::new(),
+///     },
+///     torrent_path: "data.bin.torrent".to_string(),
+///     magnet_path: Some("data.bin.magnet".to_string()),
+///     pieces_path: None,
+///     webtorrent: None,
+///     timings: RunTimings { total_secs: 2.5, setup_secs: 0.5, transfer_secs: 2.0, transfer_bytes: 40_000 },
+/// };
+/// let json = serde_json::to_value(&report).unwrap();
+/// assert_eq!(
+///     json,
+///     serde_json::json!({
+///         "schema": 1,
+///         "version": "0.1.0",
+///         "build": json["build"].clone(),
+///         "torrent_path": "data.bin.torrent",
+///         "magnet_path": "data.bin.magnet",
+///         "pieces_path": null,
+///         "webtorrent": null,
+///         "timings": { "total_secs": 2.5, "setup_secs": 0.5, "transfer_secs": 2.0, "transfer_bytes": 40000 }
+///     })
+/// );
+/// assert_eq!(json["build"]["infohash_v1"], "ab".repeat(20));
+/// assert_eq!(report.to_json(), json);
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RunReport {
+    /// Always [`REPORT_SCHEMA`] when produced by this version.
+    pub schema: u32,
+    /// torseed version that produced the report.
+    pub version: String,
+    pub build: BuildSummary,
+    pub torrent_path: String,
+    pub magnet_path: Option<String>,
+    /// Where `--pieces-out` wrote the piece hashes.
+    pub pieces_path: Option<String>,
+    /// The `--webtorrent` variant, when one was written.
+    pub webtorrent: Option<WebtorrentReport>,
+    pub timings: RunTimings,
+}
+
+impl RunReport {
+    /// The same JSON the `serde` feature produces, available without it.
+    pub fn to_json(&self) -> Value {
+        let timings = &self.timings;
+        json!({
+            "schema": self.schema,
+            "version": self.version,
+            "build": self.build.to_json(),
+            "torrent_path": self.torrent_path,
+            "magnet_path": self.magnet_path,
+            "pieces_path": self.pieces_path,
+            "webtorrent": self.webtorrent.as_ref().map(|webtorrent| json!({
+                "torrent_path": webtorrent.torrent_path,
+                "magnet_path": webtorrent.magnet_path,
+                "trackers": webtorrent.trackers,
+                "magnets": webtorrent.magnets,
+            })),
+            "timings": {
+                "total_secs": timings.total_secs,
+                "setup_secs": timings.setup_secs,
+                "transfer_secs": timings.transfer_secs,
+                "transfer_bytes": timings.transfer_bytes,
+            },
+        })
+    }
+}
+
+/// Wall-clock breakdown of a run, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RunTimings {
+    pub total_secs: f64,
+    /// HEAD requests, webseed checks and tracker gathering before streaming.
+    pub setup_secs: f64,
+    pub transfer_secs: f64,
+    pub transfer_bytes: u64,
+}
+
+/// A copy of the torrent for browser-based WebTorrent clients: the same info
+/// dictionary, announced only to WebSocket trackers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WebtorrentReport {
+    pub torrent_path: String,
+    pub magnet_path: Option<String>,
+    pub trackers: Vec<String>,
+    pub magnets: Vec<String>,
+}
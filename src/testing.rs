@@ -0,0 +1,116 @@
+//! Helpers shared by the unit tests.
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use url::Url;
+
+/// Serves `responses` to one connection each, in order, and closes every
+/// connection after its response. Returns the URL of `/data.bin` on the
+/// server.
+pub(crate) async fn serve(responses: Vec<Vec<u8>>) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/data.bin", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_request(&mut socket).await;
+            let _ = socket.write_all(&response).await;
+            let _ = socket.shutdown().await;
+        }
+    });
+    url.parse().unwrap()
+}
+
+/// Like [`serve`], and also passes on each request, head and body, as
+/// received.
+pub(crate) async fn serve_recording(responses: Vec<Vec<u8>>) -> (Url, mpsc::UnboundedReceiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/data.bin", listener.local_addr().unwrap());
+    let (requests, received) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await;
+            let _ = requests.send(String::from_utf8_lossy(&request).into_owned());
+            let _ = socket.write_all(&response).await;
+            let _ = socket.shutdown().await;
+        }
+    });
+    (url.parse().unwrap(), received)
+}
+
+/// Serves `response` to a single connection and then keeps it open without
+/// sending anything more, like a server that stalled mid-body.
+pub(crate) async fn serve_stalled(response: Vec<u8>) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/data.bin", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        read_request(&mut socket).await;
+        let _ = socket.write_all(&response).await;
+        std::future::pending::<()>().await;
+    });
+    url.parse().unwrap()
+}
+
+/// Serves `response` to a single connection in slices of `chunk` bytes,
+/// `gap` apart, like a slow but steady server.
+pub(crate) async fn serve_slowly(response: Vec<u8>, chunk: usize, gap: Duration) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/data.bin", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        read_request(&mut socket).await;
+        for slice in response.chunks(chunk) {
+            let _ = socket.write_all(slice).await;
+            tokio::time::sleep(gap).await;
+        }
+        let _ = socket.shutdown().await;
+    });
+    url.parse().unwrap()
+}
+
+/// A `200 OK` carrying `body`, with `Content-Length` set to `length` when
+/// given. Without one the body ends when the connection closes.
+pub(crate) fn response(length: Option<u64>, body: &[u8]) -> Vec<u8> {
+    let mut response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n".to_vec();
+    if let Some(length) = length {
+        response.extend_from_slice(format!("Content-Length: {length}\r\n").as_bytes());
+    }
+    response.extend_from_slice(b"\r\n");
+    response.extend_from_slice(body);
+    response
+}
+
+/// Deterministic test payload.
+pub(crate) fn payload(length: usize) -> Vec<u8> {
+    (0..length).map(|i| (i % 251) as u8).collect()
+}
+
+/// Reads the request head and as much body as its `Content-Length` names.
+async fn read_request(socket: &mut tokio::net::TcpStream) -> Vec<u8> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    let mut expected = None;
+    while expected.is_none_or(|expected| request.len() < expected) {
+        match socket.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+        if expected.is_none()
+            && let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n")
+        {
+            let head = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .and_then(|length| length.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            expected = Some(end + 4 + length);
+        }
+    }
+    request
+}
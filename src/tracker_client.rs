@@ -0,0 +1,702 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bendy::decoding::FromBencode;
+use bendy::value::Value;
+use futures::stream::{self, StreamExt};
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use rand::Rng;
+use reqwest::Client;
+use tokio::net::UdpSocket;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::error::{describe, Result, TorseedError};
+use crate::trackers;
+
+/// Magic constant identifying the BEP 15 connect request.
+const UDP_PROTOCOL_ID: u64 = 0x0417_2710_1980;
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ERROR: u32 = 3;
+
+/// Infohash used for liveness probes; not expected to exist in any swarm.
+const PROBE_INFOHASH: [u8; 20] = [0x54; 20];
+
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    /// Timeout for a single tracker probe.
+    pub timeout: Duration,
+    /// Maximum number of probes in flight at once.
+    pub concurrency: usize,
+    /// Overall budget for the whole check; trackers not probed by then are kept.
+    pub deadline: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackerStatus {
+    Alive,
+    Dead,
+    /// Not probed (ws/wss and I2P trackers, or the deadline expired first).
+    Unchecked,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CheckReport {
+    pub alive: usize,
+    pub dead: usize,
+    pub unchecked: usize,
+}
+
+/// Probes every tracker and returns the ones that are alive or could not be
+/// checked, preserving the input order.
+pub async fn check_trackers(
+    client: &Client,
+    trackers: &[String],
+    options: &CheckOptions,
+) -> (Vec<String>, CheckReport) {
+    let mut statuses = vec![TrackerStatus::Unchecked; trackers.len()];
+
+    let probes = stream::iter(trackers.iter().enumerate())
+        .map(|(index, tracker)| {
+            let client = client.clone();
+            let timeout = options.timeout;
+            async move { (index, probe_tracker(&client, tracker, timeout).await) }
+        })
+        .buffer_unordered(options.concurrency.max(1));
+    tokio::pin!(probes);
+
+    let deadline = tokio::time::sleep(options.deadline);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            next = probes.next() => match next {
+                Some((index, status)) => statuses[index] = status,
+                None => break,
+            },
+            _ = &mut deadline => {
+                info!("Tracker check deadline reached; keeping unprobed trackers");
+                break;
+            }
+        }
+    }
+
+    let mut report = CheckReport {
+        alive: 0,
+        dead: 0,
+        unchecked: 0,
+    };
+    let mut kept = Vec::with_capacity(trackers.len());
+    for (tracker, status) in trackers.iter().zip(statuses) {
+        match status {
+            TrackerStatus::Alive => report.alive += 1,
+            TrackerStatus::Dead => {
+                report.dead += 1;
+                continue;
+            }
+            TrackerStatus::Unchecked => report.unchecked += 1,
+        }
+        kept.push(tracker.clone());
+    }
+
+    (kept, report)
+}
+
+async fn probe_tracker(client: &Client, tracker: &str, timeout: Duration) -> TrackerStatus {
+    if crate::trackers::is_i2p(tracker) {
+        return TrackerStatus::Unchecked;
+    }
+    let Ok(url) = Url::parse(tracker) else {
+        return TrackerStatus::Dead;
+    };
+    let result = match url.scheme() {
+        "udp" => tokio::time::timeout(timeout, udp_connect(&url))
+            .await
+            .map_err(|_| timed_out())
+            .and_then(|inner| inner.map(|_| ())),
+        "http" | "https" => http_probe(client, &url, timeout).await,
+        _ => return TrackerStatus::Unchecked,
+    };
+    match result {
+        Ok(()) => TrackerStatus::Alive,
+        Err(err) => {
+            debug!("Tracker {} failed liveness probe: {err}", trackers::for_display(tracker));
+            TrackerStatus::Dead
+        }
+    }
+}
+
+/// Performs a BEP 15 connect handshake and returns the connection id.
+pub async fn udp_connect(url: &Url) -> Result<u64> {
+    let socket = udp_socket(url).await?;
+    udp_connect_on(&socket).await
+}
+
+/// Resolves a UDP tracker URL and returns a socket connected to it.
+pub(crate) async fn udp_socket(url: &Url) -> Result<UdpSocket> {
+    let addr = resolve(url).await?;
+    let socket = bind_for(&addr).await?;
+    socket
+        .connect(addr)
+        .await
+        .map_err(|err| TorseedError::io(format!("Failed to connect UDP socket to {addr}"), err))?;
+    Ok(socket)
+}
+
+pub(crate) async fn udp_connect_on(socket: &UdpSocket) -> Result<u64> {
+    let transaction_id: u32 = rand::thread_rng().r#gen();
+    let mut request = Vec::with_capacity(16);
+    request.extend_from_slice(&UDP_PROTOCOL_ID.to_be_bytes());
+    request.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+    socket
+        .send(&request)
+        .await
+        .map_err(|err| TorseedError::io("Failed to send UDP connect", err))?;
+
+    let mut buf = [0u8; 2048];
+    let len = socket
+        .recv(&mut buf)
+        .await
+        .map_err(|err| TorseedError::io("Failed to receive UDP connect response", err))?;
+    let response = &buf[..len];
+    let (action, body) = parse_header(response, transaction_id)?;
+    if action != ACTION_CONNECT || body.len() < 8 {
+        return Err(TorseedError::Trackers(format!("Unexpected UDP connect response (action {action}, {len} bytes)")));
+    }
+    Ok(u64::from_be_bytes(body[..8].try_into().expect("slice length checked")))
+}
+
+/// Validates the action/transaction header of a UDP tracker response and
+/// returns the action together with the remaining payload.
+pub(crate) fn parse_header(response: &[u8], transaction_id: u32) -> Result<(u32, &[u8])> {
+    if response.len() < 8 {
+        return Err(TorseedError::Trackers(format!("UDP tracker response too short ({} bytes)", response.len())));
+    }
+    let action = u32::from_be_bytes(response[0..4].try_into().expect("slice length checked"));
+    let received_tid = u32::from_be_bytes(response[4..8].try_into().expect("slice length checked"));
+    if received_tid != transaction_id {
+        return Err(TorseedError::Trackers("UDP tracker transaction id mismatch".to_string()));
+    }
+    let body = &response[8..];
+    if action == ACTION_ERROR {
+        return Err(TorseedError::Trackers(format!("UDP tracker error: {}", String::from_utf8_lossy(body))));
+    }
+    Ok((action, body))
+}
+
+pub(crate) async fn resolve(url: &Url) -> Result<SocketAddr> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| TorseedError::Trackers("Tracker URL has no host".to_string()))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url
+        .port()
+        .ok_or_else(|| TorseedError::Trackers("UDP tracker URL has no port".to_string()))?;
+    tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|err| TorseedError::io(format!("Failed to resolve {host}"), err))?
+        .next()
+        .ok_or_else(|| TorseedError::Trackers(format!("No addresses found for {host}")))
+}
+
+fn timed_out() -> TorseedError {
+    TorseedError::Trackers("timed out".to_string())
+}
+
+/// Drops the URL from `err` (it carries the info hash and maybe a passkey;
+/// callers name the tracker) and keeps its causes in the message.
+fn request_failed(context: &str, err: reqwest::Error) -> TorseedError {
+    TorseedError::Trackers(format!("{context}: {}", describe(&err.without_url())))
+}
+
+pub(crate) async fn bind_for(addr: &SocketAddr) -> Result<UdpSocket> {
+    let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    UdpSocket::bind(local)
+        .await
+        .map_err(|err| TorseedError::io("Failed to bind UDP socket", err))
+}
+
+async fn http_probe(client: &Client, url: &Url, timeout: Duration) -> Result<()> {
+    let params = AnnounceParams {
+        infohash: PROBE_INFOHASH,
+        peer_id: *b"-TS0001-probeprobepr",
+        port: 6881,
+        uploaded: 0,
+        left: 0,
+        event: AnnounceEvent::None,
+    };
+    // Even a failure-reason dictionary proves a tracker is answering.
+    http_announce_raw(client, url, &params, timeout).await.map(|_| ())
+}
+
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+
+/// Announce event reported to the tracker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnounceEvent {
+    None,
+    Started,
+    Stopped,
+}
+
+impl AnnounceEvent {
+    fn http_value(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Started => Some("started"),
+            Self::Stopped => Some("stopped"),
+        }
+    }
+
+    fn udp_value(self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Started => 2,
+            Self::Stopped => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnounceParams {
+    pub infohash: [u8; 20],
+    pub peer_id: [u8; 20],
+    pub port: u16,
+    /// Payload bytes sent to peers so far.
+    pub uploaded: u64,
+    pub left: u64,
+    pub event: AnnounceEvent,
+}
+
+/// Generates a random Azureus-style peer id for one-off announces.
+pub fn throwaway_peer_id() -> [u8; 20] {
+    let mut peer_id = *b"-TS0001-000000000000";
+    let mut rng = rand::thread_rng();
+    for byte in &mut peer_id[8..] {
+        *byte = rng.gen_range(b'0'..=b'9');
+    }
+    peer_id
+}
+
+/// Announces to a single tracker and returns the re-announce interval it
+/// asked for.
+pub async fn announce(client: &Client, tracker: &str, params: &AnnounceParams, timeout: Duration) -> Result<u32> {
+    if crate::trackers::is_i2p(tracker) {
+        return Err(TorseedError::Trackers("I2P trackers are not reachable from the clearnet".to_string()));
+    }
+    let url = Url::parse(tracker).map_err(|err| TorseedError::Trackers(format!("Invalid tracker URL: {err}")))?;
+    match url.scheme() {
+        "udp" => tokio::time::timeout(timeout, udp_announce(&url, params))
+            .await
+            .unwrap_or_else(|_| Err(timed_out())),
+        "http" | "https" => {
+            let response = http_announce_raw(client, &url, params, timeout).await?;
+            if let Some(Value::Bytes(reason)) = response.get(b"failure reason".as_slice()) {
+                return Err(TorseedError::Trackers(format!("Tracker rejected announce: {}", String::from_utf8_lossy(reason))));
+            }
+            match response.get(b"interval".as_slice()) {
+                Some(Value::Integer(interval)) => Ok(u32::try_from(*interval).unwrap_or(0)),
+                _ => Ok(0),
+            }
+        }
+        other => Err(TorseedError::Trackers(format!("{other} trackers are not supported"))),
+    }
+}
+
+async fn udp_announce(url: &Url, params: &AnnounceParams) -> Result<u32> {
+    let socket = udp_socket(url).await?;
+    let connection_id = udp_connect_on(&socket).await?;
+
+    let transaction_id: u32 = rand::thread_rng().r#gen();
+    let key: u32 = rand::thread_rng().r#gen();
+    let mut request = Vec::with_capacity(98);
+    request.extend_from_slice(&connection_id.to_be_bytes());
+    request.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+    request.extend_from_slice(&params.infohash);
+    request.extend_from_slice(&params.peer_id);
+    request.extend_from_slice(&0u64.to_be_bytes());
+    request.extend_from_slice(&params.left.to_be_bytes());
+    request.extend_from_slice(&params.uploaded.to_be_bytes());
+    request.extend_from_slice(&params.event.udp_value().to_be_bytes());
+    request.extend_from_slice(&0u32.to_be_bytes());
+    request.extend_from_slice(&key.to_be_bytes());
+    request.extend_from_slice(&(-1i32).to_be_bytes());
+    request.extend_from_slice(&params.port.to_be_bytes());
+    socket
+        .send(&request)
+        .await
+        .map_err(|err| TorseedError::io("Failed to send UDP announce", err))?;
+
+    let mut buf = [0u8; 2048];
+    let len = socket
+        .recv(&mut buf)
+        .await
+        .map_err(|err| TorseedError::io("Failed to receive UDP announce response", err))?;
+    let (action, body) = parse_header(&buf[..len], transaction_id)?;
+    if action != ACTION_ANNOUNCE || body.len() < 12 {
+        return Err(TorseedError::Trackers(format!("Unexpected UDP announce response (action {action}, {len} bytes)")));
+    }
+    Ok(u32::from_be_bytes(body[..4].try_into().expect("slice length checked")))
+}
+
+/// Sends an HTTP announce and returns the decoded response dictionary, which
+/// may be a failure-reason dictionary.
+async fn http_announce_raw(
+    client: &Client,
+    url: &Url,
+    params: &AnnounceParams,
+    timeout: Duration,
+) -> Result<BTreeMap<Cow<'static, [u8]>, Value<'static>>> {
+    let mut target = url.clone();
+    let mut query = target.query().map(|q| format!("{q}&")).unwrap_or_default();
+    query.push_str(&format!(
+        "info_hash={}&peer_id={}&port={}&uploaded={}&downloaded=0&left={}&compact=1",
+        percent_encode(&params.infohash, NON_ALPHANUMERIC),
+        percent_encode(&params.peer_id, NON_ALPHANUMERIC),
+        params.port,
+        params.uploaded,
+        params.left,
+    ));
+    if let Some(event) = params.event.http_value() {
+        query.push_str(&format!("&event={event}"));
+    }
+    target.set_query(Some(&query));
+
+    let response = client
+        .get(target)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|err| request_failed("Announce request failed", err))?;
+    let body = response
+        .bytes()
+        .await
+        .map_err(|err| request_failed("Failed to read announce response", err))?;
+
+    match Value::from_bencode(&body) {
+        Ok(Value::Dict(dict)) => Ok(dict),
+        _ => Err(TorseedError::Trackers("Announce response is not a bencoded dictionary".to_string())),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScrapeOptions {
+    /// Timeout for a single tracker scrape.
+    pub timeout: Duration,
+    /// Maximum number of scrapes in flight at once.
+    pub concurrency: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrapeStats {
+    pub seeders: u32,
+    pub leechers: u32,
+    pub completed: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum ScrapeOutcome {
+    Stats(ScrapeStats),
+    /// The tracker does not support scraping (ws/wss, I2P, or no `/announce` path).
+    Unsupported(String),
+    /// The tracker errored or did not answer in time.
+    Unresponsive(String),
+}
+
+/// Scrapes every tracker for `infohash`, returning one outcome per tracker in
+/// input order.
+pub async fn scrape_trackers(
+    client: &Client,
+    trackers: &[String],
+    infohash: [u8; 20],
+    options: &ScrapeOptions,
+) -> Vec<(String, ScrapeOutcome)> {
+    stream::iter(trackers.iter().cloned())
+        .map(|tracker| {
+            let client = client.clone();
+            let timeout = options.timeout;
+            async move {
+                let outcome = scrape_tracker(&client, &tracker, infohash, timeout).await;
+                (tracker, outcome)
+            }
+        })
+        .buffered(options.concurrency.max(1))
+        .collect()
+        .await
+}
+
+async fn scrape_tracker(client: &Client, tracker: &str, infohash: [u8; 20], timeout: Duration) -> ScrapeOutcome {
+    if crate::trackers::is_i2p(tracker) {
+        return ScrapeOutcome::Unsupported("I2P tracker".to_string());
+    }
+    let url = match Url::parse(tracker) {
+        Ok(url) => url,
+        Err(err) => return ScrapeOutcome::Unsupported(format!("invalid URL: {err}")),
+    };
+    let result = match url.scheme() {
+        "udp" => tokio::time::timeout(timeout, udp_scrape(&url, infohash))
+            .await
+            .unwrap_or_else(|_| Err(timed_out())),
+        "http" | "https" => {
+            let Some(scrape) = scrape_url(&url) else {
+                return ScrapeOutcome::Unsupported("announce path does not allow scraping".to_string());
+            };
+            http_scrape(client, &scrape, infohash, timeout).await
+        }
+        other => return ScrapeOutcome::Unsupported(format!("{other} trackers cannot be scraped")),
+    };
+    match result {
+        Ok(stats) => ScrapeOutcome::Stats(stats),
+        Err(err) => {
+            let message = describe(&err);
+            debug!("Scrape of {} failed: {message}", trackers::for_display(tracker));
+            ScrapeOutcome::Unresponsive(message)
+        }
+    }
+}
+
+/// Derives the BEP 48 scrape URL by replacing a trailing `announce` path
+/// component with `scrape`. Returns `None` when the tracker does not follow
+/// that convention.
+pub fn scrape_url(announce: &Url) -> Option<Url> {
+    let path = announce.path();
+    let (prefix, last) = path.rsplit_once('/')?;
+    let rest = last.strip_prefix("announce")?;
+    let mut url = announce.clone();
+    url.set_path(&format!("{prefix}/scrape{rest}"));
+    Some(url)
+}
+
+pub async fn udp_scrape(url: &Url, infohash: [u8; 20]) -> Result<ScrapeStats> {
+    let socket = udp_socket(url).await?;
+    let connection_id = udp_connect_on(&socket).await?;
+
+    let transaction_id: u32 = rand::thread_rng().r#gen();
+    let mut request = Vec::with_capacity(36);
+    request.extend_from_slice(&connection_id.to_be_bytes());
+    request.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+    request.extend_from_slice(&infohash);
+    socket
+        .send(&request)
+        .await
+        .map_err(|err| TorseedError::io("Failed to send UDP scrape", err))?;
+
+    let mut buf = [0u8; 2048];
+    let len = socket
+        .recv(&mut buf)
+        .await
+        .map_err(|err| TorseedError::io("Failed to receive UDP scrape response", err))?;
+    let (action, body) = parse_header(&buf[..len], transaction_id)?;
+    if action != ACTION_SCRAPE || body.len() < 12 {
+        return Err(TorseedError::Trackers(format!("Unexpected UDP scrape response (action {action}, {len} bytes)")));
+    }
+    let field = |index: usize| u32::from_be_bytes(body[index * 4..index * 4 + 4].try_into().expect("slice length checked"));
+    Ok(ScrapeStats {
+        seeders: field(0),
+        completed: field(1),
+        leechers: field(2),
+    })
+}
+
+async fn http_scrape(client: &Client, url: &Url, infohash: [u8; 20], timeout: Duration) -> Result<ScrapeStats> {
+    let mut target = url.clone();
+    let mut query = target.query().map(|q| format!("{q}&")).unwrap_or_default();
+    query.push_str(&format!("info_hash={}", percent_encode(&infohash, NON_ALPHANUMERIC)));
+    target.set_query(Some(&query));
+
+    let response = client
+        .get(target)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|err| request_failed("Scrape request failed", err))?;
+    let body = response
+        .bytes()
+        .await
+        .map_err(|err| request_failed("Failed to read scrape response", err))?;
+
+    let root = match Value::from_bencode(&body) {
+        Ok(Value::Dict(root)) => root,
+        _ => return Err(TorseedError::Trackers("Scrape response is not a bencoded dictionary".to_string())),
+    };
+    if let Some(Value::Bytes(reason)) = root.get(b"failure reason".as_slice()) {
+        return Err(TorseedError::Trackers(format!("Tracker refused scrape: {}", String::from_utf8_lossy(reason))));
+    }
+    let Some(Value::Dict(files)) = root.get(b"files".as_slice()) else {
+        return Err(TorseedError::Trackers("Scrape response has no files dictionary".to_string()));
+    };
+    let Some(Value::Dict(entry)) = files.get(infohash.as_slice()) else {
+        // Trackers omit unknown infohashes; that simply means an empty swarm.
+        return Ok(ScrapeStats {
+            seeders: 0,
+            leechers: 0,
+            completed: 0,
+        });
+    };
+    let int = |name: &str| match entry.get(name.as_bytes()) {
+        Some(Value::Integer(value)) => u32::try_from(*value).unwrap_or(0),
+        _ => 0,
+    };
+    Ok(ScrapeStats {
+        seeders: int("complete"),
+        leechers: int("incomplete"),
+        completed: int("downloaded"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{response, serve};
+
+    const CONNECTION_ID: u64 = 0x0123_4567_89ab_cdef;
+
+    /// How the fake UDP tracker answers.
+    #[derive(Clone, Copy)]
+    enum Reply {
+        Normal,
+        Error,
+        WrongTransaction,
+    }
+
+    /// A BEP 15 tracker on localhost that answers connects and announces
+    /// with a 1800 second interval.
+    async fn udp_tracker(reply: Reply) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let request = &buf[..len];
+                let action = u32::from_be_bytes(request[8..12].try_into().unwrap());
+                let mut transaction = request[12..16].to_vec();
+                if matches!(reply, Reply::WrongTransaction) {
+                    transaction[0] ^= 0xff;
+                }
+                let mut answer = Vec::new();
+                match (reply, action) {
+                    (Reply::Error, _) => {
+                        answer.extend_from_slice(&ACTION_ERROR.to_be_bytes());
+                        answer.extend_from_slice(&transaction);
+                        answer.extend_from_slice(b"go away");
+                    }
+                    (_, ACTION_CONNECT) => {
+                        assert_eq!(request[..8], UDP_PROTOCOL_ID.to_be_bytes());
+                        answer.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+                        answer.extend_from_slice(&transaction);
+                        answer.extend_from_slice(&CONNECTION_ID.to_be_bytes());
+                    }
+                    (_, ACTION_ANNOUNCE) => {
+                        assert_eq!(request[..8], CONNECTION_ID.to_be_bytes());
+                        answer.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+                        answer.extend_from_slice(&transaction);
+                        for value in [1800u32, 0, 0] {
+                            answer.extend_from_slice(&value.to_be_bytes());
+                        }
+                    }
+                    _ => continue,
+                }
+                let _ = socket.send_to(&answer, peer).await;
+            }
+        });
+        url
+    }
+
+    /// A UDP port nothing listens on.
+    fn closed_udp_tracker() -> String {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        format!("udp://{}/announce", socket.local_addr().unwrap())
+    }
+
+    async fn http_tracker(body: &[u8]) -> String {
+        serve(vec![response(Some(body.len() as u64), body)]).await.to_string()
+    }
+
+    fn params() -> AnnounceParams {
+        AnnounceParams {
+            infohash: [1; 20],
+            peer_id: throwaway_peer_id(),
+            port: 6881,
+            uploaded: 0,
+            left: 0,
+            event: AnnounceEvent::Started,
+        }
+    }
+
+    #[tokio::test]
+    async fn udp_connect_returns_the_connection_id() {
+        let url = Url::parse(&udp_tracker(Reply::Normal).await).unwrap();
+        assert_eq!(udp_connect(&url).await.unwrap(), CONNECTION_ID);
+    }
+
+    #[tokio::test]
+    async fn udp_connect_reports_tracker_errors() {
+        let url = Url::parse(&udp_tracker(Reply::Error).await).unwrap();
+        let err = udp_connect(&url).await.unwrap_err();
+        assert!(err.to_string().contains("go away"), "{err}");
+
+        let url = Url::parse(&udp_tracker(Reply::WrongTransaction).await).unwrap();
+        let err = udp_connect(&url).await.unwrap_err();
+        assert!(err.to_string().contains("transaction id"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn announces_over_udp_and_http() {
+        let client = Client::new();
+        let timeout = Duration::from_secs(5);
+        let tracker = udp_tracker(Reply::Normal).await;
+        assert_eq!(announce(&client, &tracker, &params(), timeout).await.unwrap(), 1800);
+
+        let tracker = http_tracker(b"d8:intervali900e5:peers0:e").await;
+        assert_eq!(announce(&client, &tracker, &params(), timeout).await.unwrap(), 900);
+
+        let tracker = http_tracker(b"d14:failure reason12:unregisterede").await;
+        let err = announce(&client, &tracker, &params(), timeout).await.unwrap_err();
+        assert!(err.to_string().contains("unregistered"), "{err}");
+
+        let tracker = "ws://tracker.example.org/announce";
+        assert!(announce(&client, tracker, &params(), timeout).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn check_keeps_live_and_unchecked_trackers_in_order() {
+        let trackers = vec![
+            closed_udp_tracker(),
+            udp_tracker(Reply::Normal).await,
+            "wss://tracker.example.org/announce".to_string(),
+            // A failure reason still proves the tracker answers.
+            http_tracker(b"d14:failure reason12:unregisterede").await,
+            udp_tracker(Reply::Error).await,
+            "not a url".to_string(),
+        ];
+        let options = CheckOptions {
+            timeout: Duration::from_secs(2),
+            concurrency: 4,
+            deadline: Duration::from_secs(10),
+        };
+        let (kept, report) = check_trackers(&Client::new(), &trackers, &options).await;
+        assert_eq!(kept, trackers[1..4]);
+        assert_eq!((report.alive, report.dead, report.unchecked), (2, 3, 1));
+    }
+
+    #[tokio::test]
+    async fn check_keeps_trackers_left_at_the_deadline() {
+        // Nothing answers, so the probe only ends with its timeout.
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let trackers = vec![format!("udp://{}/announce", silent.local_addr().unwrap())];
+        let options = CheckOptions {
+            timeout: Duration::from_secs(30),
+            concurrency: 1,
+            deadline: Duration::from_millis(200),
+        };
+        let (kept, report) = check_trackers(&Client::new(), &trackers, &options).await;
+        assert_eq!(kept, trackers);
+        assert_eq!((report.alive, report.dead, report.unchecked), (0, 0, 1));
+    }
+}
@@ -1,11 +1,21 @@
-use std::{collections::HashSet, time::{Duration, Instant}};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
 
-use anyhow::{anyhow, Result};
 use futures::stream::{FuturesUnordered, StreamExt};
 use rand::{seq::SliceRandom, thread_rng};
 use reqwest::Client;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
-use url::Url;
+use url::{Host, Url};
+
+use crate::error::{Result, TorseedError};
+use crate::http::{self, HttpOptions};
+pub use crate::passkey::{for_display, has_passkey, redact_passkey, redaction_enabled, set_redaction};
+use crate::tracker_client::CheckReport;
 
 const FALLBACK_TRACKERS: &str = r"udp://tracker.opentrackr.org:1337/announce
 udp://open.stealth.si:80/announce
@@ -48,99 +58,812 @@ https://tracker.imgoingto.icu:443/announce
 https://tracker.renfei.net:443/announce
 ";
 
-const TRACKER_SOURCES: &[&str] = &[
-    "https://raw.githubusercontent.com/ngosang/trackerslist/master/trackers_best.txt",
-    "https://raw.githubusercontent.com/ngosang/trackerslist/master/trackers_all.txt",
-    "https://raw.githubusercontent.com/XIU2/TrackersListCollection/master/best.txt",
-    "https://raw.githubusercontent.com/XIU2/TrackersListCollection/master/all.txt",
-    "https://trackerslist.com/all.txt",
-    "https://newtrackon.com/api/stable",
+/// URL schemes accepted for tracker announce URLs.
+pub const SUPPORTED_SCHEMES: &[&str] = &["udp", "http", "https", "ws", "wss"];
+
+const TRACKER_SOURCES: &[TrackerSource] = &[
+    TrackerSource::plain(
+        "https://raw.githubusercontent.com/ngosang/trackerslist/master/trackers_best.txt",
+        SourcePriority::Best,
+    ),
+    TrackerSource::plain(
+        "https://raw.githubusercontent.com/ngosang/trackerslist/master/trackers_all.txt",
+        SourcePriority::All,
+    ),
+    TrackerSource::plain(
+        "https://raw.githubusercontent.com/XIU2/TrackersListCollection/master/best.txt",
+        SourcePriority::Best,
+    ),
+    TrackerSource::plain(
+        "https://raw.githubusercontent.com/XIU2/TrackersListCollection/master/all.txt",
+        SourcePriority::All,
+    ),
+    TrackerSource::plain("https://trackerslist.com/all.txt", SourcePriority::All),
 ];
 
-pub async fn gather_trackers(client: &Client) -> Result<Vec<String>> {
+/// Merge priority of a tracker origin; lower values are merged first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum SourcePriority {
+    /// Trackers passed on the command line.
+    User,
+    /// Curated "best" lists.
+    Best,
+    /// Exhaustive "all" lists.
+    All,
+    /// The embedded fallback list, used to top up when remote sources fail.
+    Fallback,
+}
+
+/// Response format of a remote tracker list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SourceFormat {
+    /// One announce URL per line, `#` comments allowed.
+    Plain,
+    /// newtrackon API: entries separated by blank lines, possibly percent-encoded.
+    Newtrackon,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TrackerSource {
+    url: &'static str,
+    format: SourceFormat,
+    priority: SourcePriority,
+}
+
+impl TrackerSource {
+    const fn plain(url: &'static str, priority: SourcePriority) -> Self {
+        Self {
+            url,
+            format: SourceFormat::Plain,
+            priority,
+        }
+    }
+}
+
+/// newtrackon API endpoint to include as a tracker source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum NewtrackonEndpoint {
+    /// Trackers with at least 95% uptime.
+    Stable,
+    /// Trackers currently responding.
+    Live,
+    /// Stable UDP trackers only.
+    Udp,
+    /// Stable HTTP(S) trackers only.
+    Http,
+}
+
+impl NewtrackonEndpoint {
+    fn source(self) -> TrackerSource {
+        let (url, priority) = match self {
+            Self::Stable => ("https://newtrackon.com/api/stable", SourcePriority::Best),
+            Self::Live => ("https://newtrackon.com/api/live", SourcePriority::All),
+            Self::Udp => ("https://newtrackon.com/api/udp", SourcePriority::Best),
+            Self::Http => ("https://newtrackon.com/api/http", SourcePriority::Best),
+        };
+        TrackerSource {
+            url,
+            format: SourceFormat::Newtrackon,
+            priority,
+        }
+    }
+}
+
+/// How trackers fetched from remote sources are ordered in the final list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TrackerOrder {
+    /// Sources in declaration order within each priority, trackers sorted
+    /// within each source.
+    Stable,
+    /// Sources by response time within each priority, trackers shuffled
+    /// within each source.
+    Shuffle,
+}
+
+/// Strategy for collapsing trackers that point at the same service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum DedupeMode {
+    /// Keep one tracker per host name, picking the preferred scheme.
+    Host,
+}
+
+/// Options controlling how the announce list is assembled.
+#[derive(Debug, Clone)]
+pub struct TrackerOptions {
+    /// Announce tiers imported from an existing torrent; placed before
+    /// everything else with their tier structure intact.
+    pub imported_tiers: Vec<Vec<String>>,
+    /// Trackers supplied explicitly by the user; placed first in the main tier.
+    pub user_trackers: Vec<String>,
+    /// I2P announce URLs supplied by the user; kept in a tier of their own.
+    pub i2p_trackers: Vec<String>,
+    /// Upper bound on the number of trackers in the final list. Zero means
+    /// only the user-supplied trackers are used.
+    pub max_trackers: usize,
+    pub order: TrackerOrder,
+    /// Tracker URL schemes to keep from the fallback and fetched lists; empty
+    /// keeps every supported scheme.
+    pub schemes: Vec<String>,
+    /// Host suffixes or URL globs for trackers that must never be included.
+    pub exclude: Vec<String>,
+    /// newtrackon endpoint fetched alongside the static sources.
+    pub newtrackon: NewtrackonEndpoint,
+    /// Optional collapsing of trackers sharing a host.
+    pub dedupe: Option<DedupeMode>,
+    /// Scheme preference used when collapsing, most preferred first.
+    pub dedupe_prefer: Vec<String>,
+    /// Split the main list into user, curated and remaining tiers instead of
+    /// emitting a single tier.
+    pub tier_by_source: bool,
+    /// Timeout for fetching a single remote tracker list.
+    pub fetch_timeout: Duration,
+    /// Overall budget for fetching remote tracker lists.
+    pub fetch_deadline: Duration,
+}
+
+/// Number of trackers contributed by a single origin.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackerOrigin {
+    pub source: String,
+    pub priority: SourcePriority,
+    pub count: usize,
+}
+
+/// Final, deduplicated tracker list along with where each entry came from.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GatheredTrackers {
+    /// Announce-list tiers in order. Trackers on `.i2p` hosts always form the
+    /// last tier so clearnet clients reach them only after everything else.
+    pub tiers: Vec<Vec<String>>,
+    pub origins: Vec<TrackerOrigin>,
+    /// Liveness results when the list was filtered by `--check-trackers`.
+    pub check: Option<CheckReport>,
+    /// Number of trackers dropped by `--dedupe-trackers`.
+    pub collapsed: usize,
+}
+
+impl GatheredTrackers {
+    /// All trackers flattened in tier order.
+    pub fn all(&self) -> Vec<String> {
+        self.tiers.iter().flatten().cloned().collect()
+    }
+
+    /// Keeps only the trackers accepted by `keep`, dropping emptied tiers.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        for tier in &mut self.tiers {
+            tier.retain(|tracker| keep(tracker));
+        }
+        self.tiers.retain(|tier| !tier.is_empty());
+    }
+}
+
+pub async fn gather_trackers(
+    client: &Client,
+    http_options: &HttpOptions,
+    options: &TrackerOptions,
+    cancel: &CancellationToken,
+) -> Result<GatheredTrackers> {
+    gather_from(client, http_options, options, &all_sources(options), cancel).await
+}
+
+/// [`gather_trackers`] over an explicit list of remote sources.
+async fn gather_from(
+    client: &Client,
+    http_options: &HttpOptions,
+    options: &TrackerOptions,
+    sources: &[TrackerSource],
+    cancel: &CancellationToken,
+) -> Result<GatheredTrackers> {
     let fallback = parse_tracker_block(FALLBACK_TRACKERS);
     if fallback.is_empty() {
-        return Err(anyhow!("Fallback tracker list is empty"));
+        return Err(TorseedError::Trackers("Fallback tracker list is empty".to_string()));
+    }
+    let fallback = filter_trackers("fallback", fallback, options);
+
+    let mut aggregator = Aggregator::new(options);
+
+    for tier in &options.imported_tiers {
+        let normalized: Vec<String> = tier.iter().filter_map(|tracker| normalize_tracker(tracker)).collect();
+        aggregator.push_tier("imported", normalized);
     }
 
-    let mut aggregated = Vec::new();
-    let mut seen = HashSet::new();
+    let mut user = Vec::new();
+    for tracker in &options.user_trackers {
+        match normalize_tracker(tracker) {
+            Some(normalized) => user.push(normalized),
+            None => warn!("Ignoring invalid tracker URL: {}", for_display(tracker)),
+        }
+    }
+    let user_count = user.len();
+    let user_cap = if options.max_trackers == 0 {
+        usize::MAX
+    } else {
+        options.max_trackers
+    };
+    let user_added = aggregator.extend("user", SourcePriority::User, user, user_cap);
+    if user_added < user_count && aggregator.len() >= user_cap {
+        warn!(
+            "Only {} of {} user-supplied trackers kept due to --max-trackers {}",
+            user_added,
+            user_count,
+            options.max_trackers
+        );
+    }
 
-    for tracker in &fallback {
-        if seen.insert(tracker.clone()) {
-            aggregated.push(tracker.clone());
-            if aggregated.len() >= 1000 {
-                return Ok(aggregated);
+    for tracker in &options.i2p_trackers {
+        match normalize_tracker(tracker) {
+            Some(normalized) if is_i2p(&normalized) => {
+                if aggregator.seen.insert(normalized.clone()) {
+                    aggregator.i2p.push(normalized);
+                }
             }
+            _ => warn!("Ignoring invalid I2P tracker URL: {}", for_display(tracker)),
         }
     }
 
+    if options.max_trackers == 0 {
+        return aggregator.finish();
+    }
+
     let mut futures = FuturesUnordered::new();
-    for &source_url in TRACKER_SOURCES {
-        let client = client.clone();
-        let source = source_url.to_string();
+    for &TrackerSource { url, format, priority } in sources {
+        let (client, http_options) = (client.clone(), http_options.clone());
+        let source = url.to_string();
+        let timeout = options.fetch_timeout;
         futures.push(async move {
             let start = Instant::now();
-            let result = tokio::time::timeout(Duration::from_secs(8), client.get(&source).send()).await;
-            match result {
-                Ok(Ok(response)) => {
+            let result = http::send_probe(&http_options, client.get(&source).timeout(timeout)).await;
+            let trackers = match result {
+                Ok(response) => {
                     match response.error_for_status() {
                         Ok(response) => match response.text().await {
-                            Ok(text) => {
-                                let trackers = parse_tracker_block(&text);
-                                let elapsed = start.elapsed();
-                                return Some((elapsed, trackers, source));
-                            }
+                            Ok(text) => Some(match format {
+                                SourceFormat::Plain => parse_tracker_block(&text),
+                                SourceFormat::Newtrackon => parse_newtrackon_block(&text),
+                            }),
                             Err(err) => {
                                 warn!("Tracker source {source} text decode failed: {err}");
+                                None
                             }
                         },
                         Err(err) => {
                             warn!("Tracker source {source} returned error status: {err}");
+                            None
                         }
                     }
                 }
-                Ok(Err(err)) => {
-                    warn!("Tracker source {source} failed: {err}");
-                }
-                Err(_) => {
+                Err(err) if err.is_timeout() => {
                     warn!("Tracker source {source} timed out");
+                    None
                 }
-            }
-            None
+                Err(err) => {
+                    warn!("Tracker source {source} failed: {err}");
+                    None
+                }
+            };
+            (source, priority, trackers.map(|trackers| (start.elapsed(), trackers)))
         });
     }
 
-    let mut results = Vec::new();
-    while let Some(Some(entry)) = futures.next().await {
-        results.push(entry);
+    // Track outstanding sources per priority so the loop can stop as soon as
+    // every source that could still outrank the collected trackers is done
+    // and the cap is already covered. Dropping `futures` cancels the rest.
+    let mut outstanding: BTreeMap<SourcePriority, usize> = BTreeMap::new();
+    for source in sources {
+        *outstanding.entry(source.priority).or_default() += 1;
     }
 
-    results.sort_by_key(|(elapsed, _, _)| *elapsed);
-
-    for (elapsed, trackers, source) in results {
-        debug!("tracker_source = {source}, elapsed = {:?}, discovered = {}", elapsed, trackers.len());
-        let mut trackers = trackers;
-        trackers.shuffle(&mut thread_rng());
-        for tracker in trackers {
-            if seen.insert(tracker.clone()) {
-                aggregated.push(tracker);
-                if aggregated.len() >= 1000 {
+    let mut results = Vec::new();
+    let deadline = tokio::time::sleep(options.fetch_deadline);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            next = futures.next() => {
+                let Some((source, priority, outcome)) = next else {
                     break;
+                };
+                if let Some(count) = outstanding.get_mut(&priority) {
+                    *count -= 1;
+                }
+                if let Some((elapsed, trackers)) = outcome {
+                    debug!("tracker_source = {source}, elapsed = {:?}, discovered = {}", elapsed, trackers.len());
+                    let trackers = filter_trackers(&source, trackers, options);
+                    results.push((elapsed, trackers, source));
                 }
+                if cap_covered(&aggregator, &results, sources, &outstanding) {
+                    debug!("Tracker cap reached; cancelling {} outstanding sources", futures.len());
+                    break;
+                }
+            }
+            _ = cancel.cancelled() => return Err(TorseedError::Cancelled),
+            _ = &mut deadline => {
+                info!(
+                    "Tracker fetch deadline reached; cancelling {} outstanding sources",
+                    futures.len()
+                );
+                break;
             }
         }
-        if aggregated.len() >= 1000 {
+    }
+    let cancelled = futures.len();
+    drop(futures);
+
+    let failed_sources = sources.len() - results.len();
+
+    // Higher-priority lists always come first; within a priority the order
+    // option decides between declaration order and response latency.
+    match options.order {
+        TrackerOrder::Stable => results.sort_by_key(|(_, _, source)| source_rank(sources, source)),
+        TrackerOrder::Shuffle => results.sort_by_key(|(elapsed, _, source)| {
+            (source_priority(sources, source), *elapsed)
+        }),
+    }
+
+    for (_, mut trackers, source) in results {
+        match options.order {
+            TrackerOrder::Stable => trackers.sort(),
+            TrackerOrder::Shuffle => trackers.shuffle(&mut thread_rng()),
+        }
+        let priority = source_priority(sources, &source);
+        aggregator.extend(&source, priority, trackers, options.max_trackers);
+        if aggregator.is_full() {
             break;
         }
     }
 
-    info!("Total trackers gathered: {}", aggregated.len());
+    let remote_empty = aggregator
+        .origins
+        .iter()
+        .all(|origin| origin.priority == SourcePriority::User);
+    if !aggregator.is_full() && (failed_sources > 0 || remote_empty) {
+        debug!(
+            "{failed_sources} tracker sources failed ({cancelled} cancelled); topping up from the fallback list"
+        );
+        aggregator.extend("fallback", SourcePriority::Fallback, fallback, options.max_trackers);
+    }
 
-    if aggregated.is_empty() {
-        Err(anyhow!("No trackers available"))
-    } else {
-        Ok(aggregated)
+    if aggregator.collapsed > 0 {
+        debug!("Collapsed {} trackers sharing a host", aggregator.collapsed);
+    }
+
+    for (priority, count) in aggregator.priority_counts() {
+        debug!("tracker_priority = {:?}, count = {}", priority, count);
+    }
+
+    info!("Total trackers gathered: {}", aggregator.len());
+
+    if aggregator.len() == 0 && aggregator.i2p.is_empty() && !options.schemes.is_empty() {
+        return Err(TorseedError::Trackers(format!(
+            "No trackers left after applying --tracker-schemes {}",
+            options.schemes.join(",")
+        )));
+    }
+
+    aggregator.finish()
+}
+
+/// Formats `gathered` as a tracker list file: `#` comments recording when
+/// and from which sources it was gathered, then one announce URL per line
+/// with a blank line between tiers. [`parse_tracker_list`] reads it back.
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> torseed::Result<()> {
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use torseed::http::HttpOptions;
+/// use torseed::trackers::{self, NewtrackonEndpoint, TrackerOptions, TrackerOrder};
+///
+/// // With `max_trackers: 0` only the given trackers are used, so nothing is fetched.
+/// let options = TrackerOptions {
+///     imported_tiers: vec![vec!["https://tracker.example.net/announce".to_string()]],
+///     user_trackers: vec!["udp://tracker.example.org:1337".to_string(), "udp://open.example.com:6969".to_string()],
+///     i2p_trackers: vec!["http://tracker.example.i2p/a".to_string()],
+///     max_trackers: 0,
+///     order: TrackerOrder::Shuffle,
+///     schemes: Vec::new(),
+///     exclude: Vec::new(),
+///     newtrackon: NewtrackonEndpoint::Stable,
+///     dedupe: None,
+///     dedupe_prefer: Vec::new(),
+///     tier_by_source: false,
+///     fetch_timeout: Duration::from_secs(8),
+///     fetch_deadline: Duration::from_secs(15),
+/// };
+/// let client = reqwest::Client::new();
+/// let http_options = HttpOptions::default();
+/// let gathered = trackers::gather_trackers(&client, &http_options, &options, &Default::default()).await?;
+/// let dumped = trackers::format_tracker_list(&gathered, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+/// assert!(dumped.starts_with("# torseed tracker list, gathered 2023-11-14T22:13:20Z\n# Sources: "));
+/// assert_eq!(trackers::parse_tracker_list(&dumped), gathered.tiers);
+/// assert_eq!(gathered.tiers.len(), 3);
+/// # Ok(())
+/// # }
+/// ```
+pub fn format_tracker_list(gathered: &GatheredTrackers, gathered_at: SystemTime) -> String {
+    let mut out = format!("# torseed tracker list, gathered {}\n", humantime::format_rfc3339_seconds(gathered_at));
+    let sources: Vec<String> = gathered
+        .origins
+        .iter()
+        .filter(|origin| origin.count > 0)
+        .map(|origin| format!("{} ({})", origin.source, origin.count))
+        .collect();
+    let _ = writeln!(out, "# Sources: {}", if sources.is_empty() { "none".to_string() } else { sources.join(", ") });
+    for tier in &gathered.tiers {
+        out.push('\n');
+        for tracker in tier {
+            let _ = writeln!(out, "{tracker}");
+        }
+    }
+    out
+}
+
+/// Reads a tracker list as written by [`format_tracker_list`]: one announce
+/// URL per line, tiers separated by blank lines, `#` comments ignored.
+pub fn parse_tracker_list(text: &str) -> Vec<Vec<String>> {
+    let mut tiers = Vec::new();
+    let mut tier = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            if !tier.is_empty() {
+                tiers.push(std::mem::take(&mut tier));
+            }
+        } else if !line.starts_with('#') {
+            tier.push(line.to_string());
+        }
+    }
+    if !tier.is_empty() {
+        tiers.push(tier);
+    }
+    tiers
+}
+
+/// Returns true when the tracker URL points at an I2P (`.i2p`) host, which is
+/// only reachable from inside the I2P network.
+pub fn is_i2p(tracker: &str) -> bool {
+    Url::parse(tracker)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_ascii_lowercase()))
+        .is_some_and(|host| host == "i2p" || host.ends_with(".i2p"))
+}
+
+/// Counts trackers per URL scheme, sorted by scheme name.
+pub fn count_by_scheme(trackers: &[String]) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for tracker in trackers {
+        let scheme = tracker.split_once("://").map_or("unknown", |(scheme, _)| scheme);
+        *counts.entry(scheme.to_string()).or_default() += 1;
+    }
+    counts.into_iter().collect()
+}
+
+/// Keeps only the WebSocket (`ws`, `wss`) trackers of `tiers`, the only kind
+/// browser-based WebTorrent clients can announce to. Tiers left empty are
+/// dropped.
+///
+/// ```
+/// use torseed::trackers::webtorrent_tiers;
+///
+/// let tiers = vec![
+///     vec!["udp://tracker.example.org:1337/announce".to_string()],
+///     vec!["wss://tracker.example.com".to_string(), "https://tracker.example.net/announce".to_string()],
+/// ];
+/// assert_eq!(webtorrent_tiers(&tiers), vec![vec!["wss://tracker.example.com".to_string()]]);
+/// ```
+pub fn webtorrent_tiers(tiers: &[Vec<String>]) -> Vec<Vec<String>> {
+    let schemes = ["ws".to_string(), "wss".to_string()];
+    tiers
+        .iter()
+        .map(|tier| tier.iter().filter(|tracker| scheme_allowed(tracker, &schemes)).cloned().collect::<Vec<_>>())
+        .filter(|tier| !tier.is_empty())
+        .collect()
+}
+
+/// Applies the scheme allow-list and exclusion patterns to normalized trackers.
+fn filter_trackers(source: &str, trackers: Vec<String>, options: &TrackerOptions) -> Vec<String> {
+    let mut excluded = 0;
+    let kept: Vec<String> = trackers
+        .into_iter()
+        .filter(|tracker| scheme_allowed(tracker, &options.schemes))
+        .filter(|tracker| {
+            let hit = is_excluded(tracker, &options.exclude);
+            if hit {
+                excluded += 1;
+            }
+            !hit
+        })
+        .collect();
+    if excluded > 0 {
+        debug!("tracker_source = {source}, excluded = {excluded}");
+    }
+    kept
+}
+
+fn scheme_allowed(tracker: &str, schemes: &[String]) -> bool {
+    schemes.is_empty()
+        || tracker
+            .split_once("://")
+            .is_some_and(|(scheme, _)| schemes.iter().any(|allowed| allowed == scheme))
+}
+
+/// Returns true when `tracker` matches one of the exclusion patterns. Patterns
+/// containing `*`, `?` or `://` are globs over the whole URL; anything else is
+/// matched against the host name and its parent domains.
+fn is_excluded(tracker: &str, patterns: &[String]) -> bool {
+    if patterns.is_empty() {
+        return false;
+    }
+    let host = Url::parse(tracker)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_default();
+
+    patterns.iter().any(|pattern| {
+        if pattern.contains(['*', '?']) || pattern.contains("://") {
+            glob_match(pattern.as_bytes(), tracker.as_bytes())
+        } else {
+            let suffix = pattern.trim_start_matches('.').to_ascii_lowercase();
+            host == suffix || host.ends_with(&format!(".{suffix}"))
+        }
+    })
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| glob_match(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((ch, rest)) => text
+            .split_first()
+            .is_some_and(|(first, tail)| first.eq_ignore_ascii_case(ch) && glob_match(rest, tail)),
+    }
+}
+
+/// Reads exclusion patterns from a file, one per line; blank lines and `#`
+/// comments are ignored.
+pub fn load_exclude_file(path: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path).map_err(|err| {
+        TorseedError::io(format!("Failed to read tracker exclude file {}", path.display()), err)
+    })?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Returns true when the trackers already collected from every source of the
+/// highest completed priorities are enough to fill the cap, meaning no
+/// outstanding source could change the final list.
+fn cap_covered(
+    aggregator: &Aggregator,
+    results: &[(Duration, Vec<String>, String)],
+    sources: &[TrackerSource],
+    outstanding: &BTreeMap<SourcePriority, usize>,
+) -> bool {
+    let completed_through = outstanding
+        .iter()
+        .take_while(|(_, count)| **count == 0)
+        .map(|(priority, _)| *priority)
+        .last();
+    let Some(completed_through) = completed_through else {
+        return false;
+    };
+
+    let mut unique = HashSet::new();
+    for (_, trackers, source) in results {
+        if source_priority(sources, source) <= completed_through {
+            unique.extend(trackers.iter().filter(|t| !aggregator.seen.contains(*t)));
+        }
+    }
+    aggregator.len() + unique.len() >= aggregator.max
+}
+
+fn all_sources(options: &TrackerOptions) -> Vec<TrackerSource> {
+    let mut sources = TRACKER_SOURCES.to_vec();
+    sources.push(options.newtrackon.source());
+    sources
+}
+
+fn source_priority(sources: &[TrackerSource], source: &str) -> SourcePriority {
+    sources
+        .iter()
+        .find(|candidate| candidate.url == source)
+        .map_or(SourcePriority::All, |candidate| candidate.priority)
+}
+
+/// Declaration rank of a source, grouped by priority.
+fn source_rank(sources: &[TrackerSource], source: &str) -> (SourcePriority, usize) {
+    let index = sources
+        .iter()
+        .position(|candidate| candidate.url == source)
+        .unwrap_or(sources.len());
+    (source_priority(sources, source), index)
+}
+
+/// Deduplicating accumulator that records how many entries each origin added.
+struct Aggregator {
+    max: usize,
+    dedupe: Option<DedupeMode>,
+    dedupe_prefer: Vec<String>,
+    /// Host name -> (tracker currently kept, priority it was added with).
+    hosts: HashMap<String, (String, SourcePriority)>,
+    collapsed: usize,
+    imported: Vec<Vec<String>>,
+    tier_by_source: bool,
+    /// Main-tier trackers with the priority of the source that added them.
+    trackers: Vec<(String, SourcePriority)>,
+    i2p: Vec<String>,
+    seen: HashSet<String>,
+    origins: Vec<TrackerOrigin>,
+}
+
+impl Aggregator {
+    fn new(options: &TrackerOptions) -> Self {
+        Self {
+            max: options.max_trackers,
+            dedupe: options.dedupe,
+            dedupe_prefer: options.dedupe_prefer.clone(),
+            hosts: HashMap::new(),
+            collapsed: 0,
+            imported: Vec::new(),
+            tier_by_source: options.tier_by_source,
+            trackers: Vec::new(),
+            i2p: Vec::new(),
+            seen: HashSet::new(),
+            origins: Vec::new(),
+        }
+    }
+
+    /// Number of clearnet trackers collected so far.
+    fn len(&self) -> usize {
+        self.imported.iter().map(Vec::len).sum::<usize>() + self.trackers.len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.len() >= self.max
+    }
+
+    /// Adds a pre-grouped tier that keeps its own position in the announce
+    /// list. Imported tiers are user-supplied and therefore not capped.
+    fn push_tier(&mut self, source: &str, trackers: Vec<String>) {
+        let mut tier = Vec::new();
+        for tracker in trackers {
+            if !self.admit(&tracker, SourcePriority::User) {
+                continue;
+            }
+            if is_i2p(&tracker) {
+                self.i2p.push(tracker);
+            } else {
+                tier.push(tracker);
+            }
+        }
+        if tier.is_empty() {
+            return;
+        }
+        self.origins.push(TrackerOrigin {
+            source: source.to_string(),
+            priority: SourcePriority::User,
+            count: tier.len(),
+        });
+        self.imported.push(tier);
+    }
+
+    /// Adds trackers from `source` until `cap` total entries are reached and
+    /// returns how many were actually added.
+    fn extend(&mut self, source: &str, priority: SourcePriority, trackers: Vec<String>, cap: usize) -> usize {
+        let mut added = 0;
+        for tracker in trackers {
+            if self.len() >= cap {
+                break;
+            }
+            if !self.admit(&tracker, priority) {
+                continue;
+            }
+            if is_i2p(&tracker) {
+                self.i2p.push(tracker);
+            } else {
+                self.trackers.push((tracker, priority));
+            }
+            added += 1;
+        }
+        if added > 0 {
+            self.origins.push(TrackerOrigin {
+                source: source.to_string(),
+                priority,
+                count: added,
+            });
+        }
+        added
+    }
+
+    /// Decides whether `tracker` should be appended. In host dedupe mode a
+    /// better-scheme duplicate replaces the kept entry in place instead, so the
+    /// list length (and therefore the cap) is unaffected.
+    fn admit(&mut self, tracker: &str, priority: SourcePriority) -> bool {
+        if !self.seen.insert(tracker.to_string()) {
+            return false;
+        }
+        if self.dedupe != Some(DedupeMode::Host) {
+            return true;
+        }
+        let Some(host) = Url::parse(tracker).ok().and_then(|url| url.host_str().map(str::to_string)) else {
+            return true;
+        };
+        let Some((kept, kept_priority)) = self.hosts.get(&host).cloned() else {
+            self.hosts.insert(host, (tracker.to_string(), priority));
+            return true;
+        };
+
+        self.collapsed += 1;
+        if priority <= kept_priority && self.scheme_rank(tracker) < self.scheme_rank(&kept) {
+            if let Some(entry) = self.trackers.iter_mut().find(|(entry, _)| *entry == kept) {
+                *entry = (tracker.to_string(), priority);
+            } else if let Some(entry) = self
+                .imported
+                .iter_mut()
+                .flatten()
+                .chain(self.i2p.iter_mut())
+                .find(|entry| **entry == kept)
+            {
+                *entry = tracker.to_string();
+            }
+            self.hosts.insert(host, (tracker.to_string(), priority));
+        }
+        false
+    }
+
+    fn scheme_rank(&self, tracker: &str) -> usize {
+        let scheme = tracker.split_once("://").map_or("", |(scheme, _)| scheme);
+        self.dedupe_prefer
+            .iter()
+            .position(|preferred| preferred == scheme)
+            .unwrap_or(self.dedupe_prefer.len())
+    }
+
+    fn priority_counts(&self) -> BTreeMap<SourcePriority, usize> {
+        let mut counts = BTreeMap::new();
+        for origin in &self.origins {
+            *counts.entry(origin.priority).or_default() += origin.count;
+        }
+        counts
+    }
+
+    fn finish(self) -> Result<GatheredTrackers> {
+        let mut tiers = self.imported;
+        if self.tier_by_source {
+            let mut groups: [Vec<String>; 3] = Default::default();
+            for (tracker, priority) in self.trackers {
+                let group = match priority {
+                    SourcePriority::User => 0,
+                    SourcePriority::Best => 1,
+                    SourcePriority::All | SourcePriority::Fallback => 2,
+                };
+                groups[group].push(tracker);
+            }
+            tiers.extend(groups);
+        } else {
+            tiers.push(self.trackers.into_iter().map(|(tracker, _)| tracker).collect());
+        }
+        tiers.push(self.i2p);
+        tiers.retain(|tier| !tier.is_empty());
+        if tiers.is_empty() {
+            Err(TorseedError::Trackers("No trackers available".to_string()))
+        } else {
+            Ok(GatheredTrackers {
+                tiers,
+                origins: self.origins,
+                check: None,
+                collapsed: self.collapsed,
+            })
+        }
     }
 }
 
@@ -151,23 +874,57 @@ fn parse_tracker_block(block: &str) -> Vec<String> {
         .collect()
 }
 
+/// Parses a newtrackon API response. Entries are separated by blank lines and
+/// some deployments percent-encode each URL.
+fn parse_newtrackon_block(block: &str) -> Vec<String> {
+    block
+        .split_whitespace()
+        .filter_map(|entry| {
+            let decoded = percent_encoding::percent_decode_str(entry).decode_utf8().ok()?;
+            normalize_tracker(&decoded)
+        })
+        .collect()
+}
+
+/// Zone ids (`[fe80::1%eth0]`) only make sense on the local machine and are
+/// not valid in URLs we publish.
+fn has_ipv6_zone_id(input: &str) -> bool {
+    input
+        .split_once('[')
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .is_some_and(|(host, _)| host.contains('%'))
+}
+
 fn normalize_tracker(input: &str) -> Option<String> {
     let trimmed = input.trim();
     if trimmed.is_empty() || trimmed.starts_with('#') {
         return None;
     }
 
-    let mut url = Url::parse(trimmed).ok()?;
-    match url.scheme() {
-        "udp" | "http" | "https" | "ws" | "wss" => {}
-        _ => return None,
+    let mut url = match Url::parse(trimmed) {
+        Ok(url) => url,
+        Err(err) => {
+            if has_ipv6_zone_id(trimmed) {
+                debug!("Rejecting tracker with IPv6 zone id: {trimmed}");
+            } else {
+                debug!("Rejecting unparseable tracker {trimmed}: {err}");
+            }
+            return None;
+        }
+    };
+    if !SUPPORTED_SCHEMES.contains(&url.scheme()) {
+        return None;
     }
 
-    if let Some(host) = url.host_str() {
-        let host_lower = host.to_ascii_lowercase();
-        url.set_host(Some(&host_lower)).ok()?;
-    } else {
-        return None;
+    // IPv6 literals are already canonical and must keep their brackets;
+    // round-tripping them through set_host is not reliable across url versions.
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            let host_lower = domain.to_ascii_lowercase();
+            url.set_host(Some(&host_lower)).ok()?;
+        }
+        Some(Host::Ipv4(_) | Host::Ipv6(_)) => {}
+        None => return None,
     }
 
     let scheme_lower = url.scheme().to_ascii_lowercase();
@@ -190,3 +947,248 @@ fn normalize_tracker(input: &str) -> Option<String> {
 
     Some(normalized)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{response, serve};
+
+    const BEST: &str = include_str!("../tests/fixtures/trackers_best.txt");
+    const ALL: &str = include_str!("../tests/fixtures/trackers_all.txt");
+    const NEWTRACKON: &str = include_str!("../tests/fixtures/newtrackon_stable.txt");
+    const NEWTRACKON_ENCODED: &str = include_str!("../tests/fixtures/newtrackon_encoded.txt");
+    const USER: &str = "udp://user.example:1/announce";
+
+    fn options(max_trackers: usize) -> TrackerOptions {
+        TrackerOptions {
+            imported_tiers: Vec::new(),
+            user_trackers: vec![USER.to_string()],
+            i2p_trackers: Vec::new(),
+            max_trackers,
+            order: TrackerOrder::Stable,
+            schemes: Vec::new(),
+            exclude: Vec::new(),
+            newtrackon: NewtrackonEndpoint::Stable,
+            dedupe: None,
+            dedupe_prefer: Vec::new(),
+            tier_by_source: false,
+            fetch_timeout: Duration::from_secs(5),
+            fetch_deadline: Duration::from_secs(10),
+        }
+    }
+
+    /// A source served locally that answers each request with the next body.
+    async fn source(bodies: &[&str], format: SourceFormat, priority: SourcePriority) -> TrackerSource {
+        let responses = bodies
+            .iter()
+            .map(|body| response(Some(body.len() as u64), body.as_bytes()))
+            .collect();
+        let url = serve(responses).await;
+        TrackerSource {
+            url: Box::leak(url.to_string().into_boxed_str()),
+            format,
+            priority,
+        }
+    }
+
+    async fn gather(options: &TrackerOptions, sources: &[TrackerSource]) -> GatheredTrackers {
+        gather_from(&Client::new(), &HttpOptions::default(), options, sources, &Default::default())
+            .await
+            .unwrap()
+    }
+
+    fn strings(trackers: &[&str]) -> Vec<String> {
+        trackers.iter().map(|tracker| tracker.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn stable_order_is_reproducible() {
+        // Declared "all" first to check that priority outranks declaration order.
+        let sources = [
+            source(&[ALL, ALL], SourceFormat::Plain, SourcePriority::All).await,
+            source(&[BEST, BEST], SourceFormat::Plain, SourcePriority::Best).await,
+        ];
+        let options = options(100);
+        let first = gather(&options, &sources).await;
+        let second = gather(&options, &sources).await;
+        assert_eq!(first.tiers, second.tiers);
+
+        let main = strings(&[
+            USER,
+            "http://tracker.files.fm:6969/announce",
+            "https://tracker.gbitt.info/announce",
+            "udp://open.demonii.com:1337/announce",
+            "udp://tracker.opentrackr.org:1337/announce",
+            "udp://tracker.torrent.eu.org:451/announce",
+            "udp://exodus.desync.com:6969/announce",
+            "wss://tracker.openwebtorrent.com/",
+        ]);
+        let i2p = strings(&["http://tracker.example.i2p/announce"]);
+        assert_eq!(first.tiers, [main, i2p]);
+        let origins: Vec<_> = first
+            .origins
+            .iter()
+            .map(|origin| (origin.priority, origin.count))
+            .collect();
+        assert_eq!(
+            origins,
+            [(SourcePriority::User, 1), (SourcePriority::Best, 5), (SourcePriority::All, 3)]
+        );
+    }
+
+    #[test]
+    fn earlier_priority_wins_duplicates() {
+        let mut options = options(100);
+        options.dedupe = Some(DedupeMode::Host);
+        options.dedupe_prefer = strings(&["udp", "https", "http"]);
+        let mut aggregator = Aggregator::new(&options);
+        aggregator.extend("user", SourcePriority::User, strings(&["http://a.example/announce"]), 100);
+        aggregator.extend(
+            "best",
+            SourcePriority::Best,
+            strings(&["udp://a.example:6969/announce", "https://b.example/announce", "http://a.example/announce"]),
+            100,
+        );
+        // A preferred scheme from a lower priority never displaces the kept entry...
+        aggregator.extend("all", SourcePriority::All, strings(&["udp://b.example:6969/announce"]), 100);
+        // ...but one from the same priority replaces it in place.
+        aggregator.extend("best2", SourcePriority::Best, strings(&["udp://b.example:1/announce"]), 100);
+
+        let gathered = aggregator.finish().unwrap();
+        assert_eq!(
+            gathered.tiers,
+            [strings(&["http://a.example/announce", "udp://b.example:1/announce"])]
+        );
+        assert_eq!(gathered.collapsed, 3);
+    }
+
+    #[tokio::test]
+    async fn user_trackers_outrank_fetched_duplicates() {
+        let sources = [source(&[ALL], SourceFormat::Plain, SourcePriority::All).await];
+        let mut options = options(100);
+        options.user_trackers = strings(&[
+            "udp://tracker.example.i2p:1/announce",
+            "udp://exodus.desync.com:6969/announce",
+        ]);
+        options.tier_by_source = true;
+        let gathered = gather(&options, &sources).await;
+        assert_eq!(
+            gathered.tiers,
+            [
+                strings(&["udp://exodus.desync.com:6969/announce"]),
+                strings(&[
+                    "udp://tracker.opentrackr.org:1337/announce",
+                    "udp://user.example:1/announce",
+                    "wss://tracker.openwebtorrent.com/",
+                ]),
+                strings(&["udp://tracker.example.i2p:1/announce", "http://tracker.example.i2p/announce"]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_sources_are_topped_up_from_the_fallback() {
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead = format!("http://{}/all.txt", closed.local_addr().unwrap());
+        drop(closed);
+        let sources = [
+            source(&[BEST], SourceFormat::Plain, SourcePriority::Best).await,
+            TrackerSource::plain(Box::leak(dead.into_boxed_str()), SourcePriority::All),
+        ];
+        let mut options = options(8);
+        options.tier_by_source = true;
+        let gathered = gather(&options, &sources).await;
+
+        // Fallback entries already fetched from the curated list are skipped.
+        assert_eq!(
+            gathered.tiers,
+            [
+                strings(&[USER]),
+                strings(&[
+                    "http://tracker.files.fm:6969/announce",
+                    "https://tracker.gbitt.info/announce",
+                    "udp://open.demonii.com:1337/announce",
+                    "udp://tracker.opentrackr.org:1337/announce",
+                    "udp://tracker.torrent.eu.org:451/announce",
+                ]),
+                strings(&["udp://open.stealth.si:80/announce", "udp://tracker.nanoha.org:6969/announce"]),
+            ]
+        );
+        let fallback = gathered.origins.last().unwrap();
+        assert_eq!(
+            (fallback.source.as_str(), fallback.priority, fallback.count),
+            ("fallback", SourcePriority::Fallback, 2)
+        );
+    }
+
+    #[test]
+    fn parses_newtrackon_responses() {
+        let expected = strings(&[
+            "udp://tracker.opentrackr.org:1337/announce",
+            "udp://explodie.org:6969/announce",
+            "https://tracker.tamersunion.org/announce",
+        ]);
+        assert_eq!(parse_newtrackon_block(NEWTRACKON), expected);
+        assert_eq!(parse_newtrackon_block(NEWTRACKON_ENCODED), expected);
+        assert_eq!(parse_newtrackon_block(&NEWTRACKON.replace("\n\n", "\r\n\r\n")), expected);
+        // Invalid UTF-8 after decoding and unsupported schemes are dropped.
+        assert_eq!(
+            parse_newtrackon_block("udp%3A%2F%2F%FF.example%3A1\n\nftp://tracker.example.com\n\nnonsense"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn newtrackon_sources_are_merged_by_priority() {
+        let sources = [
+            source(&[ALL], SourceFormat::Plain, SourcePriority::All).await,
+            source(&[BEST], SourceFormat::Plain, SourcePriority::Best).await,
+            source(&[NEWTRACKON_ENCODED], SourceFormat::Newtrackon, SourcePriority::Best).await,
+        ];
+        let gathered = gather(&options(100), &sources).await;
+        // The user tracker and the curated plain list come first.
+        assert_eq!(
+            gathered.tiers[0][6..],
+            strings(&[
+                "https://tracker.tamersunion.org/announce",
+                "udp://explodie.org:6969/announce",
+                "udp://exodus.desync.com:6969/announce",
+                "wss://tracker.openwebtorrent.com/",
+            ])
+        );
+        let origins: Vec<_> = gathered.origins.iter().map(|origin| origin.count).collect();
+        assert_eq!(origins, [1, 5, 2, 3]);
+    }
+
+    #[test]
+    fn normalizes_ipv6_literals() {
+        let cases = [
+            ("udp://[2001:DB8::1]:6969/announce", "udp://[2001:db8::1]:6969/announce"),
+            ("udp://[2001:db8:0:0:0:0:0:1]:6969/announce", "udp://[2001:db8::1]:6969/announce"),
+            ("http://[2001:db8::1]:80/announce", "http://[2001:db8::1]/announce"),
+            ("https://[::1]:443/", "https://[::1]"),
+            ("HTTP://[::FFFF:192.0.2.1]:8080/announce", "http://[::ffff:c000:201]:8080/announce"),
+            ("  wss://[2001:db8::2]/  ", "wss://[2001:db8::2]/"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(normalize_tracker(input).as_deref(), Some(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_ipv6_zone_ids_and_malformed_literals() {
+        for input in [
+            "udp://[fe80::1%eth0]:6969/announce",
+            "udp://[fe80::1%25eth0]:6969/announce",
+            "http://[fe80::1%1]/announce",
+            "udp://[2001:db8::1:6969/announce",
+            "udp://2001:db8::1:6969/announce",
+            "udp://[2001:db8::g]:6969/announce",
+        ] {
+            assert_eq!(normalize_tracker(input), None, "{input}");
+        }
+        assert!(has_ipv6_zone_id("udp://[fe80::1%eth0]:6969/announce"));
+        assert!(!has_ipv6_zone_id("udp://[2001:db8::1]:6969/announce?key=a%20b"));
+        assert!(!has_ipv6_zone_id("udp://tracker.example.org:6969/announce%5b%25%5d"));
+    }
+}
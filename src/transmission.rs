@@ -0,0 +1,278 @@
+//! A small client for the Transmission RPC API, enough to hand a new
+//! torrent to a seedbox.
+
+use std::time::Duration;
+
+use data_encoding::BASE64;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::error::{Result, TorseedError};
+
+/// Header carrying Transmission's CSRF token.
+const SESSION_HEADER: &str = "X-Transmission-Session-Id";
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A torrent as identified in RPC responses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentRef {
+    pub id: i64,
+    pub name: String,
+    pub hash: String,
+}
+
+/// Result of `torrent-add`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddOutcome {
+    Added(TorrentRef),
+    /// The daemon already had the torrent. Daemons before 3.0 do not say
+    /// which one.
+    Duplicate(Option<TorrentRef>),
+}
+
+/// Session with one Transmission daemon. The CSRF session id is fetched on
+/// the first call and refreshed whenever the daemon answers 409.
+#[derive(Debug, Clone)]
+pub struct TransmissionRpc {
+    url: Url,
+    credentials: Option<(String, String)>,
+    session_id: Option<String>,
+}
+
+impl TransmissionRpc {
+    /// `url` is the RPC endpoint, usually `http://host:9091/transmission/rpc`.
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            credentials: None,
+            session_id: None,
+        }
+    }
+
+    pub fn basic_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Adds a torrent from its metainfo bytes. With `download_dir` the daemon
+    /// looks for the payload there and verifies it before seeding.
+    pub async fn torrent_add(
+        &mut self,
+        client: &Client,
+        metainfo: &[u8],
+        download_dir: Option<&str>,
+    ) -> Result<AddOutcome> {
+        let mut arguments = json!({ "metainfo": BASE64.encode(metainfo) });
+        if let Some(dir) = download_dir {
+            arguments["download-dir"] = json!(dir);
+        }
+        let body = self.call(client, "torrent-add", arguments).await?;
+        parse_add_response(&body)
+    }
+
+    /// Sends one request, repeating it once with a fresh session id when the
+    /// daemon rejects the current one.
+    async fn call(&mut self, client: &Client, method: &str, arguments: Value) -> Result<String> {
+        let request = json!({ "method": method, "arguments": arguments }).to_string();
+        for _ in 0..2 {
+            let mut builder = client
+                .post(self.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(request.clone())
+                .timeout(RPC_TIMEOUT);
+            if let Some((username, password)) = &self.credentials {
+                builder = builder.basic_auth(username, Some(password));
+            }
+            if let Some(session_id) = &self.session_id {
+                builder = builder.header(SESSION_HEADER, session_id);
+            }
+            let response = builder
+                .send()
+                .await
+                .map_err(|err| rpc_error("Transmission did not answer", Some(err)))?;
+            match response.status() {
+                StatusCode::CONFLICT => {
+                    let session_id = response
+                        .headers()
+                        .get(SESSION_HEADER)
+                        .and_then(|value| value.to_str().ok())
+                        .ok_or_else(|| rpc_error("Transmission answered 409 without a session id", None))?;
+                    self.session_id = Some(session_id.to_string());
+                }
+                StatusCode::UNAUTHORIZED => return Err(rpc_error("Transmission rejected the credentials", None)),
+                StatusCode::FORBIDDEN => {
+                    return Err(rpc_error(
+                        "Transmission does not allow RPC from this address (rpc-whitelist)",
+                        None,
+                    ));
+                }
+                status if !status.is_success() => {
+                    return Err(rpc_error(format!("Transmission answered {status}"), None));
+                }
+                _ => {
+                    return response
+                        .text()
+                        .await
+                        .map_err(|err| rpc_error("Failed to read the Transmission response", Some(err)));
+                }
+            }
+        }
+        Err(rpc_error("Transmission kept rejecting the session id", None))
+    }
+}
+
+/// Decodes a `torrent-add` response body.
+///
+/// ```
+/// use torseed::transmission::{parse_add_response, AddOutcome};
+///
+/// let added = r#"{"arguments":{"torrent-added":{"hashString":"091ceb7cd44451aa483679e7baa58ded85ba1840",
+///     "id":7,"name":"small.bin"}},"result":"success"}"#;
+/// let AddOutcome::Added(torrent) = parse_add_response(added)? else { panic!() };
+/// assert_eq!((torrent.id, torrent.name.as_str()), (7, "small.bin"));
+///
+/// // 3.0 and later report the existing torrent.
+/// let duplicate = r#"{"arguments":{"torrent-duplicate":{"hashString":"091ceb7cd44451aa483679e7baa58ded85ba1840",
+///     "id":7,"name":"small.bin"}},"result":"success"}"#;
+/// assert!(matches!(parse_add_response(duplicate)?, AddOutcome::Duplicate(Some(_))));
+///
+/// // Older daemons only put it in the result string.
+/// let legacy = r#"{"arguments":{},"result":"duplicate torrent"}"#;
+/// assert_eq!(parse_add_response(legacy)?, AddOutcome::Duplicate(None));
+///
+/// let invalid = r#"{"arguments":{},"result":"invalid or corrupt torrent file"}"#;
+/// assert!(parse_add_response(invalid).is_err());
+/// # Ok::<(), torseed::TorseedError>(())
+/// ```
+pub fn parse_add_response(body: &str) -> Result<AddOutcome> {
+    let response: Value = serde_json::from_str(body)
+        .map_err(|err| rpc_error(format!("Transmission response is not JSON: {err}"), None))?;
+    let result = response["result"].as_str().unwrap_or_default();
+    let arguments = &response["arguments"];
+    match result {
+        "success" => {
+            if let Some(torrent) = arguments.get("torrent-added") {
+                return Ok(AddOutcome::Added(torrent_ref(torrent)?));
+            }
+            if let Some(torrent) = arguments.get("torrent-duplicate") {
+                return Ok(AddOutcome::Duplicate(Some(torrent_ref(torrent)?)));
+            }
+            Err(rpc_error("Transmission reported success without naming the torrent", None))
+        }
+        "duplicate torrent" => Ok(AddOutcome::Duplicate(None)),
+        "" => Err(rpc_error("Transmission response has no result", None)),
+        error => Err(rpc_error(format!("Transmission refused the torrent: {error}"), None)),
+    }
+}
+
+fn torrent_ref(value: &Value) -> Result<TorrentRef> {
+    Ok(TorrentRef {
+        id: value["id"]
+            .as_i64()
+            .ok_or_else(|| rpc_error("Torrent entry has no id", None))?,
+        name: value["name"].as_str().unwrap_or_default().to_string(),
+        hash: value["hashString"].as_str().unwrap_or_default().to_string(),
+    })
+}
+
+fn rpc_error(message: impl Into<String>, source: Option<reqwest::Error>) -> TorseedError {
+    TorseedError::Rpc {
+        message: message.into(),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::serve_recording;
+
+    const SESSION_ID: &str = "3ERjBcxcPZ8x1IXL2G3ijvBvnNsMh6unVNfoLWXHJ9RtVtNY";
+    const CONFLICT: &[u8] = include_bytes!("../tests/fixtures/transmission_409.http");
+    const ADDED: &[u8] = include_bytes!("../tests/fixtures/transmission_added.http");
+    const DUPLICATE: &[u8] = include_bytes!("../tests/fixtures/transmission_duplicate.http");
+    const DUPLICATE_LEGACY: &[u8] = include_bytes!("../tests/fixtures/transmission_duplicate_legacy.http");
+    const CORRUPT: &[u8] = include_bytes!("../tests/fixtures/transmission_corrupt.http");
+    const UNAUTHORIZED: &[u8] = include_bytes!("../tests/fixtures/transmission_401.http");
+
+    fn image() -> TorrentRef {
+        TorrentRef {
+            id: 12,
+            name: "image.iso".to_string(),
+            hash: "4cfc2a27621f74d024cc9c39fc1bda0ec0244b69".to_string(),
+        }
+    }
+
+    /// Replays `responses` and returns the outcome of one `torrent-add` per
+    /// entry of `calls`, sharing one session, along with the requests sent.
+    async fn replay(responses: &[&[u8]], calls: usize) -> (Vec<Result<AddOutcome>>, Vec<String>) {
+        let (url, mut requests) = serve_recording(responses.iter().map(|response| response.to_vec()).collect()).await;
+        let (client, mut rpc) = (Client::new(), TransmissionRpc::new(url).basic_auth("user", "pass"));
+        let mut outcomes = Vec::new();
+        for _ in 0..calls {
+            outcomes.push(rpc.torrent_add(&client, b"d4:infod4:name1:aee", Some("/downloads")).await);
+        }
+        let mut received = Vec::new();
+        while let Ok(request) = requests.try_recv() {
+            received.push(request);
+        }
+        (outcomes, received)
+    }
+
+    fn session_header(request: &str) -> Option<&str> {
+        request
+            .lines()
+            .find_map(|line| line.strip_prefix("x-transmission-session-id: "))
+    }
+
+    #[tokio::test]
+    async fn fetches_and_reuses_the_session_id() {
+        let (outcomes, requests) = replay(&[CONFLICT, ADDED, DUPLICATE], 2).await;
+        assert_eq!(outcomes[0].as_ref().unwrap(), &AddOutcome::Added(image()));
+        assert_eq!(outcomes[1].as_ref().unwrap(), &AddOutcome::Duplicate(Some(image())));
+
+        let sessions: Vec<_> = requests.iter().map(|request| session_header(request)).collect();
+        assert_eq!(sessions, [None, Some(SESSION_ID), Some(SESSION_ID)]);
+        for request in &requests {
+            assert!(request.starts_with("POST /data.bin HTTP/1.1\r\n"), "{request}");
+            // "user:pass"
+            assert!(request.contains("\r\nauthorization: Basic dXNlcjpwYXNz\r\n"), "{request}");
+            let (_, body) = request.split_once("\r\n\r\n").unwrap();
+            let body: Value = serde_json::from_str(body).unwrap();
+            assert_eq!(
+                body,
+                json!({
+                    "method": "torrent-add",
+                    "arguments": { "metainfo": "ZDQ6aW5mb2Q0Om5hbWUxOmFlZQ==", "download-dir": "/downloads" },
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_legacy_duplicates_and_refusals() {
+        let (outcomes, _) = replay(&[CONFLICT, DUPLICATE_LEGACY, CORRUPT], 2).await;
+        assert_eq!(outcomes[0].as_ref().unwrap(), &AddOutcome::Duplicate(None));
+        let Err(TorseedError::Rpc { message, .. }) = &outcomes[1] else {
+            panic!("{:?}", outcomes[1]);
+        };
+        assert_eq!(message, "Transmission refused the torrent: invalid or corrupt torrent file");
+    }
+
+    #[tokio::test]
+    async fn reports_rejected_sessions_and_credentials() {
+        let cases: [(&[&[u8]], &str); 2] = [
+            (&[UNAUTHORIZED], "Transmission rejected the credentials"),
+            (&[CONFLICT, CONFLICT], "Transmission kept rejecting the session id"),
+        ];
+        for (responses, expected) in cases {
+            let (outcomes, requests) = replay(responses, 1).await;
+            let Err(TorseedError::Rpc { message, .. }) = &outcomes[0] else {
+                panic!("{:?}", outcomes[0]);
+            };
+            assert_eq!(message, expected);
+            assert_eq!(requests.len(), responses.len());
+        }
+    }
+}
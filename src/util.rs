@@ -1,5 +1,27 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
 const DEFAULT_NAME: &str = "download";
+const MIN_PIECE_LENGTH: usize = 16 * 1024;
 const SAFE_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789._-";
+/// Path separators plus the characters Windows forbids in file names.
+const UNSAFE_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+/// Longest name [`sanitize_filename`] returns, in bytes: the 255-byte file
+/// name limit of ext4 and NTFS less room for the `.torrent` suffix.
+pub const MAX_NAME_BYTES: usize = 255 - ".torrent".len();
+/// Extensions longer than this are truncated like the rest of the name.
+const MAX_KEPT_EXTENSION_BYTES: usize = 16;
+/// Bytes of the target name kept in a temp file name, leaving room for the
+/// dot prefix, process id and counter.
+const TEMP_NAME_BYTES: usize = 128;
+/// Device names Windows reserves whatever the extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
 
 /// Choose a v1 piece length that keeps the number of pieces reasonable (~16k max).
 pub fn choose_piece_length(size: u64) -> usize {
@@ -24,20 +46,215 @@ pub fn choose_piece_length(size: u64) -> usize {
     length_bytes as usize
 }
 
-/// Sanitizes a suggested file name for use on disk.
+/// Smallest power-of-two piece length, at least 16 KiB, that splits `size`
+/// into no more than `target` pieces.
+pub fn piece_length_for_target(size: u64, target: u64) -> usize {
+    let mut length: u64 = MIN_PIECE_LENGTH as u64;
+    while size.div_ceil(length) > target.max(1) {
+        length *= 2;
+    }
+    length as usize
+}
+
+/// Parses a byte size such as `4096`, `512KiB`, `1M` or `2.5 GB`, for use as
+/// a clap value parser. `K`, `Ki` and `KiB` (and likewise `M`, `G`, `T`, `P`,
+/// `E`) are powers of 1024; `KB`, `MB` and so on are powers of 1000. Case is
+/// ignored, a lone `B` means bytes, and fractions are rounded down to whole
+/// bytes.
+///
+/// ```
+/// use torseed::util::parse_size;
+///
+/// for (input, expected) in [
+///     ("0", 0),
+///     ("4096", 4096),
+///     ("4096B", 4096),
+///     ("512KiB", 512 * 1024),
+///     ("512kib", 512 * 1024),
+///     ("512 K", 512 * 1024),
+///     ("1M", 1 << 20),
+///     ("1Mi", 1 << 20),
+///     ("1MB", 1_000_000),
+///     ("1mb", 1_000_000),
+///     ("2.5GiB", 5 << 29),
+///     ("2.5GB", 2_500_000_000),
+///     (".5K", 512),
+///     ("1.0001KiB", 1024),
+///     ("15EiB", 15 << 60),
+///     ("15.99EiB", 18_435_214_858_663_483_146),
+///     ("18446744073709551615", u64::MAX),
+/// ] {
+///     assert_eq!(parse_size(input), Ok(expected), "{input:?}");
+/// }
+///
+/// for nonsense in [
+///     "", "KiB", "-1", "1.5", "1..5K", "1.2.3M", "12QB", "1KiBB", "1 2K", "0x10", "1e3", "∞",
+///     "16EiB", "20EiB", "18446744073709551616",
+/// ] {
+///     assert!(parse_size(nonsense).is_err(), "{nonsense:?}");
+/// }
+/// ```
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let split = trimmed
+        .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let multiplier = size_multiplier(unit.trim_start())
+        .ok_or_else(|| format!("unknown unit {:?} in {value:?}; use e.g. 4096, 512KiB, 1MB", unit.trim_start()))?;
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if (whole.is_empty() && fraction.is_empty()) || fraction.contains('.') {
+        return Err(format!("invalid size {value:?}; use e.g. 4096, 512KiB, 1MB"));
+    }
+    if !fraction.is_empty() && multiplier == 1 {
+        return Err(format!("invalid size {value:?}: byte counts must be whole"));
+    }
+
+    let too_large = || format!("size {value:?} is too large");
+    let whole: u128 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| too_large())? };
+    // Digits past the 18th cannot change the result for any multiplier.
+    let fraction = &fraction[..fraction.len().min(18)];
+    let fraction_bytes = match fraction {
+        "" => 0,
+        digits => {
+            let numerator = digits.parse::<u128>().map_err(|_| too_large())?;
+            numerator * u128::from(multiplier) / 10u128.pow(digits.len() as u32)
+        }
+    };
+    let bytes = whole
+        .checked_mul(u128::from(multiplier))
+        .and_then(|bytes| bytes.checked_add(fraction_bytes))
+        .ok_or_else(too_large)?;
+    u64::try_from(bytes).map_err(|_| too_large())
+}
+
+/// Bytes per unit for [`parse_size`], or `None` for an unknown suffix.
+fn size_multiplier(unit: &str) -> Option<u64> {
+    let unit = unit.to_ascii_lowercase();
+    let mut chars = unit.chars();
+    let Some(prefix) = chars.next() else {
+        return Some(1);
+    };
+    if unit == "b" {
+        return Some(1);
+    }
+    let exponent = "kmgtpe".find(prefix)? as u32 + 1;
+    let base: u64 = match chars.as_str() {
+        "" | "i" | "ib" => 1024,
+        "b" => 1000,
+        _ => return None,
+    };
+    base.checked_pow(exponent)
+}
+
+/// Parses an explicit piece length such as `262144` or `256KiB`; it must be a
+/// power of two of at least 16 KiB as required by BEP 52.
+pub fn parse_piece_length(value: &str) -> Result<usize, String> {
+    let length = parse_size(value)?;
+    let length = usize::try_from(length).map_err(|_| format!("piece length {value:?} is too large"))?;
+    if length < MIN_PIECE_LENGTH || !length.is_power_of_two() {
+        return Err(format!("piece length must be a power of two of at least {MIN_PIECE_LENGTH} bytes"));
+    }
+    Ok(length)
+}
+
+/// Sanitizes a suggested file name for use on disk and as the torrent name.
+/// Unicode is kept; path separators, characters Windows forbids, control
+/// characters and bidirectional overrides become `_`, and leading dots are
+/// dropped. So that Windows users can create the file, trailing dots and
+/// spaces are dropped too and reserved device names such as `CON` or
+/// `aux.iso` get a `_` after the stem. Names longer than [`MAX_NAME_BYTES`]
+/// lose the end of their stem, on a character boundary, and keep their
+/// extension.
+///
+/// ```
+/// use torseed::util::sanitize_filename;
+///
+/// for (input, expected) in [
+///     ("CON", "CON_"),
+///     ("con.txt", "con_.txt"),
+///     ("aux.iso.", "aux_.iso"),
+///     ("Nul.tar.gz", "Nul_.tar.gz"),
+///     ("COM1", "COM1_"),
+///     ("lpt9.log", "lpt9_.log"),
+///     ("COM10", "COM10"),
+///     ("console.iso", "console.iso"),
+///     ("release.iso. . .", "release.iso"),
+///     ("..", "download"),
+///     ("...", "download"),
+/// ] {
+///     assert_eq!(sanitize_filename(input), expected, "{input:?}");
+/// }
+/// ```
+///
+/// ```
+/// use torseed::util::{sanitize_filename, MAX_NAME_BYTES};
+///
+/// let fits = format!("{}.iso", "a".repeat(MAX_NAME_BYTES - 4));
+/// assert_eq!(sanitize_filename(&fits), fits);
+///
+/// let long = format!("{}.iso", "a".repeat(MAX_NAME_BYTES - 3));
+/// assert_eq!(sanitize_filename(&long), format!("{}.iso", "a".repeat(MAX_NAME_BYTES - 4)));
+///
+/// // "é" is two bytes; cutting through it would leave invalid UTF-8.
+/// let straddling = format!("{}é.tar.gz", "a".repeat(MAX_NAME_BYTES - 4));
+/// let clamped = sanitize_filename(&straddling);
+/// assert_eq!(clamped, format!("{}.gz", "a".repeat(MAX_NAME_BYTES - 4)));
+/// assert!(clamped.len() <= MAX_NAME_BYTES);
+///
+/// let dataset = format!("{}.csv", "數據".repeat(100));
+/// let clamped = sanitize_filename(&dataset);
+/// assert!(clamped.len() <= MAX_NAME_BYTES && clamped.ends_with("數.csv"));
+/// ```
+///
+/// ```
+/// use torseed::util::{sanitize_ascii_filename, sanitize_filename};
+///
+/// assert_eq!(sanitize_filename("Fedora-Workstation-日本語.iso"), "Fedora-Workstation-日本語.iso");
+/// assert_eq!(sanitize_filename("Cafe\u{301} Mu\u{308}sik.flac"), "Cafe\u{301} Mu\u{308}sik.flac");
+/// assert_eq!(sanitize_filename("🎉 release 🚀.tar.gz"), "🎉 release 🚀.tar.gz");
+/// assert_eq!(sanitize_filename("../etc/passwd"), "_etc_passwd");
+/// assert_eq!(sanitize_filename("a:b*c?\"d\"<e>|f\\g"), "a_b_c__d__e__f_g");
+/// assert_eq!(sanitize_filename("bad\u{7}\tname\u{202e}txt.exe"), "bad__name_txt.exe");
+/// assert_eq!(sanitize_filename("  ...  "), "download");
+///
+/// assert_eq!(sanitize_ascii_filename("Fedora-Workstation-日本語.iso"), "Fedora-Workstation-___.iso");
+/// assert_eq!(sanitize_ascii_filename("my file.iso"), "my_file.iso");
+/// ```
 pub fn sanitize_filename(input: &str) -> String {
+    sanitize_with(input, |ch| !ch.is_control() && !UNSAFE_CHARS.contains(&ch) && !is_bidi_control(ch))
+}
+
+/// Like [`sanitize_filename`] but keeps only ASCII letters, digits, `.`,
+/// `_` and `-`, for tools and filesystems that mishandle anything else.
+pub fn sanitize_ascii_filename(input: &str) -> String {
+    sanitize_with(input, |ch| ch.is_ascii() && SAFE_CHARS.contains(&(ch as u8)))
+}
+
+/// Characters that reorder the text around them, which lets a name display
+/// with a different extension than it has.
+fn is_bidi_control(ch: char) -> bool {
+    matches!(ch, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+fn sanitize_with(input: &str, keep: impl Fn(char) -> bool) -> String {
     let candidate = input.trim();
     let mut result = String::with_capacity(candidate.len());
 
     for ch in candidate.chars() {
-        if ch == '.' && (result.is_empty() || result == ".") {
+        if ch == '.' && result.is_empty() {
             continue;
         }
-        if ch.is_ascii() && SAFE_CHARS.contains(&(ch as u8)) {
-            result.push(ch);
-        } else {
-            result.push('_');
-        }
+        result.push(if keep(ch) { ch } else { '_' });
+    }
+    clamp_name(&mut result);
+    // Windows silently drops these, so such a name cannot be created as written.
+    result.truncate(result.trim_end_matches(['.', ' ']).len());
+
+    let stem_end = result.find('.').unwrap_or(result.len());
+    let stem = result[..stem_end].trim_end();
+    if WINDOWS_RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved)) {
+        result.insert(stem_end, '_');
     }
 
     if result.is_empty() {
@@ -47,20 +264,433 @@ pub fn sanitize_filename(input: &str) -> String {
     }
 }
 
-/// Formats bytes using GiB/MiB/...
+/// Shortens `name` to [`MAX_NAME_BYTES`], cutting the stem and keeping a
+/// short extension.
+fn clamp_name(name: &mut String) {
+    if name.len() <= MAX_NAME_BYTES {
+        return;
+    }
+    let extension_start = name
+        .rfind('.')
+        .filter(|&dot| dot > 0 && name.len() - dot <= MAX_KEPT_EXTENSION_BYTES)
+        .unwrap_or(name.len());
+    let extension = name.split_off(extension_start);
+    name.truncate(name.floor_char_boundary(MAX_NAME_BYTES - extension.len()));
+    name.push_str(&extension);
+}
+
+/// A torrent file name pattern with `{placeholder}` fields, expanded once the
+/// torrent is built. Expanded values are sanitized; literal text, including
+/// directory separators, is kept as written.
+///
+/// ```
+/// use torseed::util::{OutputTemplate, TemplateValues};
+///
+/// let template = OutputTemplate::parse("fedora-{infohash7}-{date}.torrent")?;
+/// assert!(template.needs_infohash());
+/// let path = template.expand(&TemplateValues {
+///     name: "Fedora 41.iso",
+///     infohash_v1: Some([0xab; 20]),
+///     infohash_v2: None,
+///     creation_date: 1_700_000_000,
+///     size: 2_147_483_648,
+/// });
+/// assert_eq!(path.to_str(), Some("fedora-abababa-2023-11-14.torrent"));
+///
+/// let by_name = OutputTemplate::parse("{name}.{size}.torrent")?;
+/// # let values = TemplateValues { name: "Fedora 41.iso", infohash_v1: None, infohash_v2: None, creation_date: 0, size: 7 };
+/// assert_eq!(by_name.expand(&values).to_str(), Some("Fedora 41.iso.7.torrent"));
+///
+/// assert!(OutputTemplate::parse("{hash}.torrent").is_err());
+/// assert!(OutputTemplate::parse("{name.torrent").is_err());
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Literal(String),
+    Name,
+    InfohashV1,
+    InfohashV2,
+    /// The first seven hex digits of the v1 infohash, or of the v2 one for
+    /// v2-only torrents.
+    Infohash7,
+    /// Creation date as YYYY-MM-DD in UTC.
+    Date,
+    /// Payload size in bytes.
+    Size,
+}
+
+/// What [`OutputTemplate::expand`] fills in.
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateValues<'a> {
+    pub name: &'a str,
+    pub infohash_v1: Option<[u8; 20]>,
+    pub infohash_v2: Option<[u8; 32]>,
+    pub creation_date: i64,
+    pub size: u64,
+}
+
+impl OutputTemplate {
+    pub const PLACEHOLDERS: &[&str] = &["name", "infohash_v1", "infohash_v2", "infohash7", "date", "size"];
+
+    /// Parses a template, rejecting unknown placeholders and unbalanced
+    /// braces. The error suits a clap value parser.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest[open..].starts_with('}') {
+                return Err(format!("unmatched '}}' in output template {template:?}"));
+            }
+            if open > 0 {
+                parts.push(TemplatePart::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed '{{' in output template {template:?}"))?;
+            let part = match &rest[open + 1..open + close] {
+                "name" => TemplatePart::Name,
+                "infohash_v1" => TemplatePart::InfohashV1,
+                "infohash_v2" => TemplatePart::InfohashV2,
+                "infohash7" => TemplatePart::Infohash7,
+                "date" => TemplatePart::Date,
+                "size" => TemplatePart::Size,
+                other => {
+                    return Err(format!(
+                        "unknown placeholder {{{other}}} in output template; expected one of {}",
+                        Self::PLACEHOLDERS.join(", ")
+                    ));
+                }
+            };
+            parts.push(part);
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+        if parts.is_empty() {
+            return Err("output template is empty".to_string());
+        }
+        Ok(Self { parts })
+    }
+
+    /// Whether the template can only be expanded after hashing.
+    pub fn needs_infohash(&self) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(part, TemplatePart::InfohashV1 | TemplatePart::InfohashV2 | TemplatePart::Infohash7)
+        })
+    }
+
+    pub fn expand(&self, values: &TemplateValues<'_>) -> PathBuf {
+        let mut expanded = String::new();
+        for part in &self.parts {
+            let value = match part {
+                TemplatePart::Literal(text) => {
+                    expanded.push_str(text);
+                    continue;
+                }
+                TemplatePart::Name => values.name.to_string(),
+                TemplatePart::InfohashV1 => values.infohash_v1.map(hex::encode).unwrap_or_default(),
+                TemplatePart::InfohashV2 => values.infohash_v2.map(hex::encode).unwrap_or_default(),
+                TemplatePart::Infohash7 => {
+                    let full = match (values.infohash_v1, values.infohash_v2) {
+                        (Some(v1), _) => hex::encode(v1),
+                        (None, Some(v2)) => hex::encode(v2),
+                        (None, None) => String::new(),
+                    };
+                    full.chars().take(7).collect()
+                }
+                TemplatePart::Date => {
+                    let date = std::time::UNIX_EPOCH + std::time::Duration::from_secs(values.creation_date.max(0) as u64);
+                    humantime::format_rfc3339_seconds(date).to_string()[..10].to_string()
+                }
+                TemplatePart::Size => values.size.to_string(),
+            };
+            expanded.push_str(&sanitize_filename(&value));
+        }
+        PathBuf::from(expanded)
+    }
+}
+
+/// How byte counts are shown to people. Machine-readable output always
+/// carries raw counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteUnits {
+    /// KiB, MiB, GiB, TiB and PiB, in powers of 1024.
+    #[default]
+    Binary,
+    /// KB, MB, GB, TB and PB, in powers of 1000, as download pages tend to
+    /// publish sizes.
+    Si,
+}
+
+impl ByteUnits {
+    /// Formats `bytes` in the largest unit it reaches.
+    ///
+    /// ```
+    /// use torseed::util::ByteUnits;
+    ///
+    /// for (bytes, binary, si) in [
+    ///     (0, "0 B", "0 B"),
+    ///     (999, "999 B", "999 B"),
+    ///     (1000, "1000 B", "1.00 KB"),
+    ///     (1023, "1023 B", "1.02 KB"),
+    ///     (1024, "1.00 KiB", "1.02 KB"),
+    ///     (1 << 20, "1.00 MiB", "1.05 MB"),
+    ///     (1 << 30, "1.00 GiB", "1.07 GB"),
+    ///     ((1 << 40) - 1, "1024.00 GiB", "1.10 TB"),
+    ///     (1 << 40, "1.00 TiB", "1.10 TB"),
+    ///     (2_638_827_906_662, "2.40 TiB", "2.64 TB"),
+    ///     (1_000_000_000_000_000, "909.49 TiB", "1.00 PB"),
+    ///     (1 << 50, "1.00 PiB", "1.13 PB"),
+    ///     (u64::MAX, "16384.00 PiB", "18446.74 PB"),
+    /// ] {
+    ///     assert_eq!(ByteUnits::Binary.format(bytes), binary, "{bytes}");
+    ///     assert_eq!(ByteUnits::Si.format(bytes), si, "{bytes}");
+    /// }
+    /// assert_eq!(ByteUnits::Si.format_rate(2_500_000.0), "2.50 MB/s");
+    /// ```
+    pub fn format(self, bytes: u64) -> String {
+        let (base, labels) = match self {
+            Self::Binary => (1024, ["KiB", "MiB", "GiB", "TiB", "PiB"]),
+            Self::Si => (1000, ["KB", "MB", "GB", "TB", "PB"]),
+        };
+        let mut unit = None;
+        let mut size: u64 = 1;
+        for label in labels {
+            size *= base;
+            if bytes < size {
+                break;
+            }
+            unit = Some((label, size));
+        }
+        match unit {
+            Some((label, size)) => format!("{:.2} {label}", bytes as f64 / size as f64),
+            None => format!("{bytes} B"),
+        }
+    }
+
+    pub fn format_rate(self, bytes_per_sec: f64) -> String {
+        format!("{}/s", self.format(bytes_per_sec as u64))
+    }
+}
+
+/// Formats bytes using KiB/MiB/GiB/TiB/PiB.
 pub fn format_bytes(bytes: u64) -> String {
-    const UNITS: &[(&str, u64)] = &[
-        ("GiB", 1024 * 1024 * 1024),
-        ("MiB", 1024 * 1024),
-        ("KiB", 1024),
-    ];
+    ByteUnits::Binary.format(bytes)
+}
 
-    for (label, size) in UNITS {
-        if bytes >= *size {
-            let value = bytes as f64 / *size as f64;
-            return format!("{value:.2} {label}");
+/// Query parameters to leave out of URLs written into a torrent or magnet.
+/// CDN links often carry short-lived tokens (`?token=…&expires=…`) that would
+/// both expire and leak once published; the download itself keeps them.
+///
+/// ```
+/// use torseed::util::QueryStrip;
+///
+/// let url = "https://cdn.example.com/a.iso?token=s3cr3t&Expires=1700000000&v=2#top".parse().unwrap();
+/// assert_eq!(QueryStrip::Keep.apply(&url), url);
+/// assert_eq!(QueryStrip::All.apply(&url).as_str(), "https://cdn.example.com/a.iso#top");
+/// let params = QueryStrip::Params(vec!["token".to_string(), "expires".to_string()]);
+/// assert_eq!(params.apply(&url).as_str(), "https://cdn.example.com/a.iso?v=2#top");
+/// let url = "https://cdn.example.com/a.iso?token=s3cr3t".parse().unwrap();
+/// assert_eq!(params.apply(&url).as_str(), "https://cdn.example.com/a.iso");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum QueryStrip {
+    #[default]
+    Keep,
+    /// Drop the whole query string.
+    All,
+    /// Drop these parameters, matched ignoring ASCII case, and keep the rest
+    /// in order.
+    Params(Vec<String>),
+}
+
+impl QueryStrip {
+    pub fn apply(&self, url: &url::Url) -> url::Url {
+        let mut url = url.clone();
+        match self {
+            Self::Keep => {}
+            Self::All => url.set_query(None),
+            Self::Params(params) => {
+                let kept: Vec<&str> = url
+                    .query()
+                    .unwrap_or_default()
+                    .split('&')
+                    .filter(|pair| {
+                        let name = pair.split('=').next().unwrap_or_default();
+                        !pair.is_empty() && !params.iter().any(|param| param.eq_ignore_ascii_case(name))
+                    })
+                    .collect();
+                let query = kept.join("&");
+                url.set_query(Some(query.as_str()).filter(|query| !query.is_empty()));
+            }
         }
+        url
+    }
+}
+
+/// Temp files of writes in progress, for [`remove_partial_writes`].
+static PARTIAL_WRITES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `contents` to a uniquely named temp file in the same directory,
+/// syncs it to disk and renames it over `path`, so readers only ever see the
+/// old file or the complete new one, even after a crash. The rename replaces
+/// an existing file on Windows as well. The temp file is removed on every
+/// failure path.
+///
+/// ```
+/// use torseed::util::write_atomic;
+///
+/// let dir = std::env::temp_dir().join(format!("torseed-write-atomic-{}", std::process::id()));
+/// std::fs::create_dir_all(dir.join("taken"))?;
+/// // The temp file is written, but renaming it over a directory fails.
+/// assert!(write_atomic(&dir.join("taken"), b"d8:announce0:e").is_err());
+/// let names: Vec<_> = std::fs::read_dir(&dir)?.map(|entry| entry.unwrap().file_name()).collect();
+/// assert_eq!(names, ["taken"]);
+///
+/// write_atomic(&dir.join("a.torrent"), b"old")?;
+/// write_atomic(&dir.join("a.torrent"), b"new")?;
+/// assert_eq!(std::fs::read(dir.join("a.torrent"))?, b"new");
+/// std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+    let name = &name[..name.floor_char_boundary(TEMP_NAME_BYTES)];
+    let temp = path.with_file_name(format!(
+        ".{name}.{}-{}.tmp",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _guard = PartialWrite::register(&temp);
+
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, path)?;
+    sync_parent(path);
+    Ok(())
+}
+
+/// Deletes the temp files of writes still in progress. For exit paths that
+/// skip unwinding, such as a second Ctrl-C.
+pub fn remove_partial_writes() {
+    let pending = PARTIAL_WRITES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for temp in pending.iter() {
+        let _ = fs::remove_file(temp);
     }
+}
+
+/// Removes its temp file when dropped; after a successful rename there is
+/// nothing left to remove.
+pub(crate) struct PartialWrite(PathBuf);
 
-    format!("{} B", bytes)
+impl PartialWrite {
+    pub(crate) fn register(temp: &Path) -> Self {
+        let mut pending = PARTIAL_WRITES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        pending.push(temp.to_path_buf());
+        Self(temp.to_path_buf())
+    }
+}
+
+impl Drop for PartialWrite {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+        let mut pending = PARTIAL_WRITES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        pending.retain(|temp| *temp != self.0);
+    }
+}
+
+/// Makes the rename itself durable. Best effort, and a no-op where
+/// directories cannot be opened.
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_rejects_overflow() {
+        for input in [
+            "99999999999T",
+            "99999999999.5T",
+            "16777216TiB",
+            "17592186044416MiB",
+            "18446744073709551616B",
+        ] {
+            let err = parse_size(input).unwrap_err();
+            assert!(err.contains("too large"), "{input:?}: {err}");
+        }
+        assert_eq!(parse_size("16777215TiB"), Ok(16_777_215 << 40));
+        assert_eq!(parse_size("18446744073709551.615KB"), Ok(u64::MAX));
+        assert_eq!(parse_size("000000000000000000000000001K"), Ok(1024));
+    }
+
+    #[test]
+    fn parse_size_ignores_suffix_case() {
+        for unit in ["k", "K", "ki", "KI", "kIb", "KiB", "KIB"] {
+            assert_eq!(parse_size(&format!("3{unit}")), Ok(3 * 1024), "{unit}");
+        }
+        for unit in ["kb", "kB", "Kb", "KB"] {
+            assert_eq!(parse_size(&format!("3{unit}")), Ok(3000), "{unit}");
+        }
+        for unit in ["b", "B"] {
+            assert_eq!(parse_size(&format!("3{unit}")), Ok(3), "{unit}");
+        }
+        assert_eq!(parse_size("2tb"), Ok(2_000_000_000_000));
+        assert_eq!(parse_size("  2 Ti  "), Ok(2 << 40));
+        assert!(parse_size("3bK").is_err());
+        assert!(parse_size("3kbi").is_err());
+    }
+
+    #[test]
+    fn parse_size_rounds_fractions_down() {
+        for (input, expected) in [
+            ("1.5KB", 1500),
+            ("1.5K", 1536),
+            ("0.001KB", 1),
+            ("0.0009KB", 0),
+            ("1.999999999999999999999K", 2047),
+            ("0.1MiB", 104_857),
+            ("1.K", 1024),
+        ] {
+            assert_eq!(parse_size(input), Ok(expected), "{input:?}");
+        }
+        for input in ["0.5", "0.5B", "."] {
+            assert!(parse_size(input).is_err(), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn partial_write_removes_its_file_when_dropped() {
+        let temp = std::env::temp_dir().join(format!("torseed-partial-{}.part", std::process::id()));
+        fs::write(&temp, b"partial").unwrap();
+        let pending = || PARTIAL_WRITES.lock().unwrap().contains(&temp);
+        let guard = PartialWrite::register(&temp);
+        assert!(pending());
+        drop(guard);
+        assert!(!temp.exists());
+        assert!(!pending());
+    }
 }
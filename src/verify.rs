@@ -0,0 +1,276 @@
+//! Checks that an HTTP source still serves the payload of an existing torrent.
+
+use std::fmt;
+
+use futures::stream::{self, StreamExt, TryStreamExt};
+use rand::seq::index;
+use reqwest::Client;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::builder::{self, HashOptions, Hashed};
+use crate::checksum::DigestHeaderPolicy;
+use crate::digest::Sha1;
+use crate::error::{Result, TorseedError};
+use crate::http::{self, HttpOptions, SourceMetadata};
+use crate::metainfo::{FileLayout, ParsedTorrent};
+use crate::progress::EventSink;
+
+/// Range requests in flight while sampling pieces.
+const SAMPLE_CONCURRENCY: usize = 4;
+/// Size of a v2 merkle tree leaf.
+const V2_LEAF_SIZE: u64 = 16 * 1024;
+
+/// Outcome of checking a source against a torrent.
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub pieces_checked: usize,
+    pub pieces_total: usize,
+    /// The first difference found; `None` means the source matches.
+    pub mismatch: Option<Mismatch>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.mismatch.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Length { expected: u64, actual: u64 },
+    Piece { index: usize, offset: u64 },
+    PiecesRoot,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length { expected, actual } => {
+                write!(f, "source is {actual} bytes, the torrent expects {expected}")
+            }
+            Self::Piece { index, offset } => write!(f, "piece {index} at byte {offset} differs"),
+            Self::PiecesRoot => f.write_str("v2 pieces root differs"),
+        }
+    }
+}
+
+/// Streams the whole source and compares every v1 piece hash, plus the v2
+/// pieces root for v2 and hybrid torrents whose root can be compared (see
+/// [`root_comparable`]). A length mismatch is reported without downloading
+/// anything.
+pub async fn verify_source(
+    client: &Client,
+    http_options: &HttpOptions,
+    torrent: &ParsedTorrent,
+    source: &SourceMetadata,
+    events: &EventSink,
+    cancel: &CancellationToken,
+) -> Result<VerifyReport> {
+    let (pieces_total, length_mismatch) = precheck(torrent, source)?;
+    if let Some(mismatch) = length_mismatch {
+        return Ok(VerifyReport {
+            pieces_checked: 0,
+            pieces_total,
+            mismatch: Some(mismatch),
+        });
+    }
+
+    let compare_root = root_comparable(source.content_length);
+    if torrent.pieces.is_none() && !compare_root {
+        return Err(TorseedError::InvalidInput(
+            "This v2-only torrent cannot be verified: its pieces root can only be compared when the file spans \
+             a power of two of 16 KiB blocks"
+                .to_string(),
+        ));
+    }
+
+    let piece_length = piece_length(torrent)?;
+    // A wrong digest header shows up as a piece mismatch too; reporting that
+    // is the point of verifying.
+    let options = HashOptions {
+        digest_header: DigestHeaderPolicy::Warn,
+        http: http_options.clone(),
+        ..HashOptions::default()
+    };
+    let (Hashed { pieces, v2, .. }, _) =
+        builder::hash_source(client, source, piece_length, None, options, events, cancel).await?;
+
+    let mut mismatch = None;
+    if let Some(expected) = &torrent.pieces {
+        mismatch = expected
+            .chunks(20)
+            .zip(pieces.chunks(20))
+            .position(|(expected, actual)| expected != actual)
+            .map(|index| Mismatch::Piece {
+                index,
+                offset: index as u64 * piece_length as u64,
+            });
+    }
+    if mismatch.is_none()
+        && compare_root
+        && let Some(expected) = torrent.v2.as_ref().and_then(|v2| v2.files.first()?.pieces_root)
+        && v2.is_some_and(|v2| v2.pieces_root != expected)
+    {
+        mismatch = Some(Mismatch::PiecesRoot);
+    }
+    Ok(VerifyReport {
+        pieces_checked: pieces_total,
+        pieces_total,
+        mismatch,
+    })
+}
+
+/// Fetches `count` randomly chosen pieces with Range requests and compares
+/// their v1 hashes. Cheap enough for routine mirror audits, but it only
+/// proves the sampled pieces; the v2 pieces root is not checked.
+pub async fn verify_sample(
+    client: &Client,
+    http_options: &HttpOptions,
+    torrent: &ParsedTorrent,
+    source: &SourceMetadata,
+    count: usize,
+    cancel: &CancellationToken,
+) -> Result<VerifyReport> {
+    let (pieces_total, length_mismatch) = precheck(torrent, source)?;
+    if let Some(mismatch) = length_mismatch {
+        return Ok(VerifyReport {
+            pieces_checked: 0,
+            pieces_total,
+            mismatch: Some(mismatch),
+        });
+    }
+    let Some(expected) = &torrent.pieces else {
+        return Err(TorseedError::InvalidInput(
+            "Sampling needs v1 piece hashes; this is a v2-only torrent".to_string(),
+        ));
+    };
+
+    let piece_length = piece_length(torrent)? as u64;
+    let mut indices = index::sample(&mut rand::thread_rng(), pieces_total, count.min(pieces_total)).into_vec();
+    indices.sort_unstable();
+    info!("Sampling {} of {} pieces", indices.len(), pieces_total);
+
+    let fetches = stream::iter(indices.iter().map(|&index| {
+        let offset = index as u64 * piece_length;
+        let length = piece_length.min(source.content_length - offset);
+        async move {
+            let data = http::fetch_range(client, http_options, &source.url, offset, length).await?;
+            let mut hasher = Sha1::new();
+            hasher.update(&data);
+            Ok::<_, TorseedError>((index, offset, hasher.finalize()))
+        }
+    }))
+    .buffered(SAMPLE_CONCURRENCY);
+    let checked: Vec<_> = cancel
+        .run_until_cancelled(fetches.try_collect())
+        .await
+        .ok_or(TorseedError::Cancelled)??;
+
+    let mismatch = checked
+        .iter()
+        .find(|(index, _, digest)| expected[index * 20..index * 20 + 20] != digest[..])
+        .map(|&(index, offset, _)| Mismatch::Piece { index, offset });
+    Ok(VerifyReport {
+        pieces_checked: checked.len(),
+        pieces_total,
+        mismatch,
+    })
+}
+
+/// Returns the piece count, or a length mismatch when the source size
+/// differs from the torrent.
+fn precheck(torrent: &ParsedTorrent, source: &SourceMetadata) -> Result<(usize, Option<Mismatch>)> {
+    let FileLayout::Single { length } = torrent.layout else {
+        return Err(TorseedError::InvalidInput(
+            "Only single-file torrents can be verified against a URL".to_string(),
+        ));
+    };
+    let pieces_total = length.div_ceil(torrent.piece_length.max(1)) as usize;
+    let mismatch = (source.content_length != length).then_some(Mismatch::Length {
+        expected: length,
+        actual: source.content_length,
+    });
+    Ok((pieces_total, mismatch))
+}
+
+/// The v2 hasher pairs an odd tree node with itself where BEP 52 pads with
+/// zero hashes, so its pieces root only agrees with other clients when the
+/// number of 16 KiB leaves is a power of two.
+fn root_comparable(length: u64) -> bool {
+    length.div_ceil(V2_LEAF_SIZE).is_power_of_two()
+}
+
+fn piece_length(torrent: &ParsedTorrent) -> Result<usize> {
+    usize::try_from(torrent.piece_length)
+        .ok()
+        .filter(|length| *length > 0)
+        .ok_or_else(|| TorseedError::InvalidInput(format!("Unusable piece length {}", torrent.piece_length)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::{self, MetaVersion};
+    use crate::testing::{payload, response, serve};
+    use url::Url;
+
+    fn probed(url: Url, content_length: u64) -> SourceMetadata {
+        SourceMetadata {
+            url,
+            content_length,
+            filename: "data.bin".to_string(),
+            original_filename: None,
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    async fn verify(torrent: &ParsedTorrent, data: &[u8]) -> Result<VerifyReport> {
+        let url = serve(vec![response(Some(data.len() as u64), data)]).await;
+        let source = probed(url, data.len() as u64);
+        let (client, http_options) = (Client::new(), HttpOptions::default());
+        verify_source(&client, &http_options, torrent, &source, &EventSink::default(), &CancellationToken::new()).await
+    }
+
+    /// A v2-only torrent of `data` as built by torseed.
+    async fn v2_only(data: &[u8]) -> ParsedTorrent {
+        let length = data.len() as u64;
+        let built = crate::TorrentBuilder::from_reader(std::io::Cursor::new(data.to_vec()), "data.bin", Some(length))
+            .trackers(["udp://tracker.example.org:1337/announce"])
+            .piece_length(16_384)
+            .build(&Client::new())
+            .await
+            .unwrap();
+        let torrent = metainfo::build_version(&built.input, MetaVersion::V2).unwrap().torrent;
+        metainfo::parse(&torrent).unwrap()
+    }
+
+    #[tokio::test]
+    async fn passes_a_hybrid_torrent_from_another_tool() {
+        // Five 16 KiB leaves, padded with zero hashes as BEP 52 asks.
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let torrent = metainfo::parse(&std::fs::read(fixtures.join("qbittorrent_hybrid_odd.torrent")).unwrap()).unwrap();
+        let report = verify(&torrent, &payload(65_537)).await.unwrap();
+        assert_eq!(report.mismatch, None);
+        assert_eq!((report.pieces_checked, report.pieces_total), (5, 5));
+
+        let mut corrupted = payload(65_537);
+        corrupted[40_000] ^= 1;
+        let report = verify(&torrent, &corrupted).await.unwrap();
+        assert_eq!(report.mismatch, Some(Mismatch::Piece { index: 2, offset: 32_768 }));
+    }
+
+    #[tokio::test]
+    async fn compares_v2_roots_of_power_of_two_leaves() {
+        let torrent = v2_only(&payload(65_536)).await;
+        assert_eq!(verify(&torrent, &payload(65_536)).await.unwrap().mismatch, None);
+        let mut corrupted = payload(65_536);
+        corrupted[40_000] ^= 1;
+        assert_eq!(verify(&torrent, &corrupted).await.unwrap().mismatch, Some(Mismatch::PiecesRoot));
+
+        let torrent = v2_only(&payload(65_537)).await;
+        let result = verify(&torrent, &payload(65_537)).await;
+        assert!(matches!(result, Err(TorseedError::InvalidInput(_))), "{result:?}");
+    }
+}
@@ -0,0 +1,116 @@
+//! Runs the torseed binary against a local server and checks the exit
+//! status of each failure class.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::thread;
+
+const TRACKER: &str = "udp://tracker.example.org:1337/announce";
+
+/// Answers every request with `respond(method)` on its own connection and
+/// returns the URL of `/data.bin`.
+fn serve(respond: fn(&str) -> Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/data.bin", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            let _ = reader.read_line(&mut request_line);
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                line.clear();
+            }
+            let method = request_line.split(' ').next().unwrap_or_default().to_string();
+            let _ = stream.write_all(&respond(&method));
+        }
+    });
+    url
+}
+
+/// A `200 OK` whose headers announce `length` bytes; the body is only sent
+/// for GET and ends when the connection closes.
+fn ok(method: &str, length: usize, body: &[u8]) -> Vec<u8> {
+    let mut response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n".to_vec();
+    if method == "HEAD" {
+        response.extend_from_slice(format!("Content-Length: {length}\r\n\r\n").as_bytes());
+    } else {
+        response.extend_from_slice(b"\r\n");
+        response.extend_from_slice(body);
+    }
+    response
+}
+
+fn payload(length: usize) -> Vec<u8> {
+    (0..length).map(|i| (i % 251) as u8).collect()
+}
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+/// Runs torseed in a scratch directory so nothing is written to the tree.
+fn torseed(name: &str, args: &[&str]) -> Output {
+    let dir = std::env::temp_dir().join(format!("torseed-exit-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_torseed"))
+        .args(args)
+        .current_dir(&dir)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+fn assert_exit(output: &Output, code: i32) {
+    assert_eq!(
+        output.status.code(),
+        Some(code),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn invalid_arguments_exit_2() {
+    assert_exit(&torseed("flag", &["--no-such-flag"]), 2);
+    // Rejected by torseed itself rather than by argument parsing.
+    let output = torseed(
+        "multi",
+        &["--multi", "http://127.0.0.1:9/part-{1..2}.bin", "--max-trackers", "0", "--tracker", TRACKER],
+    );
+    assert_exit(&output, 2);
+}
+
+#[test]
+fn missing_source_exits_3() {
+    let url = serve(|_| b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec());
+    assert_exit(&torseed("404", &[&url, "--max-trackers", "0", "--tracker", TRACKER]), 3);
+}
+
+#[test]
+fn short_body_exits_4() {
+    let url = serve(|method| ok(method, 40_000, &payload(20_000)));
+    assert_exit(&torseed("short", &[&url, "--max-trackers", "0", "--tracker", TRACKER]), 4);
+}
+
+#[test]
+fn verify_mismatch_exits_7() {
+    let torrent = fixture("qbittorrent_hybrid_odd.torrent");
+    let torrent = torrent.to_str().unwrap();
+    let url = serve(|method| ok(method, 65_537, &payload(65_537)));
+    assert_exit(&torseed("verify-pass", &["verify", torrent, &url]), 0);
+
+    let url = serve(|method| {
+        let mut corrupted = payload(65_537);
+        corrupted[40_000] ^= 1;
+        ok(method, 65_537, &corrupted)
+    });
+    let output = torseed("verify-fail", &["verify", torrent, &url]);
+    assert_exit(&output, 7);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("FAIL: piece 2 at byte 32768 differs"));
+}